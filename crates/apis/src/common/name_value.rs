@@ -40,10 +40,11 @@ pub struct SecretKeyRef {
     pub key: Option<String>,
 }
 
-/// DynamicValue is a dynamic value struct for the component.metadata pair value.
+/// DynamicValue is a dynamic value struct for the component.metadata pair value: a string,
+/// a number, a boolean or any other JSON value.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct DynamicValue {
-    #[serde(flatten)]
     pub raw: Value,
 }
 impl DynamicValue {
@@ -183,15 +184,8 @@ mod tests {
 
     #[test]
     fn test_name_value_pair_with_string_value() {
-        // Test string values without serialization since flatten doesn't work with primitives
-        let pair = NameValuePair {
-            name: "test_key".to_string(),
-            value: Some(DynamicValue {
-                raw: json!("test_value"),
-            }),
-            secret_key_ref: None,
-            env_ref: None,
-        };
+        let pair: NameValuePair =
+            serde_json::from_value(json!({"name": "test_key", "value": "test_value"})).unwrap();
 
         assert_eq!(pair.name, "test_key");
         assert!(pair.has_value());
@@ -299,7 +293,7 @@ mod tests {
         };
 
         let bytes = value.as_bytes().unwrap();
-        let reconstructed: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let reconstructed: Value = serde_json::from_slice(&bytes).unwrap();
 
         assert_eq!(reconstructed, json!({"key": "value"}));
     }
@@ -0,0 +1,3 @@
+pub mod v1alpha1;
+
+pub const GROUP_NAME: &str = "takulatech.rapr.io";
@@ -0,0 +1,366 @@
+use crate::{K8sListMetaV1, K8sObjectMetaV1, K8sTypeMetaV1};
+use serde::{Deserialize, Serialize};
+
+pub const KIND: &str = "Configuration";
+pub const VERSION: &str = "v1alpha1";
+/// Plural name of the resource in the Kubernetes API.
+pub const PLURAL: &str = "configurations";
+
+/// Action allowing a call.
+pub const ACTION_ALLOW: &str = "allow";
+/// Action denying a call.
+pub const ACTION_DENY: &str = "deny";
+
+/// Configuration holds the settings of the runtime of an app.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Configuration {
+    #[serde(flatten)]
+    pub type_meta: K8sTypeMetaV1,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<K8sObjectMetaV1>,
+    #[serde(default)]
+    pub spec: ConfigurationSpec,
+}
+
+impl Configuration {
+    /// Returns the configuration kind.
+    pub fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    pub fn api_version(&self) -> String {
+        format!("{}/{}", crate::configuration::GROUP_NAME, VERSION)
+    }
+
+    /// Returns the configuration name.
+    pub fn get_name(&self) -> &str {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.name.as_deref())
+            .unwrap_or("")
+    }
+}
+
+/// ConfigurationList is a list of configuration resources.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ConfigurationList {
+    #[serde(flatten)]
+    pub type_meta: K8sTypeMetaV1,
+    pub metadata: K8sListMetaV1,
+    pub items: Vec<Configuration>,
+}
+
+/// ConfigurationSpec is the spec of a configuration resource.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracing: Option<TracingSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_control: Option<AccessControlSpec>,
+    /// Feature gates, by name.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<FeatureSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<WasmSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<SecretsSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api: Option<ApiSpec>,
+}
+
+impl ConfigurationSpec {
+    /// Returns true if the feature gate is enabled. Unknown features are disabled.
+    pub fn is_feature_enabled(&self, name: &str) -> bool {
+        self.features
+            .iter()
+            .any(|feature| feature.name == name && feature.enabled)
+    }
+
+    /// Returns true if WebAssembly components must run in a strict sandbox.
+    pub fn wasm_strict_sandbox(&self) -> bool {
+        self.wasm.as_ref().is_some_and(|wasm| wasm.strict_sandbox)
+    }
+
+    /// Returns the secret scopes of a secret store, if any.
+    pub fn secrets_scope(&self, store_name: &str) -> Option<&SecretsScope> {
+        self.secrets
+            .as_ref()?
+            .scopes
+            .iter()
+            .find(|scope| scope.store_name == store_name)
+    }
+}
+
+/// TracingSpec configures the export of traces.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TracingSpec {
+    /// Fraction of the traces sampled, between `0` and `1`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sampling_rate: String,
+    /// Whether to also write spans to the standard output.
+    #[serde(default)]
+    pub stdout: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub otel: Option<OtelSpec>,
+}
+
+/// OtelSpec configures the OpenTelemetry collector traces are exported to.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OtelSpec {
+    /// Address of the collector, such as `localhost:4317`.
+    pub endpoint_address: String,
+    /// Whether the collector is reached over TLS.
+    #[serde(default)]
+    pub is_secure: bool,
+    /// `grpc` (the default) or `http`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub protocol: String,
+}
+
+/// MetricsSpec configures the metrics of the runtime.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSpec {
+    /// Whether metrics are collected. Defaults to true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<MetricsHttpSpec>,
+    /// Labels whose values are restricted, to bound the number of series.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<MetricsLabelSpec>,
+}
+
+/// MetricsHttpSpec configures the metrics of the methods invoked over HTTP.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsHttpSpec {
+    /// Patterns of the paths of the invoked methods reported, such as `/orders/{id}`. When
+    /// set, methods matching none are reported as `_`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_matching: Vec<String>,
+}
+
+/// MetricsLabelSpec restricts the values a label of the metrics is reported with.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsLabelSpec {
+    pub name: String,
+    /// Values reported as is. Other values are reported as `_`.
+    #[serde(default)]
+    pub allowed_values: Vec<String>,
+}
+
+/// AccessControlSpec restricts the apps allowed to invoke the app.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessControlSpec {
+    /// Action applied to callers no policy matches, [`ACTION_ALLOW`] or [`ACTION_DENY`].
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub default_action: String,
+    /// Trust domain of the callers no policy matches. Defaults to `public`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub trust_domain: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policies: Vec<AppPolicySpec>,
+}
+
+/// AppPolicySpec is the access control policy of a calling app.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppPolicySpec {
+    /// Id of the calling app.
+    pub app_id: String,
+    /// Action applied to the operations of the app no rule matches.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub default_action: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub trust_domain: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub namespace: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub operations: Vec<AppOperation>,
+}
+
+/// AppOperation is the action applied to the calls of a method.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppOperation {
+    /// Path of the method, where `*` matches a segment and a trailing `**` any suffix.
+    pub name: String,
+    /// HTTP verbs the rule applies to, `*` matching all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub http_verb: Vec<String>,
+    pub action: String,
+}
+
+/// FeatureSpec enables or disables a feature gate.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FeatureSpec {
+    pub name: String,
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// WasmSpec configures the WebAssembly components.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WasmSpec {
+    /// Whether WebAssembly components run with deterministic clocks and randomness.
+    #[serde(default)]
+    pub strict_sandbox: bool,
+}
+
+/// SecretsSpec restricts the secrets the app may read.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SecretsSpec {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<SecretsScope>,
+}
+
+/// SecretsScope restricts the secrets of a secret store the app may read.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretsScope {
+    pub store_name: String,
+    /// Access to the secrets not listed, [`ACTION_ALLOW`] (the default) or [`ACTION_DENY`].
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub default_access: String,
+    /// Secrets the app may read. When not empty, all others are denied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_secrets: Vec<String>,
+    /// Secrets the app may not read, whatever the other settings.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_secrets: Vec<String>,
+}
+
+impl SecretsScope {
+    /// Returns true if the app may read the secret.
+    pub fn is_secret_allowed(&self, key: &str) -> bool {
+        if self.denied_secrets.iter().any(|secret| secret == key) {
+            return false;
+        }
+        if !self.allowed_secrets.is_empty() {
+            return self.allowed_secrets.iter().any(|secret| secret == key);
+        }
+        !self.default_access.eq_ignore_ascii_case(ACTION_DENY)
+    }
+}
+
+/// ApiSpec restricts the building-block APIs the app may call.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApiSpec {
+    /// APIs the app may call. When not empty, all others are denied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<ApiRule>,
+    /// APIs the app may not call, whatever the allowed ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied: Vec<ApiRule>,
+}
+
+/// ApiRule names an API of the runtime, such as `invoke` version `v1`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApiRule {
+    pub name: String,
+    /// Version of the API, all of them when empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub version: String,
+    /// `grpc` or `http`, both when empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub protocol: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let configuration: Configuration = serde_json::from_value(serde_json::json!({
+            "apiVersion": "takulatech.rapr.io/v1alpha1",
+            "kind": "Configuration",
+            "metadata": {"name": "appconfig"},
+            "spec": {
+                "tracing": {
+                    "samplingRate": "0.5",
+                    "otel": {"endpointAddress": "localhost:4317", "isSecure": false}
+                },
+                "metrics": {
+                    "enabled": false,
+                    "http": {"pathMatching": ["/orders/{id}"]},
+                    "labels": [{"name": "component", "allowedValues": ["store"]}]
+                },
+                "accessControl": {
+                    "defaultAction": "deny",
+                    "trustDomain": "public",
+                    "policies": [{
+                        "appId": "checkout",
+                        "defaultAction": "allow",
+                        "namespace": "default",
+                        "operations": [
+                            {"name": "/orders/*", "httpVerb": ["POST"], "action": "deny"}
+                        ]
+                    }]
+                },
+                "features": [{"name": "Actors", "enabled": true}],
+                "wasm": {"strictSandbox": true},
+                "secrets": {
+                    "scopes": [{"storeName": "vault", "defaultAccess": "deny", "allowedSecrets": ["db"]}]
+                },
+                "api": {
+                    "allowed": [{"name": "invoke", "version": "v1"}],
+                    "denied": [{"name": "crypto", "version": "v1", "protocol": "http"}]
+                }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(configuration.get_name(), "appconfig");
+        let spec = &configuration.spec;
+        let tracing = spec.tracing.as_ref().unwrap();
+        assert_eq!(tracing.sampling_rate, "0.5");
+        assert_eq!(
+            tracing.otel.as_ref().unwrap().endpoint_address,
+            "localhost:4317"
+        );
+        let metrics = spec.metrics.as_ref().unwrap();
+        assert_eq!(metrics.enabled, Some(false));
+        assert_eq!(
+            metrics.http.as_ref().unwrap().path_matching,
+            ["/orders/{id}"]
+        );
+        assert_eq!(metrics.labels[0].allowed_values, ["store"]);
+        let access_control = spec.access_control.as_ref().unwrap();
+        assert_eq!(access_control.default_action, ACTION_DENY);
+        assert_eq!(access_control.policies[0].operations[0].http_verb, ["POST"]);
+        assert!(spec.is_feature_enabled("Actors"));
+        assert!(!spec.is_feature_enabled("Workflows"));
+        assert!(spec.wasm_strict_sandbox());
+        assert!(spec.secrets_scope("vault").is_some());
+        assert!(spec.secrets_scope("kms").is_none());
+        let api = spec.api.as_ref().unwrap();
+        assert_eq!(api.allowed[0].name, "invoke");
+        assert_eq!(api.denied[0].protocol, "http");
+    }
+
+    #[test]
+    fn test_is_secret_allowed() {
+        let scope = |default_access: &str, allowed: &[&str], denied: &[&str]| SecretsScope {
+            store_name: "vault".to_string(),
+            default_access: default_access.to_string(),
+            allowed_secrets: allowed.iter().map(|s| s.to_string()).collect(),
+            denied_secrets: denied.iter().map(|s| s.to_string()).collect(),
+        };
+
+        assert!(scope("", &[], &[]).is_secret_allowed("db"));
+        assert!(!scope("deny", &[], &[]).is_secret_allowed("db"));
+        assert!(scope("deny", &["db"], &[]).is_secret_allowed("db"));
+        assert!(!scope("allow", &["db"], &[]).is_secret_allowed("api"));
+        assert!(!scope("allow", &["db"], &["db"]).is_secret_allowed("db"));
+    }
+}
@@ -9,6 +9,8 @@
 
 pub mod common;
 pub mod components;
+pub mod configuration;
+pub mod resiliency;
 
 pub use kube::core::ListMeta as K8sListMetaV1;
 pub use kube::core::ObjectMeta as K8sObjectMetaV1;
@@ -0,0 +1,3 @@
+pub use types::*;
+
+mod types;
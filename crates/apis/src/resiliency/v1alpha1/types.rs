@@ -0,0 +1,187 @@
+use crate::common::Scoped;
+use crate::{K8sListMetaV1, K8sObjectMetaV1, K8sTypeMetaV1};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const KIND: &str = "Resiliency";
+pub const VERSION: &str = "v1alpha1";
+
+/// Resiliency defines named policies and the targets they apply to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Resiliency {
+    #[serde(flatten)]
+    pub type_meta: K8sTypeMetaV1,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<K8sObjectMetaV1>,
+    #[serde(default)]
+    pub spec: ResiliencySpec,
+    #[serde(flatten)]
+    pub scoped: Scoped,
+}
+
+impl Resiliency {
+    /// Returns the resiliency kind.
+    pub fn kind(&self) -> &'static str {
+        KIND
+    }
+
+    pub fn api_version(&self) -> String {
+        format!("{}/{}", crate::resiliency::GROUP_NAME, VERSION)
+    }
+
+    /// Returns the resiliency name.
+    pub fn get_name(&self) -> &str {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.name.as_deref())
+            .unwrap_or("")
+    }
+}
+
+/// ResiliencyList is a list of resiliency resources.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ResiliencyList {
+    #[serde(flatten)]
+    pub type_meta: K8sTypeMetaV1,
+    pub metadata: K8sListMetaV1,
+    pub items: Vec<Resiliency>,
+}
+
+/// ResiliencySpec is the spec of a resiliency resource.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ResiliencySpec {
+    #[serde(default)]
+    pub policies: Policies,
+    #[serde(default)]
+    pub targets: Targets,
+}
+
+/// Policies holds the named policies targets refer to.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Policies {
+    /// Timeouts, as durations such as `5s`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub timeouts: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub retries: HashMap<String, Retry>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub circuit_breakers: HashMap<String, CircuitBreaker>,
+}
+
+/// Retry is a retry policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Retry {
+    /// `constant` (the default) or `exponential`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub policy: String,
+    /// Interval between retries of the constant policy. Defaults to `5s`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub duration: String,
+    /// Longest interval between retries of the exponential policy. Defaults to `60s`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub max_interval: String,
+    /// Number of retries after the first attempt. Negative or unset retries forever.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<i64>,
+}
+
+/// CircuitBreaker is a circuit breaker policy.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CircuitBreaker {
+    /// Number of requests allowed while half-open. Defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_requests: Option<u32>,
+    /// Period after which the counts of a closed breaker are cleared. Never when `0s`,
+    /// the default.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub interval: String,
+    /// Time an open breaker waits before going half-open. Defaults to `60s`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub timeout: String,
+    /// Expression opening the breaker, such as `consecutiveFailures > 5`, the default.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub trip: String,
+}
+
+/// Targets map apps, actor types and components to the policies applied to them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Targets {
+    /// Service invocation, by app id.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub apps: HashMap<String, PolicyNames>,
+    /// Actor invocation, by actor type.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub actors: HashMap<String, PolicyNames>,
+    /// Component operations, by component name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub components: HashMap<String, ComponentPolicyNames>,
+}
+
+/// PolicyNames refers to the policies applied to a target.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyNames {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<String>,
+}
+
+/// ComponentPolicyNames refers to the policies applied to the calls the runtime makes to
+/// a component (outbound) and to those a component makes to the app (inbound).
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ComponentPolicyNames {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outbound: Option<PolicyNames>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inbound: Option<PolicyNames>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let resiliency: Resiliency = serde_json::from_value(serde_json::json!({
+            "apiVersion": "takulatech.rapr.io/v1alpha1",
+            "kind": "Resiliency",
+            "metadata": {"name": "default"},
+            "spec": {
+                "policies": {
+                    "timeouts": {"general": "5s"},
+                    "retries": {"important": {"policy": "exponential", "maxRetries": 3}},
+                    "circuitBreakers": {"simple": {"trip": "consecutiveFailures > 2"}}
+                },
+                "targets": {
+                    "apps": {"orders": {"timeout": "general", "retry": "important"}},
+                    "components": {"statestore": {"outbound": {"circuitBreaker": "simple"}}}
+                }
+            },
+            "scopes": ["checkout"]
+        }))
+        .unwrap();
+
+        assert_eq!(resiliency.get_name(), "default");
+        assert_eq!(resiliency.spec.policies.timeouts["general"], "5s");
+        assert_eq!(
+            resiliency.spec.policies.retries["important"].max_retries,
+            Some(3)
+        );
+        assert_eq!(
+            resiliency.spec.targets.apps["orders"].retry.as_deref(),
+            Some("important")
+        );
+        let outbound = resiliency.spec.targets.components["statestore"]
+            .outbound
+            .as_ref()
+            .unwrap();
+        assert_eq!(outbound.circuit_breaker.as_deref(), Some("simple"));
+        assert!(resiliency.scoped.is_app_scoped("checkout"));
+    }
+}
@@ -5,6 +5,7 @@ use std::env;
 use std::fs;
 use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
+use std::time::Duration;
 
 pub const DOT_DELIMITER: &str = ".";
 
@@ -126,6 +127,46 @@ pub fn parse_service_addr(val: &str) -> Vec<String> {
     val.split(',').map(|v| v.trim().to_string()).collect()
 }
 
+/// ParseDuration parses a duration string such as `300ms`, `1.5h` or `2h45m`, like Go's
+/// `time.ParseDuration`. Valid units are `ns`, `us` (or `µs`), `ms`, `s`, `m` and `h`.
+/// Negative durations are rejected.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration {s:?}");
+    let mut rest = s.trim();
+    if rest == "0" {
+        return Ok(Duration::ZERO);
+    }
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut total = 0f64;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .ok_or_else(|| format!("missing unit in duration {s:?}"))?;
+        let value: f64 = rest[..number_len].parse().map_err(|_| invalid())?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let nanos = match &rest[..unit_len] {
+            "ns" => 1.0,
+            "us" | "µs" => 1e3,
+            "ms" => 1e6,
+            "s" => 1e9,
+            "m" => 60e9,
+            "h" => 3600e9,
+            unit => return Err(format!("unknown unit {unit:?} in duration {s:?}")),
+        };
+        total += value * nanos;
+        rest = &rest[unit_len..];
+    }
+    if total > u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_nanos(total as u64))
+}
+
 #[derive(Debug, PartialEq, Clone)]
 struct CustomType {
     v1: String,
@@ -460,4 +501,18 @@ mod tests {
             assert_eq!(result, tc.out, "Test case: {}", tc.addr);
         }
     }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("5s"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_duration("300ms"), Ok(Duration::from_millis(300)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2h45m"), Ok(Duration::from_secs(9900)));
+        assert_eq!(parse_duration("10us"), Ok(Duration::from_micros(10)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("5").is_err());
+        assert!(parse_duration("-5s").is_err());
+        assert!(parse_duration("5 days").is_err());
+    }
 }
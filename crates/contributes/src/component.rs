@@ -0,0 +1,169 @@
+//! Registry of the component implementations contributed by this crate.
+
+use snafu::Snafu;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Metadata holds the configuration of a component, as set in its resource.
+pub type Metadata = HashMap<String, String>;
+
+/// Error returned by a factory which could not create its component.
+pub type FactoryError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Factory creates a component from its metadata.
+pub type Factory<T> = Arc<dyn Fn(&Metadata) -> Result<Arc<T>, FactoryError> + Send + Sync>;
+
+/// Error returned by a component which failed its health check.
+pub type PingError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Error returned by a component which failed to close.
+pub type CloseError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Version of components registered or created without one.
+pub const DEFAULT_VERSION: &str = "v1";
+
+#[derive(Debug, Snafu)]
+pub enum ComponentError {
+    #[snafu(display("Component type {}/{} is not registered", component_type, version))]
+    NotRegistered {
+        component_type: String,
+        version: String,
+    },
+
+    #[snafu(display("Failed to initialize component {}: {}", component_type, source))]
+    Init {
+        component_type: String,
+        source: FactoryError,
+    },
+}
+
+/// Pinger checks that a component still works, e.g. that its backing service answers. The
+/// runtime pings components periodically when health checks are enabled.
+#[async_trait::async_trait]
+pub trait Pinger: Send + Sync {
+    /// Checks the health of the component. Components which cannot check their health keep
+    /// the default, which always succeeds.
+    async fn ping(&self) -> Result<(), PingError> {
+        Ok(())
+    }
+}
+
+/// Closer releases what a component holds, such as its connections, when the runtime
+/// shuts down.
+#[async_trait::async_trait]
+pub trait Closer: Send + Sync {
+    /// Closes the component. Components which hold nothing keep the default, which does
+    /// nothing.
+    async fn close(&self) -> Result<(), CloseError> {
+        Ok(())
+    }
+}
+
+/// Registry maps component types, such as `crypto.rapr.localstorage`, to the factories
+/// creating them. `T` is the trait implemented by the components of a building block.
+pub struct Registry<T: ?Sized> {
+    factories: HashMap<(String, String), Factory<T>>,
+}
+
+impl<T: ?Sized> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+}
+
+impl<T: ?Sized> Registry<T> {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the factory of a component type and version.
+    /// An empty version stands for [`DEFAULT_VERSION`].
+    pub fn register<F>(&mut self, component_type: &str, version: &str, factory: F)
+    where
+        F: Fn(&Metadata) -> Result<Arc<T>, FactoryError> + Send + Sync + 'static,
+    {
+        self.factories.insert(
+            (component_type.to_string(), normalize_version(version)),
+            Arc::new(factory),
+        );
+    }
+
+    /// Returns true if the component type and version are registered.
+    pub fn contains(&self, component_type: &str, version: &str) -> bool {
+        self.factories
+            .contains_key(&(component_type.to_string(), normalize_version(version)))
+    }
+
+    /// Creates a component of the given type and version.
+    pub fn create(
+        &self,
+        component_type: &str,
+        version: &str,
+        metadata: &Metadata,
+    ) -> Result<Arc<T>, ComponentError> {
+        let version = normalize_version(version);
+        let factory = self
+            .factories
+            .get(&(component_type.to_string(), version.clone()))
+            .ok_or_else(|| ComponentError::NotRegistered {
+                component_type: component_type.to_string(),
+                version,
+            })?;
+        factory(metadata).map_err(|source| ComponentError::Init {
+            component_type: component_type.to_string(),
+            source,
+        })
+    }
+}
+
+/// `v0` predates versioning and is treated like the default version.
+fn normalize_version(version: &str) -> String {
+    match version {
+        "" | "v0" => DEFAULT_VERSION.to_string(),
+        version => version.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    trait Greeter: Send + Sync {
+        fn greet(&self) -> String;
+    }
+
+    struct Hello(String);
+
+    impl Greeter for Hello {
+        fn greet(&self) -> String {
+            format!("hello {}", self.0)
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry: Registry<dyn Greeter> = Registry::new();
+        registry.register("greeter.hello", "", |metadata| {
+            let name = metadata.get("name").ok_or("name is required")?;
+            Ok(Arc::new(Hello(name.clone())))
+        });
+        assert!(registry.contains("greeter.hello", "v1"));
+        assert!(!registry.contains("greeter.hello", "v2"));
+
+        let metadata = Metadata::from([("name".to_string(), "rapr".to_string())]);
+        let greeter = registry.create("greeter.hello", "v0", &metadata).unwrap();
+        assert_eq!(greeter.greet(), "hello rapr");
+
+        assert!(matches!(
+            registry.create("greeter.hello", "v1", &Metadata::new()),
+            Err(ComponentError::Init { .. })
+        ));
+        assert!(matches!(
+            registry.create("greeter.bye", "v1", &metadata),
+            Err(ComponentError::NotRegistered { .. })
+        ));
+    }
+}
@@ -0,0 +1,68 @@
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+
+/// Jwk is a JSON Web Key as defined by RFC 7517. Binary members are base64url encoded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Jwk {
+    /// Key type: `oct`, `RSA`, `EC` or `OKP`.
+    pub kty: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alg: Option<String>,
+    #[serde(rename = "use", default, skip_serializing_if = "Option::is_none")]
+    pub key_use: Option<String>,
+    /// Curve of `EC` and `OKP` keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    /// Modulus of `RSA` keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// Public exponent of `RSA` keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// Private member of asymmetric keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub d: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+    /// Value of `oct` keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub k: Option<String>,
+}
+
+/// Encodes bytes as unpadded base64url.
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Decodes base64url, tolerating the padding some encoders add.
+pub(crate) fn decode(value: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialization() {
+        let jwk = Jwk {
+            kty: "oct".to_string(),
+            key_use: Some("enc".to_string()),
+            k: Some(encode(&[0xfb; 4])),
+            ..Default::default()
+        };
+        let encoded = serde_json::to_string(&jwk).unwrap();
+        assert_eq!(encoded, r#"{"kty":"oct","use":"enc","k":"-_v7-w"}"#);
+        assert_eq!(serde_json::from_str::<Jwk>(&encoded).unwrap(), jwk);
+        assert_eq!(decode("-_v7-w=="), Some(vec![0xfb; 4]));
+    }
+}
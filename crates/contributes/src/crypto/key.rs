@@ -0,0 +1,437 @@
+use super::jwk::{self, Jwk};
+use super::{
+    A128KW, A192KW, A256KW, CryptoError, EDDSA, ES256, PS256, RS256, RSA_OAEP_256, Result,
+};
+use aes_kw::{KekAes128, KekAes192, KekAes256};
+use ed25519_dalek::{Signer, Verifier};
+use p256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use rsa::pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use rsa::rand_core::OsRng;
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, Oaep, Pkcs1v15Sign, Pss, RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+
+/// Key is a key material loaded by a key store.
+#[derive(Clone)]
+pub enum Key {
+    /// AES key of 16, 24 or 32 bytes.
+    Symmetric(Vec<u8>),
+    RsaPrivate(RsaPrivateKey),
+    RsaPublic(RsaPublicKey),
+    /// ECDSA key on the P-256 curve.
+    EcPrivate(p256::SecretKey),
+    EcPublic(p256::PublicKey),
+    Ed25519Private(ed25519_dalek::SigningKey),
+    Ed25519Public(ed25519_dalek::VerifyingKey),
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print private key material.
+        write!(f, "Key({})", self.key_type())
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> CryptoError {
+    CryptoError::InvalidKey {
+        reason: reason.into(),
+    }
+}
+
+fn failed(reason: impl ToString) -> CryptoError {
+    CryptoError::OperationFailed {
+        reason: reason.to_string(),
+    }
+}
+
+fn member(jwk: &Jwk, value: &Option<String>, name: &str) -> Result<Vec<u8>> {
+    let value = value
+        .as_deref()
+        .ok_or_else(|| invalid(format!("JWK of type {} has no \"{name}\"", jwk.kty)))?;
+    jwk::decode(value).ok_or_else(|| invalid(format!("JWK member \"{name}\" is not base64url")))
+}
+
+impl Key {
+    /// Parses a key from a JWK, a PEM document or raw symmetric key bytes.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(bytes).map(str::trim);
+        match text {
+            Ok(text) if text.starts_with('{') => {
+                let jwk: Jwk =
+                    serde_json::from_str(text).map_err(|e| invalid(format!("Invalid JWK: {e}")))?;
+                Self::from_jwk(&jwk)
+            }
+            Ok(text) if text.starts_with("-----BEGIN") => Self::from_pem(text),
+            _ => match bytes.len() {
+                16 | 24 | 32 => Ok(Key::Symmetric(bytes.to_vec())),
+                len => Err(invalid(format!(
+                    "{len} bytes is neither a JWK, a PEM document nor an AES key"
+                ))),
+            },
+        }
+    }
+
+    /// Parses a PKCS#8, PKCS#1, SEC1 or SPKI PEM document.
+    pub fn from_pem(pem: &str) -> Result<Self> {
+        let label = pem
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("-----BEGIN "))
+            .and_then(|line| line.strip_suffix("-----"))
+            .unwrap_or_default();
+        let key = match label {
+            "PRIVATE KEY" => RsaPrivateKey::from_pkcs8_pem(pem)
+                .map(Key::RsaPrivate)
+                .or_else(|_| p256::SecretKey::from_pkcs8_pem(pem).map(Key::EcPrivate))
+                .or_else(|_| {
+                    ed25519_dalek::SigningKey::from_pkcs8_pem(pem).map(Key::Ed25519Private)
+                })
+                .ok(),
+            "PUBLIC KEY" => RsaPublicKey::from_public_key_pem(pem)
+                .map(Key::RsaPublic)
+                .or_else(|_| p256::PublicKey::from_public_key_pem(pem).map(Key::EcPublic))
+                .or_else(|_| {
+                    ed25519_dalek::VerifyingKey::from_public_key_pem(pem).map(Key::Ed25519Public)
+                })
+                .ok(),
+            "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_pem(pem).map(Key::RsaPrivate).ok(),
+            "RSA PUBLIC KEY" => RsaPublicKey::from_pkcs1_pem(pem).map(Key::RsaPublic).ok(),
+            "EC PRIVATE KEY" => p256::SecretKey::from_sec1_pem(pem).map(Key::EcPrivate).ok(),
+            _ => return Err(invalid(format!("Unsupported PEM label \"{label}\""))),
+        };
+        key.ok_or_else(|| invalid(format!("Invalid or unsupported {label}")))
+    }
+
+    /// Parses a JWK. Asymmetric keys are private when the JWK has a `d` member.
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self> {
+        match jwk.kty.as_str() {
+            "oct" => match member(jwk, &jwk.k, "k")? {
+                k if matches!(k.len(), 16 | 24 | 32) => Ok(Key::Symmetric(k)),
+                k => Err(invalid(format!("AES keys cannot be {} bytes", k.len()))),
+            },
+            "RSA" => {
+                let n = BigUint::from_bytes_be(&member(jwk, &jwk.n, "n")?);
+                let e = BigUint::from_bytes_be(&member(jwk, &jwk.e, "e")?);
+                if jwk.d.is_none() {
+                    return RsaPublicKey::new(n, e)
+                        .map(Key::RsaPublic)
+                        .map_err(|e| invalid(e.to_string()));
+                }
+                let d = BigUint::from_bytes_be(&member(jwk, &jwk.d, "d")?);
+                let primes = match (&jwk.p, &jwk.q) {
+                    (Some(_), Some(_)) => vec![
+                        BigUint::from_bytes_be(&member(jwk, &jwk.p, "p")?),
+                        BigUint::from_bytes_be(&member(jwk, &jwk.q, "q")?),
+                    ],
+                    _ => Vec::new(),
+                };
+                RsaPrivateKey::from_components(n, e, d, primes)
+                    .map(Key::RsaPrivate)
+                    .map_err(|e| invalid(e.to_string()))
+            }
+            "EC" => {
+                if jwk.crv.as_deref() != Some("P-256") {
+                    return Err(invalid(format!("Unsupported curve {:?}", jwk.crv)));
+                }
+                if jwk.d.is_some() {
+                    return p256::SecretKey::from_slice(&member(jwk, &jwk.d, "d")?)
+                        .map(Key::EcPrivate)
+                        .map_err(|e| invalid(e.to_string()));
+                }
+                let x = member(jwk, &jwk.x, "x")?;
+                let y = member(jwk, &jwk.y, "y")?;
+                if x.len() != 32 || y.len() != 32 {
+                    return Err(invalid("P-256 coordinates must be 32 bytes"));
+                }
+                let point = p256::EncodedPoint::from_affine_coordinates(
+                    x.as_slice().into(),
+                    y.as_slice().into(),
+                    false,
+                );
+                p256::PublicKey::from_sec1_bytes(point.as_bytes())
+                    .map(Key::EcPublic)
+                    .map_err(|_| invalid("Point is not on the P-256 curve"))
+            }
+            "OKP" => {
+                if jwk.crv.as_deref() != Some("Ed25519") {
+                    return Err(invalid(format!("Unsupported curve {:?}", jwk.crv)));
+                }
+                if jwk.d.is_some() {
+                    let d: [u8; 32] = member(jwk, &jwk.d, "d")?
+                        .try_into()
+                        .map_err(|_| invalid("Ed25519 private keys must be 32 bytes"))?;
+                    return Ok(Key::Ed25519Private(ed25519_dalek::SigningKey::from_bytes(
+                        &d,
+                    )));
+                }
+                let x: [u8; 32] = member(jwk, &jwk.x, "x")?
+                    .try_into()
+                    .map_err(|_| invalid("Ed25519 public keys must be 32 bytes"))?;
+                ed25519_dalek::VerifyingKey::from_bytes(&x)
+                    .map(Key::Ed25519Public)
+                    .map_err(|e| invalid(e.to_string()))
+            }
+            kty => Err(invalid(format!("Unsupported key type \"{kty}\""))),
+        }
+    }
+
+    /// Returns the JWK key type.
+    pub fn key_type(&self) -> &'static str {
+        match self {
+            Key::Symmetric(_) => "oct",
+            Key::RsaPrivate(_) | Key::RsaPublic(_) => "RSA",
+            Key::EcPrivate(_) | Key::EcPublic(_) => "EC",
+            Key::Ed25519Private(_) | Key::Ed25519Public(_) => "OKP",
+        }
+    }
+
+    /// Returns the public part of the key as a JWK.
+    /// Symmetric keys have no public part and are never exported.
+    pub fn to_public_jwk(&self) -> Result<Jwk> {
+        let mut jwk = Jwk {
+            kty: self.key_type().to_string(),
+            ..Default::default()
+        };
+        match self {
+            Key::Symmetric(_) => return Err(failed("Symmetric keys cannot be exported")),
+            Key::RsaPrivate(key) => {
+                jwk.n = Some(jwk::encode(&key.n().to_bytes_be()));
+                jwk.e = Some(jwk::encode(&key.e().to_bytes_be()));
+            }
+            Key::RsaPublic(key) => {
+                jwk.n = Some(jwk::encode(&key.n().to_bytes_be()));
+                jwk.e = Some(jwk::encode(&key.e().to_bytes_be()));
+            }
+            Key::EcPrivate(key) => set_ec_point(&mut jwk, &key.public_key()),
+            Key::EcPublic(key) => set_ec_point(&mut jwk, key),
+            Key::Ed25519Private(key) => {
+                jwk.crv = Some("Ed25519".to_string());
+                jwk.x = Some(jwk::encode(key.verifying_key().as_bytes()));
+            }
+            Key::Ed25519Public(key) => {
+                jwk.crv = Some("Ed25519".to_string());
+                jwk.x = Some(jwk::encode(key.as_bytes()));
+            }
+        }
+        Ok(jwk)
+    }
+
+    fn unsupported(&self, algorithm: &str) -> CryptoError {
+        CryptoError::UnsupportedAlgorithm {
+            algorithm: algorithm.to_string(),
+            key_type: self.key_type().to_string(),
+        }
+    }
+
+    /// Wraps a key with AES key wrap or RSA-OAEP.
+    pub fn wrap(&self, algorithm: &str, plaintext_key: &[u8]) -> Result<Vec<u8>> {
+        match (self, algorithm) {
+            (Key::Symmetric(kek), A128KW | A192KW | A256KW) => {
+                check_kek_size(kek, algorithm)?;
+                match kek.len() {
+                    16 => KekAes128::try_from(kek.as_slice()).map(|k| k.wrap_vec(plaintext_key)),
+                    24 => KekAes192::try_from(kek.as_slice()).map(|k| k.wrap_vec(plaintext_key)),
+                    _ => KekAes256::try_from(kek.as_slice()).map(|k| k.wrap_vec(plaintext_key)),
+                }
+                .map_err(failed)?
+                .map_err(failed)
+            }
+            (Key::RsaPrivate(key), RSA_OAEP_256) => rsa_wrap(&key.to_public_key(), plaintext_key),
+            (Key::RsaPublic(key), RSA_OAEP_256) => rsa_wrap(key, plaintext_key),
+            _ => Err(self.unsupported(algorithm)),
+        }
+    }
+
+    /// Unwraps a key wrapped by [`Key::wrap`]. RSA-OAEP requires the private key.
+    pub fn unwrap(&self, algorithm: &str, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        match (self, algorithm) {
+            (Key::Symmetric(kek), A128KW | A192KW | A256KW) => {
+                check_kek_size(kek, algorithm)?;
+                match kek.len() {
+                    16 => KekAes128::try_from(kek.as_slice()).map(|k| k.unwrap_vec(wrapped_key)),
+                    24 => KekAes192::try_from(kek.as_slice()).map(|k| k.unwrap_vec(wrapped_key)),
+                    _ => KekAes256::try_from(kek.as_slice()).map(|k| k.unwrap_vec(wrapped_key)),
+                }
+                .map_err(failed)?
+                .map_err(failed)
+            }
+            (Key::RsaPrivate(key), RSA_OAEP_256) => key
+                .decrypt(Oaep::new::<Sha256>(), wrapped_key)
+                .map_err(failed),
+            _ => Err(self.unsupported(algorithm)),
+        }
+    }
+
+    /// Signs a SHA-256 digest. EdDSA signs the given bytes as they are.
+    pub fn sign(&self, algorithm: &str, digest: &[u8]) -> Result<Vec<u8>> {
+        match (self, algorithm) {
+            (Key::RsaPrivate(key), RS256) => key
+                .sign(Pkcs1v15Sign::new::<Sha256>(), digest)
+                .map_err(failed),
+            (Key::RsaPrivate(key), PS256) => key
+                .sign_with_rng(&mut OsRng, Pss::new::<Sha256>(), digest)
+                .map_err(failed),
+            (Key::EcPrivate(key), ES256) => {
+                let signature: p256::ecdsa::Signature = p256::ecdsa::SigningKey::from(key)
+                    .sign_prehash(digest)
+                    .map_err(failed)?;
+                Ok(signature.to_bytes().to_vec())
+            }
+            (Key::Ed25519Private(key), EDDSA) => Ok(key.sign(digest).to_bytes().to_vec()),
+            _ => Err(self.unsupported(algorithm)),
+        }
+    }
+
+    /// Verifies a signature produced by [`Key::sign`].
+    pub fn verify(&self, algorithm: &str, digest: &[u8], signature: &[u8]) -> Result<bool> {
+        let valid = match (self, algorithm) {
+            (Key::RsaPrivate(key), RS256 | PS256) => {
+                return Key::RsaPublic(key.to_public_key()).verify(algorithm, digest, signature);
+            }
+            (Key::RsaPublic(key), RS256) => key
+                .verify(Pkcs1v15Sign::new::<Sha256>(), digest, signature)
+                .is_ok(),
+            (Key::RsaPublic(key), PS256) => {
+                key.verify(Pss::new::<Sha256>(), digest, signature).is_ok()
+            }
+            (Key::EcPrivate(key), ES256) => {
+                return Key::EcPublic(key.public_key()).verify(algorithm, digest, signature);
+            }
+            (Key::EcPublic(key), ES256) => {
+                p256::ecdsa::Signature::from_slice(signature).is_ok_and(|signature| {
+                    p256::ecdsa::VerifyingKey::from(key)
+                        .verify_prehash(digest, &signature)
+                        .is_ok()
+                })
+            }
+            (Key::Ed25519Private(key), EDDSA) => {
+                return Key::Ed25519Public(key.verifying_key())
+                    .verify(algorithm, digest, signature);
+            }
+            (Key::Ed25519Public(key), EDDSA) => ed25519_dalek::Signature::from_slice(signature)
+                .is_ok_and(|signature| key.verify(digest, &signature).is_ok()),
+            _ => return Err(self.unsupported(algorithm)),
+        };
+        Ok(valid)
+    }
+}
+
+fn set_ec_point(jwk: &mut Jwk, key: &p256::PublicKey) {
+    use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+    let point = key.to_encoded_point(false);
+    jwk.crv = Some("P-256".to_string());
+    jwk.x = point.x().map(|x| jwk::encode(x));
+    jwk.y = point.y().map(|y| jwk::encode(y));
+}
+
+/// AES key wrap algorithms name the size of the key encryption key.
+fn check_kek_size(kek: &[u8], algorithm: &str) -> Result<()> {
+    let expected = match algorithm {
+        A128KW => 16,
+        A192KW => 24,
+        _ => 32,
+    };
+    if kek.len() != expected {
+        return Err(CryptoError::UnsupportedAlgorithm {
+            algorithm: algorithm.to_string(),
+            key_type: format!("oct/{}", kek.len() * 8),
+        });
+    }
+    Ok(())
+}
+
+fn rsa_wrap(key: &RsaPublicKey, plaintext_key: &[u8]) -> Result<Vec<u8>> {
+    key.encrypt(&mut OsRng, Oaep::new::<Sha256>(), plaintext_key)
+        .map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::Digest;
+
+    const RSA_PEM: &str = include_str!("testdata/rsa.pem");
+    const EC_PEM: &str = include_str!("testdata/ec.pem");
+    const ED25519_PEM: &str = include_str!("testdata/ed25519.pem");
+
+    #[test]
+    fn test_parse() {
+        assert!(matches!(
+            Key::parse(RSA_PEM.as_bytes()),
+            Ok(Key::RsaPrivate(_))
+        ));
+        assert!(matches!(
+            Key::parse(EC_PEM.as_bytes()),
+            Ok(Key::EcPrivate(_))
+        ));
+        assert!(matches!(
+            Key::parse(ED25519_PEM.as_bytes()),
+            Ok(Key::Ed25519Private(_))
+        ));
+        assert!(matches!(Key::parse(&[7; 32]), Ok(Key::Symmetric(_))));
+        assert!(matches!(
+            Key::parse(&[7; 20]),
+            Err(CryptoError::InvalidKey { .. })
+        ));
+
+        // Public JWKs round trip.
+        for pem in [RSA_PEM, EC_PEM, ED25519_PEM] {
+            let jwk = Key::parse(pem.as_bytes()).unwrap().to_public_jwk().unwrap();
+            let public = Key::from_jwk(&jwk).unwrap();
+            assert_eq!(public.to_public_jwk().unwrap(), jwk);
+        }
+    }
+
+    #[test]
+    fn test_wrap_unwrap() {
+        let cek = [42u8; 32];
+        for kek in [vec![1u8; 16], vec![1u8; 24], vec![1u8; 32]] {
+            let algorithm = match kek.len() {
+                16 => A128KW,
+                24 => A192KW,
+                _ => A256KW,
+            };
+            let key = Key::Symmetric(kek);
+            let wrapped = key.wrap(algorithm, &cek).unwrap();
+            assert_eq!(key.unwrap(algorithm, &wrapped).unwrap(), cek);
+            assert!(key.unwrap(algorithm, &wrapped[1..]).is_err());
+        }
+        assert!(matches!(
+            Key::Symmetric(vec![1; 16]).wrap(A256KW, &cek),
+            Err(CryptoError::UnsupportedAlgorithm { .. })
+        ));
+
+        let private = Key::parse(RSA_PEM.as_bytes()).unwrap();
+        let public = Key::from_jwk(&private.to_public_jwk().unwrap()).unwrap();
+        let wrapped = public.wrap(RSA_OAEP_256, &cek).unwrap();
+        assert_eq!(private.unwrap(RSA_OAEP_256, &wrapped).unwrap(), cek);
+        assert!(matches!(
+            public.unwrap(RSA_OAEP_256, &wrapped),
+            Err(CryptoError::UnsupportedAlgorithm { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let digest = Sha256::digest(b"hello rapr");
+        let cases = [
+            (RSA_PEM, RS256),
+            (RSA_PEM, PS256),
+            (EC_PEM, ES256),
+            (ED25519_PEM, EDDSA),
+        ];
+        for (pem, algorithm) in cases {
+            let private = Key::parse(pem.as_bytes()).unwrap();
+            let public = Key::from_jwk(&private.to_public_jwk().unwrap()).unwrap();
+            let mut signature = private.sign(algorithm, &digest).unwrap();
+            assert!(public.verify(algorithm, &digest, &signature).unwrap());
+            assert!(private.verify(algorithm, &digest, &signature).unwrap());
+
+            signature[0] ^= 1;
+            assert!(!public.verify(algorithm, &digest, &signature).unwrap());
+            assert!(public.sign(algorithm, &digest).is_err());
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use super::{ALGORITHMS, CryptoError, Jwk, Key, KeyStore, Result};
+use crate::component::{Closer, Metadata, PingError, Pinger};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// LocalStorageKeyStore reads keys from the files of a local directory.
+///
+/// The name of a key is the name of its file, which holds a JWK, a PEM document or the raw
+/// bytes of an AES key. Files are read on every operation so keys can be rotated in place.
+#[derive(Debug, Clone)]
+pub struct LocalStorageKeyStore {
+    path: PathBuf,
+}
+
+impl LocalStorageKeyStore {
+    /// Component type of the key store.
+    pub const COMPONENT_TYPE: &str = "crypto.rapr.localstorage";
+
+    /// Creates a key store reading keys from a directory.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Creates a key store from the `path` metadata of its component.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let path = metadata
+            .get("path")
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| CryptoError::MissingMetadata {
+                name: "path".to_string(),
+            })?;
+        Ok(Self::new(path))
+    }
+
+    /// Returns the directory keys are read from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn load(&self, name: &str) -> Result<Key> {
+        // Key names must not escape the directory.
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(CryptoError::InvalidKeyName {
+                name: name.to_string(),
+            });
+        }
+        let bytes = tokio::fs::read(self.path.join(name))
+            .await
+            .map_err(|source| match source.kind() {
+                io::ErrorKind::NotFound => CryptoError::KeyNotFound {
+                    name: name.to_string(),
+                },
+                _ => CryptoError::ReadKey {
+                    name: name.to_string(),
+                    source,
+                },
+            })?;
+        Key::parse(&bytes)
+    }
+}
+
+/// The key store is healthy as long as its directory exists.
+#[async_trait::async_trait]
+impl Pinger for LocalStorageKeyStore {
+    async fn ping(&self) -> std::result::Result<(), PingError> {
+        match tokio::fs::metadata(&self.path).await?.is_dir() {
+            true => Ok(()),
+            false => Err(format!("{} is not a directory", self.path.display()).into()),
+        }
+    }
+}
+
+/// Keys are read on every operation: nothing is left open.
+impl Closer for LocalStorageKeyStore {}
+
+#[async_trait::async_trait]
+impl KeyStore for LocalStorageKeyStore {
+    fn algorithms(&self) -> Vec<String> {
+        ALGORITHMS.iter().map(|a| a.to_string()).collect()
+    }
+
+    async fn get_key(&self, name: &str) -> Result<Jwk> {
+        let mut jwk = self.load(name).await?.to_public_jwk()?;
+        jwk.kid = Some(name.to_string());
+        Ok(jwk)
+    }
+
+    async fn wrap_key(&self, name: &str, algorithm: &str, plaintext_key: &[u8]) -> Result<Vec<u8>> {
+        self.load(name).await?.wrap(algorithm, plaintext_key)
+    }
+
+    async fn unwrap_key(&self, name: &str, algorithm: &str, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        self.load(name).await?.unwrap(algorithm, wrapped_key)
+    }
+
+    async fn sign(&self, name: &str, algorithm: &str, digest: &[u8]) -> Result<Vec<u8>> {
+        self.load(name).await?.sign(algorithm, digest)
+    }
+
+    async fn verify(
+        &self,
+        name: &str,
+        algorithm: &str,
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<bool> {
+        self.load(name).await?.verify(algorithm, digest, signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{A256KW, ES256, registry};
+    use std::sync::Arc;
+
+    fn store() -> (tempfile::TempDir, Arc<dyn KeyStore>) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("aes"), [9u8; 32]).unwrap();
+        std::fs::write(dir.path().join("ec.pem"), include_str!("testdata/ec.pem")).unwrap();
+        let metadata = Metadata::from([(
+            "path".to_string(),
+            dir.path().to_string_lossy().into_owned(),
+        )]);
+        let store = registry()
+            .create(LocalStorageKeyStore::COMPONENT_TYPE, "v1", &metadata)
+            .unwrap();
+        (dir, store)
+    }
+
+    #[tokio::test]
+    async fn test_operations() {
+        let (_dir, store) = store();
+
+        let wrapped = store.wrap_key("aes", A256KW, &[1; 16]).await.unwrap();
+        assert_eq!(
+            store.unwrap_key("aes", A256KW, &wrapped).await.unwrap(),
+            [1; 16]
+        );
+
+        let digest = [3u8; 32];
+        let signature = store.sign("ec.pem", ES256, &digest).await.unwrap();
+        assert!(
+            store
+                .verify("ec.pem", ES256, &digest, &signature)
+                .await
+                .unwrap()
+        );
+
+        let jwk = store.get_key("ec.pem").await.unwrap();
+        assert_eq!(jwk.kty, "EC");
+        assert_eq!(jwk.kid.as_deref(), Some("ec.pem"));
+        assert!(jwk.d.is_none());
+        assert!(store.get_key("aes").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_names() {
+        let (_dir, store) = store();
+        assert!(matches!(
+            store.get_key("missing").await,
+            Err(CryptoError::KeyNotFound { .. })
+        ));
+        for name in ["", "..", "../aes", "a/b"] {
+            assert!(matches!(
+                store.get_key(name).await,
+                Err(CryptoError::InvalidKeyName { .. })
+            ));
+        }
+        assert!(matches!(
+            LocalStorageKeyStore::from_metadata(&Metadata::new()),
+            Err(CryptoError::MissingMetadata { .. })
+        ));
+    }
+}
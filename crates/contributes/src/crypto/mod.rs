@@ -0,0 +1,112 @@
+//! Cryptography building block.
+//!
+//! Key stores hold the keys used to wrap, unwrap, sign and verify. Key material never
+//! leaves a key store: only public keys can be read from it.
+
+mod jwk;
+mod key;
+mod localstorage;
+
+pub use jwk::*;
+pub use key::*;
+pub use localstorage::*;
+
+use crate::component::{Closer, Pinger, Registry};
+use snafu::Snafu;
+use std::io;
+use std::sync::Arc;
+
+/// AES key wrap with a 128 bits key (RFC 3394).
+pub const A128KW: &str = "A128KW";
+/// AES key wrap with a 192 bits key (RFC 3394).
+pub const A192KW: &str = "A192KW";
+/// AES key wrap with a 256 bits key (RFC 3394).
+pub const A256KW: &str = "A256KW";
+/// RSA-OAEP with SHA-256.
+pub const RSA_OAEP_256: &str = "RSA-OAEP-256";
+/// RSASSA-PKCS1-v1_5 with SHA-256.
+pub const RS256: &str = "RS256";
+/// RSASSA-PSS with SHA-256.
+pub const PS256: &str = "PS256";
+/// ECDSA on the P-256 curve with SHA-256.
+pub const ES256: &str = "ES256";
+/// EdDSA on the Ed25519 curve.
+pub const EDDSA: &str = "EdDSA";
+
+/// Every algorithm defined above.
+pub const ALGORITHMS: &[&str] = &[
+    A128KW,
+    A192KW,
+    A256KW,
+    RSA_OAEP_256,
+    RS256,
+    PS256,
+    ES256,
+    EDDSA,
+];
+
+#[derive(Debug, Snafu)]
+pub enum CryptoError {
+    #[snafu(display("Key {} not found", name))]
+    KeyNotFound { name: String },
+
+    #[snafu(display("Invalid key name {:?}", name))]
+    InvalidKeyName { name: String },
+
+    #[snafu(display("Failed to read key {}: {}", name, source))]
+    ReadKey { name: String, source: io::Error },
+
+    #[snafu(display("Invalid key: {}", reason))]
+    InvalidKey { reason: String },
+
+    #[snafu(display("Algorithm {} is not supported by {} keys", algorithm, key_type))]
+    UnsupportedAlgorithm { algorithm: String, key_type: String },
+
+    #[snafu(display("Cryptographic operation failed: {}", reason))]
+    OperationFailed { reason: String },
+
+    #[snafu(display("Missing metadata {}", name))]
+    MissingMetadata { name: String },
+}
+
+pub type Result<T> = std::result::Result<T, CryptoError>;
+
+/// KeyStore is the component interface of the cryptography building block.
+#[async_trait::async_trait]
+pub trait KeyStore: Pinger + Closer {
+    /// Returns the algorithms the key store supports, advertised as its capabilities.
+    /// Key stores which cannot tell keep the default, which advertises none.
+    fn algorithms(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Returns the public part of a key.
+    async fn get_key(&self, name: &str) -> Result<Jwk>;
+
+    /// Wraps (encrypts) a key with the named key.
+    async fn wrap_key(&self, name: &str, algorithm: &str, plaintext_key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Unwraps (decrypts) a key wrapped with the named key.
+    async fn unwrap_key(&self, name: &str, algorithm: &str, wrapped_key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Signs a digest with the named key.
+    async fn sign(&self, name: &str, algorithm: &str, digest: &[u8]) -> Result<Vec<u8>>;
+
+    /// Verifies the signature of a digest with the named key.
+    async fn verify(
+        &self,
+        name: &str,
+        algorithm: &str,
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<bool>;
+}
+
+/// Returns a registry of the key stores contributed by this crate.
+pub fn registry() -> Registry<dyn KeyStore> {
+    let mut registry: Registry<dyn KeyStore> = Registry::new();
+    registry.register(LocalStorageKeyStore::COMPONENT_TYPE, "v1", |metadata| {
+        Ok(Arc::new(LocalStorageKeyStore::from_metadata(metadata)?))
+    });
+    registry
+}
@@ -6,3 +6,7 @@
 )]
 #![allow(missing_docs)]
 #![allow(dead_code)]
+
+pub mod component;
+pub mod crypto;
+pub mod nameresolution;
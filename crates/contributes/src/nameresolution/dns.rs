@@ -0,0 +1,557 @@
+use super::{NameResolutionError, NameResolver, ResolveRequest, Result};
+use crate::component::Metadata;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u16 = 3;
+/// Largest DNS message over UDP without EDNS.
+const MAX_UDP_MESSAGE: usize = 512;
+
+/// DnsResolver resolves apps with DNS SRV records, such as those Kubernetes publishes for
+/// the named ports of headless services.
+///
+/// Metadata:
+/// - `server`: `host:port` of the DNS server. Defaults to the first nameserver of
+///   `/etc/resolv.conf`.
+/// - `template`: SRV name of an app, where `{id}` and `{namespace}` are replaced. Defaults to
+///   [`DnsResolver::DEFAULT_TEMPLATE`].
+/// - `timeoutMs`: timeout of a query in milliseconds. Defaults to 2000.
+///
+/// Records are cached for their TTL. Among the records of the lowest priority, one is
+/// picked at random in proportion to its weight (RFC 2782).
+#[derive(Debug)]
+pub struct DnsResolver {
+    server: SocketAddr,
+    template: String,
+    timeout: Duration,
+    cache: Mutex<HashMap<String, (Instant, Vec<SrvRecord>)>>,
+}
+
+/// SrvRecord is a resolved SRV record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    /// Host name of the target.
+    pub target: String,
+    /// Address of the target, when the server sent it along.
+    pub address: Option<IpAddr>,
+}
+
+impl SrvRecord {
+    /// Returns the `host:port` address of the target.
+    pub fn address(&self) -> String {
+        match self.address {
+            Some(ip) => SocketAddr::new(ip, self.port).to_string(),
+            None => format!("{}:{}", self.target, self.port),
+        }
+    }
+}
+
+impl DnsResolver {
+    /// Component type of the resolver.
+    pub const COMPONENT_TYPE: &str = "nameresolution.dns";
+
+    /// SRV name of apps when the metadata does not set a template.
+    pub const DEFAULT_TEMPLATE: &str =
+        "_rapr-internal._tcp.{id}-rapr.{namespace}.svc.cluster.local";
+
+    /// Creates a resolver querying a DNS server.
+    pub fn new(server: SocketAddr) -> Self {
+        Self {
+            server,
+            template: Self::DEFAULT_TEMPLATE.to_string(),
+            timeout: Duration::from_secs(2),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the SRV name template of apps.
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// Sets the timeout of a query.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Creates a resolver from the metadata of its component.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let invalid = |name: &str, reason: String| NameResolutionError::InvalidMetadata {
+            name: name.to_string(),
+            reason,
+        };
+        let server = match metadata.get("server") {
+            Some(server) => parse_server(server).map_err(|reason| invalid("server", reason))?,
+            None => system_nameserver().ok_or_else(|| NameResolutionError::MissingMetadata {
+                name: "server".to_string(),
+            })?,
+        };
+        let mut resolver = Self::new(server);
+        if let Some(template) = metadata.get("template") {
+            resolver = resolver.with_template(template.clone());
+        }
+        if let Some(timeout) = metadata.get("timeoutMs") {
+            let timeout = timeout
+                .parse()
+                .map_err(|e| invalid("timeoutMs", format!("{e}")))?;
+            resolver = resolver.with_timeout(Duration::from_millis(timeout));
+        }
+        Ok(resolver)
+    }
+
+    /// Returns the SRV records of a name, from the cache while their TTL lasts.
+    pub async fn lookup_srv(&self, name: &str) -> Result<Vec<SrvRecord>> {
+        if let Some((expires, records)) = self.cache().get(name)
+            && *expires > Instant::now()
+        {
+            return Ok(records.clone());
+        }
+        let (ttl, records) = self.query(name).await?;
+        self.cache()
+            .insert(name.to_string(), (Instant::now() + ttl, records.clone()));
+        Ok(records)
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, HashMap<String, (Instant, Vec<SrvRecord>)>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    async fn query(&self, name: &str) -> Result<(Duration, Vec<SrvRecord>)> {
+        let lookup = |reason: String| NameResolutionError::Lookup {
+            id: name.to_string(),
+            reason,
+        };
+        let id: u16 = rand::random();
+        let query = encode_query(id, name).map_err(lookup)?;
+
+        let bind: SocketAddr = match self.server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .map_err(|e| lookup(e.to_string()))?;
+        socket
+            .connect(self.server)
+            .await
+            .map_err(|e| lookup(e.to_string()))?;
+        socket
+            .send(&query)
+            .await
+            .map_err(|e| lookup(e.to_string()))?;
+
+        let mut buf = [0u8; MAX_UDP_MESSAGE];
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            let len = tokio::time::timeout_at(deadline, socket.recv(&mut buf))
+                .await
+                .map_err(|_| lookup(format!("no answer from {} in time", self.server)))?
+                .map_err(|e| lookup(e.to_string()))?;
+            // Answers to other queries are ignored.
+            match decode_response(id, &buf[..len]) {
+                Ok(Some(Answer::NxDomain)) => {
+                    return Err(NameResolutionError::NotFound {
+                        id: name.to_string(),
+                    });
+                }
+                Ok(Some(Answer::Records { ttl, records })) => return Ok((ttl, records)),
+                Ok(None) => continue,
+                Err(reason) => return Err(lookup(reason)),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl NameResolver for DnsResolver {
+    async fn resolve(&self, request: &ResolveRequest) -> Result<String> {
+        let name = self
+            .template
+            .replace("{id}", &request.id)
+            .replace("{namespace}", &request.namespace);
+        let records = self.lookup_srv(&name).await?;
+        pick(&records)
+            .map(SrvRecord::address)
+            .ok_or_else(|| NameResolutionError::NotFound {
+                id: request.id.clone(),
+            })
+    }
+}
+
+/// Picks a record of the lowest priority at random, in proportion to its weight.
+fn pick(records: &[SrvRecord]) -> Option<&SrvRecord> {
+    let priority = records.iter().map(|r| r.priority).min()?;
+    let candidates: Vec<&SrvRecord> = records.iter().filter(|r| r.priority == priority).collect();
+    let total: u32 = candidates.iter().map(|r| u32::from(r.weight)).sum();
+    if total == 0 {
+        return candidates
+            .get(rand::random_range(0..candidates.len()))
+            .copied();
+    }
+    let mut pick = rand::random_range(0..total);
+    for record in &candidates {
+        if pick < u32::from(record.weight) {
+            return Some(record);
+        }
+        pick -= u32::from(record.weight);
+    }
+    candidates.last().copied()
+}
+
+fn parse_server(server: &str) -> std::result::Result<SocketAddr, String> {
+    if let Ok(address) = server.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+    server
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| format!("{server:?} is not an IP address with optional port"))
+}
+
+fn system_nameserver() -> Option<SocketAddr> {
+    let conf = std::fs::read_to_string("/etc/resolv.conf").ok()?;
+    conf.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .find_map(|server| parse_server(server.trim()).ok())
+}
+
+/// Encodes a recursive SRV query.
+pub(crate) fn encode_query(id: u16, name: &str) -> std::result::Result<Vec<u8>, String> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid DNS name {name:?}"));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+enum Answer {
+    NxDomain,
+    Records {
+        /// Lowest TTL of the records.
+        ttl: Duration,
+        records: Vec<SrvRecord>,
+    },
+}
+
+/// Reads DNS messages, bounds checked.
+pub(crate) struct Reader<'a> {
+    message: &'a [u8],
+    pub(crate) offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(message: &'a [u8]) -> Self {
+        Self { message, offset: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> std::result::Result<&'a [u8], String> {
+        let bytes = self
+            .message
+            .get(self.offset..self.offset + len)
+            .ok_or("truncated DNS message")?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u16(&mut self) -> std::result::Result<u16, String> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> std::result::Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a possibly compressed name.
+    pub(crate) fn name(&mut self) -> std::result::Result<String, String> {
+        let mut labels = Vec::new();
+        let mut offset = self.offset;
+        let mut end = None;
+        // Every pointer must go backwards, which bounds the number of jumps.
+        let mut limit = offset;
+        loop {
+            let len = *self.message.get(offset).ok_or("truncated DNS name")? as usize;
+            match len {
+                0 => {
+                    self.offset = end.unwrap_or(offset + 1);
+                    return Ok(labels.join("."));
+                }
+                len if len & 0xc0 == 0xc0 => {
+                    let low = *self.message.get(offset + 1).ok_or("truncated DNS name")?;
+                    let target = ((len & 0x3f) << 8) | low as usize;
+                    if target >= limit {
+                        return Err("invalid DNS name pointer".to_string());
+                    }
+                    end.get_or_insert(offset + 2);
+                    limit = target;
+                    offset = target;
+                }
+                len if len <= 63 => {
+                    let label = self
+                        .message
+                        .get(offset + 1..offset + 1 + len)
+                        .ok_or("truncated DNS name")?;
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    offset += 1 + len;
+                }
+                _ => return Err("invalid DNS label".to_string()),
+            }
+        }
+    }
+}
+
+/// Decodes the response to a query, `None` meaning the message answers another query.
+fn decode_response(id: u16, message: &[u8]) -> std::result::Result<Option<Answer>, String> {
+    let mut reader = Reader::new(message);
+    let flags = {
+        if reader.u16()? != id {
+            return Ok(None);
+        }
+        reader.u16()?
+    };
+    if flags & 0x8000 == 0 {
+        return Ok(None);
+    }
+    if flags & 0x0200 != 0 {
+        return Err("truncated DNS response".to_string());
+    }
+    match flags & 0x000f {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Some(Answer::NxDomain)),
+        rcode => return Err(format!("DNS server answered with code {rcode}")),
+    }
+    let questions = reader.u16()?;
+    // Counts are widened before they are added: the server may claim up to 0xffff of each.
+    let answers = usize::from(reader.u16()?);
+    let authorities = usize::from(reader.u16()?);
+    let additionals = usize::from(reader.u16()?);
+    for _ in 0..questions {
+        reader.name()?;
+        reader.bytes(4)?;
+    }
+
+    let mut ttl = u32::MAX;
+    let mut records = Vec::new();
+    let mut addresses = HashMap::new();
+    for index in 0..answers + authorities + additionals {
+        let name = reader.name()?;
+        let record_type = reader.u16()?;
+        let _class = reader.u16()?;
+        let record_ttl = reader.u32()?;
+        let len = reader.u16()? as usize;
+        let end = reader.offset + len;
+        let is_answer = index < answers;
+        match record_type {
+            TYPE_SRV if is_answer => {
+                ttl = ttl.min(record_ttl);
+                records.push(SrvRecord {
+                    priority: reader.u16()?,
+                    weight: reader.u16()?,
+                    port: reader.u16()?,
+                    target: reader.name()?,
+                    address: None,
+                });
+            }
+            TYPE_A if len == 4 => {
+                let ip: [u8; 4] = reader.bytes(4)?.try_into().expect("4 bytes");
+                addresses.entry(name).or_insert(IpAddr::from(ip));
+            }
+            TYPE_AAAA if len == 16 => {
+                let ip: [u8; 16] = reader.bytes(16)?.try_into().expect("16 bytes");
+                addresses.entry(name).or_insert(IpAddr::from(ip));
+            }
+            _ => {}
+        }
+        if reader.offset > end {
+            return Err("invalid DNS record length".to_string());
+        }
+        reader.offset = end;
+    }
+
+    for record in &mut records {
+        record.address = addresses.get(&record.target).copied();
+    }
+    let ttl = if records.is_empty() { 0 } else { ttl };
+    Ok(Some(Answer::Records {
+        ttl: Duration::from_secs(ttl.into()),
+        records,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers SRV queries for `_rapr-internal._tcp.orders-rapr.default.svc.cluster.local`
+    /// and counts the queries it receives. Queries for `billing` are answered with record
+    /// counts far beyond the records of the response.
+    async fn stub_server() -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_UDP_MESSAGE];
+            loop {
+                let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response = stub_response(&buf[..len]);
+                socket.send_to(&response, peer).await.unwrap();
+            }
+        });
+        (address, queries)
+    }
+
+    fn stub_response(query: &[u8]) -> Vec<u8> {
+        let mut reader = Reader::new(query);
+        reader.offset = 12;
+        let name = reader.name().unwrap();
+        let question = &query[12..reader.offset + 4];
+
+        let mut response = query[..2].to_vec();
+        if name == "_rapr-internal._tcp.billing-rapr.default.svc.cluster.local" {
+            response.extend_from_slice(&[0x81, 0x80, 0, 1, 0xff, 0xff, 0, 0, 0, 1]);
+            response.extend_from_slice(question);
+            return response;
+        }
+        if name != "_rapr-internal._tcp.orders-rapr.default.svc.cluster.local" {
+            response.extend_from_slice(&[0x81, 0x83, 0, 1, 0, 0, 0, 0, 0, 0]);
+            response.extend_from_slice(question);
+            return response;
+        }
+        response.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 2, 0, 0, 0, 1]);
+        response.extend_from_slice(question);
+        // A backup record, then the preferred one; both point to the question name.
+        for (priority, ttl, port, target) in
+            [(20u16, 30u32, 7000u16, "backup"), (10, 60, 6000, "pod")]
+        {
+            response.extend_from_slice(&[0xc0, 12]);
+            response.extend_from_slice(&TYPE_SRV.to_be_bytes());
+            response.extend_from_slice(&CLASS_IN.to_be_bytes());
+            response.extend_from_slice(&ttl.to_be_bytes());
+            response.extend_from_slice(&(6 + 1 + target.len() as u16 + 1).to_be_bytes());
+            response.extend_from_slice(&priority.to_be_bytes());
+            response.extend_from_slice(&5u16.to_be_bytes());
+            response.extend_from_slice(&port.to_be_bytes());
+            response.push(target.len() as u8);
+            response.extend_from_slice(target.as_bytes());
+            response.push(0);
+        }
+        // Address of the preferred target.
+        response.extend_from_slice(&[3, b'p', b'o', b'd', 0]);
+        response.extend_from_slice(&TYPE_A.to_be_bytes());
+        response.extend_from_slice(&CLASS_IN.to_be_bytes());
+        response.extend_from_slice(&60u32.to_be_bytes());
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&[10, 0, 0, 7]);
+        response
+    }
+
+    fn request(id: &str) -> ResolveRequest {
+        ResolveRequest {
+            id: id.to_string(),
+            namespace: "default".to_string(),
+            port: 0,
+            data: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_srv() {
+        let (server, queries) = stub_server().await;
+        let metadata = Metadata::from([("server".to_string(), server.to_string())]);
+        let resolver = DnsResolver::from_metadata(&metadata).unwrap();
+
+        let records = resolver
+            .lookup_srv("_rapr-internal._tcp.orders-rapr.default.svc.cluster.local")
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].target, "backup");
+        assert_eq!(records[0].address, None);
+        assert_eq!(records[1].address(), "10.0.0.7:6000");
+
+        // Lowest priority first, from the cache.
+        for _ in 0..3 {
+            assert_eq!(
+                resolver.resolve(&request("orders")).await.unwrap(),
+                "10.0.0.7:6000"
+            );
+        }
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+
+        assert!(matches!(
+            resolver.resolve(&request("payments")).await,
+            Err(NameResolutionError::NotFound { .. })
+        ));
+        assert!(matches!(
+            resolver.resolve(&request("billing")).await,
+            Err(NameResolutionError::Lookup { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // Nothing answers on this socket.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver =
+            DnsResolver::new(silent.local_addr().unwrap()).with_timeout(Duration::from_millis(50));
+        assert!(matches!(
+            resolver.resolve(&request("orders")).await,
+            Err(NameResolutionError::Lookup { .. })
+        ));
+    }
+
+    #[test]
+    fn test_pick_by_weight() {
+        let record = |priority, weight, target: &str| SrvRecord {
+            priority,
+            weight,
+            port: 1,
+            target: target.to_string(),
+            address: None,
+        };
+        let records = [record(1, 0, "a"), record(1, 3, "b"), record(2, 100, "c")];
+        for _ in 0..20 {
+            assert_eq!(pick(&records).unwrap().target, "b");
+        }
+        assert!(pick(&[]).is_none());
+    }
+
+    #[test]
+    fn test_name_pointers() {
+        // A pointer to itself must not loop.
+        let message = [0u8, 0, 0xc0, 2];
+        let mut reader = Reader::new(&message);
+        reader.offset = 2;
+        assert!(reader.name().is_err());
+        assert!(encode_query(1, "a..b").is_err());
+    }
+}
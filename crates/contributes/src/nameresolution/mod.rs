@@ -0,0 +1,64 @@
+//! Name resolution building block.
+//!
+//! A name resolver turns the id of an app into the address of one of its sidecars, which
+//! service invocation then dials.
+
+mod dns;
+mod static_list;
+
+pub use dns::*;
+pub use static_list::*;
+
+use crate::component::Registry;
+use snafu::Snafu;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug, Snafu)]
+pub enum NameResolutionError {
+    #[snafu(display("No address found for app {}", id))]
+    NotFound { id: String },
+
+    #[snafu(display("Missing metadata {}", name))]
+    MissingMetadata { name: String },
+
+    #[snafu(display("Invalid metadata {}: {}", name, reason))]
+    InvalidMetadata { name: String, reason: String },
+
+    #[snafu(display("Failed to resolve app {}: {}", id, reason))]
+    Lookup { id: String, reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, NameResolutionError>;
+
+/// ResolveRequest identifies the app to resolve.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResolveRequest {
+    /// Id of the app.
+    pub id: String,
+    /// Namespace of the app.
+    pub namespace: String,
+    /// Port the sidecars of the app accept service invocation on.
+    pub port: u16,
+    /// Additional data understood by specific resolvers.
+    pub data: HashMap<String, String>,
+}
+
+/// NameResolver is the component interface of the name resolution building block.
+#[async_trait::async_trait]
+pub trait NameResolver: Send + Sync {
+    /// Returns the address, as `host:port`, of a sidecar of the app.
+    async fn resolve(&self, request: &ResolveRequest) -> Result<String>;
+}
+
+/// Returns a registry of the name resolvers contributed by this crate.
+pub fn registry() -> Registry<dyn NameResolver> {
+    let mut registry: Registry<dyn NameResolver> = Registry::new();
+    registry.register(StaticResolver::COMPONENT_TYPE, "v1", |metadata| {
+        Ok(Arc::new(StaticResolver::from_metadata(metadata)?))
+    });
+    registry.register(DnsResolver::COMPONENT_TYPE, "v1", |metadata| {
+        Ok(Arc::new(DnsResolver::from_metadata(metadata)?))
+    });
+    registry
+}
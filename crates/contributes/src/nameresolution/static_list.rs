@@ -0,0 +1,144 @@
+use super::{NameResolutionError, NameResolver, ResolveRequest, Result};
+use crate::component::Metadata;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// StaticResolver resolves apps from a fixed list of addresses, in round-robin.
+///
+/// Every metadata entry maps an app, as `<app-id>` or `<app-id>.<namespace>`, to a comma
+/// separated list of `host:port` addresses. Addresses without port use the port of the
+/// request. Entries with a namespace take precedence.
+#[derive(Debug)]
+pub struct StaticResolver {
+    apps: HashMap<String, Addresses>,
+}
+
+#[derive(Debug)]
+struct Addresses {
+    addresses: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl StaticResolver {
+    /// Component type of the resolver.
+    pub const COMPONENT_TYPE: &str = "nameresolution.static";
+
+    /// Creates a resolver from the metadata of its component.
+    pub fn from_metadata(metadata: &Metadata) -> Result<Self> {
+        let mut apps = HashMap::new();
+        for (app, addresses) in metadata {
+            let addresses: Vec<String> = addresses
+                .split(',')
+                .map(str::trim)
+                .filter(|address| !address.is_empty())
+                .map(str::to_string)
+                .collect();
+            if addresses.is_empty() {
+                return Err(NameResolutionError::InvalidMetadata {
+                    name: app.clone(),
+                    reason: "no address".to_string(),
+                });
+            }
+            apps.insert(
+                app.clone(),
+                Addresses {
+                    addresses,
+                    next: AtomicUsize::new(0),
+                },
+            );
+        }
+        Ok(Self { apps })
+    }
+}
+
+#[async_trait::async_trait]
+impl NameResolver for StaticResolver {
+    async fn resolve(&self, request: &ResolveRequest) -> Result<String> {
+        let app = self
+            .apps
+            .get(&format!("{}.{}", request.id, request.namespace))
+            .or_else(|| self.apps.get(&request.id))
+            .ok_or_else(|| NameResolutionError::NotFound {
+                id: request.id.clone(),
+            })?;
+        let next = app.next.fetch_add(1, Ordering::Relaxed);
+        let address = &app.addresses[next % app.addresses.len()];
+        if has_port(address) {
+            Ok(address.clone())
+        } else {
+            Ok(format!("{address}:{}", request.port))
+        }
+    }
+}
+
+/// Returns true if the address ends with a port, IPv6 addresses being bracketed.
+fn has_port(address: &str) -> bool {
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            (!host.contains(':') || host.ends_with(']')) && port.parse::<u16>().is_ok()
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: &str, namespace: &str) -> ResolveRequest {
+        ResolveRequest {
+            id: id.to_string(),
+            namespace: namespace.to_string(),
+            port: 50002,
+            data: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let metadata = Metadata::from([
+            (
+                "orders".to_string(),
+                "10.0.0.1:6000, 10.0.0.2,[::1]".to_string(),
+            ),
+            ("orders.prod".to_string(), "10.1.0.1:6000".to_string()),
+        ]);
+        let resolver = StaticResolver::from_metadata(&metadata).unwrap();
+
+        let mut resolved = Vec::new();
+        for _ in 0..4 {
+            resolved.push(
+                resolver
+                    .resolve(&request("orders", "default"))
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(
+            resolved,
+            [
+                "10.0.0.1:6000",
+                "10.0.0.2:50002",
+                "[::1]:50002",
+                "10.0.0.1:6000"
+            ]
+        );
+        assert_eq!(
+            resolver.resolve(&request("orders", "prod")).await.unwrap(),
+            "10.1.0.1:6000"
+        );
+        assert!(matches!(
+            resolver.resolve(&request("billing", "default")).await,
+            Err(NameResolutionError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_metadata() {
+        let metadata = Metadata::from([("orders".to_string(), " , ".to_string())]);
+        assert!(matches!(
+            StaticResolver::from_metadata(&metadata),
+            Err(NameResolutionError::InvalidMetadata { .. })
+        ));
+    }
+}
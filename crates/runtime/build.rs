@@ -0,0 +1,43 @@
+//! Compiles the protobuf definitions of the runtime APIs, of the certificate authority and
+//! of the OpenTelemetry trace exporter.
+
+fn main() -> std::io::Result<()> {
+    tonic_prost_build::configure().compile_with_config(
+        config(),
+        &[
+            "proto/rapr/ca/v1/ca.proto",
+            "proto/rapr/invocation/v1/invocation.proto",
+            "proto/rapr/workflow/v1/workflow.proto",
+            "proto/opentelemetry/proto/collector/trace/v1/trace_service.proto",
+        ],
+        &["proto"],
+    )?;
+    // The runtime API and the app callbacks reuse the invocation messages rather than
+    // generating them twice. Imported packages are generated again regardless, so this pass
+    // gets its own directory to leave the files of the first one alone.
+    let out_dir =
+        std::path::Path::new(&std::env::var("OUT_DIR").expect("set by cargo")).join("runtime");
+    std::fs::create_dir_all(&out_dir)?;
+    tonic_prost_build::configure()
+        .out_dir(out_dir)
+        .extern_path(".rapr.invocation.v1", "crate::invocation::grpc::proto")
+        .compile_with_config(
+            config(),
+            &[
+                "proto/rapr/runtime/v1/runtime.proto",
+                "proto/rapr/appcallback/v1/appcallback.proto",
+            ],
+            &["proto"],
+        )
+}
+
+fn config() -> tonic_prost_build::Config {
+    let mut config = tonic_prost_build::Config::new();
+    // Fall back to the vendored compiler so that building does not require protoc.
+    if std::env::var_os("PROTOC").is_none()
+        && let Ok(protoc) = protoc_bin_vendored::protoc_bin_path()
+    {
+        config.protoc_executable(protoc);
+    }
+    config
+}
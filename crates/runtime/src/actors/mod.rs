@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub mod transaction;
+pub mod turn;
+
+pub use turn::{TurnGuard, TurnLocks};
+
+/// Separator used when an actor key is flattened into a single string.
+pub const ACTOR_KEY_DELIMITER: &str = "||";
+
+/// ActorKey uniquely identifies an actor instance by its type and id.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ActorKey {
+    #[serde(rename = "actorType")]
+    pub actor_type: String,
+    #[serde(rename = "actorId")]
+    pub actor_id: String,
+}
+
+impl ActorKey {
+    /// Creates a new ActorKey for the given actor type and id.
+    pub fn new(actor_type: impl Into<String>, actor_id: impl Into<String>) -> Self {
+        Self {
+            actor_type: actor_type.into(),
+            actor_id: actor_id.into(),
+        }
+    }
+}
+
+impl fmt::Display for ActorKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.actor_type, ACTOR_KEY_DELIMITER, self.actor_id
+        )
+    }
+}
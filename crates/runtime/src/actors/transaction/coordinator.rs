@@ -0,0 +1,613 @@
+use super::{
+    AbortedSnafu, ActorOperations, InDoubtSnafu, LogRecord, Participant, Result, TransactionError,
+    TransactionLog,
+};
+use crate::actors::{ActorKey, TurnGuard, TurnLocks};
+use futures::future::join_all;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+const DEFAULT_TURN_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_PREPARE_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_COMMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// CoordinatorOptions configures the timeouts of the transaction coordinator.
+#[derive(Debug, Clone)]
+pub struct CoordinatorOptions {
+    /// How long to wait for the turns of all participating actors.
+    pub turn_timeout: Duration,
+    /// How long participants have to prepare before the transaction is aborted.
+    pub prepare_timeout: Duration,
+    /// How long participants have to acknowledge the decision before the transaction is
+    /// left in doubt for recovery.
+    pub commit_timeout: Duration,
+}
+
+impl Default for CoordinatorOptions {
+    fn default() -> Self {
+        Self {
+            turn_timeout: DEFAULT_TURN_TIMEOUT,
+            prepare_timeout: DEFAULT_PREPARE_TIMEOUT,
+            commit_timeout: DEFAULT_COMMIT_TIMEOUT,
+        }
+    }
+}
+
+/// RecoveryReport lists the in-doubt transactions found in the log and how they were resolved.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Transactions whose commit decision was re-applied.
+    pub committed: Vec<String>,
+    /// Transactions that never reached a commit decision and were rolled back.
+    pub aborted: Vec<String>,
+    /// Transactions that still could not be completed and remain in the log.
+    pub unresolved: Vec<String>,
+}
+
+/// Coordinator executes transactions spanning the state of many actors.
+pub struct Coordinator {
+    log: Arc<dyn TransactionLog>,
+    participant: Arc<dyn Participant>,
+    turns: Arc<TurnLocks>,
+    options: CoordinatorOptions,
+    /// Held shared by executions and exclusively by recovery, which rewrites the log.
+    recovery: RwLock<()>,
+    /// Whether the log was recovered since the coordinator was created.
+    recovered: AtomicBool,
+    /// Turns of the actors of transactions left in doubt. They are held until recovery
+    /// completes the transactions, so that no other turn sees or changes the state of the
+    /// actors in between.
+    in_doubt: Mutex<Vec<TurnGuard>>,
+}
+
+impl Coordinator {
+    /// Creates a coordinator recording into the given log and staging changes on the given participant.
+    /// `turns` must be the same locks used to dispatch actor calls so that transactions are
+    /// isolated from regular turns.
+    pub fn new(
+        log: Arc<dyn TransactionLog>,
+        participant: Arc<dyn Participant>,
+        turns: Arc<TurnLocks>,
+        options: CoordinatorOptions,
+    ) -> Self {
+        Self {
+            log,
+            participant,
+            turns,
+            options,
+            recovery: RwLock::new(()),
+            recovered: AtomicBool::new(false),
+            in_doubt: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Executes the operations atomically and returns the id of the committed transaction.
+    /// Fails until [`recover`](Self::recover) has run once.
+    pub async fn execute(&self, actors: Vec<ActorOperations>) -> Result<String> {
+        let actors = merge_by_actor(actors);
+        if actors.iter().all(|a| a.operations.is_empty()) {
+            return Err(TransactionError::NoOperations);
+        }
+
+        let _recovery = self.recovery.read().await;
+        if !self.recovered.load(Ordering::Acquire) {
+            return Err(TransactionError::NotRecovered);
+        }
+        let keys: Vec<ActorKey> = actors.iter().map(|a| a.actor.clone()).collect();
+        let turns = self
+            .turns
+            .lock_all(&keys, self.options.turn_timeout)
+            .await
+            .map_err(|actor| TransactionError::TurnTimeout {
+                actor,
+                timeout: self.options.turn_timeout,
+            })?;
+
+        let tx_id = Uuid::new_v4().to_string();
+        self.log
+            .append(&LogRecord::Begin {
+                tx_id: tx_id.clone(),
+                actors: actors.clone(),
+            })
+            .await?;
+
+        if let Err(reason) = self.prepare(&tx_id, &actors).await {
+            self.log
+                .append(&LogRecord::Decision {
+                    tx_id: tx_id.clone(),
+                    commit: false,
+                })
+                .await?;
+            // The outcome is decided; participants that fail to abort are rolled back on recovery.
+            if self.abort(&tx_id, &actors).await.is_ok() {
+                self.log
+                    .append(&LogRecord::End {
+                        tx_id: tx_id.clone(),
+                    })
+                    .await?;
+            }
+            return AbortedSnafu { tx_id, reason }.fail();
+        }
+
+        self.log
+            .append(&LogRecord::Decision {
+                tx_id: tx_id.clone(),
+                commit: true,
+            })
+            .await?;
+
+        if let Err(reason) = self.commit(&tx_id, &actors).await {
+            self.in_doubt
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(turns);
+            return InDoubtSnafu { tx_id, reason }.fail();
+        }
+        self.log
+            .append(&LogRecord::End {
+                tx_id: tx_id.clone(),
+            })
+            .await?;
+
+        Ok(tx_id)
+    }
+
+    /// Completes the transactions left in doubt, by a previous run or by this one, and
+    /// compacts the log. Must be called before executing new transactions; executions wait
+    /// for recoveries to end.
+    pub async fn recover(&self) -> Result<RecoveryReport> {
+        let _recovery = self.recovery.write().await;
+        let records = self.log.load().await?;
+
+        let mut order = Vec::new();
+        let mut begun: HashMap<String, Vec<ActorOperations>> = HashMap::new();
+        let mut decisions: HashMap<String, bool> = HashMap::new();
+        let mut ended: HashSet<String> = HashSet::new();
+        for record in &records {
+            match record {
+                LogRecord::Begin { tx_id, actors } => {
+                    order.push(tx_id.clone());
+                    begun.insert(tx_id.clone(), actors.clone());
+                }
+                LogRecord::Decision { tx_id, commit } => {
+                    decisions.insert(tx_id.clone(), *commit);
+                }
+                LogRecord::End { tx_id } => {
+                    ended.insert(tx_id.clone());
+                }
+            }
+        }
+
+        // Turns held by this recovery: those of the transactions left in doubt by this run
+        // and those taken below.
+        let mut held =
+            std::mem::take(&mut *self.in_doubt.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut held_actors: HashSet<ActorKey> = held
+            .iter()
+            .flat_map(|turns| turns.actors().iter().cloned())
+            .collect();
+        let mut unresolved_actors = HashSet::new();
+
+        let mut report = RecoveryReport::default();
+        let mut remaining = Vec::new();
+        for tx_id in order.into_iter().filter(|id| !ended.contains(id)) {
+            let actors = begun.remove(&tx_id).unwrap_or_default();
+            let keys: Vec<ActorKey> = actors
+                .iter()
+                .map(|a| a.actor.clone())
+                .filter(|actor| !held_actors.contains(actor))
+                .collect();
+            // Presumed abort: without a commit decision no participant applied anything.
+            let commit = decisions.get(&tx_id).copied().unwrap_or(false);
+            // Transactions whose actors are busy in another turn are left for the next run.
+            let completed = match self.turns.lock_all(&keys, self.options.turn_timeout).await {
+                Ok(turns) => {
+                    held_actors.extend(keys);
+                    held.push(turns);
+                    match commit {
+                        true => self.commit(&tx_id, &actors).await,
+                        false => self.abort(&tx_id, &actors).await,
+                    }
+                }
+                Err(actor) => Err(format!("turn of actor {actor} is busy")),
+            };
+
+            match completed {
+                Ok(()) if commit => report.committed.push(tx_id),
+                Ok(()) => report.aborted.push(tx_id),
+                Err(_) => {
+                    unresolved_actors.extend(actors.iter().map(|a| a.actor.clone()));
+                    remaining.push(LogRecord::Begin {
+                        tx_id: tx_id.clone(),
+                        actors,
+                    });
+                    remaining.push(LogRecord::Decision {
+                        tx_id: tx_id.clone(),
+                        commit,
+                    });
+                    report.unresolved.push(tx_id);
+                }
+            }
+        }
+
+        // Actors of the transactions still in doubt stay fenced.
+        held.retain(|turns| {
+            turns
+                .actors()
+                .iter()
+                .any(|actor| unresolved_actors.contains(actor))
+        });
+        *self.in_doubt.lock().unwrap_or_else(|e| e.into_inner()) = held;
+        self.log.replace(&remaining).await?;
+        self.recovered.store(true, Ordering::Release);
+        Ok(report)
+    }
+
+    async fn prepare(
+        &self,
+        tx_id: &str,
+        actors: &[ActorOperations],
+    ) -> std::result::Result<(), String> {
+        let prepares = join_all(actors.iter().map(|a| async move {
+            self.participant
+                .prepare(tx_id, &a.actor, &a.operations)
+                .await
+                .map_err(|e| format!("actor {} failed to prepare: {e}", a.actor))
+        }));
+
+        match tokio::time::timeout(self.options.prepare_timeout, prepares).await {
+            Ok(results) => results.into_iter().collect(),
+            Err(_) => Err(format!(
+                "participants did not prepare within {:?}",
+                self.options.prepare_timeout
+            )),
+        }
+    }
+
+    async fn commit(
+        &self,
+        tx_id: &str,
+        actors: &[ActorOperations],
+    ) -> std::result::Result<(), String> {
+        let commits = join_all(actors.iter().map(|a| async move {
+            self.participant
+                .commit(tx_id, &a.actor, &a.operations)
+                .await
+                .map_err(|e| format!("actor {} failed to commit: {e}", a.actor))
+        }));
+        self.complete(commits).await
+    }
+
+    async fn abort(
+        &self,
+        tx_id: &str,
+        actors: &[ActorOperations],
+    ) -> std::result::Result<(), String> {
+        let aborts = join_all(actors.iter().map(|a| async move {
+            self.participant
+                .abort(tx_id, &a.actor)
+                .await
+                .map_err(|e| format!("actor {} failed to abort: {e}", a.actor))
+        }));
+        self.complete(aborts).await
+    }
+
+    async fn complete(
+        &self,
+        acks: impl Future<Output = Vec<std::result::Result<(), String>>>,
+    ) -> std::result::Result<(), String> {
+        match tokio::time::timeout(self.options.commit_timeout, acks).await {
+            Ok(results) => results.into_iter().collect(),
+            Err(_) => Err(format!(
+                "participants did not acknowledge within {:?}",
+                self.options.commit_timeout
+            )),
+        }
+    }
+}
+
+/// Merges operations targeting the same actor, keeping their relative order.
+fn merge_by_actor(actors: Vec<ActorOperations>) -> Vec<ActorOperations> {
+    let mut merged: BTreeMap<ActorKey, Vec<_>> = BTreeMap::new();
+    for a in actors {
+        merged.entry(a.actor).or_default().extend(a.operations);
+    }
+    merged
+        .into_iter()
+        .map(|(actor, operations)| ActorOperations { actor, operations })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::transaction::{InMemoryTransactionLog, StateOperation};
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockParticipant {
+        state: Mutex<HashMap<(ActorKey, String), Vec<u8>>>,
+        fail_prepare: HashSet<ActorKey>,
+        fail_commit: Mutex<bool>,
+        aborted: Mutex<Vec<ActorKey>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Participant for MockParticipant {
+        async fn prepare(
+            &self,
+            _: &str,
+            actor: &ActorKey,
+            _: &[StateOperation],
+        ) -> std::result::Result<(), String> {
+            if self.fail_prepare.contains(actor) {
+                return Err("etag mismatch".to_string());
+            }
+            Ok(())
+        }
+
+        async fn commit(
+            &self,
+            _: &str,
+            actor: &ActorKey,
+            operations: &[StateOperation],
+        ) -> std::result::Result<(), String> {
+            if *self.fail_commit.lock().unwrap() {
+                return Err("store unavailable".to_string());
+            }
+            let mut state = self.state.lock().unwrap();
+            for op in operations {
+                let key = (actor.clone(), op.key().to_string());
+                match op {
+                    StateOperation::Upsert { value, .. } => state.insert(key, value.clone()),
+                    StateOperation::Delete { .. } => state.remove(&key),
+                };
+            }
+            Ok(())
+        }
+
+        async fn abort(&self, _: &str, actor: &ActorKey) -> std::result::Result<(), String> {
+            self.aborted.lock().unwrap().push(actor.clone());
+            Ok(())
+        }
+    }
+
+    fn upsert(actor: &ActorKey, key: &str, value: &str) -> ActorOperations {
+        ActorOperations {
+            actor: actor.clone(),
+            operations: vec![StateOperation::Upsert {
+                key: key.to_string(),
+                value: value.as_bytes().to_vec(),
+            }],
+        }
+    }
+
+    /// Returns a recovered coordinator.
+    async fn coordinator(
+        log: Arc<InMemoryTransactionLog>,
+        participant: Arc<MockParticipant>,
+    ) -> Coordinator {
+        let coordinator = Coordinator::new(
+            log,
+            participant,
+            Arc::new(TurnLocks::new()),
+            CoordinatorOptions {
+                turn_timeout: Duration::from_millis(50),
+                ..Default::default()
+            },
+        );
+        coordinator.recover().await.unwrap();
+        coordinator
+    }
+
+    #[tokio::test]
+    async fn test_execute_commits_all_actors() {
+        let log = Arc::new(InMemoryTransactionLog::new());
+        let participant = Arc::new(MockParticipant::default());
+        let coordinator = coordinator(log.clone(), participant.clone()).await;
+
+        let from = ActorKey::new("account", "alice");
+        let to = ActorKey::new("account", "bob");
+        let tx_id = coordinator
+            .execute(vec![
+                upsert(&from, "balance", "90"),
+                upsert(&to, "balance", "110"),
+            ])
+            .await
+            .unwrap();
+
+        {
+            let state = participant.state.lock().unwrap();
+            assert_eq!(state[&(from, "balance".to_string())], b"90");
+            assert_eq!(state[&(to, "balance".to_string())], b"110");
+        }
+
+        let records = log.load().await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.tx_id() == tx_id));
+        assert_eq!(records[2], LogRecord::End { tx_id });
+    }
+
+    #[tokio::test]
+    async fn test_execute_aborts_when_a_participant_fails_to_prepare() {
+        let log = Arc::new(InMemoryTransactionLog::new());
+        let to = ActorKey::new("account", "bob");
+        let participant = Arc::new(MockParticipant {
+            fail_prepare: HashSet::from([to.clone()]),
+            ..Default::default()
+        });
+        let coordinator = coordinator(log.clone(), participant.clone()).await;
+
+        let from = ActorKey::new("account", "alice");
+        let err = coordinator
+            .execute(vec![
+                upsert(&from, "balance", "90"),
+                upsert(&to, "balance", "110"),
+            ])
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, TransactionError::Aborted { .. }));
+        assert!(participant.state.lock().unwrap().is_empty());
+        assert_eq!(participant.aborted.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_execute_times_out_waiting_for_turn() {
+        let turns = Arc::new(TurnLocks::new());
+        let coordinator = Coordinator::new(
+            Arc::new(InMemoryTransactionLog::new()),
+            Arc::new(MockParticipant::default()),
+            turns.clone(),
+            CoordinatorOptions {
+                turn_timeout: Duration::from_millis(20),
+                ..Default::default()
+            },
+        );
+
+        let actor = ActorKey::new("account", "alice");
+        let err = coordinator
+            .execute(vec![upsert(&actor, "balance", "90")])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TransactionError::NotRecovered));
+        coordinator.recover().await.unwrap();
+
+        let _busy = turns.lock(&actor).await;
+        let err = coordinator
+            .execute(vec![upsert(&actor, "balance", "90")])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TransactionError::TurnTimeout { .. }));
+
+        let err = coordinator.execute(vec![]).await.unwrap_err();
+        assert!(matches!(err, TransactionError::NoOperations));
+    }
+
+    #[tokio::test]
+    async fn test_recover_completes_in_doubt_transactions() {
+        let log = Arc::new(InMemoryTransactionLog::new());
+        let participant = Arc::new(MockParticipant::default());
+        *participant.fail_commit.lock().unwrap() = true;
+        let coordinator = coordinator(log.clone(), participant.clone()).await;
+
+        let actor = ActorKey::new("account", "alice");
+        let err = coordinator
+            .execute(vec![upsert(&actor, "balance", "90")])
+            .await
+            .unwrap_err();
+        let TransactionError::InDoubt {
+            tx_id: committed, ..
+        } = err
+        else {
+            panic!("expected in-doubt transaction, got {err}");
+        };
+
+        // A transaction which crashed before reaching a decision.
+        log.append(&LogRecord::Begin {
+            tx_id: "undecided".to_string(),
+            actors: vec![upsert(&actor, "balance", "0")],
+        })
+        .await
+        .unwrap();
+
+        // Still failing: the committed transaction stays in the log.
+        let report = coordinator.recover().await.unwrap();
+        assert_eq!(report.unresolved, vec![committed.clone()]);
+        assert_eq!(report.aborted, vec!["undecided".to_string()]);
+
+        *participant.fail_commit.lock().unwrap() = false;
+        let report = coordinator.recover().await.unwrap();
+        assert_eq!(report.committed, vec![committed]);
+        assert!(report.unresolved.is_empty());
+        assert!(log.load().await.unwrap().is_empty());
+        assert_eq!(
+            participant.state.lock().unwrap()[&(actor, "balance".to_string())],
+            b"90"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_in_doubt_actors_are_fenced() {
+        let log = Arc::new(InMemoryTransactionLog::new());
+        let participant = Arc::new(MockParticipant::default());
+        let turns = Arc::new(TurnLocks::new());
+        let coordinator = Coordinator::new(
+            log.clone(),
+            participant.clone(),
+            turns.clone(),
+            CoordinatorOptions {
+                turn_timeout: Duration::from_millis(20),
+                ..Default::default()
+            },
+        );
+        coordinator.recover().await.unwrap();
+
+        let actor = ActorKey::new("account", "alice");
+        *participant.fail_commit.lock().unwrap() = true;
+        let err = coordinator
+            .execute(vec![upsert(&actor, "balance", "90")])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, TransactionError::InDoubt { .. }));
+
+        // Until the transaction is recovered, no turn may change the state of the actor.
+        let (keys, timeout) = ([actor.clone()], Duration::from_millis(20));
+        assert!(turns.lock_all(&keys, timeout).await.is_err());
+        assert_eq!(coordinator.recover().await.unwrap().unresolved.len(), 1);
+        assert!(turns.lock_all(&keys, timeout).await.is_err());
+
+        *participant.fail_commit.lock().unwrap() = false;
+        assert_eq!(coordinator.recover().await.unwrap().committed.len(), 1);
+        assert!(turns.lock_all(&keys, timeout).await.is_ok());
+        coordinator
+            .execute(vec![upsert(&actor, "balance", "80")])
+            .await
+            .unwrap();
+        assert_eq!(
+            participant.state.lock().unwrap()[&(actor, "balance".to_string())],
+            b"80"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_recover_waits_for_turns() {
+        let log = Arc::new(InMemoryTransactionLog::new());
+        let participant = Arc::new(MockParticipant::default());
+        let turns = Arc::new(TurnLocks::new());
+        let coordinator = Coordinator::new(
+            log.clone(),
+            participant.clone(),
+            turns.clone(),
+            CoordinatorOptions {
+                turn_timeout: Duration::from_millis(20),
+                ..Default::default()
+            },
+        );
+
+        let actor = ActorKey::new("account", "alice");
+        log.append(&LogRecord::Begin {
+            tx_id: "undecided".to_string(),
+            actors: vec![upsert(&actor, "balance", "0")],
+        })
+        .await
+        .unwrap();
+
+        // The actor is busy: the transaction is neither aborted nor dropped from the log.
+        let busy = turns.lock(&actor).await;
+        let report = coordinator.recover().await.unwrap();
+        assert_eq!(report.unresolved, vec!["undecided".to_string()]);
+        assert!(participant.aborted.lock().unwrap().is_empty());
+        drop(busy);
+
+        let report = coordinator.recover().await.unwrap();
+        assert_eq!(report.aborted, vec!["undecided".to_string()]);
+        assert_eq!(*participant.aborted.lock().unwrap(), vec![actor]);
+        assert!(log.load().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use super::{ActorOperations, LogSnafu, Result};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+
+/// LogRecord is an entry of the transaction log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "camelCase")]
+pub enum LogRecord {
+    /// The transaction started and its participants are about to be prepared.
+    Begin {
+        #[serde(rename = "txId")]
+        tx_id: String,
+        actors: Vec<ActorOperations>,
+    },
+    /// The coordinator decided the outcome of the transaction.
+    Decision {
+        #[serde(rename = "txId")]
+        tx_id: String,
+        commit: bool,
+    },
+    /// Every participant acknowledged the decision.
+    End {
+        #[serde(rename = "txId")]
+        tx_id: String,
+    },
+}
+
+impl LogRecord {
+    /// Returns the id of the transaction the record belongs to.
+    pub fn tx_id(&self) -> &str {
+        match self {
+            LogRecord::Begin { tx_id, .. }
+            | LogRecord::Decision { tx_id, .. }
+            | LogRecord::End { tx_id } => tx_id,
+        }
+    }
+}
+
+/// TransactionLog durably records the progress of transactions.
+#[async_trait::async_trait]
+pub trait TransactionLog: Send + Sync {
+    /// Appends a record. The record must be durable once this returns.
+    async fn append(&self, record: &LogRecord) -> Result<()>;
+
+    /// Loads every record in the order they were appended.
+    async fn load(&self) -> Result<Vec<LogRecord>>;
+
+    /// Replaces the content of the log with the given records.
+    async fn replace(&self, records: &[LogRecord]) -> Result<()>;
+}
+
+/// InMemoryTransactionLog keeps records in memory. Useful for tests.
+#[derive(Debug, Default)]
+pub struct InMemoryTransactionLog {
+    records: Mutex<Vec<LogRecord>>,
+}
+
+impl InMemoryTransactionLog {
+    /// Creates an empty in-memory log.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionLog for InMemoryTransactionLog {
+    async fn append(&self, record: &LogRecord) -> Result<()> {
+        self.records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(record.clone());
+        Ok(())
+    }
+
+    async fn load(&self) -> Result<Vec<LogRecord>> {
+        Ok(self
+            .records
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone())
+    }
+
+    async fn replace(&self, records: &[LogRecord]) -> Result<()> {
+        *self.records.lock().unwrap_or_else(|e| e.into_inner()) = records.to_vec();
+        Ok(())
+    }
+}
+
+/// FileTransactionLog stores one JSON record per line in a file, syncing after every append.
+#[derive(Debug)]
+pub struct FileTransactionLog {
+    path: PathBuf,
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileTransactionLog {
+    /// Creates a log stored at the given path. The file is created on the first append.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Returns the path of the log file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn encode(record: &LogRecord) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record)
+        .map_err(std::io::Error::other)
+        .context(LogSnafu)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+#[async_trait::async_trait]
+impl TransactionLog for FileTransactionLog {
+    async fn append(&self, record: &LogRecord) -> Result<()> {
+        let line = encode(record)?;
+        let _guard = self.write_lock.lock().await;
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .context(LogSnafu)?;
+        file.write_all(&line).await.context(LogSnafu)?;
+        file.sync_data().await.context(LogSnafu)
+    }
+
+    async fn load(&self) -> Result<Vec<LogRecord>> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(LogSnafu),
+        };
+
+        let mut records = Vec::new();
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                // A torn write at the tail of the log is the only thing a crash can leave
+                // behind, and the record it belonged to was never acted upon.
+                Err(e) if e.is_eof() => break,
+                Err(e) => return Err(std::io::Error::other(e)).context(LogSnafu),
+            }
+        }
+        Ok(records)
+    }
+
+    async fn replace(&self, records: &[LogRecord]) -> Result<()> {
+        let mut content = Vec::new();
+        for record in records {
+            content.extend(encode(record)?);
+        }
+
+        let _guard = self.write_lock.lock().await;
+        let tmp = self.path.with_extension("tmp");
+        let mut file = tokio::fs::File::create(&tmp).await.context(LogSnafu)?;
+        file.write_all(&content).await.context(LogSnafu)?;
+        file.sync_data().await.context(LogSnafu)?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .context(LogSnafu)?;
+        // The rename is only durable once the directory holding the log is synced.
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = tokio::fs::File::open(dir).await.context(LogSnafu)?;
+        dir.sync_all().await.context(LogSnafu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actors::ActorKey;
+    use crate::actors::transaction::StateOperation;
+
+    #[tokio::test]
+    async fn test_file_log_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = FileTransactionLog::new(dir.path().join("tx.log"));
+        assert!(log.load().await.unwrap().is_empty());
+
+        let begin = LogRecord::Begin {
+            tx_id: "tx1".to_string(),
+            actors: vec![ActorOperations {
+                actor: ActorKey::new("cart", "1"),
+                operations: vec![StateOperation::Delete {
+                    key: "items".to_string(),
+                }],
+            }],
+        };
+        let decision = LogRecord::Decision {
+            tx_id: "tx1".to_string(),
+            commit: true,
+        };
+        log.append(&begin).await.unwrap();
+        log.append(&decision).await.unwrap();
+        assert_eq!(log.load().await.unwrap(), vec![begin.clone(), decision]);
+
+        log.replace(std::slice::from_ref(&begin)).await.unwrap();
+        assert_eq!(log.load().await.unwrap(), vec![begin]);
+    }
+
+    #[tokio::test]
+    async fn test_file_log_ignores_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tx.log");
+        std::fs::write(
+            &path,
+            "{\"record\":\"end\",\"txId\":\"tx1\"}\n{\"record\":\"decision\",\"tx",
+        )
+        .unwrap();
+
+        let log = FileTransactionLog::new(&path);
+        assert_eq!(
+            log.load().await.unwrap(),
+            vec![LogRecord::End {
+                tx_id: "tx1".to_string()
+            }]
+        );
+    }
+}
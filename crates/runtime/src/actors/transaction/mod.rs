@@ -0,0 +1,102 @@
+//! Cross-actor transactions.
+//!
+//! The [`Coordinator`] runs a two-phase commit over the actors taking part in a transaction:
+//! it holds the turn of every participating actor, asks the [`Participant`] to stage the
+//! state changes of each actor, and then commits or aborts all of them. Every step is
+//! recorded in a [`TransactionLog`] so that transactions left in doubt by a crash can be
+//! completed by [`Coordinator::recover`].
+
+use super::ActorKey;
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+use std::time::Duration;
+
+mod coordinator;
+mod log;
+
+pub use coordinator::*;
+pub use log::*;
+
+/// StateOperation is a single state change applied to an actor as part of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "camelCase")]
+pub enum StateOperation {
+    /// Upsert creates or replaces the value stored under the key.
+    Upsert { key: String, value: Vec<u8> },
+    /// Delete removes the value stored under the key.
+    Delete { key: String },
+}
+
+impl StateOperation {
+    /// Returns the state key this operation applies to.
+    pub fn key(&self) -> &str {
+        match self {
+            StateOperation::Upsert { key, .. } | StateOperation::Delete { key } => key,
+        }
+    }
+}
+
+/// ActorOperations groups the state changes applied to one actor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActorOperations {
+    pub actor: ActorKey,
+    pub operations: Vec<StateOperation>,
+}
+
+/// Participant stages and applies the state changes of the actors taking part in a transaction.
+///
+/// The coordinator calls `prepare` for every actor and only calls `commit` once all of them
+/// succeeded. `commit` and `abort` may be called again for the same transaction during
+/// recovery, so implementations must be idempotent.
+#[async_trait::async_trait]
+pub trait Participant: Send + Sync {
+    /// Validates and stages the operations of an actor. An error votes to abort the transaction.
+    async fn prepare(
+        &self,
+        tx_id: &str,
+        actor: &ActorKey,
+        operations: &[StateOperation],
+    ) -> std::result::Result<(), String>;
+
+    /// Applies the operations staged for an actor.
+    async fn commit(
+        &self,
+        tx_id: &str,
+        actor: &ActorKey,
+        operations: &[StateOperation],
+    ) -> std::result::Result<(), String>;
+
+    /// Discards anything staged for an actor.
+    async fn abort(&self, tx_id: &str, actor: &ActorKey) -> std::result::Result<(), String>;
+}
+
+pub type Result<T> = std::result::Result<T, TransactionError>;
+
+#[derive(Debug, Snafu)]
+pub enum TransactionError {
+    #[snafu(display("Transaction has no operations"))]
+    NoOperations,
+
+    #[snafu(display("Transactions cannot be executed before the log is recovered"))]
+    NotRecovered,
+
+    #[snafu(display(
+        "Timed out after {:?} waiting for the turn of actor {}",
+        timeout,
+        actor
+    ))]
+    TurnTimeout { actor: ActorKey, timeout: Duration },
+
+    #[snafu(display("Transaction {} aborted: {}", tx_id, reason))]
+    Aborted { tx_id: String, reason: String },
+
+    #[snafu(display(
+        "Transaction {} committed but not all participants acknowledged it: {}",
+        tx_id,
+        reason
+    ))]
+    InDoubt { tx_id: String, reason: String },
+
+    #[snafu(display("Failed to access transaction log: {}", source))]
+    Log { source: std::io::Error },
+}
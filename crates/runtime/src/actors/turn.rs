@@ -0,0 +1,123 @@
+use super::ActorKey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+/// TurnLocks serializes access to actors so that only one turn runs at a time per actor.
+#[derive(Debug, Default)]
+pub struct TurnLocks {
+    locks: Mutex<HashMap<ActorKey, Arc<AsyncMutex<()>>>>,
+}
+
+/// TurnGuard holds the turn of one or more actors until it is dropped.
+#[derive(Debug)]
+pub struct TurnGuard {
+    actors: Vec<ActorKey>,
+    _guards: Vec<OwnedMutexGuard<()>>,
+}
+
+impl TurnGuard {
+    /// Returns the actors whose turn is held by this guard.
+    pub fn actors(&self) -> &[ActorKey] {
+        &self.actors
+    }
+}
+
+impl TurnLocks {
+    /// Creates an empty set of turn locks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock_for(&self, actor: &ActorKey) -> Arc<AsyncMutex<()>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.entry(actor.clone()).or_default().clone()
+    }
+
+    /// Acquires the turn of a single actor.
+    pub async fn lock(&self, actor: &ActorKey) -> TurnGuard {
+        let guard = self.lock_for(actor).lock_owned().await;
+        TurnGuard {
+            actors: vec![actor.clone()],
+            _guards: vec![guard],
+        }
+    }
+
+    /// Acquires the turns of all the given actors, or returns the actor that could not be
+    /// locked before the timeout elapsed.
+    /// Actors are locked in a stable order so that concurrent callers cannot deadlock.
+    pub async fn lock_all(
+        &self,
+        actors: &[ActorKey],
+        timeout: Duration,
+    ) -> Result<TurnGuard, ActorKey> {
+        let mut sorted = actors.to_vec();
+        sorted.sort();
+        sorted.dedup();
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut guards = Vec::with_capacity(sorted.len());
+        for actor in &sorted {
+            let lock = self.lock_for(actor);
+            match tokio::time::timeout_at(deadline, lock.lock_owned()).await {
+                Ok(guard) => guards.push(guard),
+                Err(_) => return Err(actor.clone()),
+            }
+        }
+
+        Ok(TurnGuard {
+            actors: sorted,
+            _guards: guards,
+        })
+    }
+
+    /// Drops the locks of actors which are not currently held by anyone.
+    pub fn prune(&self) {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_lock_all_times_out_when_turn_is_held() {
+        let locks = TurnLocks::new();
+        let a = ActorKey::new("cart", "1");
+        let b = ActorKey::new("cart", "2");
+
+        let held = locks.lock(&b).await;
+        let result = locks
+            .lock_all(&[a.clone(), b.clone()], Duration::from_millis(20))
+            .await;
+        assert_eq!(result.unwrap_err(), b);
+
+        drop(held);
+        let guard = locks
+            .lock_all(
+                &[b.clone(), a.clone(), a.clone()],
+                Duration::from_millis(20),
+            )
+            .await
+            .unwrap();
+        assert_eq!(guard.actors(), &[a, b]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_held_locks() {
+        let locks = TurnLocks::new();
+        let a = ActorKey::new("cart", "1");
+        let b = ActorKey::new("cart", "2");
+
+        let _held = locks.lock(&a).await;
+        drop(locks.lock(&b).await);
+        locks.prune();
+
+        let remaining = locks.locks.lock().unwrap();
+        assert!(remaining.contains_key(&a));
+        assert!(!remaining.contains_key(&b));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::crypto::CryptoError;
+use crate::invocation::InvocationError;
+use crate::resiliency::PolicyError;
+use rapr_contributes::crypto as components;
+use std::fmt;
+use tonic::Code;
+
+/// Error code of malformed requests.
+pub const ERR_MALFORMED_REQUEST: &str = "ERR_MALFORMED_REQUEST";
+/// Error code of calls without a valid API token.
+pub const ERR_UNAUTHENTICATED: &str = "ERR_UNAUTHENTICATED";
+/// Error code of service invocation failures.
+pub const ERR_DIRECT_INVOKE: &str = "ERR_DIRECT_INVOKE";
+/// Error code of calls to building blocks which are not configured.
+pub const ERR_NOT_CONFIGURED: &str = "ERR_NOT_CONFIGURED";
+/// Error code of calls to crypto components which do not exist.
+pub const ERR_CRYPTO_PROVIDER_NOT_FOUND: &str = "ERR_CRYPTO_PROVIDER_NOT_FOUND";
+/// Error code of calls to keys which do not exist.
+pub const ERR_CRYPTO_KEY_NOT_FOUND: &str = "ERR_CRYPTO_KEY_NOT_FOUND";
+/// Error code of other failures of the cryptography building block.
+pub const ERR_CRYPTO: &str = "ERR_CRYPTO";
+
+/// ApiError is the error of a call to the API. Both protocols report the same error code,
+/// along with the gRPC code or the HTTP status derived from the same gRPC code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub code: Code,
+    /// Stable identifier of the error, such as [`ERR_DIRECT_INVOKE`].
+    pub error_code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    /// Creates an error.
+    pub fn new(code: Code, error_code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            error_code,
+            message: message.into(),
+        }
+    }
+
+    /// Creates the error of a malformed request.
+    pub fn malformed(message: impl Into<String>) -> Self {
+        Self::new(Code::InvalidArgument, ERR_MALFORMED_REQUEST, message)
+    }
+
+    /// Creates the error of a call without a valid API token.
+    pub fn unauthenticated(message: impl Into<String>) -> Self {
+        Self::new(Code::Unauthenticated, ERR_UNAUTHENTICATED, message)
+    }
+
+    /// Creates the error of a call to a building block which is not configured.
+    pub fn not_configured(building_block: &str) -> Self {
+        Self::new(
+            Code::Unimplemented,
+            ERR_NOT_CONFIGURED,
+            format!("{building_block} is not configured"),
+        )
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.error_code, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<InvocationError> for ApiError {
+    fn from(e: InvocationError) -> Self {
+        let status = crate::invocation::grpc::to_status(e);
+        Self::new(status.code(), ERR_DIRECT_INVOKE, status.message())
+    }
+}
+
+impl From<CryptoError> for ApiError {
+    fn from(e: CryptoError) -> Self {
+        let message = e.to_string();
+        let (code, error_code) = match e {
+            CryptoError::ComponentNotFound { .. } => {
+                (Code::InvalidArgument, ERR_CRYPTO_PROVIDER_NOT_FOUND)
+            }
+            CryptoError::UnsupportedFormat { .. }
+            | CryptoError::InvalidHeader { .. }
+            | CryptoError::Decryption { .. }
+            | CryptoError::TooLarge => (Code::InvalidArgument, ERR_CRYPTO),
+            CryptoError::KeyStore { source } => match source {
+                components::CryptoError::KeyNotFound { .. } => {
+                    (Code::NotFound, ERR_CRYPTO_KEY_NOT_FOUND)
+                }
+                components::CryptoError::InvalidKeyName { .. }
+                | components::CryptoError::InvalidKey { .. }
+                | components::CryptoError::UnsupportedAlgorithm { .. } => {
+                    (Code::InvalidArgument, ERR_CRYPTO)
+                }
+                _ => (Code::Internal, ERR_CRYPTO),
+            },
+            CryptoError::Resiliency { source } => match source {
+                PolicyError::Timeout { .. } => (Code::DeadlineExceeded, ERR_CRYPTO),
+                _ => (Code::Unavailable, ERR_CRYPTO),
+            },
+            CryptoError::Io { .. } => (Code::Internal, ERR_CRYPTO),
+        };
+        Self::new(code, error_code, message)
+    }
+}
@@ -0,0 +1,490 @@
+//! gRPC server of the building-block API.
+
+use super::{Api, ApiError, ApiProtocol, GrpcSnafu, ServerError, ServerOptions, api_of};
+use crate::diagnostics::{Metrics, OTHER_VALUE, TraceContext, Tracer, start_server_span};
+use crate::invocation::InvokeRequest;
+use crate::invocation::grpc::proto::InvokeResponse;
+use crate::workflow::grpc::WorkflowService;
+use axum::extract::State;
+use axum::middleware::{self, Next};
+use futures::FutureExt;
+use proto::rapr_server::Rapr;
+use snafu::ResultExt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tonic::service::Routes;
+use tonic::transport::Server;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+pub use proto::rapr_client::RaprClient;
+pub use proto::rapr_server::RaprServer;
+
+#[allow(missing_docs, unused_qualifications, clippy::all)]
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/runtime/rapr.runtime.v1.rs"));
+}
+
+/// Default port of the gRPC API.
+pub const DEFAULT_GRPC_PORT: u16 = 50001;
+
+/// GrpcServer serves the API on a TCP port and, optionally, a Unix domain socket.
+///
+/// Listeners are bound by [`GrpcServer::bind`], so that address conflicts are reported
+/// before the sidecar reports itself as started.
+pub struct GrpcServer {
+    tcp: TcpListener,
+    #[cfg(unix)]
+    unix: Option<(tokio::net::UnixListener, std::path::PathBuf)>,
+}
+
+impl GrpcServer {
+    /// Binds the listeners of the server.
+    pub async fn bind(options: &ServerOptions) -> Result<Self, ServerError> {
+        let tcp = super::bind_tcp(options).await?;
+        #[cfg(unix)]
+        let unix = match &options.unix_socket {
+            Some(path) => Some((super::bind_unix_socket(path)?, path.clone())),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if let Some(path) = &options.unix_socket {
+            tracing::warn!(path = %path.display(), "Unix sockets are not supported, ignoring");
+        }
+        Ok(Self {
+            tcp,
+            #[cfg(unix)]
+            unix,
+        })
+    }
+
+    /// Returns the TCP address the server listens on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    /// Serves the API until `shutdown` completes, then lets in-flight calls finish. The
+    /// Unix socket is removed once the server stops.
+    pub async fn serve(
+        self,
+        api: Api,
+        shutdown: impl Future<Output = ()> + Send,
+    ) -> Result<(), ServerError> {
+        let routes = routes(api);
+        let shutdown = shutdown.shared();
+        if let Ok(address) = self.tcp.local_addr() {
+            tracing::info!(%address, "gRPC API listening on TCP");
+        }
+        let tcp = Server::builder()
+            .add_routes(routes.clone())
+            .serve_with_incoming_shutdown(TcpIncoming::from(self.tcp), shutdown.clone());
+
+        #[cfg(unix)]
+        if let Some((listener, path)) = self.unix {
+            tracing::info!(path = %path.display(), "gRPC API listening on Unix socket");
+            let unix = Server::builder()
+                .add_routes(routes)
+                .serve_with_incoming_shutdown(
+                    tokio_stream::wrappers::UnixListenerStream::new(listener),
+                    shutdown,
+                );
+            let result = tokio::try_join!(tcp, unix);
+            super::remove_unix_socket(&path);
+            return result.map(|_| ()).context(GrpcSnafu);
+        }
+        tcp.await.context(GrpcSnafu)
+    }
+}
+
+/// Returns the gRPC services of the API.
+pub fn routes(api: Api) -> Routes {
+    let workflows = api.workflow_engine().cloned();
+    let guarded = api.is_guarded().then(|| api.clone());
+    let tracer = api.tracer().cloned();
+    let metrics = api.metrics().cloned();
+    let mut routes = Routes::new(RaprService::new(api).into_server());
+    if let Some(engine) = workflows {
+        routes = routes.add_service(WorkflowService::new(engine).into_server());
+    }
+    if guarded.is_none() && tracer.is_none() && metrics.is_none() {
+        return routes;
+    }
+    let mut router = routes.into_axum_router();
+    if let Some(tracer) = tracer {
+        router = router.layer(middleware::from_fn_with_state(tracer, trace));
+    }
+    if let Some(metrics) = metrics {
+        router = router.layer(middleware::from_fn_with_state(metrics, measure));
+    }
+    if let Some(api) = guarded {
+        router = router.layer(middleware::from_fn_with_state(api, guard));
+    }
+    Routes::from(router)
+}
+
+/// Traces a call, named after its service and method, such as
+/// `rapr.runtime.v1.Rapr/InvokeService`.
+async fn trace(
+    State(tracer): State<Tracer>,
+    mut request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let name = request.uri().path().trim_start_matches('/').to_string();
+    let mut span = start_server_span(&tracer, name.clone(), &mut request);
+    if span.is_recording() {
+        let (service, method) = name.split_once('/').unwrap_or((&name, ""));
+        span.set_attribute("rpc.system", "grpc");
+        span.set_attribute("rpc.service", service);
+        span.set_attribute("rpc.method", method);
+    }
+    let response = next.run(request).await;
+    // Failures of unary calls come without body, their status in the headers.
+    let status = Status::from_header_map(response.headers());
+    let code = status.as_ref().map_or(tonic::Code::Ok, Status::code);
+    span.set_attribute("rpc.grpc.status_code", code as i64);
+    if let Some(status) = status
+        && status.code() != tonic::Code::Ok
+    {
+        span.set_error(status.message());
+    }
+    response
+}
+
+/// Records the metrics of a call, under its service and method.
+async fn measure(
+    State(metrics): State<Metrics>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    let path = request.uri().path();
+    let building_block = api_of(ApiProtocol::Grpc, path)
+        .map_or(OTHER_VALUE, |(name, _)| name)
+        .to_string();
+    let operation = path.trim_start_matches('/').to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let code = Status::from_header_map(response.headers())
+        .as_ref()
+        .map_or(tonic::Code::Ok, Status::code);
+    metrics.record_api_call(
+        "grpc",
+        &building_block,
+        &operation,
+        &format!("{code:?}"),
+        start.elapsed(),
+    );
+    response
+}
+
+async fn guard(
+    State(api): State<Api>,
+    request: axum::extract::Request,
+    next: Next,
+) -> axum::response::Response {
+    match api.guard(ApiProtocol::Grpc, &request) {
+        Ok(()) => next.run(request).await,
+        Err(e) => Status::from(e).into_http(),
+    }
+}
+
+impl From<ApiError> for Status {
+    fn from(e: ApiError) -> Self {
+        Status::new(e.code, e.to_string())
+    }
+}
+
+/// RaprService implements the building-block API on top of an [`Api`].
+#[derive(Clone)]
+pub struct RaprService {
+    api: Api,
+}
+
+impl RaprService {
+    /// Creates a service serving the building blocks of the API.
+    pub fn new(api: Api) -> Self {
+        Self { api }
+    }
+
+    /// Wraps the service into a server which can be added to a tonic router.
+    pub fn into_server(self) -> RaprServer<Self> {
+        RaprServer::new(self)
+    }
+}
+
+#[tonic::async_trait]
+impl Rapr for RaprService {
+    async fn get_metadata(
+        &self,
+        _request: Request<proto::GetMetadataRequest>,
+    ) -> Result<Response<proto::GetMetadataResponse>, Status> {
+        let metadata = self.api.metadata();
+        Ok(Response::new(proto::GetMetadataResponse {
+            id: metadata.id,
+            namespace: metadata.namespace,
+            mode: metadata.mode,
+            runtime_version: metadata.runtime_version,
+            components: metadata
+                .components
+                .into_iter()
+                .map(|c| proto::ComponentMetadata {
+                    name: c.name,
+                    r#type: c.component_type,
+                    version: c.version,
+                    capabilities: c.capabilities,
+                    metadata: c.metadata.into_iter().collect(),
+                })
+                .collect(),
+            subscriptions: metadata
+                .subscriptions
+                .into_iter()
+                .map(|s| proto::Subscription {
+                    pubsub_name: s.pubsub_name,
+                    topic: s.topic,
+                    route: s.route,
+                    dead_letter_topic: s.dead_letter_topic,
+                })
+                .collect(),
+            actors: metadata
+                .actors
+                .into_iter()
+                .map(|a| proto::ActiveActors {
+                    r#type: a.actor_type,
+                    count: a.count,
+                })
+                .collect(),
+            extended: metadata.extended.into_iter().collect(),
+        }))
+    }
+
+    async fn set_metadata(
+        &self,
+        request: Request<proto::SetMetadataRequest>,
+    ) -> Result<Response<proto::SetMetadataResponse>, Status> {
+        let request = request.into_inner();
+        self.api.set_metadata(&request.key, request.value)?;
+        Ok(Response::new(proto::SetMetadataResponse {}))
+    }
+
+    async fn invoke_service(
+        &self,
+        request: Request<proto::InvokeServiceRequest>,
+    ) -> Result<Response<InvokeResponse>, Status> {
+        let trace_context = request.extensions().get::<TraceContext>().cloned();
+        let request = request.into_inner();
+        let message = request
+            .message
+            .ok_or_else(|| ApiError::malformed("Missing message to invoke"))?;
+        let mut message = InvokeRequest::from(message);
+        if let Some(context) = &trace_context {
+            message.set_trace_context(context);
+        }
+        let response = self.api.invoke_service(&request.id, message).await?;
+        Ok(Response::new(response.into()))
+    }
+
+    async fn subtle_get_key(
+        &self,
+        request: Request<proto::SubtleGetKeyRequest>,
+    ) -> Result<Response<proto::SubtleGetKeyResponse>, Status> {
+        let request = request.into_inner();
+        let key = self
+            .api
+            .get_key(&request.component_name, &request.name)
+            .await?;
+        Ok(Response::new(proto::SubtleGetKeyResponse {
+            name: request.name,
+            public_key: serde_json::to_string(&key).expect("keys serialize to JSON"),
+        }))
+    }
+
+    async fn subtle_wrap_key(
+        &self,
+        request: Request<proto::SubtleWrapKeyRequest>,
+    ) -> Result<Response<proto::SubtleWrapKeyResponse>, Status> {
+        let request = request.into_inner();
+        let wrapped_key = self
+            .api
+            .wrap_key(
+                &request.component_name,
+                &request.key_name,
+                &request.algorithm,
+                &request.plaintext_key,
+            )
+            .await?;
+        Ok(Response::new(proto::SubtleWrapKeyResponse { wrapped_key }))
+    }
+
+    async fn subtle_unwrap_key(
+        &self,
+        request: Request<proto::SubtleUnwrapKeyRequest>,
+    ) -> Result<Response<proto::SubtleUnwrapKeyResponse>, Status> {
+        let request = request.into_inner();
+        let plaintext_key = self
+            .api
+            .unwrap_key(
+                &request.component_name,
+                &request.key_name,
+                &request.algorithm,
+                &request.wrapped_key,
+            )
+            .await?;
+        Ok(Response::new(proto::SubtleUnwrapKeyResponse {
+            plaintext_key,
+        }))
+    }
+
+    async fn subtle_sign(
+        &self,
+        request: Request<proto::SubtleSignRequest>,
+    ) -> Result<Response<proto::SubtleSignResponse>, Status> {
+        let request = request.into_inner();
+        let signature = self
+            .api
+            .sign(
+                &request.component_name,
+                &request.key_name,
+                &request.algorithm,
+                &request.digest,
+            )
+            .await?;
+        Ok(Response::new(proto::SubtleSignResponse { signature }))
+    }
+
+    async fn subtle_verify(
+        &self,
+        request: Request<proto::SubtleVerifyRequest>,
+    ) -> Result<Response<proto::SubtleVerifyResponse>, Status> {
+        let request = request.into_inner();
+        let valid = self
+            .api
+            .verify(
+                &request.component_name,
+                &request.key_name,
+                &request.algorithm,
+                &request.digest,
+                &request.signature,
+            )
+            .await?;
+        Ok(Response::new(proto::SubtleVerifyResponse { valid }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{API_TOKEN_HEADER, ApiRules, ApiToken};
+    use crate::meta::{Meta, Options};
+    use rapr_apis::configuration::v1alpha1::{ApiRule, ApiSpec};
+    use rapr_common::RaprMode;
+    use tonic::Code;
+    use tonic::transport::Channel;
+
+    fn api() -> Api {
+        Api::new(Meta::new(Options {
+            id: "app".to_string(),
+            pod_name: String::new(),
+            namespace: "default".to_string(),
+            strict_sandbox: false,
+            mode: RaprMode::Standalone,
+        }))
+    }
+
+    async fn serve(api: Api) -> RaprClient<Channel> {
+        let server = GrpcServer::bind(&ServerOptions::localhost(0))
+            .await
+            .unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(server.serve(api, std::future::pending()));
+        RaprClient::connect(format!("http://{address}"))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_api_token() {
+        let mut client = serve(api().with_api_token(ApiToken::new("s3cret"))).await;
+
+        let status = client
+            .get_metadata(proto::GetMetadataRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        let mut request = Request::new(proto::GetMetadataRequest {});
+        request
+            .metadata_mut()
+            .insert(API_TOKEN_HEADER, "s3cret".parse().unwrap());
+        let response = client.get_metadata(request).await.unwrap();
+        assert_eq!(response.into_inner().id, "app");
+    }
+
+    #[tokio::test]
+    async fn test_api_rules() {
+        let rules = ApiRules::from_spec(&ApiSpec {
+            allowed: Vec::new(),
+            denied: vec![ApiRule {
+                name: "metadata".to_string(),
+                version: "v1".to_string(),
+                protocol: "grpc".to_string(),
+            }],
+        })
+        .unwrap();
+        let mut client = serve(api().with_api_rules(rules)).await;
+        let status = client
+            .get_metadata(proto::GetMetadataRequest {})
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(
+            status.message(),
+            "ERR_API_DENIED: The metadata API v1 is not allowed over gRPC"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tracing() {
+        use crate::diagnostics::proto::common::v1::any_value::Value as AttributeValue;
+        use crate::diagnostics::{Collector, TRACEPARENT_HEADER, attribute};
+
+        let collector = Collector::default();
+        let tracer = collector.tracer().await;
+        let mut client = serve(api().with_tracer(tracer.clone())).await;
+        let mut request = Request::new(proto::GetMetadataRequest {});
+        request.metadata_mut().insert(
+            TRACEPARENT_HEADER,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        client.get_metadata(request).await.unwrap();
+        let status = client
+            .invoke_service(proto::InvokeServiceRequest::default())
+            .await
+            .unwrap_err();
+        tracer.flush().await;
+
+        let span = collector.span("rapr.runtime.v1.Rapr/GetMetadata");
+        assert_eq!(
+            span.parent_span_id,
+            [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]
+        );
+        assert_eq!(
+            attribute(&span, "rpc.method"),
+            Some(AttributeValue::StringValue("GetMetadata".to_string()))
+        );
+        assert_eq!(
+            attribute(&span, "rpc.grpc.status_code"),
+            Some(AttributeValue::IntValue(0))
+        );
+        let failed = collector.span("rapr.runtime.v1.Rapr/InvokeService");
+        assert!(failed.parent_span_id.is_empty());
+        assert_eq!(
+            attribute(&failed, "rpc.grpc.status_code"),
+            Some(AttributeValue::IntValue(status.code() as i64))
+        );
+        assert_eq!(failed.status.unwrap().message, status.message());
+    }
+}
@@ -0,0 +1,844 @@
+//! HTTP server of the building-block API.
+//!
+//! The endpoints mirror the calls of the gRPC API as REST resources under `/v1.0`. Binary
+//! fields of JSON bodies are base64 encoded. Errors are reported with the HTTP status
+//! matching their gRPC code and a JSON body holding their error code:
+//!
+//! ```json
+//! {"errorCode": "ERR_CRYPTO_KEY_NOT_FOUND", "message": "Key store operation failed: ..."}
+//! ```
+//!
+//! | Endpoint                                          | Call                        |
+//! |---------------------------------------------------|-----------------------------|
+//! | `GET /v1.0/healthz`                               | [`Health::report`]          |
+//! | `GET /v1.0/healthz/outbound`                      | [`Health::report_outbound`] |
+//! | `GET /v1.0/metadata`                              | [`Api::metadata`]           |
+//! | `PUT /v1.0/metadata/{key}`                        | [`Api::set_metadata`]       |
+//! | `* /v1.0/invoke/{app-id}/method/{method}`         | [`Api::invoke_service`]     |
+//! | `GET /v1.0/crypto/{component}/keys/{name}`        | [`Api::get_key`]            |
+//! | `POST /v1.0/crypto/{component}/wrapkey`           | [`Api::wrap_key`]           |
+//! | `POST /v1.0/crypto/{component}/unwrapkey`         | [`Api::unwrap_key`]         |
+//! | `POST /v1.0/crypto/{component}/sign`              | [`Api::sign`]               |
+//! | `POST /v1.0/crypto/{component}/verify`            | [`Api::verify`]             |
+//!
+//! Health probes answer `200 OK` when the sidecar is healthy and `503 Service Unavailable`
+//! otherwise, with the [`HealthReport`] as body. They are also served without version, as
+//! `/healthz` and `/healthz/outbound`, the paths probes are configured with.
+//!
+//! The [`API_TOKEN_HEADER`] header is not forwarded with invocations. Traced calls are
+//! named after their method and route, such as `GET /v1.0/metadata`, which is also the
+//! operation their metrics are reported under.
+//!
+//! [`Health::report`]: crate::health::Health::report
+//! [`Health::report_outbound`]: crate::health::Health::report_outbound
+
+use super::{
+    API_TOKEN_HEADER, Api, ApiError, ApiProtocol, HttpSnafu, Metadata, ServerError, ServerOptions,
+    api_of,
+};
+use crate::diagnostics::{Metrics, OTHER_VALUE, Tracer, start_server_span};
+use crate::health::HealthReport;
+use crate::invocation::InvokeRequest;
+use axum::body::Bytes;
+use axum::extract::{MatchedPath, Path, Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{any, get, post, put};
+use axum::{Json, Router};
+use futures::FutureExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tonic::Code;
+
+/// Default port of the HTTP API.
+pub const DEFAULT_HTTP_PORT: u16 = 3500;
+
+/// Headers which only make sense on the connection they were received on.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "content-length",
+    "host",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Returns true for the headers which only make sense on the connection they were received
+/// on.
+pub(crate) fn is_hop_by_hop(name: &HeaderName) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name.as_str())
+}
+
+/// HttpServer serves the API on a TCP port and, optionally, a Unix domain socket, over
+/// HTTP/1.1 and HTTP/2 without TLS.
+pub struct HttpServer {
+    tcp: TcpListener,
+    #[cfg(unix)]
+    unix: Option<(tokio::net::UnixListener, std::path::PathBuf)>,
+}
+
+impl HttpServer {
+    /// Binds the listeners of the server.
+    pub async fn bind(options: &ServerOptions) -> Result<Self, ServerError> {
+        let tcp = super::bind_tcp(options).await?;
+        #[cfg(unix)]
+        let unix = match &options.unix_socket {
+            Some(path) => Some((super::bind_unix_socket(path)?, path.clone())),
+            None => None,
+        };
+        #[cfg(not(unix))]
+        if let Some(path) = &options.unix_socket {
+            tracing::warn!(path = %path.display(), "Unix sockets are not supported, ignoring");
+        }
+        Ok(Self {
+            tcp,
+            #[cfg(unix)]
+            unix,
+        })
+    }
+
+    /// Returns the TCP address the server listens on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    /// Serves the API until `shutdown` completes, then lets in-flight requests finish. The
+    /// Unix socket is removed once the server stops.
+    pub async fn serve(
+        self,
+        api: Api,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> Result<(), ServerError> {
+        let router = router(api);
+        let shutdown = shutdown.shared();
+        if let Ok(address) = self.tcp.local_addr() {
+            tracing::info!(%address, "HTTP API listening on TCP");
+        }
+        let tcp = axum::serve(self.tcp, router.clone())
+            .with_graceful_shutdown(shutdown.clone())
+            .into_future();
+
+        #[cfg(unix)]
+        if let Some((listener, path)) = self.unix {
+            tracing::info!(path = %path.display(), "HTTP API listening on Unix socket");
+            let unix = axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown)
+                .into_future();
+            let result = tokio::try_join!(tcp, unix);
+            super::remove_unix_socket(&path);
+            return result.map(|_| ()).context(HttpSnafu);
+        }
+        tcp.await.context(HttpSnafu)
+    }
+}
+
+/// Returns the routes of the API.
+pub fn router(api: Api) -> Router {
+    let guarded = api.is_guarded().then(|| api.clone());
+    let tracer = api.tracer().cloned();
+    let metrics = api.metrics().cloned();
+    let mut router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/healthz/outbound", get(healthz_outbound))
+        .route("/v1.0/healthz", get(healthz))
+        .route("/v1.0/healthz/outbound", get(healthz_outbound))
+        .route("/v1.0/metadata", get(get_metadata))
+        .route("/v1.0/metadata/{key}", put(set_metadata))
+        .route("/v1.0/invoke/{id}/method/{*method}", any(invoke_service))
+        .route("/v1.0/crypto/{component}/keys/{name}", get(get_key))
+        .route("/v1.0/crypto/{component}/wrapkey", post(wrap_key))
+        .route("/v1.0/crypto/{component}/unwrapkey", post(unwrap_key))
+        .route("/v1.0/crypto/{component}/sign", post(sign))
+        .route("/v1.0/crypto/{component}/verify", post(verify))
+        .with_state(api);
+    // Calls rejected by the guard are traced and measured too.
+    if let Some(api) = guarded {
+        router = router.route_layer(middleware::from_fn_with_state(api, guard));
+    }
+    if let Some(tracer) = tracer {
+        router = router.route_layer(middleware::from_fn_with_state(tracer, trace));
+    }
+    if let Some(metrics) = metrics {
+        router = router.route_layer(middleware::from_fn_with_state(metrics, measure));
+    }
+    router
+}
+
+/// Traces a call, whose handler and the apps it invokes see the context of its span.
+async fn trace(State(tracer): State<Tracer>, mut request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let method = request.method().to_string();
+    let mut span = start_server_span(&tracer, format!("{method} {route}"), &mut request);
+    if span.is_recording() {
+        span.set_attribute("http.request.method", method);
+        span.set_attribute("http.route", route);
+        span.set_attribute("url.path", request.uri().path());
+    }
+    let response = next.run(request).await;
+    let status = response.status();
+    span.set_attribute("http.response.status_code", i64::from(status.as_u16()));
+    if status.is_server_error() {
+        span.set_error(format!("Responded with status {status}"));
+    }
+    response
+}
+
+/// Records the metrics of a call, under its method and route.
+async fn measure(State(metrics): State<Metrics>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let building_block = api_of(ApiProtocol::Http, request.uri().path())
+        .map_or(OTHER_VALUE, |(name, _)| name)
+        .to_string();
+    let operation = format!("{} {route}", request.method());
+    let start = Instant::now();
+    let response = next.run(request).await;
+    metrics.record_api_call(
+        "http",
+        &building_block,
+        &operation,
+        response.status().as_str(),
+        start.elapsed(),
+    );
+    response
+}
+
+async fn guard(State(api): State<Api>, request: Request, next: Next) -> Response {
+    match api.guard(ApiProtocol::Http, &request) {
+        Ok(()) => next.run(request).await,
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Returns the HTTP status of a gRPC code, as mapped by gRPC gateways.
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).expect("499 is a valid status"),
+        Code::InvalidArgument | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::FailedPrecondition => StatusCode::BAD_REQUEST,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorBody<'a> {
+    error_code: &'a str,
+    message: &'a str,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error_code: self.error_code,
+            message: &self.message,
+        };
+        (http_status(self.code), Json(body)).into_response()
+    }
+}
+
+/// Parses a JSON body, reporting failures as malformed requests.
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|e| ApiError::malformed(format!("Invalid body: {e}")))
+}
+
+/// Answers a health probe with the report and a status telling whether it is healthy.
+fn probe(report: HealthReport) -> Response {
+    let status = match report.healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report)).into_response()
+}
+
+async fn healthz(State(api): State<Api>) -> Response {
+    probe(api.health().report())
+}
+
+async fn healthz_outbound(State(api): State<Api>) -> Response {
+    probe(api.health().report_outbound())
+}
+
+async fn get_metadata(State(api): State<Api>) -> Json<Metadata> {
+    Json(api.metadata())
+}
+
+/// Sets an extended attribute to the body of the request.
+async fn set_metadata(
+    State(api): State<Api>,
+    Path(key): Path<String>,
+    body: Bytes,
+) -> Result<StatusCode, ApiError> {
+    let value = String::from_utf8(body.to_vec())
+        .map_err(|_| ApiError::malformed("Metadata values must be UTF-8"))?;
+    api.set_metadata(&key, value)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn invoke_service(
+    State(api): State<Api>,
+    Path((id, method)): Path<(String, String)>,
+    verb: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let mut request = InvokeRequest::new(method)
+        .with_verb(verb.as_str())
+        .with_data(content_type, body);
+    request.query = uri.query().unwrap_or_default().to_string();
+    request.headers = headers
+        .iter()
+        .filter(|(name, _)| {
+            *name != header::CONTENT_TYPE && *name != API_TOKEN_HEADER && !is_hop_by_hop(name)
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    let invoked = api.invoke_service(&id, request).await?;
+    let status = StatusCode::from_u16(invoked.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = (status, invoked.data).into_response();
+    let response_headers = response.headers_mut();
+    for (name, value) in invoked.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value))
+            && !is_hop_by_hop(&name)
+        {
+            response_headers.append(name, value);
+        }
+    }
+    if let Ok(content_type) = HeaderValue::try_from(invoked.content_type)
+        && !content_type.is_empty()
+    {
+        response_headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    Ok(response)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetKeyResponse {
+    name: String,
+    public_key: rapr_contributes::crypto::Jwk,
+}
+
+async fn get_key(
+    State(api): State<Api>,
+    Path((component, name)): Path<(String, String)>,
+) -> Result<Json<GetKeyResponse>, ApiError> {
+    let public_key = api.get_key(&component, &name).await?;
+    Ok(Json(GetKeyResponse { name, public_key }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyRequest {
+    key_name: String,
+    algorithm: String,
+    #[serde(default, with = "base64_bytes")]
+    plaintext_key: Vec<u8>,
+    #[serde(default, with = "base64_bytes")]
+    wrapped_key: Vec<u8>,
+    #[serde(default, with = "base64_bytes")]
+    digest: Vec<u8>,
+    #[serde(default, with = "base64_bytes")]
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WrapKeyResponse {
+    #[serde(with = "base64_bytes")]
+    wrapped_key: Vec<u8>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnwrapKeyResponse {
+    #[serde(with = "base64_bytes")]
+    plaintext_key: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct SignResponse {
+    #[serde(with = "base64_bytes")]
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct VerifyResponse {
+    valid: bool,
+}
+
+async fn wrap_key(
+    State(api): State<Api>,
+    Path(component): Path<String>,
+    body: Bytes,
+) -> Result<Json<WrapKeyResponse>, ApiError> {
+    let request: KeyRequest = parse(&body)?;
+    let wrapped_key = api
+        .wrap_key(
+            &component,
+            &request.key_name,
+            &request.algorithm,
+            &request.plaintext_key,
+        )
+        .await?;
+    Ok(Json(WrapKeyResponse { wrapped_key }))
+}
+
+async fn unwrap_key(
+    State(api): State<Api>,
+    Path(component): Path<String>,
+    body: Bytes,
+) -> Result<Json<UnwrapKeyResponse>, ApiError> {
+    let request: KeyRequest = parse(&body)?;
+    let plaintext_key = api
+        .unwrap_key(
+            &component,
+            &request.key_name,
+            &request.algorithm,
+            &request.wrapped_key,
+        )
+        .await?;
+    Ok(Json(UnwrapKeyResponse { plaintext_key }))
+}
+
+async fn sign(
+    State(api): State<Api>,
+    Path(component): Path<String>,
+    body: Bytes,
+) -> Result<Json<SignResponse>, ApiError> {
+    let request: KeyRequest = parse(&body)?;
+    let signature = api
+        .sign(
+            &component,
+            &request.key_name,
+            &request.algorithm,
+            &request.digest,
+        )
+        .await?;
+    Ok(Json(SignResponse { signature }))
+}
+
+async fn verify(
+    State(api): State<Api>,
+    Path(component): Path<String>,
+    body: Bytes,
+) -> Result<Json<VerifyResponse>, ApiError> {
+    let request: KeyRequest = parse(&body)?;
+    let valid = api
+        .verify(
+            &component,
+            &request.key_name,
+            &request.algorithm,
+            &request.digest,
+            &request.signature,
+        )
+        .await?;
+    Ok(Json(VerifyResponse { valid }))
+}
+
+/// Encodes binary fields in base64.
+mod base64_bytes {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiRules, ApiToken};
+    use crate::crypto::Crypto;
+    use crate::health::Health;
+    use crate::invocation::{
+        CALLER_APP_ID_HEADER, DirectMessaging, InvocationHandler, InvokeResponse,
+    };
+    use crate::meta::{Meta, Options};
+    use axum::body::Body;
+    use axum::http::Request;
+    use rapr_common::RaprMode;
+    use rapr_contributes::crypto::LocalStorageKeyStore;
+    use rapr_contributes::nameresolution::{NameResolver, ResolveRequest};
+    use serde_json::{Value, json};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn meta() -> Meta {
+        Meta::new(Options {
+            id: "app".to_string(),
+            pod_name: String::new(),
+            namespace: "default".to_string(),
+            strict_sandbox: false,
+            mode: RaprMode::Standalone,
+        })
+    }
+
+    async fn call(api: &Api, request: Request<Body>) -> (StatusCode, HeaderMap, Bytes) {
+        let response = router(api.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, body)
+    }
+
+    async fn call_json(api: &Api, method: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let (status, _, body) = call(api, request).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    struct Unreachable;
+
+    #[async_trait::async_trait]
+    impl NameResolver for Unreachable {
+        async fn resolve(
+            &self,
+            _request: &ResolveRequest,
+        ) -> rapr_contributes::nameresolution::Result<String> {
+            Ok("127.0.0.1:1".to_string())
+        }
+    }
+
+    /// Echoes the invocation in the response.
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl InvocationHandler for Echo {
+        async fn on_invoke(
+            &self,
+            request: InvokeRequest,
+        ) -> crate::invocation::Result<InvokeResponse> {
+            let mut response = InvokeResponse::ok(request.content_type, request.data);
+            response.status = 201;
+            response.headers = request.headers;
+            response.headers.push((
+                "x-invoked".to_string(),
+                format!("{} /{}?{}", request.verb, request.method, request.query),
+            ));
+            Ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metadata() {
+        let api = Api::new(meta());
+        let (status, body) = call_json(&api, "GET", "/v1.0/metadata", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], "app");
+        assert_eq!(body["mode"], "standalone");
+        assert_eq!(body["runtimeVersion"], crate::api::RUNTIME_VERSION);
+        assert_eq!(body["components"], json!([]));
+
+        let base = crate::meta::MetaBase::new(
+            "store".to_string(),
+            [
+                ("host".to_string(), "localhost".to_string()),
+                ("password".to_string(), "hunter2".to_string()),
+            ]
+            .into(),
+        );
+        api.add_component(crate::api::ComponentMetadata::new(
+            &base,
+            "state.redis",
+            "v1",
+            vec!["ETAG".to_string(), "TRANSACTIONAL".to_string()],
+        ));
+        let api = api.with_actor_counter(Arc::new(|| {
+            vec![crate::api::ActiveActors {
+                actor_type: "cart".to_string(),
+                count: 3,
+            }]
+        }));
+        let request = Request::put("/v1.0/metadata/team")
+            .body(Body::from("payments"))
+            .unwrap();
+        assert_eq!(call(&api, request).await.0, StatusCode::NO_CONTENT);
+
+        let (_, body) = call_json(&api, "GET", "/v1.0/metadata", Value::Null).await;
+        assert_eq!(
+            body["components"],
+            json!([{
+                "name": "store",
+                "type": "state.redis",
+                "version": "v1",
+                "capabilities": ["ETAG", "TRANSACTIONAL"],
+                "metadata": {"host": "localhost", "password": "<redacted>"}
+            }])
+        );
+        assert_eq!(body["actors"], json!([{"type": "cart", "count": 3}]));
+        assert_eq!(body["extended"], json!({"team": "payments"}));
+    }
+
+    #[tokio::test]
+    async fn test_invoke_service() {
+        let api = Api::new(meta());
+        let (status, body) =
+            call_json(&api, "GET", "/v1.0/invoke/app/method/ping", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(body["errorCode"], "ERR_NOT_CONFIGURED");
+
+        let messaging = DirectMessaging::new(&meta(), Arc::new(Unreachable), 0)
+            .with_local_handler(Arc::new(Echo));
+        let api = api.with_direct_messaging(Arc::new(messaging));
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/v1.0/invoke/app/method/orders/1?dry=true")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .header("x-tenant", "a")
+            .header("keep-alive", "timeout=5")
+            .header("proxy-connection", "keep-alive")
+            .body(Body::from("hello"))
+            .unwrap();
+        let (status, headers, body) = call(&api, request).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body, "hello");
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(headers["x-tenant"], "a");
+        assert!(!headers.contains_key("keep-alive"));
+        assert!(!headers.contains_key("proxy-connection"));
+        assert_eq!(headers["x-invoked"], "PUT /orders/1?dry=true");
+        assert_eq!(headers[CALLER_APP_ID_HEADER], "app");
+
+        let (status, body) =
+            call_json(&api, "GET", "/v1.0/invoke/app./method/ping", json!({})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errorCode"], "ERR_DIRECT_INVOKE");
+    }
+
+    #[tokio::test]
+    async fn test_api_token() {
+        let messaging = DirectMessaging::new(&meta(), Arc::new(Unreachable), 0)
+            .with_local_handler(Arc::new(Echo));
+        let api = Api::new(meta())
+            .with_direct_messaging(Arc::new(messaging))
+            .with_api_token(ApiToken::new("s3cret"));
+        let (status, body) = call_json(&api, "GET", "/v1.0/metadata", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["errorCode"], "ERR_UNAUTHENTICATED");
+
+        let request = |token: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/v1.0/invoke/app/method/ping")
+                .header(API_TOKEN_HEADER, token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _, _) = call(&api, request("wrong")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // The token of the caller is not forwarded to the invoked app.
+        let (status, headers, _) = call(&api, request("s3cret")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(!headers.contains_key(API_TOKEN_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_api_rules() {
+        let spec = serde_json::from_value(json!({"allowed": [{"name": "metadata"}]})).unwrap();
+        let api = Api::new(meta()).with_api_rules(ApiRules::from_spec(&spec).unwrap());
+        let (status, _) = call_json(&api, "GET", "/v1.0/metadata", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) =
+            call_json(&api, "POST", "/v1.0/invoke/app/method/ping", json!({})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["errorCode"], "ERR_API_DENIED");
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let health = Health::new();
+        let api = Api::new(meta())
+            .with_api_token(ApiToken::new("s3cret"))
+            .with_health(health.clone());
+        let (status, body) = call_json(&api, "GET", "/v1.0/healthz", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({"healthy": true, "components": []}));
+
+        health.require_app_channel();
+        let (status, body) = call_json(&api, "GET", "/v1.0/healthz", Value::Null).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["appChannelReady"], false);
+        // The app may call the building blocks before it is ready.
+        let (status, _) = call_json(&api, "GET", "/v1.0/healthz/outbound", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call_json(&api, "GET", "/healthz/outbound", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call_json(&api, "GET", "/healthz", Value::Null).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+        health.set_app_channel_ready(true);
+        let (status, _) = call_json(&api, "GET", "/v1.0/healthz", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = call_json(&api, "GET", "/healthz", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_tracing() {
+        use crate::diagnostics::proto::common::v1::any_value::Value as AttributeValue;
+        use crate::diagnostics::{Collector, TRACEPARENT_HEADER, TraceContext, attribute};
+
+        let collector = Collector::default();
+        let tracer = collector.tracer().await;
+        let messaging = DirectMessaging::new(&meta(), Arc::new(Unreachable), 0)
+            .with_local_handler(Arc::new(Echo))
+            .with_tracer(tracer.clone());
+        let api = Api::new(meta())
+            .with_direct_messaging(Arc::new(messaging))
+            .with_tracer(tracer.clone());
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let request = Request::builder()
+            .method("PUT")
+            .uri("/v1.0/invoke/app/method/orders/1")
+            .header(TRACEPARENT_HEADER, parent)
+            .body(Body::empty())
+            .unwrap();
+        let (status, headers, _) = call(&api, request).await;
+        assert_eq!(status, StatusCode::CREATED);
+        tracer.flush().await;
+
+        let server = collector.span("PUT /v1.0/invoke/{id}/method/{*method}");
+        let parent = TraceContext::parse(parent, None).unwrap();
+        assert_eq!(server.trace_id, parent.trace_id);
+        assert_eq!(server.parent_span_id, parent.span_id);
+        assert_eq!(
+            attribute(&server, "http.response.status_code"),
+            Some(AttributeValue::IntValue(201))
+        );
+        let client = collector.span("CallLocal/app");
+        assert_eq!(client.parent_span_id, server.span_id);
+        // The app receives the context of the span of the invocation.
+        let received = TraceContext::from_headers(&headers).unwrap();
+        assert_eq!(received.trace_id, parent.trace_id);
+        assert_eq!(received.span_id.as_slice(), client.span_id);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        use crate::diagnostics::Metrics;
+
+        let metrics = Metrics::new();
+        let messaging = DirectMessaging::new(&meta(), Arc::new(Unreachable), 0)
+            .with_local_handler(Arc::new(Echo))
+            .with_metrics(metrics.clone());
+        let api = Api::new(meta())
+            .with_direct_messaging(Arc::new(messaging))
+            .with_metrics(metrics.clone());
+        let (status, _) = call_json(&api, "GET", "/v1.0/metadata", Value::Null).await;
+        assert_eq!(status, StatusCode::OK);
+        let guarded = api.clone().with_api_token(ApiToken::new("s3cret"));
+        let (status, _) = call_json(&guarded, "GET", "/v1.0/metadata", Value::Null).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) =
+            call_json(&api, "PUT", "/v1.0/invoke/app/method/orders/1", json!({})).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "rapr_api_requests_total{protocol=\"http\",building_block=\"metadata\",operation=\"GET /v1.0/metadata\",status=\"200\"} 1\n"
+        ));
+        assert!(text.contains(
+            "rapr_api_requests_total{protocol=\"http\",building_block=\"metadata\",operation=\"GET /v1.0/metadata\",status=\"401\"} 1\n"
+        ));
+        assert!(text.contains(
+            "rapr_api_requests_total{protocol=\"http\",building_block=\"invoke\",operation=\"PUT /v1.0/invoke/{id}/method/{*method}\",status=\"201\"} 1\n"
+        ));
+        assert!(text.contains(
+            "rapr_service_invocation_requests_total{side=\"client\",app_id=\"app\",method=\"orders/1\",status=\"201\"} 1\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_crypto() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("aes"), [7u8; 32]).unwrap();
+        let mut crypto = Crypto::new();
+        crypto.add_component("keys", Arc::new(LocalStorageKeyStore::new(dir.path())));
+        let api = Api::new(meta()).with_crypto(Arc::new(crypto));
+
+        let (status, body) = call_json(
+            &api,
+            "POST",
+            "/v1.0/crypto/keys/wrapkey",
+            json!({"keyName": "aes", "algorithm": "A256KW", "plaintextKey": "AAECAwQFBgcICQoLDA0ODw=="}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = call_json(
+            &api,
+            "POST",
+            "/v1.0/crypto/keys/unwrapkey",
+            json!({"keyName": "aes", "algorithm": "A256KW", "wrappedKey": body["wrappedKey"]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["plaintextKey"], "AAECAwQFBgcICQoLDA0ODw==");
+
+        let (status, body) =
+            call_json(&api, "GET", "/v1.0/crypto/keys/keys/missing", Value::Null).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["errorCode"], "ERR_CRYPTO_KEY_NOT_FOUND");
+        let (status, body) =
+            call_json(&api, "GET", "/v1.0/crypto/other/keys/aes", Value::Null).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errorCode"], "ERR_CRYPTO_PROVIDER_NOT_FOUND");
+        let (status, body) = call_json(
+            &api,
+            "POST",
+            "/v1.0/crypto/keys/sign",
+            json!({"keyName": "aes", "algorithm": "RS256", "digest": "not base64!"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errorCode"], "ERR_MALFORMED_REQUEST");
+    }
+}
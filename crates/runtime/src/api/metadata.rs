@@ -0,0 +1,198 @@
+use crate::meta::MetaBase;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// Value replacing the sensitive properties of components.
+pub const REDACTED: &str = "<redacted>";
+
+/// Substrings of the names of the properties reported as [`REDACTED`], compared
+/// case-insensitively.
+const SENSITIVE_PROPERTIES: &[&str] = &[
+    "password",
+    "secret",
+    "token",
+    "key",
+    "credential",
+    "connectionstring",
+    "auth",
+];
+
+/// Metadata describes the sidecar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    pub id: String,
+    pub namespace: String,
+    /// `kubernetes` or `standalone`.
+    pub mode: String,
+    pub runtime_version: String,
+    pub components: Vec<ComponentMetadata>,
+    pub subscriptions: Vec<Subscription>,
+    pub actors: Vec<ActiveActors>,
+    /// Attributes set by the app.
+    pub extended: BTreeMap<String, String>,
+}
+
+/// ComponentMetadata describes a loaded component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentMetadata {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub component_type: String,
+    pub version: String,
+    /// Features of the component, such as `ETAG`, `TRANSACTIONAL` or `QUERY_API`.
+    pub capabilities: Vec<String>,
+    /// Properties of the component, sensitive values redacted.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl ComponentMetadata {
+    /// Describes a component from its metadata. Sensitive properties are redacted here,
+    /// so that their values are never held by the metadata API.
+    pub fn new(
+        base: &MetaBase,
+        component_type: impl Into<String>,
+        version: impl Into<String>,
+        capabilities: Vec<String>,
+    ) -> Self {
+        let metadata = base
+            .properties
+            .iter()
+            .map(|(name, value)| {
+                let value = if is_sensitive(name) {
+                    REDACTED.to_string()
+                } else {
+                    value.clone()
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        Self {
+            name: base.name.clone(),
+            component_type: component_type.into(),
+            version: version.into(),
+            capabilities,
+            metadata,
+        }
+    }
+}
+
+fn is_sensitive(property: &str) -> bool {
+    let property = property.to_ascii_lowercase();
+    SENSITIVE_PROPERTIES
+        .iter()
+        .any(|pattern| property.contains(pattern))
+}
+
+/// Subscription is a topic the app subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub pubsub_name: String,
+    pub topic: String,
+    /// Path of the app receiving the messages.
+    pub route: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub dead_letter_topic: String,
+}
+
+/// ActiveActors counts the active actors of a type.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ActiveActors {
+    #[serde(rename = "type")]
+    pub actor_type: String,
+    pub count: u64,
+}
+
+/// Reports the number of active actors of each type.
+pub type ActorCounter = Arc<dyn Fn() -> Vec<ActiveActors> + Send + Sync>;
+
+/// MetadataStore holds what the sidecar loaded, as reported by the metadata API.
+#[derive(Default)]
+pub(crate) struct MetadataStore {
+    components: RwLock<Vec<ComponentMetadata>>,
+    subscriptions: RwLock<Vec<Subscription>>,
+    actors: RwLock<Option<ActorCounter>>,
+    extended: RwLock<BTreeMap<String, String>>,
+}
+
+impl MetadataStore {
+    pub(crate) fn add_component(&self, component: ComponentMetadata) {
+        let mut components = self.components.write().unwrap_or_else(|e| e.into_inner());
+        components.retain(|c| c.name != component.name);
+        components.push(component);
+        components.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    pub(crate) fn add_subscription(&self, subscription: Subscription) {
+        let mut subscriptions = self
+            .subscriptions
+            .write()
+            .unwrap_or_else(|e| e.into_inner());
+        subscriptions.push(subscription);
+    }
+
+    pub(crate) fn set_actor_counter(&self, counter: ActorCounter) {
+        *self.actors.write().unwrap_or_else(|e| e.into_inner()) = Some(counter);
+    }
+
+    pub(crate) fn set_extended(&self, key: String, value: String) {
+        let mut extended = self.extended.write().unwrap_or_else(|e| e.into_inner());
+        extended.insert(key, value);
+    }
+
+    /// Fills the lists of a snapshot of the metadata.
+    pub(crate) fn fill(&self, metadata: &mut Metadata) {
+        metadata.components = self
+            .components
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        metadata.subscriptions = self
+            .subscriptions
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let counter = self
+            .actors
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        metadata.actors = counter.map(|count| count()).unwrap_or_default();
+        metadata.extended = self
+            .extended
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_redact_sensitive_properties() {
+        let properties = HashMap::from([
+            ("redisHost".to_string(), "localhost:6379".to_string()),
+            ("redisPassword".to_string(), "hunter2".to_string()),
+            ("accountKey".to_string(), "abc".to_string()),
+            ("ConnectionString".to_string(), "Server=db".to_string()),
+            ("apiToken".to_string(), "t".to_string()),
+        ]);
+        let base = MetaBase::new("store".to_string(), properties);
+        let component = ComponentMetadata::new(&base, "state.redis", "v1", vec![]);
+        assert_eq!(component.metadata["redisHost"], "localhost:6379");
+        for name in [
+            "redisPassword",
+            "accountKey",
+            "ConnectionString",
+            "apiToken",
+        ] {
+            assert_eq!(component.metadata[name], REDACTED, "{name}");
+        }
+    }
+}
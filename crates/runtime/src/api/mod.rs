@@ -0,0 +1,390 @@
+//! Building-block API the sidecar exposes to its app.
+//!
+//! [`Api`] gathers the building blocks of the sidecar and implements the calls of the API.
+//! The [`grpc`] and [`http`] modules expose the same calls over gRPC and as REST endpoints
+//! under `/v1.0`, each served on a TCP port and on a Unix domain socket at the same time.
+//! Failures are [`ApiError`]s, so both protocols report them with the same codes.
+//!
+//! With an [`ApiToken`], calls must carry it in the [`API_TOKEN_HEADER`] header or gRPC
+//! metadata, except for health probes: otherwise any process of the node could use the
+//! building blocks of the app. [`ApiRules`] further restrict the APIs the app may call.
+//!
+//! With a [`Tracer`], each call gets a span, child of the trace context it carries, whose
+//! context is propagated to the apps it invokes. With [`Metrics`], each call is counted
+//! and timed by API, operation and status. Health probes report the [`Health`] of the
+//! sidecar.
+
+mod error;
+pub mod grpc;
+pub mod http;
+mod metadata;
+mod rules;
+mod token;
+
+pub use error::*;
+pub use metadata::*;
+pub use rules::*;
+pub use token::*;
+
+use crate::crypto::Crypto;
+use crate::diagnostics::{Metrics, Tracer};
+use crate::health::Health;
+use crate::invocation::{DirectMessaging, InvokeRequest, InvokeResponse};
+use crate::meta::Meta;
+use crate::workflow::WorkflowEngine;
+use axum::http::Request;
+use rapr_common::RaprMode;
+use rapr_contributes::crypto::Jwk;
+use snafu::{ResultExt, Snafu};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub type Result<T> = std::result::Result<T, ApiError>;
+
+/// Version of the runtime, reported by the metadata API.
+pub const RUNTIME_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Paths probing the health of the sidecar, which orchestrators call without token and
+/// which no rule denies.
+const HEALTH_PATHS: &[&str] = &["/healthz", "/v1.0/healthz", "/grpc.health.v1.Health"];
+
+/// Returns true if a path probes the health of the sidecar.
+pub(crate) fn is_health_probe(path: &str) -> bool {
+    HEALTH_PATHS.iter().any(|health| {
+        path.strip_prefix(health)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Api holds the building blocks served to the app. Building blocks which are not set are
+/// reported as not configured.
+#[derive(Clone)]
+pub struct Api {
+    meta: Meta,
+    messaging: Option<Arc<DirectMessaging>>,
+    crypto: Arc<Crypto>,
+    workflows: Option<WorkflowEngine>,
+    store: Arc<MetadataStore>,
+    token: Option<ApiToken>,
+    rules: Option<Arc<ApiRules>>,
+    tracer: Option<Tracer>,
+    metrics: Option<Metrics>,
+    health: Health,
+}
+
+impl Api {
+    /// Creates an API without building blocks for the app of `meta`.
+    pub fn new(meta: Meta) -> Self {
+        Self {
+            meta,
+            messaging: None,
+            crypto: Arc::new(Crypto::new()),
+            workflows: None,
+            store: Arc::default(),
+            token: None,
+            rules: None,
+            tracer: None,
+            metrics: None,
+            health: Health::new(),
+        }
+    }
+
+    /// Serves service invocation through the client.
+    pub fn with_direct_messaging(mut self, messaging: Arc<DirectMessaging>) -> Self {
+        self.messaging = Some(messaging);
+        self
+    }
+
+    /// Serves the cryptography building block with the key stores of `crypto`.
+    pub fn with_crypto(mut self, crypto: Arc<Crypto>) -> Self {
+        self.crypto = crypto;
+        self
+    }
+
+    /// Serves the management of the instances of the workflow engine.
+    pub fn with_workflow_engine(mut self, engine: WorkflowEngine) -> Self {
+        self.workflows = Some(engine);
+        self
+    }
+
+    /// Requires calls to present the token.
+    pub fn with_api_token(mut self, token: ApiToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Restricts the APIs the app may call.
+    pub fn with_api_rules(mut self, rules: ApiRules) -> Self {
+        self.rules = Some(Arc::new(rules));
+        self
+    }
+
+    /// Traces the calls to the API.
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Records the metrics of the calls to the API.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Reports the health tracked by `health` to the health probes.
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Reports the active actors counted by `counter` in the metadata.
+    pub fn with_actor_counter(self, counter: ActorCounter) -> Self {
+        self.store.set_actor_counter(counter);
+        self
+    }
+
+    /// Reports a loaded component in the metadata. Components are listed by name: adding a
+    /// component again replaces it.
+    pub fn add_component(&self, component: ComponentMetadata) {
+        self.store.add_component(component);
+    }
+
+    /// Reports a subscription of the app in the metadata.
+    pub fn add_subscription(&self, subscription: Subscription) {
+        self.store.add_subscription(subscription);
+    }
+
+    /// Returns the metadata of the app.
+    pub fn meta(&self) -> &Meta {
+        &self.meta
+    }
+
+    /// Returns the tracer of the calls to the API, if they are traced.
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    /// Returns the metrics of the calls to the API, if they are recorded.
+    pub fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    /// Returns the health of the sidecar.
+    pub fn health(&self) -> &Health {
+        &self.health
+    }
+
+    /// Returns true if calls are checked before they reach the API, for their token or
+    /// against rules.
+    pub fn is_guarded(&self) -> bool {
+        self.token.is_some() || self.rules.is_some()
+    }
+
+    /// Checks that a call may reach the API: it presents the token, if any, and the app
+    /// may call its API.
+    pub(crate) fn guard<B>(&self, protocol: ApiProtocol, request: &Request<B>) -> Result<()> {
+        if let Some(token) = &self.token {
+            authenticate(token, request)?;
+        }
+        if let Some(rules) = &self.rules {
+            rules.check(protocol, request.uri().path())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the workflow engine, if any.
+    pub fn workflow_engine(&self) -> Option<&WorkflowEngine> {
+        self.workflows.as_ref()
+    }
+
+    /// Describes the sidecar.
+    pub fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata {
+            id: self.meta.id.clone(),
+            namespace: self.meta.namespace.clone(),
+            mode: match self.meta.mode {
+                RaprMode::Kubernetes => "kubernetes",
+                RaprMode::Standalone => "standalone",
+            }
+            .to_string(),
+            runtime_version: RUNTIME_VERSION.to_string(),
+            components: Vec::new(),
+            subscriptions: Vec::new(),
+            actors: Vec::new(),
+            extended: Default::default(),
+        };
+        self.store.fill(&mut metadata);
+        metadata
+    }
+
+    /// Sets an extended attribute of the metadata, on behalf of the app.
+    pub fn set_metadata(&self, key: &str, value: impl Into<String>) -> Result<()> {
+        if key.is_empty() {
+            return Err(ApiError::malformed("Missing metadata key"));
+        }
+        self.store.set_extended(key.to_string(), value.into());
+        Ok(())
+    }
+
+    /// Invokes a method of another app, given as `<app-id>` or `<app-id>.<namespace>`.
+    pub async fn invoke_service(
+        &self,
+        target: &str,
+        request: InvokeRequest,
+    ) -> Result<InvokeResponse> {
+        let messaging = self
+            .messaging
+            .as_ref()
+            .ok_or_else(|| ApiError::not_configured("Service invocation"))?;
+        Ok(messaging.invoke(target, request).await?)
+    }
+
+    /// Returns the public part of a key of a crypto component.
+    pub async fn get_key(&self, component: &str, key_name: &str) -> Result<Jwk> {
+        Ok(self.crypto.get_key(component, key_name).await?)
+    }
+
+    /// Wraps a key with a key of a crypto component.
+    pub async fn wrap_key(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        plaintext_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        let wrapped = self
+            .crypto
+            .wrap_key(component, key_name, algorithm, plaintext_key);
+        Ok(wrapped.await?)
+    }
+
+    /// Unwraps a key with a key of a crypto component.
+    pub async fn unwrap_key(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        wrapped_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        let plaintext = self
+            .crypto
+            .unwrap_key(component, key_name, algorithm, wrapped_key);
+        Ok(plaintext.await?)
+    }
+
+    /// Signs a digest with a key of a crypto component.
+    pub async fn sign(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        digest: &[u8],
+    ) -> Result<Vec<u8>> {
+        Ok(self
+            .crypto
+            .sign(component, key_name, algorithm, digest)
+            .await?)
+    }
+
+    /// Verifies the signature of a digest with a key of a crypto component.
+    pub async fn verify(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<bool> {
+        let valid = self
+            .crypto
+            .verify(component, key_name, algorithm, digest, signature);
+        Ok(valid.await?)
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum ServerError {
+    #[snafu(display("Failed to listen on {}: {}", address, source))]
+    Bind {
+        address: SocketAddr,
+        source: io::Error,
+    },
+
+    #[snafu(display("Failed to listen on Unix socket {}: {}", path.display(), source))]
+    BindUnixSocket { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Cannot listen on {}: the file exists and is not a socket", path.display()))]
+    PathInUse { path: PathBuf },
+
+    #[snafu(display("gRPC server failed: {}", source))]
+    Grpc { source: tonic::transport::Error },
+
+    #[snafu(display("HTTP server failed: {}", source))]
+    Http { source: io::Error },
+}
+
+/// ServerOptions sets where an API server listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerOptions {
+    /// TCP address. Port 0 picks a free port.
+    pub address: SocketAddr,
+    /// Path of the Unix domain socket also served, if any. Only supported on Unix.
+    pub unix_socket: Option<PathBuf>,
+}
+
+impl ServerOptions {
+    /// Listens on a port of the loopback interface only.
+    pub fn localhost(port: u16) -> Self {
+        Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            unix_socket: None,
+        }
+    }
+}
+
+/// Returns the path of the Unix socket serving an API of an app in `dir`, such as
+/// `rapr-<app-id>-grpc.socket`.
+pub fn socket_path(dir: &Path, app_id: &str, protocol: &str) -> PathBuf {
+    dir.join(format!("rapr-{app_id}-{protocol}.socket"))
+}
+
+/// Binds a Unix socket. A socket left at the path by a previous run is removed; any other
+/// file is left alone.
+#[cfg(unix)]
+pub(crate) fn bind_unix_socket(
+    path: &Path,
+) -> std::result::Result<tokio::net::UnixListener, ServerError> {
+    if rapr_common::utils::socket_exists(path) {
+        tracing::debug!(path = %path.display(), "Removing stale Unix socket");
+        std::fs::remove_file(path).context(BindUnixSocketSnafu { path })?;
+    } else if path.exists() {
+        return Err(ServerError::PathInUse {
+            path: path.to_path_buf(),
+        });
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context(BindUnixSocketSnafu { path })?;
+    }
+    tokio::net::UnixListener::bind(path).context(BindUnixSocketSnafu { path })
+}
+
+/// Removes the Unix socket of a server which stopped.
+#[cfg(unix)]
+pub(crate) fn remove_unix_socket(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        tracing::warn!(path = %path.display(), error = %e, "Failed to remove Unix socket");
+    }
+}
+
+/// Binds the TCP listener of a server.
+pub(crate) async fn bind_tcp(
+    options: &ServerOptions,
+) -> std::result::Result<tokio::net::TcpListener, ServerError> {
+    tokio::net::TcpListener::bind(options.address)
+        .await
+        .context(BindSnafu {
+            address: options.address,
+        })
+}
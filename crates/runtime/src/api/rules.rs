@@ -0,0 +1,255 @@
+use super::{ApiError, is_health_probe};
+use rapr_apis::configuration::v1alpha1::{ApiRule, ApiSpec};
+use snafu::Snafu;
+use std::fmt;
+use tonic::Code;
+
+/// Error code of calls to APIs the app may not call.
+pub const ERR_API_DENIED: &str = "ERR_API_DENIED";
+
+#[derive(Debug, Snafu)]
+pub enum ApiRuleError {
+    #[snafu(display("Invalid {} API rule {}: {}", list, index, reason))]
+    InvalidRule {
+        list: &'static str,
+        index: usize,
+        reason: String,
+    },
+}
+
+/// Protocol of a call to the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiProtocol {
+    Grpc,
+    Http,
+}
+
+impl fmt::Display for ApiProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Grpc => "gRPC",
+            Self::Http => "HTTP",
+        })
+    }
+}
+
+/// ApiRules restricts the APIs the app may call, by name, version and protocol. Versions are
+/// compared without their minor `.0`: `v1` matches both the `/v1.0` HTTP endpoints and the
+/// `v1` gRPC services.
+#[derive(Debug, Clone, Default)]
+pub struct ApiRules {
+    allowed: Vec<Rule>,
+    denied: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    /// Any version when empty.
+    version: String,
+    /// Both protocols when `None`.
+    protocol: Option<ApiProtocol>,
+}
+
+impl Rule {
+    fn from_spec(list: &'static str, index: usize, rule: &ApiRule) -> Result<Self, ApiRuleError> {
+        let invalid = |reason: String| ApiRuleError::InvalidRule {
+            list,
+            index,
+            reason,
+        };
+        if rule.name.is_empty() {
+            return Err(invalid("the name is required".to_string()));
+        }
+        let protocol = match rule.protocol.to_ascii_lowercase().as_str() {
+            "" => None,
+            "grpc" => Some(ApiProtocol::Grpc),
+            "http" => Some(ApiProtocol::Http),
+            _ => {
+                return Err(invalid(format!(
+                    "unknown protocol {:?}: expected grpc or http",
+                    rule.protocol
+                )));
+            }
+        };
+        Ok(Self {
+            name: rule.name.to_ascii_lowercase(),
+            version: normalize_version(&rule.version),
+            protocol,
+        })
+    }
+
+    fn matches(&self, protocol: ApiProtocol, name: &str, version: &str) -> bool {
+        self.name == name
+            && (self.version.is_empty() || self.version == version)
+            && self.protocol.is_none_or(|p| p == protocol)
+    }
+}
+
+impl ApiRules {
+    /// Validates the rules of a configuration.
+    pub fn from_spec(spec: &ApiSpec) -> Result<Self, ApiRuleError> {
+        let rules = |list, rules: &[ApiRule]| {
+            rules
+                .iter()
+                .enumerate()
+                .map(|(index, rule)| Rule::from_spec(list, index, rule))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            allowed: rules("allowed", &spec.allowed)?,
+            denied: rules("denied", &spec.denied)?,
+        })
+    }
+
+    /// Returns true if the app may call a version of an API over a protocol.
+    pub fn is_allowed(&self, protocol: ApiProtocol, name: &str, version: &str) -> bool {
+        let version = normalize_version(version);
+        let matches = |rule: &Rule| rule.matches(protocol, name, &version);
+        if self.denied.iter().any(matches) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(matches)
+    }
+
+    /// Checks that the app may call the API of a path. Health probes and paths of no known
+    /// API are always allowed.
+    pub(crate) fn check(&self, protocol: ApiProtocol, path: &str) -> Result<(), ApiError> {
+        if is_health_probe(path) {
+            return Ok(());
+        }
+        match api_of(protocol, path) {
+            Some((name, version)) if !self.is_allowed(protocol, name, version) => {
+                Err(ApiError::new(
+                    Code::PermissionDenied,
+                    ERR_API_DENIED,
+                    format!("The {name} API {version} is not allowed over {protocol}"),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the name and version of the API of a path, if it belongs to a known API.
+pub(crate) fn api_of(protocol: ApiProtocol, path: &str) -> Option<(&str, &str)> {
+    match protocol {
+        ApiProtocol::Http => http_api(path),
+        ApiProtocol::Grpc => grpc_api(path),
+    }
+}
+
+/// Returns the name and version of the API of an HTTP path, such as `invoke` and `v1.0`
+/// for `/v1.0/invoke/orders/method/new`.
+fn http_api(path: &str) -> Option<(&str, &str)> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let version = segments.next()?;
+    let name = segments.next()?;
+    Some((name, version))
+}
+
+/// Returns the name and version of the API of a gRPC path, such as `invoke` and `v1` for
+/// `/rapr.runtime.v1.Rapr/InvokeService`.
+fn grpc_api(path: &str) -> Option<(&'static str, &str)> {
+    let (service, method) = path.trim_start_matches('/').split_once('/')?;
+    let (package, service) = service.rsplit_once('.')?;
+    let (_, version) = package.rsplit_once('.')?;
+    let name = match (service, method) {
+        ("Rapr", "GetMetadata" | "SetMetadata") => "metadata",
+        ("Rapr", "InvokeService") => "invoke",
+        ("Rapr", method) if method.starts_with("Subtle") => "crypto",
+        ("Workflows", _) => "workflows",
+        _ => return None,
+    };
+    Some((name, version))
+}
+
+fn normalize_version(version: &str) -> String {
+    version.to_ascii_lowercase().replacen(".0", "", 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, version: &str, protocol: &str) -> ApiRule {
+        ApiRule {
+            name: name.to_string(),
+            version: version.to_string(),
+            protocol: protocol.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let rules = ApiRules::from_spec(&ApiSpec {
+            allowed: vec![rule("invoke", "v1", ""), rule("crypto", "v1.0", "")],
+            denied: vec![rule("crypto", "", "grpc")],
+        })
+        .unwrap();
+        let allowed = |protocol, path| rules.check(protocol, path).is_ok();
+
+        assert!(allowed(ApiProtocol::Http, "/v1.0/invoke/orders/method/new"));
+        assert!(allowed(
+            ApiProtocol::Grpc,
+            "/rapr.runtime.v1.Rapr/InvokeService"
+        ));
+        assert!(allowed(ApiProtocol::Http, "/v1.0/crypto/vault/sign"));
+        assert!(!allowed(
+            ApiProtocol::Grpc,
+            "/rapr.runtime.v1.Rapr/SubtleSign"
+        ));
+        assert!(!allowed(ApiProtocol::Http, "/v1.0/metadata"));
+        assert!(!allowed(
+            ApiProtocol::Grpc,
+            "/rapr.workflow.v1.Workflows/StartWorkflow"
+        ));
+        assert!(!allowed(
+            ApiProtocol::Http,
+            "/v2.0/invoke/orders/method/new"
+        ));
+        // Health probes are always allowed.
+        assert!(allowed(ApiProtocol::Http, "/v1.0/healthz"));
+        assert!(allowed(ApiProtocol::Http, "/healthz"));
+
+        let error = rules
+            .check(ApiProtocol::Http, "/v1.0/metadata")
+            .unwrap_err();
+        assert_eq!(error.code, Code::PermissionDenied);
+        assert_eq!(error.error_code, ERR_API_DENIED);
+        assert_eq!(
+            error.message,
+            "The metadata API v1.0 is not allowed over HTTP"
+        );
+
+        // Without allowed APIs, all those not denied are.
+        let rules = ApiRules::from_spec(&ApiSpec {
+            allowed: Vec::new(),
+            denied: vec![rule("invoke", "v1", "http")],
+        })
+        .unwrap();
+        assert!(!rules.is_allowed(ApiProtocol::Http, "invoke", "v1.0"));
+        assert!(rules.is_allowed(ApiProtocol::Grpc, "invoke", "v1"));
+        assert!(rules.is_allowed(ApiProtocol::Http, "metadata", "v1.0"));
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let error = ApiRules::from_spec(&ApiSpec {
+            allowed: vec![rule("invoke", "v1", ""), rule("state", "v1", "websocket")],
+            denied: Vec::new(),
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid allowed API rule 1: unknown protocol \"websocket\": expected grpc or http"
+        );
+        assert!(
+            ApiRules::from_spec(&ApiSpec {
+                allowed: Vec::new(),
+                denied: vec![rule("", "v1", "")],
+            })
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use super::{ApiError, is_health_probe};
+use axum::http::Request;
+use std::fmt;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+/// Header carrying the API token, on calls to the API and on calls of the sidecar to its
+/// app.
+pub const API_TOKEN_HEADER: &str = "rapr-api-token";
+
+/// ApiToken is a secret shared by the sidecar and its app. The token is compared in
+/// constant time and hidden from debug output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ApiToken(Arc<str>);
+
+impl ApiToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into().into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Checks a presented token.
+    pub fn matches(&self, presented: &[u8]) -> bool {
+        self.0.as_bytes().ct_eq(presented).into()
+    }
+}
+
+impl fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiToken(<redacted>)")
+    }
+}
+
+/// Checks that a call to the API presents the token, unless it probes the health of the
+/// sidecar.
+pub(crate) fn authenticate<B>(token: &ApiToken, request: &Request<B>) -> Result<(), ApiError> {
+    if is_health_probe(request.uri().path()) {
+        return Ok(());
+    }
+    match request.headers().get(API_TOKEN_HEADER) {
+        Some(presented) if token.matches(presented.as_bytes()) => Ok(()),
+        Some(_) => Err(ApiError::unauthenticated("Invalid API token")),
+        None => Err(ApiError::unauthenticated(format!(
+            "Missing API token: set the {API_TOKEN_HEADER} header"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn request(path: &str, token: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri(path);
+        if let Some(token) = token {
+            builder = builder.header(API_TOKEN_HEADER, token);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let token = ApiToken::new("s3cret");
+        assert!(authenticate(&token, &request("/v1.0/metadata", Some("s3cret"))).is_ok());
+
+        let error = authenticate(&token, &request("/v1.0/metadata", Some("s3cre"))).unwrap_err();
+        assert_eq!(error.code, Code::Unauthenticated);
+        assert_eq!(error.message, "Invalid API token");
+        assert!(authenticate(&token, &request("/v1.0/metadata", None)).is_err());
+
+        // Health probes need no token, but only of the health endpoints.
+        assert!(authenticate(&token, &request("/v1.0/healthz", None)).is_ok());
+        assert!(authenticate(&token, &request("/v1.0/healthz/outbound", None)).is_ok());
+        assert!(authenticate(&token, &request("/healthz/outbound", None)).is_ok());
+        assert!(authenticate(&token, &request("/grpc.health.v1.Health/Check", None)).is_ok());
+        assert!(authenticate(&token, &request("/v1.0/healthzz", None)).is_err());
+
+        assert_eq!(format!("{token:?}"), "ApiToken(<redacted>)");
+    }
+}
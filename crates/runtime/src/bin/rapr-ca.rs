@@ -0,0 +1,151 @@
+//! Certificate authority issuing the certificates of the sidecars of a trust domain.
+
+#![allow(missing_docs)]
+
+use clap::{Parser, Subcommand};
+use rapr_runtime::ca::{
+    CaService, CertificateAuthority, DEFAULT_CA_PORT, DEFAULT_WORKLOAD_TTL, InsecureValidator,
+    TokenValidator, Validator,
+};
+use rapr_runtime::invocation::DEFAULT_TRUST_DOMAIN;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "rapr-ca",
+    version,
+    about = "Certificate authority of rapr sidecars"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serves the certificate authority
+    Serve {
+        /// Directory of the ca.crt and ca.key root, generated on first start
+        #[arg(long)]
+        root_dir: PathBuf,
+
+        /// Trust domain of the certificates
+        #[arg(long, default_value = DEFAULT_TRUST_DOMAIN)]
+        trust_domain: String,
+
+        /// Port to listen on
+        #[arg(long, default_value_t = DEFAULT_CA_PORT)]
+        port: u16,
+
+        /// Host name sidecars reach the authority at, may be repeated
+        #[arg(long, default_values_t = ["localhost".to_string()])]
+        hostname: Vec<String>,
+
+        /// Validity of the certificates of apps, in seconds
+        #[arg(long, default_value_t = DEFAULT_WORKLOAD_TTL.as_secs())]
+        workload_ttl: u64,
+
+        /// File of the secret the join tokens of apps are minted with. Without it, any
+        /// requester gets a certificate for any app
+        #[arg(long)]
+        token_secret_file: Option<PathBuf>,
+    },
+
+    /// Prints the join token of an app
+    Token {
+        /// File of the secret the join tokens are minted with
+        #[arg(long)]
+        secret_file: PathBuf,
+
+        /// Namespace of the app
+        #[arg(long, default_value = "default")]
+        namespace: String,
+
+        /// Id of the app
+        #[arg(long)]
+        app_id: String,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt().init();
+    let result = match Cli::parse().command {
+        Command::Serve {
+            root_dir,
+            trust_domain,
+            port,
+            hostname,
+            workload_ttl,
+            token_secret_file,
+        } => {
+            serve(
+                &root_dir,
+                trust_domain,
+                port,
+                &hostname,
+                Duration::from_secs(workload_ttl),
+                token_secret_file.as_deref(),
+            )
+            .await
+        }
+        Command::Token {
+            secret_file,
+            namespace,
+            app_id,
+        } => token(&secret_file, &namespace, &app_id),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!(error = %e, "Certificate authority failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn token(
+    secret_file: &Path,
+    namespace: &str,
+    app_id: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let secret = std::fs::read(secret_file)?;
+    println!("{}", TokenValidator::new(&secret).token(namespace, app_id));
+    Ok(())
+}
+
+async fn serve(
+    root_dir: &Path,
+    trust_domain: String,
+    port: u16,
+    hostnames: &[String],
+    ttl: Duration,
+    token_secret_file: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let authority = CertificateAuthority::load_or_generate(root_dir, trust_domain)?;
+    let validator: Arc<dyn Validator> = match token_secret_file {
+        Some(path) => Arc::new(TokenValidator::new(&std::fs::read(path)?)),
+        None => {
+            tracing::warn!("No token secret: issuing certificates to any requester");
+            Arc::new(InsecureValidator)
+        }
+    };
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))).await?;
+    tracing::info!(
+        address = %listener.local_addr()?,
+        trust_domain = authority.trust_domain(),
+        "Serving certificate authority"
+    );
+    CaService::new(Arc::new(authority), validator)
+        .with_ttl(ttl)
+        .serve(listener, hostnames, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+    Ok(())
+}
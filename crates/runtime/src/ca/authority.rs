@@ -0,0 +1,304 @@
+use super::{CaError, CertificateSnafu, InvalidRequestSnafu, ReadSnafu, Result, WriteSnafu};
+use crate::security::SpiffeId;
+use rcgen::{
+    BasicConstraints, CertificateParams, CertificateSigningRequestParams, DistinguishedName,
+    DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair, KeyUsagePurpose, PublicKeyData,
+    SanType,
+};
+use snafu::ResultExt;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Validity of the root certificate of an authority.
+const ROOT_VALIDITY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+/// Allowance for the clocks of peers running behind.
+const CLOCK_SKEW: Duration = Duration::from_secs(15 * 60);
+/// File of the root certificate in the directory of an authority.
+pub const ROOT_CERT_FILE: &str = "ca.crt";
+/// File of the root private key in the directory of an authority.
+pub const ROOT_KEY_FILE: &str = "ca.key";
+
+/// IssuedCertificate is a certificate, its private key and the trust bundle of its issuer,
+/// PEM encoded.
+#[derive(Debug, Clone)]
+pub struct IssuedCertificate {
+    /// Certificate of the identity followed by the certificate of the authority.
+    pub cert_chain_pem: String,
+    pub private_key_pem: String,
+    pub trust_bundle_pem: String,
+    pub expires_at: SystemTime,
+}
+
+/// SignedCertificate is a certificate signed for a certificate signing request.
+#[derive(Debug, Clone)]
+pub struct SignedCertificate {
+    /// Certificate of the identity followed by the certificate of the authority.
+    pub cert_chain_pem: String,
+    pub expires_at: SystemTime,
+}
+
+/// CertificateAuthority issues the certificates of the apps of a trust domain from a
+/// self-signed root.
+pub struct CertificateAuthority {
+    trust_domain: String,
+    issuer: Issuer<'static, KeyPair>,
+    root_pem: String,
+}
+
+impl CertificateAuthority {
+    /// Creates an authority with a root generated on the spot, which only lives as long as
+    /// the process. Meant for development and tests.
+    pub fn generate(trust_domain: impl Into<String>) -> Result<Self> {
+        let trust_domain = trust_domain.into();
+        let key = KeyPair::generate().context(CertificateSnafu)?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name = distinguished_name(&format!("rapr CA {trust_domain}"));
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        set_validity(&mut params, ROOT_VALIDITY);
+        let root = params.self_signed(&key).context(CertificateSnafu)?;
+        Ok(Self {
+            trust_domain,
+            issuer: Issuer::new(params, key),
+            root_pem: root.pem(),
+        })
+    }
+
+    /// Loads the root of an authority from [`ROOT_CERT_FILE`] and [`ROOT_KEY_FILE`] in `dir`,
+    /// generating and saving one first if the directory has none. The key is only readable
+    /// by its owner.
+    pub fn load_or_generate(dir: &Path, trust_domain: impl Into<String>) -> Result<Self> {
+        let cert_path = dir.join(ROOT_CERT_FILE);
+        let key_path = dir.join(ROOT_KEY_FILE);
+        if !cert_path.exists() && !key_path.exists() {
+            let authority = Self::generate(trust_domain)?;
+            std::fs::create_dir_all(dir).context(WriteSnafu { path: dir })?;
+            write_private(&key_path, &authority.issuer.key().serialize_pem())?;
+            std::fs::write(&cert_path, &authority.root_pem)
+                .context(WriteSnafu { path: &cert_path })?;
+            return Ok(authority);
+        }
+
+        let root_pem =
+            std::fs::read_to_string(&cert_path).context(ReadSnafu { path: &cert_path })?;
+        let key_pem = std::fs::read_to_string(&key_path).context(ReadSnafu { path: &key_path })?;
+        let key = KeyPair::from_pem(&key_pem).context(CertificateSnafu)?;
+        let issuer = Issuer::from_ca_cert_pem(&root_pem, key).context(CertificateSnafu)?;
+        Ok(Self {
+            trust_domain: trust_domain.into(),
+            issuer,
+            root_pem,
+        })
+    }
+
+    pub fn trust_domain(&self) -> &str {
+        &self.trust_domain
+    }
+
+    /// Returns the trust bundle of the authority, PEM encoded.
+    pub fn trust_bundle_pem(&self) -> &str {
+        &self.root_pem
+    }
+
+    /// Issues a certificate and its private key to an app, valid for `ttl`.
+    pub fn issue(&self, namespace: &str, app_id: &str, ttl: Duration) -> Result<IssuedCertificate> {
+        let key = KeyPair::generate().context(CertificateSnafu)?;
+        let id = SpiffeId::new(self.trust_domain.clone(), namespace, app_id);
+        let signed = self.sign_key(&key, &id, ttl)?;
+        Ok(IssuedCertificate {
+            cert_chain_pem: signed.cert_chain_pem,
+            private_key_pem: key.serialize_pem(),
+            trust_bundle_pem: self.root_pem.clone(),
+            expires_at: signed.expires_at,
+        })
+    }
+
+    /// Signs a certificate for the PEM certificate signing request of an app, valid for
+    /// `ttl`. The request may only ask for the SPIFFE ID of the app; its other attributes
+    /// are ignored.
+    pub fn sign(
+        &self,
+        csr_pem: &str,
+        namespace: &str,
+        app_id: &str,
+        ttl: Duration,
+    ) -> Result<SignedCertificate> {
+        let csr = CertificateSigningRequestParams::from_pem(csr_pem).map_err(|e| {
+            CaError::InvalidRequest {
+                reason: format!("invalid certificate signing request: {e}"),
+            }
+        })?;
+        let id = SpiffeId::new(self.trust_domain.clone(), namespace, app_id);
+        let uri = id.to_string();
+        for name in &csr.params.subject_alt_names {
+            if !matches!(name, SanType::URI(requested) if requested.as_str() == uri) {
+                return InvalidRequestSnafu {
+                    reason: format!("the request may only name {uri}, not {name:?}"),
+                }
+                .fail();
+            }
+        }
+        self.sign_key(&csr.public_key, &id, ttl)
+    }
+
+    /// Issues the certificate of a server of the authority itself, for host names.
+    pub fn issue_server(&self, hostnames: &[String]) -> Result<IssuedCertificate> {
+        let key = KeyPair::generate().context(CertificateSnafu)?;
+        let mut params = CertificateParams::new(hostnames).context(CertificateSnafu)?;
+        params.distinguished_name = distinguished_name(&format!("rapr CA {}", self.trust_domain));
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        let expires_at = set_validity(&mut params, ROOT_VALIDITY);
+        let certificate = params
+            .signed_by(&key, &self.issuer)
+            .context(CertificateSnafu)?;
+        Ok(IssuedCertificate {
+            cert_chain_pem: format!("{}{}", certificate.pem(), self.root_pem),
+            private_key_pem: key.serialize_pem(),
+            trust_bundle_pem: self.root_pem.clone(),
+            expires_at,
+        })
+    }
+
+    fn sign_key(
+        &self,
+        key: &impl PublicKeyData,
+        id: &SpiffeId,
+        ttl: Duration,
+    ) -> Result<SignedCertificate> {
+        let mut params = CertificateParams::default();
+        params.distinguished_name = distinguished_name(&id.app_id);
+        params.subject_alt_names = vec![SanType::URI(
+            id.to_string().try_into().context(CertificateSnafu)?,
+        )];
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        params.use_authority_key_identifier_extension = true;
+        let expires_at = set_validity(&mut params, ttl);
+        let certificate = params
+            .signed_by(key, &self.issuer)
+            .context(CertificateSnafu)?;
+        Ok(SignedCertificate {
+            cert_chain_pem: format!("{}{}", certificate.pem(), self.root_pem),
+            expires_at,
+        })
+    }
+}
+
+fn distinguished_name(common_name: &str) -> DistinguishedName {
+    let mut name = DistinguishedName::new();
+    name.push(DnType::CommonName, common_name);
+    name
+}
+
+/// Makes a certificate valid from a little before now for `validity`, returning its expiry.
+fn set_validity(params: &mut CertificateParams, validity: Duration) -> SystemTime {
+    let now = SystemTime::now();
+    let expires_at = now + validity;
+    params.not_before = (now - CLOCK_SKEW).into();
+    params.not_after = expires_at.into();
+    expires_at
+}
+
+/// Writes a file only its owner may read.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).context(WriteSnafu { path })?;
+    file.write_all(contents.as_bytes())
+        .context(WriteSnafu { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn test_load_or_generate() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        let authority = CertificateAuthority::load_or_generate(&root, "cluster.local").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = std::fs::metadata(root.join(ROOT_KEY_FILE)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        // The root survives restarts: certificates issued before are still trusted.
+        let reloaded = CertificateAuthority::load_or_generate(&root, "cluster.local").unwrap();
+        assert_eq!(reloaded.trust_bundle_pem(), authority.trust_bundle_pem());
+        let issued = reloaded.issue("default", "orders", TTL).unwrap();
+        let credentials = crate::security::Credentials::from_pem(
+            &issued.cert_chain_pem,
+            &issued.private_key_pem,
+            authority.trust_bundle_pem(),
+        )
+        .unwrap();
+        assert_eq!(
+            credentials.identity(),
+            SpiffeId::new("cluster.local", "default", "orders")
+        );
+
+        // A root missing half of its files is not silently replaced.
+        std::fs::remove_file(root.join(ROOT_KEY_FILE)).unwrap();
+        assert!(matches!(
+            CertificateAuthority::load_or_generate(&root, "cluster.local"),
+            Err(CaError::Read { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sign() {
+        let authority = CertificateAuthority::generate("cluster.local").unwrap();
+        let request = |names: Vec<SanType>| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::default();
+            params.subject_alt_names = names;
+            let csr = params.serialize_request(&key).unwrap().pem().unwrap();
+            (key, csr)
+        };
+        let uri = |uri: &str| SanType::URI(uri.try_into().unwrap());
+
+        let (key, csr) = request(Vec::new());
+        let signed = authority.sign(&csr, "default", "orders", TTL).unwrap();
+        let credentials = crate::security::Credentials::from_pem(
+            &signed.cert_chain_pem,
+            &key.serialize_pem(),
+            authority.trust_bundle_pem(),
+        )
+        .unwrap();
+        assert_eq!(credentials.identity().app_id, "orders");
+
+        let (_, csr) = request(vec![uri("spiffe://cluster.local/ns/default/orders")]);
+        assert!(authority.sign(&csr, "default", "orders", TTL).is_ok());
+
+        let (_, csr) = request(vec![uri("spiffe://cluster.local/ns/default/billing")]);
+        assert!(matches!(
+            authority.sign(&csr, "default", "orders", TTL),
+            Err(CaError::InvalidRequest { .. })
+        ));
+        let (_, csr) = request(vec![SanType::DnsName(
+            "orders.example.com".try_into().unwrap(),
+        )]);
+        assert!(matches!(
+            authority.sign(&csr, "default", "orders", TTL),
+            Err(CaError::InvalidRequest { .. })
+        ));
+        assert!(matches!(
+            authority.sign("not a request", "default", "orders", TTL),
+            Err(CaError::InvalidRequest { .. })
+        ));
+    }
+}
@@ -0,0 +1,238 @@
+use super::proto::SignCertificateRequest;
+use super::proto::certificate_authority_client::CertificateAuthorityClient;
+use super::{
+    CertificateSnafu, ConnectSnafu, CredentialsSnafu, IssuedCertificate, RemoteSnafu, Result,
+};
+use crate::security::Credentials;
+use rcgen::{CertificateParams, KeyPair};
+use snafu::ResultExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+
+/// Interval between two attempts to renew a certificate after a failure.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// CaClient requests the certificate of the app of a sidecar from a certificate authority.
+#[derive(Clone)]
+pub struct CaClient {
+    client: CertificateAuthorityClient<Channel>,
+    namespace: String,
+    app_id: String,
+    token: String,
+}
+
+impl CaClient {
+    /// Creates a client of the authority at `address`, a `host:port` pair, whose server
+    /// certificate is checked against a PEM trust bundle. Connects on first use.
+    pub fn new(
+        address: &str,
+        trust_bundle_pem: &str,
+        namespace: impl Into<String>,
+        app_id: impl Into<String>,
+        token: impl Into<String>,
+    ) -> Result<Self> {
+        let tls = ClientTlsConfig::new().ca_certificate(Certificate::from_pem(trust_bundle_pem));
+        let channel = Endpoint::from_shared(format!("https://{address}"))
+            .and_then(|endpoint| endpoint.tls_config(tls))
+            .context(ConnectSnafu { address })?
+            .connect_lazy();
+        Ok(Self {
+            client: CertificateAuthorityClient::new(channel),
+            namespace: namespace.into(),
+            app_id: app_id.into(),
+            token: token.into(),
+        })
+    }
+
+    /// Requests a certificate for a new private key.
+    pub async fn request(&self) -> Result<IssuedCertificate> {
+        let key = KeyPair::generate().context(CertificateSnafu)?;
+        let csr = CertificateParams::default()
+            .serialize_request(&key)
+            .and_then(|csr| csr.pem())
+            .context(CertificateSnafu)?;
+        let response = self
+            .client
+            .clone()
+            .sign_certificate(SignCertificateRequest {
+                app_id: self.app_id.clone(),
+                namespace: self.namespace.clone(),
+                token: self.token.clone(),
+                certificate_signing_request: csr,
+            })
+            .await
+            .context(RemoteSnafu)?
+            .into_inner();
+        Ok(IssuedCertificate {
+            cert_chain_pem: response.cert_chain,
+            private_key_pem: key.serialize_pem(),
+            trust_bundle_pem: response.trust_bundle,
+            expires_at: UNIX_EPOCH
+                + Duration::from_secs(response.expires_at.try_into().unwrap_or(0)),
+        })
+    }
+
+    /// Requests a certificate and returns credentials holding it.
+    pub async fn credentials(&self) -> Result<(Credentials, SystemTime)> {
+        let issued = self.request().await?;
+        let credentials = Credentials::from_pem(
+            &issued.cert_chain_pem,
+            &issued.private_key_pem,
+            &issued.trust_bundle_pem,
+        )
+        .context(CredentialsSnafu)?;
+        Ok((credentials, issued.expires_at))
+    }
+
+    /// Renews the certificate of the credentials when half of its remaining validity has
+    /// passed, until the returned task is aborted. Failed renewals are retried.
+    pub fn keep_renewed(
+        self,
+        credentials: Arc<Credentials>,
+        mut expires_at: SystemTime,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut delay = renewal_delay(expires_at);
+            loop {
+                tokio::time::sleep(delay).await;
+                let renewed = self.request().await.and_then(|issued| {
+                    credentials
+                        .rotate(
+                            &issued.cert_chain_pem,
+                            &issued.private_key_pem,
+                            &issued.trust_bundle_pem,
+                        )
+                        .context(CredentialsSnafu)?;
+                    Ok(issued.expires_at)
+                });
+                delay = match renewed {
+                    Ok(renewed) => {
+                        expires_at = renewed;
+                        tracing::info!(identity = %credentials.identity(), "Renewed certificate");
+                        renewal_delay(expires_at)
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Failed to renew certificate");
+                        RETRY_INTERVAL
+                            .min(renewal_delay(expires_at).max(Duration::from_millis(100)))
+                    }
+                };
+            }
+        })
+    }
+}
+
+/// Returns the time until half of the remaining validity of a certificate has passed.
+fn renewal_delay(expires_at: SystemTime) -> Duration {
+    expires_at
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+        / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::{CaError, CaService, CertificateAuthority, Claim, TokenValidator, Validator};
+    use crate::security::SpiffeId;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// Counts the claims it validates.
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    #[async_trait::async_trait]
+    impl Validator for Counting {
+        async fn validate(&self, _: &Claim) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    async fn serve(
+        validator: Arc<dyn Validator>,
+        ttl: Duration,
+    ) -> (String, Arc<CertificateAuthority>) {
+        let authority = Arc::new(CertificateAuthority::generate("cluster.local").unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("localhost:{}", listener.local_addr().unwrap().port());
+        let service = CaService::new(authority.clone(), validator).with_ttl(ttl);
+        tokio::spawn(async move {
+            service
+                .serve(listener, &["localhost".to_string()], std::future::pending())
+                .await
+        });
+        (address, authority)
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        let validator = TokenValidator::new(b"secret");
+        let (address, authority) = serve(
+            Arc::new(TokenValidator::new(b"secret")),
+            Duration::from_secs(3600),
+        )
+        .await;
+        let bundle = authority.trust_bundle_pem();
+
+        let token = validator.token("default", "orders");
+        let client = CaClient::new(&address, bundle, "default", "orders", token).unwrap();
+        let (credentials, expires_at) = client.credentials().await.unwrap();
+        assert_eq!(
+            credentials.identity(),
+            SpiffeId::new("cluster.local", "default", "orders")
+        );
+        assert!(expires_at > SystemTime::now() + Duration::from_secs(3500));
+
+        let token = validator.token("default", "orders");
+        let client = CaClient::new(&address, bundle, "default", "billing", token).unwrap();
+        match client.request().await {
+            Err(CaError::Remote { source }) => {
+                assert_eq!(source.code(), tonic::Code::PermissionDenied)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        // The client does not talk to servers outside of its trust bundle.
+        let other = CertificateAuthority::generate("cluster.local").unwrap();
+        let token = validator.token("default", "orders");
+        let client = CaClient::new(
+            &address,
+            other.trust_bundle_pem(),
+            "default",
+            "orders",
+            token,
+        )
+        .unwrap();
+        assert!(matches!(
+            client.request().await,
+            Err(CaError::Remote { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_keep_renewed() {
+        let validator = Arc::new(Counting::default());
+        let (address, authority) = serve(validator.clone(), Duration::from_secs(1)).await;
+        let client = CaClient::new(
+            &address,
+            authority.trust_bundle_pem(),
+            "default",
+            "orders",
+            "",
+        )
+        .unwrap();
+        let (credentials, expires_at) = client.credentials().await.unwrap();
+        let credentials = Arc::new(credentials);
+        assert_eq!(validator.0.load(Ordering::SeqCst), 1);
+
+        let renewal = client.keep_renewed(credentials.clone(), expires_at);
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert!(validator.0.load(Ordering::SeqCst) >= 3);
+        assert_eq!(credentials.identity().app_id, "orders");
+        renewal.abort();
+    }
+}
@@ -0,0 +1,77 @@
+//! Certificate authority issuing the certificates sidecars authenticate each other with.
+//!
+//! The [`CertificateAuthority`] keeps a root key, persisted in a directory so certificates
+//! stay trusted across restarts, and signs the certificates of apps with their
+//! [`SpiffeId`](crate::security::SpiffeId). [`CaService`] serves it over gRPC: a sidecar
+//! sends a certificate signing request for its app along with a token proving it may claim
+//! the app, which a [`Validator`] checks. [`CaClient`] requests certificates and renews them
+//! before they expire.
+
+mod authority;
+mod client;
+mod server;
+mod validator;
+
+pub use authority::*;
+pub use client::*;
+pub use server::*;
+pub use validator::*;
+
+use crate::security::SecurityError;
+use snafu::Snafu;
+use std::path::PathBuf;
+
+#[allow(missing_docs, unused_qualifications, clippy::all)]
+pub mod proto {
+    tonic::include_proto!("rapr.ca.v1");
+}
+
+#[derive(Debug, Snafu)]
+pub enum CaError {
+    #[snafu(display("Failed to read {}: {}", path.display(), source))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to write {}: {}", path.display(), source))]
+    Write {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to issue certificate: {}", source))]
+    Certificate { source: rcgen::Error },
+
+    #[snafu(display("Invalid certificate request: {}", reason))]
+    InvalidRequest { reason: String },
+
+    #[snafu(display(
+        "App {}/{} may not request a certificate: {}",
+        namespace,
+        app_id,
+        reason
+    ))]
+    Unauthorized {
+        namespace: String,
+        app_id: String,
+        reason: String,
+    },
+
+    #[snafu(display("Failed to serve the certificate authority: {}", source))]
+    Serve { source: tonic::transport::Error },
+
+    #[snafu(display("Invalid certificate authority address {}: {}", address, source))]
+    Connect {
+        address: String,
+        source: tonic::transport::Error,
+    },
+
+    #[snafu(display("Certificate authority refused the request: {}", source))]
+    Remote { source: tonic::Status },
+
+    #[snafu(display("Invalid certificate from the certificate authority: {}", source))]
+    Credentials { source: SecurityError },
+}
+
+pub type Result<T> = std::result::Result<T, CaError>;
@@ -0,0 +1,210 @@
+use super::proto::certificate_authority_server::{
+    CertificateAuthority as CaRpc, CertificateAuthorityServer,
+};
+use super::proto::{SignCertificateRequest, SignCertificateResponse};
+use super::{CaError, CertificateAuthority, Claim, Result, ServeSnafu, Validator};
+use snafu::ResultExt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+/// Default port of the certificate authority.
+pub const DEFAULT_CA_PORT: u16 = 50003;
+/// Default validity of the certificates of apps.
+pub const DEFAULT_WORKLOAD_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// CaService signs the certificates of the sidecars whose claims the validator accepts.
+#[derive(Clone)]
+pub struct CaService {
+    authority: Arc<CertificateAuthority>,
+    validator: Arc<dyn Validator>,
+    ttl: Duration,
+}
+
+impl CaService {
+    pub fn new(authority: Arc<CertificateAuthority>, validator: Arc<dyn Validator>) -> Self {
+        Self {
+            authority,
+            validator,
+            ttl: DEFAULT_WORKLOAD_TTL,
+        }
+    }
+
+    /// Sets the validity of the certificates of apps.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Wraps the service into a server which can be added to a tonic router.
+    pub fn into_server(self) -> CertificateAuthorityServer<Self> {
+        CertificateAuthorityServer::new(self)
+    }
+
+    /// Serves the authority over TLS until `shutdown` completes, with a certificate for
+    /// `hostnames` issued by the authority itself.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        hostnames: &[String],
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let server = self.authority.issue_server(hostnames)?;
+        let identity = Identity::from_pem(server.cert_chain_pem, server.private_key_pem);
+        Server::builder()
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .context(ServeSnafu)?
+            .add_service(self.into_server())
+            .serve_with_incoming_shutdown(TcpIncoming::from(listener), shutdown)
+            .await
+            .context(ServeSnafu)
+    }
+
+    async fn sign(&self, request: SignCertificateRequest) -> Result<SignCertificateResponse> {
+        // Both end up in the token message and in the SPIFFE ID: a separator in either
+        // would make them ambiguous.
+        check_name("namespace", &request.namespace)?;
+        check_name("app id", &request.app_id)?;
+        let claim = Claim {
+            namespace: request.namespace,
+            app_id: request.app_id,
+            token: request.token,
+        };
+        self.validator.validate(&claim).await?;
+        let signed = self.authority.sign(
+            &request.certificate_signing_request,
+            &claim.namespace,
+            &claim.app_id,
+            self.ttl,
+        )?;
+        Ok(SignCertificateResponse {
+            cert_chain: signed.cert_chain_pem,
+            trust_bundle: self.authority.trust_bundle_pem().to_string(),
+            expires_at: signed
+                .expires_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+                .try_into()
+                .unwrap_or(i64::MAX),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl CaRpc for CaService {
+    async fn sign_certificate(
+        &self,
+        request: Request<SignCertificateRequest>,
+    ) -> std::result::Result<Response<SignCertificateResponse>, Status> {
+        let request = request.into_inner();
+        let (namespace, app_id) = (request.namespace.clone(), request.app_id.clone());
+        match self.sign(request).await {
+            Ok(response) => {
+                tracing::info!(%namespace, %app_id, "Issued certificate");
+                Ok(Response::new(response))
+            }
+            Err(e) => {
+                tracing::warn!(%namespace, %app_id, error = %e, "Refused certificate");
+                Err(to_status(e))
+            }
+        }
+    }
+}
+
+/// Checks that a name is a DNS label: 1 to 63 ASCII letters, digits or hyphens, neither
+/// starting nor ending with a hyphen.
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    let valid = (1..=63).contains(&name.len())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        && !name.starts_with('-')
+        && !name.ends_with('-');
+    match valid {
+        true => Ok(()),
+        false => Err(CaError::InvalidRequest {
+            reason: format!("the {kind} {name:?} is not a DNS label"),
+        }),
+    }
+}
+
+fn to_status(e: CaError) -> Status {
+    let message = e.to_string();
+    match e {
+        CaError::InvalidRequest { .. } => Status::invalid_argument(message),
+        CaError::Unauthorized { .. } => Status::permission_denied(message),
+        _ => Status::internal(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::TokenValidator;
+    use crate::security::SpiffeId;
+
+    fn request(namespace: &str, app_id: &str, token: String) -> SignCertificateRequest {
+        SignCertificateRequest {
+            namespace: namespace.to_string(),
+            app_id: app_id.to_string(),
+            token,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sign_checks_names() {
+        let validator = TokenValidator::new(b"secret");
+        let authority = Arc::new(CertificateAuthority::generate("cluster.local").unwrap());
+        let service = CaService::new(authority, Arc::new(TokenValidator::new(b"secret")));
+
+        // The token of a/b-c also authenticates a-b/c, which is never checked.
+        let token = validator.token("a", "b/c");
+        let claim = Claim {
+            namespace: "a/b".to_string(),
+            app_id: "c".to_string(),
+            token: token.clone(),
+        };
+        assert!(validator.validate(&claim).await.is_ok());
+        for (namespace, app_id) in [("a", "b/c"), ("a/b", "c")] {
+            assert!(matches!(
+                service
+                    .sign(request(namespace, app_id, token.clone()))
+                    .await,
+                Err(CaError::InvalidRequest { .. })
+            ));
+        }
+
+        // Names which would not make a SPIFFE ID are refused before their token is checked.
+        for (namespace, app_id) in [
+            ("default", "orders/v2"),
+            ("default", ""),
+            ("", "orders"),
+            ("default", "orders?v=2"),
+            ("default", "-orders"),
+            ("default", &"o".repeat(64)),
+        ] {
+            let token = validator.token(namespace, app_id);
+            assert!(matches!(
+                service.sign(request(namespace, app_id, token)).await,
+                Err(CaError::InvalidRequest { .. })
+            ));
+        }
+        assert!(
+            "spiffe://cluster.local/ns/default/orders/v2"
+                .parse::<SpiffeId>()
+                .is_err()
+        );
+
+        // Valid names reach the validator.
+        assert!(matches!(
+            service
+                .sign(request("default", "orders-v2", "bad".to_string()))
+                .await,
+            Err(CaError::Unauthorized { .. })
+        ));
+    }
+}
@@ -0,0 +1,106 @@
+use super::{CaError, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ring::hmac;
+
+/// Claim is the identity a sidecar asks a certificate for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Claim {
+    pub namespace: String,
+    pub app_id: String,
+    /// Proof that the sidecar may claim the identity.
+    pub token: String,
+}
+
+/// Validator checks that sidecars may claim the identities they ask certificates for.
+#[async_trait::async_trait]
+pub trait Validator: Send + Sync {
+    async fn validate(&self, claim: &Claim) -> Result<()>;
+}
+
+/// InsecureValidator accepts every claim. Any requester can get a certificate for any app:
+/// only use it in development.
+pub struct InsecureValidator;
+
+#[async_trait::async_trait]
+impl Validator for InsecureValidator {
+    async fn validate(&self, _: &Claim) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// TokenValidator accepts the claims carrying a join token minted with a secret shared
+/// with the authority: the HMAC-SHA256 of `<namespace>/<app-id>`, base64url encoded.
+pub struct TokenValidator {
+    key: hmac::Key,
+}
+
+impl TokenValidator {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+        }
+    }
+
+    /// Mints the join token of an app.
+    pub fn token(&self, namespace: &str, app_id: &str) -> String {
+        let tag = hmac::sign(&self.key, message(namespace, app_id).as_bytes());
+        URL_SAFE_NO_PAD.encode(tag.as_ref())
+    }
+}
+
+#[async_trait::async_trait]
+impl Validator for TokenValidator {
+    async fn validate(&self, claim: &Claim) -> Result<()> {
+        let unauthorized = |reason: &str| CaError::Unauthorized {
+            namespace: claim.namespace.clone(),
+            app_id: claim.app_id.clone(),
+            reason: reason.to_string(),
+        };
+        let tag = URL_SAFE_NO_PAD
+            .decode(&claim.token)
+            .map_err(|_| unauthorized("malformed token"))?;
+        hmac::verify(
+            &self.key,
+            message(&claim.namespace, &claim.app_id).as_bytes(),
+            &tag,
+        )
+        .map_err(|_| unauthorized("invalid token"))
+    }
+}
+
+fn message(namespace: &str, app_id: &str) -> String {
+    format!("{namespace}/{app_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim(app_id: &str, token: &str) -> Claim {
+        Claim {
+            namespace: "default".to_string(),
+            app_id: app_id.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_validator() {
+        let validator = TokenValidator::new(b"secret");
+        let token = validator.token("default", "orders");
+        assert!(validator.validate(&claim("orders", &token)).await.is_ok());
+
+        assert_eq!(
+            validator
+                .validate(&claim("billing", &token))
+                .await
+                .unwrap_err()
+                .to_string(),
+            "App default/billing may not request a certificate: invalid token"
+        );
+        assert!(validator.validate(&claim("orders", "%%")).await.is_err());
+        let other = TokenValidator::new(b"other secret").token("default", "orders");
+        assert!(validator.validate(&claim("orders", &other)).await.is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! App channel to apps speaking gRPC.
+
+use super::{AppAddress, AppChannel, AppHealth, ConcurrencyLimit, InvalidAddressSnafu, Result};
+use crate::api::{API_TOKEN_HEADER, ApiToken};
+use crate::invocation::{InvocationError, InvocationHandler, InvokeRequest, InvokeResponse};
+use proto::app_callback_client::AppCallbackClient;
+use proto::app_callback_health_check_client::AppCallbackHealthCheckClient;
+use snafu::ResultExt;
+use tonic::Code;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint};
+
+pub use proto::app_callback_health_check_server::{
+    AppCallbackHealthCheck, AppCallbackHealthCheckServer,
+};
+pub use proto::app_callback_server::{AppCallback, AppCallbackServer};
+
+#[allow(missing_docs, unused_qualifications, clippy::all)]
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/runtime/rapr.appcallback.v1.rs"));
+}
+
+/// GrpcChannel calls the `AppCallback` service of the app.
+pub struct GrpcChannel {
+    channel: Channel,
+    app_token: Option<MetadataValue<tonic::metadata::Ascii>>,
+    limit: ConcurrencyLimit,
+}
+
+impl GrpcChannel {
+    pub(crate) fn new(
+        address: &AppAddress,
+        app_token: Option<ApiToken>,
+        limit: ConcurrencyLimit,
+    ) -> Result<Self> {
+        let uri = match address {
+            AppAddress::Port(port) => format!("http://127.0.0.1:{port}"),
+            AppAddress::Unix(path) => format!("unix://{}", path.display()),
+        };
+        let channel = Endpoint::from_shared(uri.clone())
+            .context(InvalidAddressSnafu { address: uri })?
+            .connect_lazy();
+        let app_token = app_token.and_then(|token| token.as_str().parse().ok());
+        Ok(Self {
+            channel,
+            app_token,
+            limit,
+        })
+    }
+
+    /// Wraps a message into a request carrying the app token.
+    fn request<T>(&self, message: T) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        if let Some(token) = &self.app_token {
+            request
+                .metadata_mut()
+                .insert(API_TOKEN_HEADER, token.clone());
+        }
+        request
+    }
+}
+
+#[async_trait::async_trait]
+impl InvocationHandler for GrpcChannel {
+    async fn on_invoke(
+        &self,
+        mut request: InvokeRequest,
+    ) -> crate::invocation::Result<InvokeResponse> {
+        // Only the sidecar may present the app token.
+        request
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(API_TOKEN_HEADER));
+        let _permit = self.limit.acquire().await;
+        let response = AppCallbackClient::new(self.channel.clone())
+            .on_invoke(self.request(request.into()))
+            .await
+            .map_err(|status| InvocationError::App {
+                reason: status.message().to_string(),
+            })?;
+        Ok(response.into_inner().into())
+    }
+}
+
+#[async_trait::async_trait]
+impl AppChannel for GrpcChannel {
+    /// Apps which do not implement `AppCallbackHealthCheck` are healthy as long as they
+    /// answer.
+    async fn health_probe(&self) -> AppHealth {
+        let result = AppCallbackHealthCheckClient::new(self.channel.clone())
+            .health_check(self.request(proto::HealthCheckRequest {}))
+            .await;
+        match result {
+            Ok(_) => AppHealth::Healthy,
+            Err(status) if status.code() == Code::Unimplemented => AppHealth::Healthy,
+            // Transport failures surface as statuses without gRPC response.
+            Err(status) if status.code() == Code::Unavailable => AppHealth::Unreachable,
+            Err(_) => AppHealth::Unhealthy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{AppChannelConfig, AppProtocol};
+    use crate::invocation::grpc::proto;
+    use tokio::net::TcpListener;
+    use tonic::transport::Server;
+    use tonic::transport::server::TcpIncoming;
+    use tonic::{Request, Response, Status};
+
+    struct App;
+
+    #[tonic::async_trait]
+    impl AppCallback for App {
+        async fn on_invoke(
+            &self,
+            request: Request<proto::InvokeRequest>,
+        ) -> std::result::Result<Response<proto::InvokeResponse>, Status> {
+            let token = request.metadata().get(API_TOKEN_HEADER).cloned();
+            let request = request.into_inner();
+            if request.method == "token" {
+                let token = token.map(|token| token.to_str().unwrap().to_string());
+                return Ok(Response::new(proto::InvokeResponse {
+                    status_code: 200,
+                    data: token.unwrap_or_default().into_bytes(),
+                    ..Default::default()
+                }));
+            }
+            if request.method == "fail" {
+                return Err(Status::internal("failed"));
+            }
+            Ok(Response::new(proto::InvokeResponse {
+                status_code: 200,
+                content_type: request.content_type,
+                data: request.data,
+                headers: request.headers,
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_channel() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            Server::builder()
+                .add_service(AppCallbackServer::new(App))
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+
+        let channel = AppChannelConfig::new(AppProtocol::Grpc, AppAddress::Port(port))
+            .build()
+            .unwrap();
+        assert_eq!(channel.health_probe().await, AppHealth::Healthy);
+        let response = channel
+            .on_invoke(InvokeRequest::new("echo").with_data("text/plain", "hi"))
+            .await
+            .unwrap();
+        assert_eq!(response.data, b"hi");
+        assert!(matches!(
+            channel.on_invoke(InvokeRequest::new("fail")).await,
+            Err(InvocationError::App { .. })
+        ));
+        let response = channel.on_invoke(InvokeRequest::new("token")).await;
+        assert!(response.unwrap().data.is_empty());
+
+        let mut config = AppChannelConfig::new(AppProtocol::Grpc, AppAddress::Port(port));
+        config.app_token = Some(ApiToken::new("app-s3cret"));
+        let channel = config.build().unwrap();
+        let response = channel.on_invoke(InvokeRequest::new("token")).await;
+        assert_eq!(response.unwrap().data, b"app-s3cret");
+
+        // Nothing listens on the port anymore.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let channel = AppChannelConfig::new(AppProtocol::Grpc, AppAddress::Port(port))
+            .build()
+            .unwrap();
+        assert_eq!(channel.health_probe().await, AppHealth::Unreachable);
+    }
+}
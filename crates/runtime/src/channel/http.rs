@@ -0,0 +1,357 @@
+//! App channel to apps speaking HTTP/1.1.
+
+use super::{AppAddress, AppChannel, AppHealth, ConcurrencyLimit};
+use crate::api::http::is_hop_by_hop;
+use crate::api::{API_TOKEN_HEADER, ApiToken};
+use crate::invocation::{InvocationError, InvocationHandler, InvokeRequest, InvokeResponse};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::header::{CONTENT_TYPE, HeaderName, HeaderValue};
+use hyper::{Method, Request, Response, Uri};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
+
+/// HttpChannel calls the methods of the app as HTTP endpoints: method `orders/1` of an
+/// invocation is `/orders/1` on the app.
+pub struct HttpChannel {
+    client: HttpClient,
+    /// Scheme and authority of the URIs of the app.
+    base: String,
+    health_check_path: String,
+    app_token: Option<HeaderValue>,
+    limit: ConcurrencyLimit,
+}
+
+enum HttpClient {
+    Tcp(Client<HttpConnector, Full<Bytes>>),
+    #[cfg(unix)]
+    Unix(Client<unix::UnixConnector, Full<Bytes>>),
+}
+
+impl HttpClient {
+    async fn request(
+        &self,
+        request: Request<Full<Bytes>>,
+    ) -> Result<Response<hyper::body::Incoming>, hyper_util::client::legacy::Error> {
+        match self {
+            Self::Tcp(client) => client.request(request).await,
+            #[cfg(unix)]
+            Self::Unix(client) => client.request(request).await,
+        }
+    }
+}
+
+impl HttpChannel {
+    pub(crate) fn new(
+        address: &AppAddress,
+        health_check_path: &str,
+        app_token: Option<ApiToken>,
+        limit: ConcurrencyLimit,
+    ) -> Self {
+        let builder = Client::builder(TokioExecutor::new());
+        let (client, base) = match address {
+            AppAddress::Port(port) => (
+                HttpClient::Tcp(builder.build(HttpConnector::new())),
+                format!("http://127.0.0.1:{port}"),
+            ),
+            #[cfg(unix)]
+            AppAddress::Unix(path) => (
+                HttpClient::Unix(builder.build(unix::UnixConnector::new(path))),
+                "http://localhost".to_string(),
+            ),
+            #[cfg(not(unix))]
+            AppAddress::Unix(path) => {
+                tracing::error!(path = %path.display(), "Unix sockets are not supported");
+                (
+                    HttpClient::Tcp(builder.build(HttpConnector::new())),
+                    "http://localhost".to_string(),
+                )
+            }
+        };
+        Self {
+            client,
+            base,
+            health_check_path: format!("/{}", health_check_path.trim_start_matches('/')),
+            app_token: app_token.and_then(|token| {
+                let mut value = HeaderValue::try_from(token.as_str()).ok()?;
+                value.set_sensitive(true);
+                Some(value)
+            }),
+            limit,
+        }
+    }
+
+    fn uri(&self, path: &str, query: &str) -> Result<Uri, InvocationError> {
+        let uri = match query {
+            "" => format!("{}/{path}", self.base),
+            query => format!("{}/{path}?{query}", self.base),
+        };
+        uri.parse().map_err(|e| InvocationError::App {
+            reason: format!("Invalid method {path:?}: {e}"),
+        })
+    }
+}
+
+fn app_error(reason: impl ToString) -> InvocationError {
+    InvocationError::App {
+        reason: reason.to_string(),
+    }
+}
+
+#[async_trait::async_trait]
+impl InvocationHandler for HttpChannel {
+    async fn on_invoke(&self, request: InvokeRequest) -> crate::invocation::Result<InvokeResponse> {
+        let verb = Method::from_bytes(request.verb.as_bytes()).map_err(app_error)?;
+        let mut builder = Request::builder()
+            .method(verb)
+            .uri(self.uri(&request.method, &request.query)?);
+        // Only the sidecar may present the app token, and it frames the request itself.
+        for (name, value) in &request.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::try_from(value.as_str()),
+            ) && name != API_TOKEN_HEADER
+                && !is_hop_by_hop(&name)
+            {
+                builder = builder.header(name, value);
+            }
+        }
+        if let Ok(content_type) = HeaderValue::try_from(request.content_type.as_str())
+            && !content_type.is_empty()
+            && let Some(headers) = builder.headers_mut()
+        {
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+        if let Some(token) = &self.app_token {
+            builder = builder.header(API_TOKEN_HEADER, token.clone());
+        }
+        let http_request = builder
+            .body(Full::new(Bytes::from(request.data)))
+            .map_err(app_error)?;
+
+        let _permit = self.limit.acquire().await;
+        let response = self.client.request(http_request).await.map_err(app_error)?;
+        let (parts, body) = response.into_parts();
+        let data = body.collect().await.map_err(app_error)?.to_bytes();
+
+        let mut content_type = String::new();
+        let mut headers = Vec::with_capacity(parts.headers.len());
+        for (name, value) in &parts.headers {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            if name == CONTENT_TYPE {
+                content_type = value.to_string();
+            } else {
+                headers.push((name.to_string(), value.to_string()));
+            }
+        }
+        Ok(InvokeResponse {
+            status: parts.status.as_u16(),
+            headers,
+            content_type,
+            data: data.to_vec(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl AppChannel for HttpChannel {
+    /// Apps are healthy when their health endpoint answers with a success status.
+    async fn health_probe(&self) -> AppHealth {
+        let path = self.health_check_path.trim_start_matches('/');
+        let Ok(uri) = self.uri(path, "") else {
+            return AppHealth::Unhealthy;
+        };
+        let mut builder = Request::get(uri);
+        if let Some(token) = &self.app_token {
+            builder = builder.header(API_TOKEN_HEADER, token.clone());
+        }
+        let request = builder
+            .body(Full::new(Bytes::new()))
+            .expect("health probes are valid requests");
+        match self.client.request(request).await {
+            Ok(response) if response.status().is_success() => AppHealth::Healthy,
+            Ok(_) => AppHealth::Unhealthy,
+            Err(_) => AppHealth::Unreachable,
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use futures::future::BoxFuture;
+    use hyper::Uri;
+    use hyper_util::rt::TokioIo;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::net::UnixStream;
+
+    /// UnixConnector connects every request to the same Unix socket.
+    #[derive(Clone)]
+    pub(super) struct UnixConnector(Arc<PathBuf>);
+
+    impl UnixConnector {
+        pub(super) fn new(path: &Path) -> Self {
+            Self(Arc::new(path.to_path_buf()))
+        }
+    }
+
+    impl tower::Service<Uri> for UnixConnector {
+        type Response = TokioIo<UnixStream>;
+        type Error = io::Error;
+        type Future = BoxFuture<'static, io::Result<Self::Response>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _uri: Uri) -> Self::Future {
+            let path = self.0.clone();
+            Box::pin(async move { UnixStream::connect(path.as_path()).await.map(TokioIo::new) })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{AppChannelConfig, AppProtocol};
+    use axum::Router;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Calls {
+        current: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    fn app(calls: Arc<Calls>) -> Router {
+        Router::new()
+            .route(
+                "/healthz",
+                get(|| async { StatusCode::SERVICE_UNAVAILABLE }),
+            )
+            .route(
+                "/orders/{id}",
+                post(
+                    |uri: Uri, headers: axum::http::HeaderMap, body: Bytes| async move {
+                        let tenant = headers["x-tenant"].clone();
+                        let content_types: Vec<&str> = headers
+                            .get_all(CONTENT_TYPE)
+                            .iter()
+                            .map(|value| value.to_str().unwrap())
+                            .collect();
+                        (
+                            StatusCode::CREATED,
+                            [("x-query", uri.query().unwrap_or_default().to_string())],
+                            [("x-tenant", tenant)],
+                            [("x-content-types", content_types.join(","))],
+                            body,
+                        )
+                    },
+                ),
+            )
+            .route(
+                "/slow",
+                post(move || async move {
+                    let current = calls.current.fetch_add(1, Ordering::SeqCst) + 1;
+                    calls.max.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    calls.current.fetch_sub(1, Ordering::SeqCst);
+                }),
+            )
+    }
+
+    #[tokio::test]
+    async fn test_http_channel() {
+        let calls = Arc::new(Calls::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(axum::serve(listener, app(calls.clone())).into_future());
+
+        let mut config = AppChannelConfig::new(AppProtocol::Http, AppAddress::Port(port));
+        config.max_concurrency = Some(2);
+        let channel = config.build().unwrap();
+        assert_eq!(channel.health_probe().await, AppHealth::Unhealthy);
+
+        let mut request = InvokeRequest::new("orders/1").with_data("text/plain", "hello");
+        request.query = "dry=true".to_string();
+        request.set_header("x-tenant", "a");
+        // Framing headers of the invoker are not forwarded to the app.
+        request.set_header("Content-Length", "100");
+        request.set_header("transfer-encoding", "chunked");
+        request.set_header("Host", "evil.example");
+        request.set_header("content-type", "text/html");
+        let response = channel.on_invoke(request).await.unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.data, b"hello");
+        assert_eq!(response.content_type, "application/octet-stream");
+        assert_eq!(response.header("x-query"), Some("dry=true"));
+        assert_eq!(response.header("x-tenant"), Some("a"));
+        assert_eq!(response.header("x-content-types"), Some("text/plain"));
+        let response = channel
+            .on_invoke(InvokeRequest::new("missing"))
+            .await
+            .unwrap();
+        assert_eq!(response.status, 404);
+
+        let slow = (0..6).map(|_| channel.on_invoke(InvokeRequest::new("slow")));
+        for response in futures::future::join_all(slow).await {
+            assert_eq!(response.unwrap().status, 200);
+        }
+        assert_eq!(calls.max.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_http_channel_app_token() {
+        let app = Router::new().route(
+            "/token",
+            post(|headers: axum::http::HeaderMap| async move {
+                let tokens = headers.get_all(API_TOKEN_HEADER).iter();
+                tokens
+                    .map(|token| token.to_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(axum::serve(listener, app).into_future());
+
+        let mut config = AppChannelConfig::new(AppProtocol::Http, AppAddress::Port(port));
+        config.app_token = Some(ApiToken::new("app-s3cret"));
+        let channel = config.build().unwrap();
+        let mut request = InvokeRequest::new("token");
+        request.set_header(API_TOKEN_HEADER, "forged");
+        let response = channel.on_invoke(request).await.unwrap();
+        assert_eq!(response.data, b"app-s3cret");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_http_channel_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("app.socket");
+        let channel = AppChannelConfig::new(AppProtocol::Http, AppAddress::Unix(path.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(channel.health_probe().await, AppHealth::Unreachable);
+
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(axum::serve(listener, app(Arc::default())).into_future());
+        let mut request = InvokeRequest::new("orders/2");
+        request.set_header("x-tenant", "b");
+        let response = channel.on_invoke(request).await.unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("x-tenant"), Some("b"));
+        assert_eq!(channel.health_probe().await, AppHealth::Unhealthy);
+    }
+}
@@ -0,0 +1,252 @@
+//! App channel: the calls the sidecar makes to its app.
+//!
+//! Invocations from other apps, actor methods and, eventually, pub/sub and bindings
+//! deliveries reach the app through an [`AppChannel`], speaking gRPC or HTTP to the app on
+//! a local port or a Unix domain socket. A channel caps the number of calls in flight, and
+//! the sidecar waits for the app to be [ready](wait_until_ready) before delivering anything.
+//! With an app token, every call carries it in the
+//! [`API_TOKEN_HEADER`](crate::api::API_TOKEN_HEADER) header, so the app can tell its
+//! sidecar from other callers.
+
+pub mod grpc;
+pub mod http;
+
+use crate::api::ApiToken;
+use crate::invocation::InvocationHandler;
+use snafu::Snafu;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// Default path of the health endpoint of HTTP apps.
+pub const DEFAULT_HEALTH_CHECK_PATH: &str = "/healthz";
+
+#[derive(Debug, Snafu)]
+pub enum ChannelError {
+    #[snafu(display("Invalid app address {}: {}", address, source))]
+    InvalidAddress {
+        address: String,
+        source: tonic::transport::Error,
+    },
+
+    #[snafu(display("App did not become ready within {:?}", timeout))]
+    NotReady { timeout: Duration },
+
+    #[snafu(display("Unknown app protocol {:?}: expected grpc or http", protocol))]
+    UnknownProtocol { protocol: String },
+}
+
+pub type Result<T> = std::result::Result<T, ChannelError>;
+
+/// Health of an app, as reported by a probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppHealth {
+    /// The app reported itself healthy.
+    Healthy,
+    /// The app answered the probe, but reported itself unhealthy.
+    Unhealthy,
+    /// The app could not be reached.
+    Unreachable,
+}
+
+/// AppChannel delivers calls to the app. Invocations go through [`InvocationHandler`].
+#[async_trait::async_trait]
+pub trait AppChannel: InvocationHandler {
+    /// Probes the health of the app.
+    async fn health_probe(&self) -> AppHealth;
+}
+
+/// Protocol the app speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppProtocol {
+    Grpc,
+    Http,
+}
+
+impl FromStr for AppProtocol {
+    type Err = ChannelError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "grpc" => Ok(Self::Grpc),
+            "http" => Ok(Self::Http),
+            _ => Err(ChannelError::UnknownProtocol {
+                protocol: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for AppProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Grpc => "grpc",
+            Self::Http => "http",
+        })
+    }
+}
+
+/// Where the app listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppAddress {
+    /// A port of the loopback interface.
+    Port(u16),
+    /// A Unix domain socket.
+    Unix(PathBuf),
+}
+
+impl fmt::Display for AppAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Port(port) => write!(f, "127.0.0.1:{port}"),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+/// AppChannelConfig describes how to reach the app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppChannelConfig {
+    pub protocol: AppProtocol,
+    pub address: AppAddress,
+    /// Maximum number of calls in flight to the app. Unlimited when `None`.
+    pub max_concurrency: Option<usize>,
+    /// Path probed for the health of HTTP apps.
+    pub health_check_path: String,
+    /// Token sent with every call to the app, if any.
+    pub app_token: Option<ApiToken>,
+}
+
+impl AppChannelConfig {
+    /// Creates the configuration of an app without concurrency limit.
+    pub fn new(protocol: AppProtocol, address: AppAddress) -> Self {
+        Self {
+            protocol,
+            address,
+            max_concurrency: None,
+            health_check_path: DEFAULT_HEALTH_CHECK_PATH.to_string(),
+            app_token: None,
+        }
+    }
+
+    /// Creates the channel. Connections are only made by the first calls.
+    pub fn build(&self) -> Result<Arc<dyn AppChannel>> {
+        let limit = ConcurrencyLimit::new(self.max_concurrency);
+        Ok(match self.protocol {
+            AppProtocol::Grpc => Arc::new(grpc::GrpcChannel::new(
+                &self.address,
+                self.app_token.clone(),
+                limit,
+            )?),
+            AppProtocol::Http => Arc::new(http::HttpChannel::new(
+                &self.address,
+                &self.health_check_path,
+                self.app_token.clone(),
+                limit,
+            )),
+        })
+    }
+}
+
+/// Waits until the app accepts calls, probing it every `interval`, for at most `timeout`.
+/// Apps answering their probe are ready even when they report themselves unhealthy.
+pub async fn wait_until_ready(
+    channel: &dyn AppChannel,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<()> {
+    tokio::time::timeout(timeout, poll_until_ready(channel, interval))
+        .await
+        .map_err(|_| ChannelError::NotReady { timeout })
+}
+
+/// Waits, however long it takes, until the app accepts calls, probing it every `interval`.
+pub async fn poll_until_ready(channel: &dyn AppChannel, interval: Duration) {
+    let mut attempts = 0u32;
+    while channel.health_probe().await == AppHealth::Unreachable {
+        attempts += 1;
+        if attempts.is_multiple_of(10) {
+            tracing::info!(attempts, "Waiting for the app to be ready");
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// ConcurrencyLimit caps the number of calls in flight.
+pub(crate) struct ConcurrencyLimit(Option<Semaphore>);
+
+impl ConcurrencyLimit {
+    pub(crate) fn new(max: Option<usize>) -> Self {
+        Self(max.map(|max| Semaphore::new(max.max(1))))
+    }
+
+    /// Waits for a slot, released when the permit is dropped.
+    pub(crate) async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        match &self.0 {
+            Some(semaphore) => Some(
+                semaphore
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed"),
+            ),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::invocation::{InvokeRequest, InvokeResponse};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Becomes reachable after a number of probes.
+    struct Starting(AtomicU32);
+
+    #[async_trait::async_trait]
+    impl InvocationHandler for Starting {
+        async fn on_invoke(
+            &self,
+            _request: InvokeRequest,
+        ) -> crate::invocation::Result<InvokeResponse> {
+            Ok(InvokeResponse::ok("", ""))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AppChannel for Starting {
+        async fn health_probe(&self) -> AppHealth {
+            match self.0.fetch_sub(1, Ordering::SeqCst) {
+                0 => AppHealth::Unhealthy,
+                _ => AppHealth::Unreachable,
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_until_ready() {
+        let interval = Duration::from_secs(1);
+        let app = Starting(AtomicU32::new(3));
+        wait_until_ready(&app, interval, Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        let app = Starting(AtomicU32::new(30));
+        assert!(matches!(
+            wait_until_ready(&app, interval, Duration::from_secs(10)).await,
+            Err(ChannelError::NotReady { .. })
+        ));
+        // Slow apps become ready once polled long enough.
+        poll_until_ready(&app, interval).await;
+    }
+
+    #[test]
+    fn test_protocol() {
+        assert_eq!("GRPC".parse::<AppProtocol>().unwrap(), AppProtocol::Grpc);
+        assert_eq!("http".parse::<AppProtocol>().unwrap(), AppProtocol::Http);
+        assert!("h2".parse::<AppProtocol>().is_err());
+    }
+}
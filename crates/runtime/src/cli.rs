@@ -0,0 +1,819 @@
+//! Command line of the runtime binary.
+//!
+//! Every flag falls back to an environment variable, then to a default. [`Cli::resolve`]
+//! validates the result into a [`RuntimeConfig`].
+
+use crate::api::ApiToken;
+use crate::api::grpc::DEFAULT_GRPC_PORT;
+use crate::api::http::DEFAULT_HTTP_PORT;
+use crate::channel::{AppAddress, AppChannelConfig, AppProtocol};
+use crate::configuration::ConfigurationSource;
+use crate::diagnostics::DEFAULT_METRICS_PORT;
+use crate::invocation::grpc::DEFAULT_INTERNAL_GRPC_PORT;
+use crate::logging::{LogFormat, LogLevels};
+use crate::meta::Options;
+use crate::shutdown::DEFAULT_GRACE_PERIOD;
+use clap::Parser;
+use rapr_apis::configuration::v1alpha1::ConfigurationSpec;
+use rapr_common::RaprMode;
+use rapr_common::utils::get_env_or_else;
+use snafu::{ResultExt, Snafu};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Environment variable holding the id of the app.
+pub const ENV_APP_ID: &str = "APP_ID";
+/// Environment variable holding the port of the app.
+pub const ENV_APP_PORT: &str = "APP_PORT";
+/// Environment variable holding the Unix socket of the app.
+pub const ENV_APP_UDS_PATH: &str = "RAPR_APP_UDS_PATH";
+/// Environment variable holding the protocol of the app.
+pub const ENV_APP_PROTOCOL: &str = "RAPR_APP_PROTOCOL";
+/// Environment variable holding the concurrency limit of the app.
+pub const ENV_APP_MAX_CONCURRENCY: &str = "RAPR_APP_MAX_CONCURRENCY";
+/// Environment variable holding the mode of the runtime.
+pub const ENV_MODE: &str = "RAPR_MODE";
+/// Environment variable holding the resource directories, comma separated.
+pub const ENV_RESOURCES_PATH: &str = "RAPR_RESOURCES_PATH";
+/// Environment variable holding the port of the gRPC API.
+pub const ENV_GRPC_PORT: &str = "RAPR_GRPC_PORT";
+/// Environment variable holding the port of the HTTP API.
+pub const ENV_HTTP_PORT: &str = "RAPR_HTTP_PORT";
+/// Environment variable holding the port other sidecars invoke the app on.
+pub const ENV_INTERNAL_GRPC_PORT: &str = "RAPR_INTERNAL_GRPC_PORT";
+/// Environment variable holding the port of the metrics endpoint.
+pub const ENV_METRICS_PORT: &str = "RAPR_METRICS_PORT";
+/// Environment variable enabling mutual TLS between sidecars.
+pub const ENV_ENABLE_MTLS: &str = "RAPR_ENABLE_MTLS";
+/// Environment variable holding the directory of the credentials of the sidecar.
+pub const ENV_CREDENTIALS_DIR: &str = "RAPR_CREDENTIALS_DIR";
+/// Environment variable holding the address of the certificate authority.
+pub const ENV_CA_ADDRESS: &str = "RAPR_CA_ADDRESS";
+/// Environment variable holding the trust bundle of the certificate authority.
+pub const ENV_CA_TRUST_BUNDLE: &str = "RAPR_CA_TRUST_BUNDLE";
+/// Environment variable holding the token the sidecar proves its app id to the certificate
+/// authority with. Only read from the environment, so it does not show in process lists.
+pub const ENV_CA_TOKEN: &str = "RAPR_CA_TOKEN";
+/// Environment variable holding the token calls to the APIs must present.
+pub const ENV_API_TOKEN: &str = "RAPR_API_TOKEN";
+/// Environment variable holding the file of the token calls to the APIs must present.
+pub const ENV_API_TOKEN_FILE: &str = "RAPR_API_TOKEN_FILE";
+/// Environment variable holding the token the sidecar presents on calls to the app.
+pub const ENV_APP_API_TOKEN: &str = "APP_API_TOKEN";
+/// Environment variable holding the file of the token the sidecar presents on calls to the
+/// app.
+pub const ENV_APP_API_TOKEN_FILE: &str = "APP_API_TOKEN_FILE";
+/// Environment variable holding the directory of the Unix sockets of the APIs.
+pub const ENV_UDS_DIR: &str = "RAPR_UDS_DIR";
+/// Environment variable holding the directory of the histories of workflow instances.
+pub const ENV_WORKFLOW_DIR: &str = "RAPR_WORKFLOW_DIR";
+/// Environment variable holding the log levels.
+pub const ENV_LOG_LEVEL: &str = "RAPR_LOG_LEVEL";
+/// Environment variable holding the format of the log lines.
+pub const ENV_LOG_FORMAT: &str = "RAPR_LOG_FORMAT";
+/// Environment variable holding the time the runtime is given to shut down, in seconds.
+pub const ENV_SHUTDOWN_GRACE_PERIOD: &str = "RAPR_SHUTDOWN_GRACE_PERIOD";
+/// Environment variable holding the configuration.
+pub const ENV_CONFIG: &str = "RAPR_CONFIG";
+/// Environment variable holding the name of the pod of the sidecar.
+pub const ENV_POD_NAME: &str = "POD_NAME";
+/// Environment variable holding the namespace of the app.
+pub const ENV_NAMESPACE: &str = "NAMESPACE";
+
+#[derive(Debug, Snafu)]
+pub enum CliError {
+    #[snafu(display("The app id is required: pass --app-id or set {}", ENV_APP_ID))]
+    MissingAppId,
+
+    #[snafu(display(
+        "Invalid app id {:?}: app ids may only contain letters, digits and dashes",
+        app_id
+    ))]
+    InvalidAppId { app_id: String },
+
+    #[snafu(display("Invalid value {:?} for {}: {}", value, name, reason))]
+    InvalidValue {
+        name: String,
+        value: String,
+        reason: String,
+    },
+
+    #[snafu(display("The {} and {} cannot both listen on port {}", first, second, port))]
+    PortConflict {
+        first: String,
+        second: String,
+        port: u16,
+    },
+
+    #[snafu(display("The {} {} does not exist", name, path.display()))]
+    PathNotFound { name: String, path: PathBuf },
+
+    #[snafu(display("Failed to read the {} {}: {}", name, path.display(), source))]
+    ReadFile {
+        name: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, CliError>;
+
+/// Command line of the runtime.
+#[derive(Debug, Default, Parser)]
+#[command(
+    name = "rapr-runtime",
+    version,
+    about = "Sidecar runtime of a rapr app"
+)]
+pub struct Cli {
+    /// Id of the app [env: APP_ID]
+    #[arg(long)]
+    pub app_id: Option<String>,
+
+    /// Mode of the runtime, kubernetes or standalone [env: RAPR_MODE] [default: standalone]
+    #[arg(long)]
+    pub mode: Option<String>,
+
+    /// Directory of resource files, may be repeated [env: RAPR_RESOURCES_PATH, comma separated]
+    #[arg(long)]
+    pub resources_path: Vec<PathBuf>,
+
+    /// Port of the gRPC API [env: RAPR_GRPC_PORT] [default: 50001]
+    #[arg(long)]
+    pub grpc_port: Option<u16>,
+
+    /// Port of the HTTP API [env: RAPR_HTTP_PORT] [default: 3500]
+    #[arg(long)]
+    pub http_port: Option<u16>,
+
+    /// Port other sidecars invoke the app on [env: RAPR_INTERNAL_GRPC_PORT] [default: 50002]
+    #[arg(long)]
+    pub internal_grpc_port: Option<u16>,
+
+    /// Port of the Prometheus metrics endpoint, unless the configuration disables metrics
+    /// [env: RAPR_METRICS_PORT] [default: 9090]
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    /// Authenticate and encrypt the traffic between sidecars [env: RAPR_ENABLE_MTLS]
+    #[arg(long)]
+    pub enable_mtls: bool,
+
+    /// Directory of the tls.crt, tls.key and ca.crt credentials of the sidecar, reloaded
+    /// when they change. Without it, the certificate is requested from --ca-address, or
+    /// else issued by a self-signed CA [env: RAPR_CREDENTIALS_DIR]
+    #[arg(long)]
+    pub credentials_dir: Option<PathBuf>,
+
+    /// Address of the certificate authority issuing the certificate of the sidecar when no
+    /// credentials directory is set, as host:port [env: RAPR_CA_ADDRESS]
+    #[arg(long)]
+    pub ca_address: Option<String>,
+
+    /// PEM trust bundle the certificate authority is checked against, required with
+    /// --ca-address [env: RAPR_CA_TRUST_BUNDLE]
+    #[arg(long)]
+    pub ca_trust_bundle: Option<PathBuf>,
+
+    /// File of the token calls to the APIs must present, in the rapr-api-token header
+    /// [env: RAPR_API_TOKEN holding the token, or RAPR_API_TOKEN_FILE]
+    #[arg(long)]
+    pub api_token_file: Option<PathBuf>,
+
+    /// File of the token the sidecar presents on calls to the app, in the rapr-api-token
+    /// header [env: APP_API_TOKEN holding the token, or APP_API_TOKEN_FILE]
+    #[arg(long)]
+    pub app_api_token_file: Option<PathBuf>,
+
+    /// Directory of the Unix sockets also serving the APIs [env: RAPR_UDS_DIR]
+    #[arg(long)]
+    pub uds_dir: Option<PathBuf>,
+
+    /// Directory the histories of workflow instances are stored in. Without it, they are
+    /// kept in memory and lost on restart [env: RAPR_WORKFLOW_DIR]
+    #[arg(long)]
+    pub workflow_dir: Option<PathBuf>,
+
+    /// Port the app listens on [env: APP_PORT]
+    #[arg(long)]
+    pub app_port: Option<u16>,
+
+    /// Unix socket the app listens on, instead of a port [env: RAPR_APP_UDS_PATH]
+    #[arg(long)]
+    pub app_uds_path: Option<PathBuf>,
+
+    /// Protocol the app speaks, grpc or http [env: RAPR_APP_PROTOCOL] [default: http]
+    #[arg(long)]
+    pub app_protocol: Option<String>,
+
+    /// Maximum number of concurrent calls to the app [env: RAPR_APP_MAX_CONCURRENCY]
+    #[arg(long)]
+    pub app_max_concurrency: Option<usize>,
+
+    /// Log level: trace, debug, info, warn or error, optionally followed by levels per scope
+    /// such as info,runtime.actors=debug [env: RAPR_LOG_LEVEL] [default: info]
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// Format of the log lines, text or json [env: RAPR_LOG_FORMAT] [default: text]
+    #[arg(long)]
+    pub log_format: Option<String>,
+
+    /// Seconds the runtime is given to drain in-flight calls and close its components once
+    /// asked to stop [env: RAPR_SHUTDOWN_GRACE_PERIOD] [default: 5]
+    #[arg(long)]
+    pub shutdown_grace_period: Option<u64>,
+
+    /// Configuration of the runtime: a file in standalone mode, the name of a resource in
+    /// kubernetes mode [env: RAPR_CONFIG]
+    #[arg(long)]
+    pub config: Option<String>,
+}
+
+/// RuntimeConfig is the validated configuration of the runtime.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub app_id: String,
+    pub mode: RaprMode,
+    pub namespace: String,
+    pub pod_name: String,
+    pub resources_paths: Vec<PathBuf>,
+    pub grpc_port: u16,
+    pub http_port: u16,
+    pub internal_grpc_port: u16,
+    pub metrics_port: u16,
+    pub enable_mtls: bool,
+    pub credentials_dir: Option<PathBuf>,
+    pub ca: Option<CaConfig>,
+    /// Token calls to the APIs must present, if any.
+    pub api_token: Option<ApiToken>,
+    pub uds_dir: Option<PathBuf>,
+    /// Directory of the histories of workflow instances, if they are persisted.
+    pub workflow_dir: Option<PathBuf>,
+    /// How to reach the app, if it listens.
+    pub app_channel: Option<AppChannelConfig>,
+    pub log_levels: LogLevels,
+    pub log_format: LogFormat,
+    pub shutdown_grace_period: Duration,
+    pub config: Option<ConfigurationSource>,
+}
+
+/// CaConfig is how the sidecar requests its certificate from a certificate authority.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaConfig {
+    /// Address of the authority, as host:port.
+    pub address: String,
+    pub trust_bundle: PathBuf,
+    pub token: String,
+}
+
+impl RuntimeConfig {
+    /// Returns the options of the metadata of the app, under a configuration.
+    pub fn meta_options(&self, configuration: &ConfigurationSpec) -> Options {
+        Options {
+            id: self.app_id.clone(),
+            pod_name: self.pod_name.clone(),
+            namespace: self.namespace.clone(),
+            strict_sandbox: configuration.wasm_strict_sandbox(),
+            mode: self.mode.clone(),
+        }
+    }
+}
+
+impl Cli {
+    /// Validates the command line, reading unset flags from the environment.
+    pub fn resolve(self) -> Result<RuntimeConfig> {
+        self.resolve_with(get_env_or_else)
+    }
+
+    /// Validates the command line, reading unset flags with `env`, which returns the value
+    /// of a variable or the given default.
+    pub fn resolve_with(self, env: impl Fn(&str, &str) -> String) -> Result<RuntimeConfig> {
+        // Empty values stand for unset variables.
+        let var = |name: &str| Some(env(name, "")).filter(|value| !value.is_empty());
+
+        let app_id = self
+            .app_id
+            .or_else(|| var(ENV_APP_ID))
+            .ok_or(CliError::MissingAppId)?;
+        if !app_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(CliError::InvalidAppId { app_id });
+        }
+
+        let mode = match self.mode.or_else(|| var(ENV_MODE)) {
+            None => RaprMode::Standalone,
+            Some(mode) => match mode.to_ascii_lowercase().as_str() {
+                "standalone" => RaprMode::Standalone,
+                "kubernetes" => RaprMode::Kubernetes,
+                _ => {
+                    return Err(invalid("--mode", mode, "expected kubernetes or standalone"));
+                }
+            },
+        };
+
+        let resources_paths = match self.resources_path.is_empty() {
+            false => self.resources_path,
+            true => var(ENV_RESOURCES_PATH)
+                .map(|paths| {
+                    paths
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|path| !path.is_empty())
+                        .map(PathBuf::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        for path in &resources_paths {
+            if !path.is_dir() {
+                return Err(CliError::PathNotFound {
+                    name: "resources directory".to_string(),
+                    path: path.clone(),
+                });
+            }
+        }
+
+        let grpc_port =
+            port("--grpc-port", self.grpc_port, var(ENV_GRPC_PORT))?.unwrap_or(DEFAULT_GRPC_PORT);
+        let http_port =
+            port("--http-port", self.http_port, var(ENV_HTTP_PORT))?.unwrap_or(DEFAULT_HTTP_PORT);
+        let internal_grpc_port = port(
+            "--internal-grpc-port",
+            self.internal_grpc_port,
+            var(ENV_INTERNAL_GRPC_PORT),
+        )?
+        .unwrap_or(DEFAULT_INTERNAL_GRPC_PORT);
+        let metrics_port = port("--metrics-port", self.metrics_port, var(ENV_METRICS_PORT))?
+            .unwrap_or(DEFAULT_METRICS_PORT);
+        let ports = [
+            ("gRPC API", grpc_port),
+            ("HTTP API", http_port),
+            ("internal gRPC server", internal_grpc_port),
+            ("metrics endpoint", metrics_port),
+        ];
+        for (i, (first, port)) in ports.iter().enumerate() {
+            if let Some((second, _)) = ports[i + 1..]
+                .iter()
+                .find(|(_, other)| other == port && *port != 0)
+            {
+                return Err(CliError::PortConflict {
+                    first: first.to_string(),
+                    second: second.to_string(),
+                    port: *port,
+                });
+            }
+        }
+
+        let enable_mtls = match (self.enable_mtls, var(ENV_ENABLE_MTLS)) {
+            (true, _) | (false, None) => self.enable_mtls,
+            (false, Some(enabled)) => enabled
+                .parse()
+                .map_err(|_| invalid("--enable-mtls", enabled, "expected true or false"))?,
+        };
+        let credentials_dir = self
+            .credentials_dir
+            .or_else(|| var(ENV_CREDENTIALS_DIR).map(PathBuf::from));
+        if let Some(dir) = &credentials_dir
+            && !dir.is_dir()
+        {
+            return Err(CliError::PathNotFound {
+                name: "credentials directory".to_string(),
+                path: dir.clone(),
+            });
+        }
+
+        let ca = match self.ca_address.or_else(|| var(ENV_CA_ADDRESS)) {
+            None => None,
+            Some(address) => {
+                let trust_bundle = self
+                    .ca_trust_bundle
+                    .or_else(|| var(ENV_CA_TRUST_BUNDLE).map(PathBuf::from))
+                    .ok_or_else(|| {
+                        invalid(
+                            "--ca-address",
+                            address.as_str(),
+                            "--ca-trust-bundle is required with it",
+                        )
+                    })?;
+                if !trust_bundle.is_file() {
+                    return Err(CliError::PathNotFound {
+                        name: "certificate authority trust bundle".to_string(),
+                        path: trust_bundle,
+                    });
+                }
+                Some(CaConfig {
+                    address,
+                    trust_bundle,
+                    token: env(ENV_CA_TOKEN, ""),
+                })
+            }
+        };
+
+        let api_token = token(
+            "API token file",
+            self.api_token_file,
+            var(ENV_API_TOKEN),
+            var(ENV_API_TOKEN_FILE),
+        )?;
+        let app_token = token(
+            "app API token file",
+            self.app_api_token_file,
+            var(ENV_APP_API_TOKEN),
+            var(ENV_APP_API_TOKEN_FILE),
+        )?;
+
+        let app_port = port("--app-port", self.app_port, var(ENV_APP_PORT))?;
+        let app_uds_path = self
+            .app_uds_path
+            .or_else(|| var(ENV_APP_UDS_PATH).map(PathBuf::from));
+        let app_address = match (app_port, app_uds_path) {
+            (Some(_), Some(path)) => {
+                return Err(invalid(
+                    "--app-uds-path",
+                    path.display().to_string(),
+                    "the app listens on either a port or a Unix socket",
+                ));
+            }
+            (Some(port), None) => Some(AppAddress::Port(port)),
+            (None, Some(path)) => Some(AppAddress::Unix(path)),
+            (None, None) => None,
+        };
+        let app_protocol = match self.app_protocol.or_else(|| var(ENV_APP_PROTOCOL)) {
+            None => AppProtocol::Http,
+            Some(protocol) => protocol
+                .parse()
+                .map_err(|e: crate::channel::ChannelError| {
+                    invalid("--app-protocol", protocol, e.to_string())
+                })?,
+        };
+        let app_max_concurrency = match self.app_max_concurrency {
+            Some(max) => Some(max),
+            None => var(ENV_APP_MAX_CONCURRENCY)
+                .map(|max| {
+                    max.parse()
+                        .map_err(|_| invalid("--app-max-concurrency", max, "expected a number"))
+                })
+                .transpose()?,
+        };
+        if app_max_concurrency == Some(0) {
+            return Err(invalid("--app-max-concurrency", "0", "expected at least 1"));
+        }
+        let app_channel = app_address.map(|address| AppChannelConfig {
+            max_concurrency: app_max_concurrency,
+            app_token,
+            ..AppChannelConfig::new(app_protocol, address)
+        });
+
+        let log_levels = match self.log_level.or_else(|| var(ENV_LOG_LEVEL)) {
+            None => LogLevels::default(),
+            Some(levels) => levels.parse().map_err(|_| {
+                invalid(
+                    "--log-level",
+                    levels,
+                    "expected trace, debug, info, warn or error, then scope=level pairs",
+                )
+            })?,
+        };
+        let log_format = match self.log_format.or_else(|| var(ENV_LOG_FORMAT)) {
+            None => LogFormat::Text,
+            Some(format) => format
+                .parse()
+                .map_err(|_| invalid("--log-format", format, "expected text or json"))?,
+        };
+
+        let shutdown_grace_period = match self.shutdown_grace_period {
+            Some(seconds) => Duration::from_secs(seconds),
+            None => match var(ENV_SHUTDOWN_GRACE_PERIOD) {
+                None => DEFAULT_GRACE_PERIOD,
+                Some(seconds) => seconds.parse().map(Duration::from_secs).map_err(|_| {
+                    invalid(
+                        "--shutdown-grace-period",
+                        seconds,
+                        "expected a number of seconds",
+                    )
+                })?,
+            },
+        };
+
+        let namespace = env(ENV_NAMESPACE, "default");
+        let config = match (self.config.or_else(|| var(ENV_CONFIG)), &mode) {
+            (None, _) => None,
+            (Some(name), RaprMode::Kubernetes) => Some(ConfigurationSource::Kubernetes {
+                namespace: namespace.clone(),
+                name,
+            }),
+            (Some(path), _) => {
+                let path = PathBuf::from(path);
+                if !path.is_file() {
+                    return Err(CliError::PathNotFound {
+                        name: "configuration file".to_string(),
+                        path,
+                    });
+                }
+                Some(ConfigurationSource::File(path))
+            }
+        };
+
+        Ok(RuntimeConfig {
+            app_id,
+            mode,
+            namespace,
+            pod_name: env(ENV_POD_NAME, ""),
+            resources_paths,
+            grpc_port,
+            http_port,
+            internal_grpc_port,
+            metrics_port,
+            enable_mtls,
+            credentials_dir,
+            ca,
+            api_token,
+            uds_dir: self.uds_dir.or_else(|| var(ENV_UDS_DIR).map(PathBuf::from)),
+            workflow_dir: self
+                .workflow_dir
+                .or_else(|| var(ENV_WORKFLOW_DIR).map(PathBuf::from)),
+            app_channel,
+            log_levels,
+            log_format,
+            shutdown_grace_period,
+            config,
+        })
+    }
+}
+
+fn invalid(name: &str, value: impl Into<String>, reason: impl Into<String>) -> CliError {
+    CliError::InvalidValue {
+        name: name.to_string(),
+        value: value.into(),
+        reason: reason.into(),
+    }
+}
+
+/// Returns the port of a flag, or of its environment variable when the flag is not set.
+fn port(flag: &str, value: Option<u16>, env: Option<String>) -> Result<Option<u16>> {
+    match (value, env) {
+        (Some(port), _) => Ok(Some(port)),
+        (None, Some(port)) => port
+            .parse()
+            .map(Some)
+            .map_err(|_| invalid(flag, port, "expected a port between 0 and 65535")),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Returns the token of the file of a flag or, when the flag is not set, of the environment:
+/// a variable holding the token, or else one naming its file.
+fn token(
+    name: &str,
+    file: Option<PathBuf>,
+    env: Option<String>,
+    file_env: Option<String>,
+) -> Result<Option<ApiToken>> {
+    let path = match (file, env) {
+        (Some(path), _) => path,
+        (None, Some(token)) => return Ok(Some(ApiToken::new(token))),
+        (None, None) => match file_env {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(None),
+        },
+    };
+    let token = std::fs::read_to_string(&path).context(ReadFileSnafu { name, path: &path })?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(invalid(
+            name,
+            path.display().to_string(),
+            "the file is empty",
+        ));
+    }
+    Ok(Some(ApiToken::new(token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn resolve(args: &[&str], env: &[(&str, &str)]) -> Result<RuntimeConfig> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let cli = Cli::try_parse_from(std::iter::once("rapr-runtime").chain(args.iter().copied()))
+            .unwrap();
+        cli.resolve_with(|name, default| {
+            env.get(name)
+                .cloned()
+                .unwrap_or_else(|| default.to_string())
+        })
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = resolve(&["--app-id", "orders"], &[]).unwrap();
+        assert_eq!(config.app_id, "orders");
+        assert_eq!(config.mode, RaprMode::Standalone);
+        assert_eq!(config.namespace, "default");
+        assert_eq!(config.grpc_port, DEFAULT_GRPC_PORT);
+        assert_eq!(config.http_port, DEFAULT_HTTP_PORT);
+        assert_eq!(config.internal_grpc_port, DEFAULT_INTERNAL_GRPC_PORT);
+        assert_eq!(config.metrics_port, DEFAULT_METRICS_PORT);
+        assert!(!config.enable_mtls);
+        assert_eq!(config.app_channel, None);
+        assert_eq!(config.log_levels, LogLevels::default());
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert_eq!(config.shutdown_grace_period, DEFAULT_GRACE_PERIOD);
+        assert_eq!(config.config, None);
+        assert_eq!(config.ca, None);
+        assert_eq!(config.api_token, None);
+        assert_eq!(config.workflow_dir, None);
+        let options = config.meta_options(&ConfigurationSpec::default());
+        assert_eq!(options.id, "orders");
+        assert!(!options.strict_sandbox);
+    }
+
+    #[test]
+    fn test_env_fallbacks() {
+        let dir = tempfile::tempdir().unwrap();
+        let resources = dir.path().display().to_string();
+        let bundle = dir.path().join("ca.crt");
+        std::fs::write(&bundle, "").unwrap();
+        let bundle = bundle.display().to_string();
+        let app_token = dir.path().join("app-token");
+        std::fs::write(&app_token, "app-token\n").unwrap();
+        let app_token = app_token.display().to_string();
+        let env = [
+            (ENV_APP_ID, "orders"),
+            (ENV_MODE, "kubernetes"),
+            (ENV_GRPC_PORT, "6000"),
+            (ENV_METRICS_PORT, "9091"),
+            (ENV_APP_PORT, "8080"),
+            (ENV_APP_PROTOCOL, "grpc"),
+            (ENV_LOG_LEVEL, "debug,runtime.actors=trace"),
+            (ENV_LOG_FORMAT, "json"),
+            (ENV_SHUTDOWN_GRACE_PERIOD, "30"),
+            (ENV_RESOURCES_PATH, resources.as_str()),
+            (ENV_NAMESPACE, "prod"),
+            (ENV_CONFIG, "appconfig"),
+            (ENV_ENABLE_MTLS, "true"),
+            (ENV_CA_ADDRESS, "ca.rapr-system:50003"),
+            (ENV_CA_TRUST_BUNDLE, bundle.as_str()),
+            (ENV_CA_TOKEN, "token"),
+            (ENV_API_TOKEN, "api-token"),
+            (ENV_APP_API_TOKEN_FILE, app_token.as_str()),
+            (ENV_WORKFLOW_DIR, "/var/lib/rapr/workflows"),
+        ];
+        let config = resolve(&["--grpc-port", "7000"], &env).unwrap();
+        assert_eq!(config.app_id, "orders");
+        assert_eq!(config.mode, RaprMode::Kubernetes);
+        assert_eq!(config.namespace, "prod");
+        // Flags take precedence over the environment.
+        assert_eq!(config.grpc_port, 7000);
+        assert_eq!(config.metrics_port, 9091);
+        assert_eq!(config.resources_paths, [dir.path()]);
+        assert_eq!(
+            config.log_levels,
+            "debug,runtime.actors=trace".parse().unwrap()
+        );
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert_eq!(config.shutdown_grace_period, Duration::from_secs(30));
+        assert!(config.enable_mtls);
+        assert_eq!(
+            config.config,
+            Some(ConfigurationSource::Kubernetes {
+                namespace: "prod".to_string(),
+                name: "appconfig".to_string(),
+            })
+        );
+        assert_eq!(
+            config.ca,
+            Some(CaConfig {
+                address: "ca.rapr-system:50003".to_string(),
+                trust_bundle: PathBuf::from(&bundle),
+                token: "token".to_string(),
+            })
+        );
+        assert_eq!(config.api_token, Some(ApiToken::new("api-token")));
+        assert_eq!(
+            config.workflow_dir,
+            Some(PathBuf::from("/var/lib/rapr/workflows"))
+        );
+        let channel = config.app_channel.unwrap();
+        assert_eq!(channel.protocol, AppProtocol::Grpc);
+        assert_eq!(channel.address, AppAddress::Port(8080));
+        assert_eq!(channel.app_token, Some(ApiToken::new("app-token")));
+    }
+
+    #[test]
+    fn test_app_uds_path() {
+        let env = [(ENV_APP_ID, "orders"), (ENV_APP_UDS_PATH, "/tmp/env.sock")];
+        let config = resolve(&[], &env).unwrap();
+        assert_eq!(
+            config.app_channel.unwrap().address,
+            AppAddress::Unix(PathBuf::from("/tmp/env.sock"))
+        );
+        let config = resolve(&["--app-uds-path", "/tmp/app.sock"], &env).unwrap();
+        let channel = config.app_channel.unwrap();
+        assert_eq!(channel.protocol, AppProtocol::Http);
+        assert_eq!(
+            channel.address,
+            AppAddress::Unix(PathBuf::from("/tmp/app.sock"))
+        );
+    }
+
+    type Case<'a> = (&'a [&'a str], &'a [(&'a str, &'a str)], &'a str);
+
+    #[test]
+    fn test_validation_errors() {
+        let cases: &[Case] = &[
+            (
+                &[],
+                &[],
+                "The app id is required: pass --app-id or set APP_ID",
+            ),
+            (
+                &["--app-id", "a.b"],
+                &[],
+                "Invalid app id \"a.b\": app ids may only contain letters, digits and dashes",
+            ),
+            (
+                &["--app-id", "a", "--mode", "cloud"],
+                &[],
+                "Invalid value \"cloud\" for --mode: expected kubernetes or standalone",
+            ),
+            (
+                &["--app-id", "a"],
+                &[(ENV_HTTP_PORT, "http")],
+                "Invalid value \"http\" for --http-port: expected a port between 0 and 65535",
+            ),
+            (
+                &[
+                    "--app-id",
+                    "a",
+                    "--grpc-port",
+                    "4000",
+                    "--http-port",
+                    "4000",
+                ],
+                &[],
+                "The gRPC API and HTTP API cannot both listen on port 4000",
+            ),
+            (
+                &["--app-id", "a", "--internal-grpc-port", "3500"],
+                &[],
+                "The HTTP API and internal gRPC server cannot both listen on port 3500",
+            ),
+            (
+                &["--app-id", "a", "--metrics-port", "50001"],
+                &[],
+                "The gRPC API and metrics endpoint cannot both listen on port 50001",
+            ),
+            (
+                &["--app-id", "a"],
+                &[(ENV_ENABLE_MTLS, "yes")],
+                "Invalid value \"yes\" for --enable-mtls: expected true or false",
+            ),
+            (
+                &["--app-id", "a", "--ca-address", "ca:50003"],
+                &[],
+                "Invalid value \"ca:50003\" for --ca-address: --ca-trust-bundle is required with it",
+            ),
+            (
+                &["--app-id", "a", "--api-token-file", "/does/not/exist"],
+                &[],
+                "Failed to read the API token file /does/not/exist: No such file or directory (os error 2)",
+            ),
+            (
+                &["--app-id", "a", "--log-level", "loud"],
+                &[],
+                "Invalid value \"loud\" for --log-level: expected trace, debug, info, warn or error, then scope=level pairs",
+            ),
+            (
+                &["--app-id", "a", "--log-format", "xml"],
+                &[],
+                "Invalid value \"xml\" for --log-format: expected text or json",
+            ),
+            (
+                &["--app-id", "a"],
+                &[(ENV_SHUTDOWN_GRACE_PERIOD, "5s")],
+                "Invalid value \"5s\" for --shutdown-grace-period: expected a number of seconds",
+            ),
+            (
+                &["--app-id", "a", "--app-uds-path", "/tmp/app.sock"],
+                &[(ENV_APP_PORT, "8080")],
+                "Invalid value \"/tmp/app.sock\" for --app-uds-path: the app listens on either a port or a Unix socket",
+            ),
+            (
+                &["--app-id", "a", "--config", "/does/not/exist.yaml"],
+                &[],
+                "The configuration file /does/not/exist.yaml does not exist",
+            ),
+        ];
+        for (args, env, message) in cases {
+            assert_eq!(resolve(args, env).unwrap_err().to_string(), *message);
+        }
+    }
+}
@@ -0,0 +1,1300 @@
+use super::{
+    ClusterError, MAX_PACKET_SIZE, Member, MemberStatus, Message, NodeMetadata, NodeQuery, Result,
+    Transport,
+};
+use crate::diagnostics::Metrics;
+use futures::Stream;
+use rand::seq::SliceRandom;
+use rapr_common::utils::parse_service_addr;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Notify, broadcast, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+
+const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+const DEFAULT_SUSPICION_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(3);
+const DEFAULT_DOWN_MEMBER_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_INDIRECT_PROBES: usize = 3;
+const DEFAULT_RETRANSMIT_MULT: usize = 4;
+const DEFAULT_MAX_PIGGYBACK: usize = 8;
+const EVENTS_CAPACITY: usize = 256;
+
+/// MembershipConfig configures the membership protocol of a node.
+#[derive(Debug, Clone)]
+pub struct MembershipConfig {
+    /// Unique name of the local node.
+    pub name: String,
+    /// Addresses of the nodes contacted to join the cluster.
+    pub seeds: Vec<String>,
+    /// How often a member is probed.
+    pub probe_interval: Duration,
+    /// How long to wait for a direct ack before probing indirectly.
+    pub probe_timeout: Duration,
+    /// Number of members asked to probe an unresponsive member.
+    pub indirect_probes: usize,
+    /// How long a member stays suspected before it is declared dead.
+    pub suspicion_timeout: Duration,
+    /// How long to wait for a seed to answer a join.
+    pub join_timeout: Duration,
+    /// How long dead and left members are remembered, so that stale gossip about them
+    /// does not bring them back, before they are forgotten.
+    pub down_member_ttl: Duration,
+    /// Each update is retransmitted `retransmit_mult * log10(n + 1)` times.
+    pub retransmit_mult: usize,
+    /// Maximum number of updates piggybacked on a single message.
+    pub max_piggyback: usize,
+    /// Tags advertised by the local node when it starts.
+    pub metadata: BTreeMap<String, String>,
+    /// Metrics the number of active members is reported to.
+    pub metrics: Option<Metrics>,
+}
+
+impl MembershipConfig {
+    /// Creates a configuration with default timings for the named node.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            seeds: Vec::new(),
+            probe_interval: DEFAULT_PROBE_INTERVAL,
+            probe_timeout: DEFAULT_PROBE_TIMEOUT,
+            indirect_probes: DEFAULT_INDIRECT_PROBES,
+            suspicion_timeout: DEFAULT_SUSPICION_TIMEOUT,
+            join_timeout: DEFAULT_JOIN_TIMEOUT,
+            down_member_ttl: DEFAULT_DOWN_MEMBER_TTL,
+            retransmit_mult: DEFAULT_RETRANSMIT_MULT,
+            max_piggyback: DEFAULT_MAX_PIGGYBACK,
+            metadata: BTreeMap::new(),
+            metrics: None,
+        }
+    }
+
+    /// Sets the seeds from a comma separated list of addresses.
+    pub fn with_seeds(mut self, seeds: &str) -> Self {
+        self.seeds = parse_service_addr(seeds)
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .collect();
+        self
+    }
+
+    /// Reports the number of active members to the metrics.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Adds a tag to the metadata advertised by the local node.
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// MembershipEvent notifies a change of the cluster membership.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MembershipEvent {
+    /// A member joined, or rejoined after being declared dead.
+    Joined(Member),
+    /// A member stopped answering probes.
+    Suspected(Member),
+    /// A suspected member refuted the suspicion.
+    Recovered(Member),
+    /// A member was declared dead.
+    Failed(Member),
+    /// A member left gracefully.
+    Left(Member),
+    /// A member changed its metadata.
+    Updated(Member),
+}
+
+impl MembershipEvent {
+    /// Returns the member the event is about.
+    pub fn member(&self) -> &Member {
+        match self {
+            MembershipEvent::Joined(m)
+            | MembershipEvent::Suspected(m)
+            | MembershipEvent::Recovered(m)
+            | MembershipEvent::Failed(m)
+            | MembershipEvent::Left(m)
+            | MembershipEvent::Updated(m) => m,
+        }
+    }
+}
+
+struct Entry {
+    member: Member,
+    suspected_at: Option<Instant>,
+    /// When the member was declared dead or left.
+    down_at: Option<Instant>,
+}
+
+struct Pending {
+    update: Member,
+    transmits: usize,
+}
+
+struct State {
+    incarnation: u64,
+    metadata: NodeMetadata,
+    left: bool,
+    members: HashMap<String, Entry>,
+    queue: Vec<Pending>,
+    probe_order: Vec<String>,
+}
+
+struct Relay {
+    requester: String,
+    seq: u64,
+    expires_at: Instant,
+}
+
+struct Inner {
+    config: MembershipConfig,
+    addr: String,
+    transport: Arc<dyn Transport>,
+    state: Mutex<State>,
+    events: broadcast::Sender<MembershipEvent>,
+    seq: AtomicU64,
+    acks: Mutex<HashMap<u64, oneshot::Sender<()>>>,
+    relays: Mutex<HashMap<u64, Relay>>,
+    synced: Notify,
+}
+
+/// Membership runs the SWIM protocol for the local node and tracks the members of the cluster.
+pub struct Membership {
+    inner: Arc<Inner>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Membership {
+    /// Starts the protocol on the given transport. Call [`Membership::join`] to contact the seeds.
+    pub fn start(config: MembershipConfig, transport: Arc<dyn Transport>) -> Self {
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        if let Some(metrics) = &config.metrics {
+            metrics.set_cluster_members(1);
+        }
+        let metadata = NodeMetadata::next(0, config.metadata.clone());
+        let inner = Arc::new(Inner {
+            addr: transport.local_addr(),
+            config,
+            transport,
+            state: Mutex::new(State {
+                incarnation: 0,
+                metadata,
+                left: false,
+                members: HashMap::new(),
+                queue: Vec::new(),
+                probe_order: Vec::new(),
+            }),
+            events,
+            seq: AtomicU64::new(0),
+            acks: Mutex::new(HashMap::new()),
+            relays: Mutex::new(HashMap::new()),
+            synced: Notify::new(),
+        });
+
+        let tasks = vec![
+            tokio::spawn(inner.clone().receive_loop()),
+            tokio::spawn(inner.clone().probe_loop()),
+        ];
+        Self { inner, tasks }
+    }
+
+    /// Joins the cluster through the configured seeds and returns the number of known members.
+    /// Succeeds immediately when no seed other than the local node is configured.
+    pub async fn join(&self) -> Result<usize> {
+        let seeds: Vec<&String> = self
+            .inner
+            .config
+            .seeds
+            .iter()
+            .filter(|s| **s != self.inner.addr)
+            .collect();
+        if seeds.is_empty() {
+            return Ok(self.members().len());
+        }
+
+        let synced = self.inner.synced.notified();
+        tokio::pin!(synced);
+        synced.as_mut().enable();
+        let join = Message::Join {
+            member: self.inner.local(),
+        };
+        for seed in &seeds {
+            self.inner.send(seed, &join).await;
+        }
+
+        match tokio::time::timeout(self.inner.config.join_timeout, synced).await {
+            Ok(()) => Ok(self.members().len()),
+            Err(_) => Err(ClusterError::JoinFailed {
+                seeds: seeds.into_iter().cloned().collect(),
+            }),
+        }
+    }
+
+    /// Leaves the cluster gracefully, notifying the other members.
+    pub async fn leave(&self) {
+        let (left, targets) = {
+            let mut state = self.inner.state();
+            state.left = true;
+            let left = Member {
+                status: MemberStatus::Left,
+                ..self.inner.local_locked(&state)
+            };
+            let targets: Vec<String> = state
+                .members
+                .values()
+                .filter(|e| e.member.status.is_active())
+                .map(|e| e.member.addr.clone())
+                .collect();
+            (left, targets)
+        };
+
+        let message = Message::Ping {
+            seq: self.inner.next_seq(),
+            from: self.inner.config.name.clone(),
+            updates: vec![left],
+        };
+        for target in targets {
+            self.inner.send(&target, &message).await;
+        }
+    }
+
+    /// Replaces the metadata advertised by the local node and disseminates it.
+    pub fn set_metadata(&self, tags: BTreeMap<String, String>) {
+        let mut state = self.inner.state();
+        state.metadata = NodeMetadata::next(state.metadata.version, tags);
+        let local = self.inner.local_locked(&state);
+        self.inner.enqueue(&mut state, local);
+    }
+
+    /// Sets a single tag of the metadata advertised by the local node.
+    pub fn set_tag(&self, key: impl Into<String>, value: impl Into<String>) {
+        let mut tags = self.local().metadata.tags;
+        tags.insert(key.into(), value.into());
+        self.set_metadata(tags);
+    }
+
+    /// Returns the active members, including the local node, matching the query.
+    pub fn query(&self, query: &NodeQuery) -> Vec<Member> {
+        self.members()
+            .into_iter()
+            .filter(|m| query.matches(m))
+            .collect()
+    }
+
+    /// Returns the local node as advertised to the cluster.
+    pub fn local(&self) -> Member {
+        self.inner.local()
+    }
+
+    /// Returns the active members of the cluster, including the local node.
+    pub fn members(&self) -> Vec<Member> {
+        let state = self.inner.state();
+        let mut members: Vec<Member> = state
+            .members
+            .values()
+            .filter(|e| e.member.status.is_active())
+            .map(|e| e.member.clone())
+            .collect();
+        members.push(self.inner.local_locked(&state));
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        members
+    }
+
+    /// Returns the member with the given name, whatever its status.
+    pub fn member(&self, name: &str) -> Option<Member> {
+        if name == self.inner.config.name {
+            return Some(self.local());
+        }
+        self.inner
+            .state()
+            .members
+            .get(name)
+            .map(|e| e.member.clone())
+    }
+
+    /// Returns a stream of membership changes happening from now on.
+    /// Slow subscribers skip the events they could not keep up with.
+    pub fn subscribe(&self) -> impl Stream<Item = MembershipEvent> + Send + Unpin + 'static {
+        BroadcastStream::new(self.inner.events.subscribe()).filter_map(|e| e.ok())
+    }
+
+    /// Stops the protocol without notifying the other members.
+    pub fn shutdown(&self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for Membership {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Inner {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn local(&self) -> Member {
+        self.local_locked(&self.state())
+    }
+
+    fn local_locked(&self, state: &State) -> Member {
+        Member {
+            name: self.config.name.clone(),
+            addr: self.addr.clone(),
+            incarnation: state.incarnation,
+            status: if state.left {
+                MemberStatus::Left
+            } else {
+                MemberStatus::Alive
+            },
+            metadata: state.metadata.clone(),
+        }
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    async fn send(&self, addr: &str, message: &Message) {
+        if let Err(e) = self.transport.send_to(addr, message.encode()).await {
+            tracing::debug!("failed to send {message:?} to {addr}: {e}");
+        }
+    }
+
+    async fn receive_loop(self: Arc<Self>) {
+        loop {
+            let (payload, from) = match self.transport.recv_from().await {
+                Ok(packet) => packet,
+                Err(e) => {
+                    tracing::warn!("cluster transport stopped receiving: {e}");
+                    return;
+                }
+            };
+            match Message::decode(&payload) {
+                Some(message) => self.handle(message, from).await,
+                None => tracing::debug!("dropping malformed cluster packet from {from}"),
+            }
+        }
+    }
+
+    async fn handle(&self, message: Message, from: String) {
+        self.merge_received(message.updates());
+
+        match message {
+            Message::Ping {
+                seq, from: name, ..
+            } => {
+                let mut updates = self.piggyback();
+                // A member declared dead keeps probing us until it learns about it, so tell
+                // it directly and let it refute by rejoining with a higher incarnation.
+                if let Some(dead) = self.member_with_status(&name, MemberStatus::Dead) {
+                    updates.insert(0, dead);
+                }
+                let ack = Message::Ack {
+                    seq,
+                    from: self.config.name.clone(),
+                    updates,
+                };
+                self.send(&from, &ack).await;
+            }
+            Message::PingReq { seq, target, .. } => {
+                let relay_seq = self.next_seq();
+                self.relays
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(
+                        relay_seq,
+                        Relay {
+                            requester: from,
+                            seq,
+                            expires_at: Instant::now() + self.config.probe_interval,
+                        },
+                    );
+                let ping = Message::Ping {
+                    seq: relay_seq,
+                    from: self.config.name.clone(),
+                    updates: self.piggyback(),
+                };
+                self.send(&target.addr, &ping).await;
+            }
+            Message::Ack { seq, .. } => {
+                let waiter = self
+                    .acks
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&seq);
+                if let Some(waiter) = waiter {
+                    let _ = waiter.send(());
+                    return;
+                }
+                let relay = self
+                    .relays
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&seq);
+                if let Some(relay) = relay {
+                    let ack = Message::Ack {
+                        seq: relay.seq,
+                        from: self.config.name.clone(),
+                        updates: self.piggyback(),
+                    };
+                    self.send(&relay.requester, &ack).await;
+                }
+            }
+            Message::Join { .. } => {
+                let members = {
+                    let state = self.state();
+                    let mut members: Vec<Member> =
+                        state.members.values().map(|e| e.member.clone()).collect();
+                    members.push(self.local_locked(&state));
+                    members
+                };
+                for members in sync_chunks(members) {
+                    self.send(&from, &Message::Sync { members }).await;
+                }
+            }
+            Message::Sync { .. } => self.synced.notify_waiters(),
+        }
+    }
+
+    fn member_with_status(&self, name: &str, status: MemberStatus) -> Option<Member> {
+        self.state()
+            .members
+            .get(name)
+            .filter(|e| e.member.status == status)
+            .map(|e| e.member.clone())
+    }
+
+    /// Applies membership updates received from other members.
+    ///
+    /// Suspicions at the largest incarnation are dropped: the member could not refute them
+    /// with a larger one. Failures of such a member are still detected by each node's own
+    /// probes, they are just not disseminated. Updates carrying metadata past
+    /// [`NodeMetadata::MAX_VERSION`] are dropped too, as no later write could supersede it.
+    fn merge_received(&self, updates: &[Member]) {
+        let (updates, dropped): (Vec<Member>, Vec<Member>) =
+            updates.iter().cloned().partition(|u| {
+                let suspicion = matches!(u.status, MemberStatus::Suspect | MemberStatus::Dead);
+                !(suspicion && u.incarnation == u64::MAX)
+                    && u.metadata.version <= NodeMetadata::MAX_VERSION
+            });
+        for update in dropped {
+            tracing::debug!(
+                member = %update.name,
+                incarnation = update.incarnation,
+                version = update.metadata.version,
+                "dropping update which could not be superseded"
+            );
+        }
+        self.merge(&updates);
+    }
+
+    /// Applies membership updates.
+    fn merge(&self, updates: &[Member]) {
+        if updates.is_empty() {
+            return;
+        }
+        let mut events = Vec::new();
+        {
+            let mut state = self.state();
+            for update in updates {
+                if let Some(event) = self.apply(&mut state, update.clone()) {
+                    events.push(event);
+                }
+            }
+            if let Some(metrics) = &self.config.metrics
+                && !events.is_empty()
+            {
+                let active = state
+                    .members
+                    .values()
+                    .filter(|e| e.member.status.is_active());
+                metrics.set_cluster_members(active.count() + 1);
+            }
+        }
+        for event in events {
+            tracing::debug!("cluster membership changed: {event:?}");
+            let _ = self.events.send(event);
+        }
+    }
+
+    fn apply(&self, state: &mut State, update: Member) -> Option<MembershipEvent> {
+        if update.name == self.config.name {
+            // Refute any suspicion about the local node by bumping its incarnation, or by
+            // disseminating the current one again to whoever still holds a stale view.
+            let mut refute =
+                !state.left && matches!(update.status, MemberStatus::Suspect | MemberStatus::Dead);
+            if refute && update.incarnation >= state.incarnation {
+                // Suspicions at the largest incarnation are dropped on receipt: this does not
+                // saturate.
+                state.incarnation = update.incarnation.saturating_add(1);
+            }
+            // The cluster remembers metadata written before a restart; supersede it.
+            if update.metadata.version >= state.metadata.version
+                && update.metadata != state.metadata
+            {
+                let tags = std::mem::take(&mut state.metadata.tags);
+                state.metadata = NodeMetadata::next(update.metadata.version, tags);
+                refute = true;
+            }
+            if refute {
+                let local = self.local_locked(state);
+                self.enqueue(state, local);
+            }
+            return None;
+        }
+
+        let now = Instant::now();
+        let Some(entry) = state.members.get_mut(&update.name) else {
+            if !update.status.is_active() {
+                return None;
+            }
+            state.members.insert(
+                update.name.clone(),
+                Entry {
+                    member: update.clone(),
+                    suspected_at: (update.status == MemberStatus::Suspect).then_some(now),
+                    down_at: (!update.status.is_active()).then_some(now),
+                },
+            );
+            self.enqueue(state, update.clone());
+            return Some(MembershipEvent::Joined(update));
+        };
+
+        let current = &entry.member;
+        let overrides = match update.status {
+            MemberStatus::Alive => update.incarnation > current.incarnation,
+            MemberStatus::Suspect => match current.status {
+                MemberStatus::Alive => update.incarnation >= current.incarnation,
+                MemberStatus::Suspect => update.incarnation > current.incarnation,
+                _ => false,
+            },
+            MemberStatus::Dead | MemberStatus::Left => {
+                if current.status.is_active() {
+                    update.incarnation >= current.incarnation
+                } else {
+                    update.incarnation > current.incarnation
+                }
+            }
+        };
+        let newer_metadata = update.metadata.version > current.metadata.version;
+        if !overrides {
+            if !newer_metadata || !current.status.is_active() {
+                return None;
+            }
+            entry.member.metadata = update.metadata;
+            let updated = entry.member.clone();
+            self.enqueue(state, updated.clone());
+            return Some(MembershipEvent::Updated(updated));
+        }
+
+        let previous = current.status;
+        let update = if newer_metadata {
+            update
+        } else {
+            Member {
+                metadata: current.metadata.clone(),
+                ..update
+            }
+        };
+        entry.member = update.clone();
+        entry.suspected_at = match update.status {
+            MemberStatus::Suspect => entry.suspected_at.or(Some(now)),
+            _ => None,
+        };
+        entry.down_at = match update.status {
+            MemberStatus::Dead | MemberStatus::Left => entry.down_at.or(Some(now)),
+            _ => None,
+        };
+        self.enqueue(state, update.clone());
+
+        match (previous, update.status) {
+            (MemberStatus::Dead | MemberStatus::Left, MemberStatus::Alive) => {
+                Some(MembershipEvent::Joined(update))
+            }
+            (MemberStatus::Suspect, MemberStatus::Alive) => {
+                Some(MembershipEvent::Recovered(update))
+            }
+            (MemberStatus::Alive, MemberStatus::Suspect) => {
+                Some(MembershipEvent::Suspected(update))
+            }
+            (_, MemberStatus::Dead) => Some(MembershipEvent::Failed(update)),
+            (_, MemberStatus::Left) => Some(MembershipEvent::Left(update)),
+            _ if newer_metadata => Some(MembershipEvent::Updated(update)),
+            _ => None,
+        }
+    }
+
+    fn enqueue(&self, state: &mut State, update: Member) {
+        let active = state
+            .members
+            .values()
+            .filter(|e| e.member.status.is_active())
+            .count();
+        let transmits = self.config.retransmit_mult * ((active + 2) as f64).log10().ceil() as usize;
+        state.queue.retain(|p| p.update.name != update.name);
+        state.queue.push(Pending {
+            update,
+            transmits: transmits.max(1),
+        });
+    }
+
+    /// Takes the updates to piggyback on an outgoing message, least transmitted first.
+    fn piggyback(&self) -> Vec<Member> {
+        let mut state = self.state();
+        state.queue.sort_by_key(|p| std::cmp::Reverse(p.transmits));
+        let mut updates = Vec::new();
+        for pending in state.queue.iter_mut().take(self.config.max_piggyback) {
+            pending.transmits -= 1;
+            updates.push(pending.update.clone());
+        }
+        state.queue.retain(|p| p.transmits > 0);
+        updates
+    }
+
+    async fn probe_loop(self: Arc<Self>) {
+        let mut ticker = tokio::time::interval(self.config.probe_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.expire_suspects();
+            self.expire_down_members();
+            self.expire_relays();
+            if let Some(target) = self.next_probe_target() {
+                self.probe(target).await;
+            }
+        }
+    }
+
+    /// Picks the next member to probe, walking a shuffled list of members round-robin.
+    fn next_probe_target(&self) -> Option<Member> {
+        let mut state = self.state();
+        if state.left {
+            return None;
+        }
+        loop {
+            let Some(name) = state.probe_order.pop() else {
+                let mut order: Vec<String> = state
+                    .members
+                    .values()
+                    .filter(|e| e.member.status.is_active())
+                    .map(|e| e.member.name.clone())
+                    .collect();
+                if order.is_empty() {
+                    return None;
+                }
+                order.shuffle(&mut rand::rng());
+                state.probe_order = order;
+                continue;
+            };
+            if let Some(entry) = state.members.get(&name)
+                && entry.member.status.is_active()
+            {
+                return Some(entry.member.clone());
+            }
+        }
+    }
+
+    async fn probe(&self, target: Member) {
+        let seq = self.next_seq();
+        let (tx, mut rx) = oneshot::channel();
+        self.acks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(seq, tx);
+
+        let mut updates = self.piggyback();
+        if target.status == MemberStatus::Suspect {
+            // Tell the suspect directly so that it gets a chance to refute the suspicion
+            // even after the gossip about it died out.
+            updates.insert(0, target.clone());
+        }
+        let ping = Message::Ping {
+            seq,
+            from: self.config.name.clone(),
+            updates,
+        };
+        self.send(&target.addr, &ping).await;
+        if tokio::time::timeout(self.config.probe_timeout, &mut rx)
+            .await
+            .is_ok()
+        {
+            return;
+        }
+
+        let helpers: Vec<Member> = {
+            let state = self.state();
+            let mut helpers: Vec<Member> = state
+                .members
+                .values()
+                .filter(|e| e.member.status == MemberStatus::Alive && e.member.name != target.name)
+                .map(|e| e.member.clone())
+                .collect();
+            helpers.shuffle(&mut rand::rng());
+            helpers.truncate(self.config.indirect_probes);
+            helpers
+        };
+        for helper in helpers {
+            let ping_req = Message::PingReq {
+                seq,
+                from: self.config.name.clone(),
+                target: target.clone(),
+                updates: self.piggyback(),
+            };
+            self.send(&helper.addr, &ping_req).await;
+        }
+
+        let remaining = self
+            .config
+            .probe_interval
+            .saturating_sub(self.config.probe_timeout);
+        let acked = tokio::time::timeout(remaining, &mut rx).await.is_ok();
+        self.acks
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&seq);
+        if !acked {
+            self.suspect(&target);
+        }
+    }
+
+    fn suspect(&self, target: &Member) {
+        let suspect = Member {
+            status: MemberStatus::Suspect,
+            ..target.clone()
+        };
+        self.merge(&[suspect]);
+    }
+
+    fn expire_suspects(&self) {
+        let now = Instant::now();
+        let dead: Vec<Member> = self
+            .state()
+            .members
+            .values()
+            .filter(|e| {
+                e.suspected_at
+                    .is_some_and(|at| now.duration_since(at) >= self.config.suspicion_timeout)
+            })
+            .map(|e| Member {
+                status: MemberStatus::Dead,
+                ..e.member.clone()
+            })
+            .collect();
+        self.merge(&dead);
+    }
+
+    /// Forgets the members which have been dead or gone for longer than the TTL.
+    fn expire_down_members(&self) {
+        let now = Instant::now();
+        let ttl = self.config.down_member_ttl;
+        self.state()
+            .members
+            .retain(|_, e| e.down_at.is_none_or(|at| now.duration_since(at) < ttl));
+    }
+
+    fn expire_relays(&self) {
+        let now = Instant::now();
+        self.relays
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, r| r.expires_at > now);
+    }
+}
+
+/// Splits the members sent in reply to a join into lists whose [`Message::Sync`] fits in
+/// a packet. Members which alone would not fit are left out: the joiner learns about them
+/// through gossip.
+fn sync_chunks(members: Vec<Member>) -> Vec<Vec<Member>> {
+    let overhead = Message::Sync {
+        members: Vec::new(),
+    }
+    .encode()
+    .len();
+    let mut chunks = Vec::new();
+    let (mut chunk, mut size) = (Vec::new(), overhead);
+    for member in members {
+        // Members are separated by a comma.
+        let len = serde_json::to_vec(&member).map_or(usize::MAX, |m| m.len()) + 1;
+        if overhead + len > MAX_PACKET_SIZE {
+            tracing::debug!(member = %member.name, "member does not fit in a sync packet");
+            continue;
+        }
+        if size + len > MAX_PACKET_SIZE {
+            chunks.push(std::mem::take(&mut chunk));
+            size = overhead;
+        }
+        size += len;
+        chunk.push(member);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::sim::SimNetwork;
+    use crate::cluster::{METADATA_ACTOR_TYPES, METADATA_ZONE, UdpTransport};
+
+    fn config(name: &str) -> MembershipConfig {
+        MembershipConfig {
+            probe_interval: Duration::from_millis(100),
+            probe_timeout: Duration::from_millis(40),
+            suspicion_timeout: Duration::from_millis(500),
+            join_timeout: Duration::from_millis(500),
+            ..MembershipConfig::new(name)
+        }
+        .with_seeds("node-0, node-1")
+    }
+
+    async fn cluster(network: &SimNetwork, size: usize) -> Vec<Membership> {
+        let mut nodes = Vec::new();
+        for i in 0..size {
+            let name = format!("node-{i}");
+            let transport = Arc::new(network.bind(&name));
+            let node = Membership::start(config(&name), transport);
+            if i > 0 {
+                node.join().await.unwrap();
+            }
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    async fn converge(nodes: &[&Membership], expected: usize) {
+        for _ in 0..100 {
+            if nodes.iter().all(|n| n.members().len() == expected) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("cluster did not converge to {expected} members");
+    }
+
+    #[test]
+    fn test_with_seeds() {
+        let config = MembershipConfig::new("a").with_seeds("10.0.0.1:7946, 10.0.0.2:7946,");
+        assert_eq!(config.seeds, vec!["10.0.0.1:7946", "10.0.0.2:7946"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_through_seeds() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 4).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 4).await;
+
+        let names: Vec<String> = nodes[3].members().into_iter().map(|m| m.name).collect();
+        assert_eq!(names, vec!["node-0", "node-1", "node-2", "node-3"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_join_fails_without_seeds() {
+        let network = SimNetwork::new();
+        let node = Membership::start(config("node-5"), Arc::new(network.bind("node-5")));
+        assert!(matches!(
+            node.join().await,
+            Err(ClusterError::JoinFailed { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failure_detection() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 3).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+
+        let mut events = nodes[0].subscribe();
+        network.isolate("node-2");
+        converge(&[&nodes[0], &nodes[1]], 2).await;
+
+        let mut seen = Vec::new();
+        while let Some(event) = events.next().await {
+            let failed = matches!(event, MembershipEvent::Failed(_));
+            seen.push(event);
+            if failed {
+                break;
+            }
+        }
+        assert!(matches!(&seen[0], MembershipEvent::Suspected(m) if m.name == "node-2"));
+        assert!(matches!(seen.last(), Some(MembershipEvent::Failed(m)) if m.name == "node-2"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reports_members_to_metrics() {
+        let network = SimNetwork::new();
+        let mut nodes = cluster(&network, 2).await;
+        let metrics = Metrics::new();
+        let transport = Arc::new(network.bind("node-2"));
+        let node = Membership::start(config("node-2").with_metrics(metrics.clone()), transport);
+        node.join().await.unwrap();
+        nodes.push(node);
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+        assert!(metrics.render().contains("rapr_cluster_members 3\n"));
+
+        network.isolate("node-0");
+        converge(&[&nodes[1], &nodes[2]], 2).await;
+        assert!(metrics.render().contains("rapr_cluster_members 2\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_forgets_down_members() {
+        let network = SimNetwork::new();
+        let config = |name: &str| MembershipConfig {
+            down_member_ttl: Duration::from_secs(5),
+            ..config(name)
+        };
+        let mut nodes = Vec::new();
+        for i in 0..3 {
+            let name = format!("node-{i}");
+            let node = Membership::start(config(&name), Arc::new(network.bind(&name)));
+            if i > 0 {
+                node.join().await.unwrap();
+            }
+            nodes.push(node);
+        }
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+
+        network.isolate("node-2");
+        converge(&[&nodes[0], &nodes[1]], 2).await;
+        assert!(nodes[0].inner.state().members.contains_key("node-2"));
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        assert!(!nodes[0].inner.state().members.contains_key("node-2"));
+        assert!(!nodes[1].inner.state().members.contains_key("node-2"));
+    }
+
+    #[test]
+    fn test_sync_chunks() {
+        let member = |i: usize| Member {
+            metadata: NodeMetadata {
+                version: 0,
+                tags: BTreeMap::from([("tag".to_string(), "x".repeat(1000))]),
+            },
+            name: format!("node-{i}"),
+            addr: format!("10.0.0.{i}:7946"),
+            incarnation: 0,
+            status: MemberStatus::Alive,
+        };
+        let members: Vec<Member> = (0..200).map(member).collect();
+        let chunks = sync_chunks(members.clone());
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            let members = chunk.clone();
+            assert!(Message::Sync { members }.encode().len() <= MAX_PACKET_SIZE);
+        }
+        assert_eq!(chunks.concat(), members);
+
+        // A member too large for any packet is left out.
+        let mut large = member(200);
+        large
+            .metadata
+            .tags
+            .insert("tag".to_string(), "x".repeat(MAX_PACKET_SIZE));
+        assert_eq!(sync_chunks(vec![member(0), large]), vec![vec![member(0)]]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_indirect_probe_prevents_false_suspicion() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 3).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+
+        let mut events = nodes[0].subscribe();
+        network.partition("node-0", "node-2");
+        tokio::time::sleep(Duration::from_secs(3)).await;
+
+        assert_eq!(nodes[0].members().len(), 3);
+        assert_eq!(
+            nodes[0].member("node-2").unwrap().status,
+            MemberStatus::Alive
+        );
+        let next = tokio::time::timeout(Duration::from_millis(1), events.next()).await;
+        assert!(next.is_err(), "unexpected membership event {next:?}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_suspicion_is_refuted() {
+        let network = SimNetwork::new();
+        let config = |name: &str| MembershipConfig {
+            suspicion_timeout: Duration::from_secs(10),
+            ..config(name)
+        };
+        let mut nodes = Vec::new();
+        for i in 0..3 {
+            let name = format!("node-{i}");
+            let node = Membership::start(config(&name), Arc::new(network.bind(&name)));
+            if i > 0 {
+                node.join().await.unwrap();
+            }
+            nodes.push(node);
+        }
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+
+        network.isolate("node-2");
+        for _ in 0..100 {
+            if nodes[0].member("node-2").unwrap().status == MemberStatus::Suspect {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(
+            nodes[0].member("node-2").unwrap().status,
+            MemberStatus::Suspect
+        );
+
+        let mut events = nodes[0].subscribe();
+        network.heal();
+        let recovered = loop {
+            // The isolated node suspected the others too, so they may refute as well.
+            if let MembershipEvent::Recovered(m) = events.next().await.unwrap()
+                && m.name == "node-2"
+            {
+                break m;
+            }
+        };
+        assert!(recovered.incarnation > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_converges_on_lossy_network() {
+        let network = SimNetwork::with_seed(7);
+        network.set_delay(Duration::from_millis(5));
+        let nodes = cluster(&network, 5).await;
+        network.set_drop_rate(0.1);
+        converge(&nodes.iter().collect::<Vec<_>>(), 5).await;
+
+        // False suspicions caused by lost packets are refuted and the view stays complete.
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 5).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leave() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 3).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+
+        let mut events = nodes[0].subscribe();
+        nodes[2].leave().await;
+        let event = events.next().await.unwrap();
+        assert!(matches!(event, MembershipEvent::Left(m) if m.name == "node-2"));
+        converge(&[&nodes[0], &nodes[1]], 2).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metadata_dissemination() {
+        let network = SimNetwork::new();
+        let mut nodes = Vec::new();
+        for (i, zone) in ["zone-a", "zone-b", "zone-a"].iter().enumerate() {
+            let name = format!("node-{i}");
+            let config = config(&name)
+                .with_tag(METADATA_ZONE, *zone)
+                .with_tag(METADATA_ACTOR_TYPES, "cart");
+            let node = Membership::start(config, Arc::new(network.bind(&name)));
+            if i > 0 {
+                node.join().await.unwrap();
+            }
+            nodes.push(node);
+        }
+        converge(&nodes.iter().collect::<Vec<_>>(), 3).await;
+
+        let mut events = nodes[0].subscribe();
+        nodes[2].set_tag(METADATA_ACTOR_TYPES, "cart,order");
+        let updated = loop {
+            if let MembershipEvent::Updated(m) = events.next().await.unwrap() {
+                break m;
+            }
+        };
+        assert_eq!(updated.name, "node-2");
+
+        for _ in 0..50 {
+            if nodes[1].query(&NodeQuery::new().hosting("order")).len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let names =
+            |members: Vec<Member>| -> Vec<String> { members.into_iter().map(|m| m.name).collect() };
+        let query = NodeQuery::new().hosting("order").in_zone("zone-a");
+        assert_eq!(names(nodes[1].query(&query)), vec!["node-2"]);
+        let query = NodeQuery::new().hosting("cart").in_zone("zone-a");
+        assert_eq!(names(nodes[1].query(&query)), vec!["node-0", "node-2"]);
+        assert!(
+            nodes[1]
+                .query(&NodeQuery::new().in_zone("zone-c"))
+                .is_empty()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_metadata_is_ignored() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 2).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 2).await;
+
+        nodes[1].set_tag(METADATA_ZONE, "zone-b");
+        let current = nodes[1].local();
+        let stale = Member {
+            metadata: NodeMetadata {
+                version: current.metadata.version - 1,
+                tags: BTreeMap::from([(METADATA_ZONE.to_string(), "zone-a".to_string())]),
+            },
+            ..current.clone()
+        };
+
+        nodes[0].inner.merge(&[current]);
+        nodes[0].inner.merge(&[stale]);
+        let seen = nodes[0].member("node-1").unwrap();
+        assert_eq!(seen.metadata.zone(), Some("zone-b"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restarted_node_supersedes_remembered_metadata() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 2).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 2).await;
+
+        let local = nodes[1].local();
+        let remembered = Member {
+            metadata: NodeMetadata {
+                version: local.metadata.version + 1_000,
+                tags: BTreeMap::from([(METADATA_ZONE.to_string(), "old".to_string())]),
+            },
+            ..local.clone()
+        };
+        nodes[1].inner.merge(std::slice::from_ref(&remembered));
+
+        let local = nodes[1].local();
+        assert!(local.metadata.version > remembered.metadata.version);
+        assert_eq!(local.metadata.zone(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_suspicion_at_max_incarnation() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 2).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 2).await;
+
+        // Suspicions at the largest incarnation could not be refuted: they are dropped, by
+        // the suspected node and by its peers, and it stays alive.
+        let suspected = Member {
+            incarnation: u64::MAX,
+            status: MemberStatus::Suspect,
+            ..nodes[1].local()
+        };
+        nodes[1]
+            .inner
+            .merge_received(std::slice::from_ref(&suspected));
+        nodes[0]
+            .inner
+            .merge_received(std::slice::from_ref(&suspected));
+        assert_eq!(nodes[1].local().incarnation, 0);
+        let member = nodes[0].member("node-1").unwrap();
+        assert_eq!(member.status, MemberStatus::Alive);
+        assert_eq!(member.incarnation, 0);
+
+        // Below it, the node refutes the suspicion with the largest incarnation left.
+        let suspected = Member {
+            incarnation: u64::MAX - 1,
+            ..suspected
+        };
+        nodes[0]
+            .inner
+            .merge_received(std::slice::from_ref(&suspected));
+        assert_eq!(
+            nodes[0].member("node-1").unwrap().status,
+            MemberStatus::Suspect
+        );
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(nodes[1].local().incarnation, u64::MAX);
+        let member = nodes[0].member("node-1").unwrap();
+        assert_eq!(member.status, MemberStatus::Alive);
+        assert_eq!(member.incarnation, u64::MAX);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metadata_past_max_version() {
+        let network = SimNetwork::new();
+        let nodes = cluster(&network, 2).await;
+        converge(&nodes.iter().collect::<Vec<_>>(), 2).await;
+
+        let local = nodes[1].local();
+        let remembered = Member {
+            metadata: NodeMetadata {
+                version: u64::MAX,
+                tags: BTreeMap::from([(METADATA_ZONE.to_string(), "old".to_string())]),
+            },
+            ..local.clone()
+        };
+        nodes[0]
+            .inner
+            .merge_received(std::slice::from_ref(&remembered));
+        nodes[1]
+            .inner
+            .merge_received(std::slice::from_ref(&remembered));
+        assert_eq!(nodes[1].local().metadata, local.metadata);
+        assert_eq!(nodes[0].member("node-1").unwrap().metadata, local.metadata);
+
+        // Later writes still supersede the current metadata everywhere.
+        nodes[1].set_tag(METADATA_ZONE, "new");
+        for _ in 0..50 {
+            if nodes[0].member("node-1").unwrap().metadata.zone() == Some("new") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let seen = nodes[0].member("node-1").unwrap();
+        assert_eq!(seen.metadata, nodes[1].local().metadata);
+        assert!(seen.metadata.version > local.metadata.version);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_over_udp() {
+        let config = |name: &str| MembershipConfig {
+            probe_interval: Duration::from_millis(50),
+            probe_timeout: Duration::from_millis(20),
+            ..MembershipConfig::new(name)
+        };
+        let seed = Arc::new(UdpTransport::bind("127.0.0.1:0").await.unwrap());
+        let seed_addr = seed.local_addr();
+        let first = Membership::start(config("first").with_tag(METADATA_ZONE, "a"), seed);
+
+        let transport = Arc::new(UdpTransport::bind("127.0.0.1:0").await.unwrap());
+        let second = Membership::start(
+            config("second")
+                .with_seeds(&seed_addr)
+                .with_tag(METADATA_ACTOR_TYPES, "cart"),
+            transport,
+        );
+        assert_eq!(second.join().await.unwrap(), 2);
+
+        for _ in 0..100 {
+            if !first.query(&NodeQuery::new().hosting("cart")).is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let hosts = first.query(&NodeQuery::new().hosting("cart"));
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].name, "second");
+        assert_eq!(
+            second.query(&NodeQuery::new().in_zone("a"))[0].name,
+            "first"
+        );
+    }
+}
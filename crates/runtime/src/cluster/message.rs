@@ -0,0 +1,91 @@
+use super::NodeMetadata;
+use serde::{Deserialize, Serialize};
+
+/// MemberStatus is the state of a member as seen by the local node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MemberStatus {
+    /// The member answers probes.
+    Alive,
+    /// The member did not answer a probe and will be declared dead unless it refutes it.
+    Suspect,
+    /// The member was declared failed.
+    Dead,
+    /// The member left the cluster gracefully.
+    Left,
+}
+
+impl MemberStatus {
+    /// Returns true if the member is still considered part of the cluster.
+    pub fn is_active(&self) -> bool {
+        matches!(self, MemberStatus::Alive | MemberStatus::Suspect)
+    }
+}
+
+/// Member is a node of the cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    /// Unique name of the node.
+    pub name: String,
+    /// Address the node's cluster transport listens on.
+    pub addr: String,
+    /// Incarnation is bumped by the node itself to refute suspicions about it.
+    pub incarnation: u64,
+    pub status: MemberStatus,
+    /// Tags advertised by the node.
+    #[serde(default)]
+    pub metadata: NodeMetadata,
+}
+
+/// Message is a packet exchanged by the membership protocol.
+/// Every message carries membership updates piggybacked on it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Message {
+    /// Direct probe, answered with an [`Message::Ack`] carrying the same sequence number.
+    Ping {
+        seq: u64,
+        from: String,
+        updates: Vec<Member>,
+    },
+    /// Asks the receiver to probe `target` on behalf of the sender.
+    PingReq {
+        seq: u64,
+        from: String,
+        target: Member,
+        updates: Vec<Member>,
+    },
+    /// Answer to a direct or indirect probe.
+    Ack {
+        seq: u64,
+        from: String,
+        updates: Vec<Member>,
+    },
+    /// Sent to seeds by a node joining the cluster.
+    Join { member: Member },
+    /// Full membership list sent in reply to a join.
+    Sync { members: Vec<Member> },
+}
+
+impl Message {
+    /// Encodes the message for the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("messages only hold strings, numbers and string maps")
+    }
+
+    /// Decodes a message received from the wire.
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        serde_json::from_slice(payload).ok()
+    }
+
+    /// Returns the membership updates carried by the message.
+    pub fn updates(&self) -> &[Member] {
+        match self {
+            Message::Ping { updates, .. }
+            | Message::PingReq { updates, .. }
+            | Message::Ack { updates, .. } => updates,
+            Message::Join { member } => std::slice::from_ref(member),
+            Message::Sync { members } => members,
+        }
+    }
+}
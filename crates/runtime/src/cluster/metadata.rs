@@ -0,0 +1,175 @@
+use super::Member;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Metadata key listing the actor types hosted by a node, separated by commas.
+pub const METADATA_ACTOR_TYPES: &str = "actorTypes";
+/// Metadata key holding the zone a node runs in.
+pub const METADATA_ZONE: &str = "zone";
+/// Metadata key holding the runtime version of a node.
+pub const METADATA_VERSION: &str = "version";
+
+/// NodeMetadata is the set of tags a node advertises to the cluster.
+///
+/// Only the node itself writes its metadata. Every write gets a higher version, and members
+/// keep the version they saw last, so the most recent write wins wherever it is received.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeMetadata {
+    pub version: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl NodeMetadata {
+    /// Largest version accepted from peers. Versions are wall-clock milliseconds, which
+    /// stay far below it, so larger ones can only come from a corrupt or malicious peer.
+    pub const MAX_VERSION: u64 = u64::MAX / 2;
+
+    /// Creates metadata holding the given tags, versioned after `previous`.
+    /// Versions follow the wall clock so that a restarted node does not reuse old versions.
+    pub fn next(previous: u64, tags: BTreeMap<String, String>) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Self {
+            version: now.max(previous.saturating_add(1)),
+            tags,
+        }
+    }
+
+    /// Returns the value of a tag.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    /// Returns the actor types hosted by the node.
+    pub fn actor_types(&self) -> impl Iterator<Item = &str> {
+        self.get(METADATA_ACTOR_TYPES)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+    }
+
+    /// Returns the zone of the node.
+    pub fn zone(&self) -> Option<&str> {
+        self.get(METADATA_ZONE)
+    }
+}
+
+/// NodeQuery selects members by the metadata they advertise.
+///
+/// ```
+/// use rapr_runtime::cluster::NodeQuery;
+///
+/// let query = NodeQuery::new().hosting("cart").in_zone("eu-west-1a");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NodeQuery {
+    actor_type: Option<String>,
+    tags: BTreeMap<String, String>,
+}
+
+impl NodeQuery {
+    /// Creates a query matching every member.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only matches members hosting the actor type.
+    pub fn hosting(mut self, actor_type: impl Into<String>) -> Self {
+        self.actor_type = Some(actor_type.into());
+        self
+    }
+
+    /// Only matches members in the zone.
+    pub fn in_zone(self, zone: impl Into<String>) -> Self {
+        self.with_tag(METADATA_ZONE, zone)
+    }
+
+    /// Only matches members whose tag has the given value.
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns true if the member satisfies every condition of the query.
+    pub fn matches(&self, member: &Member) -> bool {
+        let metadata = &member.metadata;
+        if let Some(actor_type) = &self.actor_type
+            && !metadata.actor_types().any(|t| t == actor_type)
+        {
+            return false;
+        }
+        self.tags
+            .iter()
+            .all(|(k, v)| metadata.get(k) == Some(v.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::MemberStatus;
+
+    fn member(tags: &[(&str, &str)]) -> Member {
+        Member {
+            name: "node".to_string(),
+            addr: "node:7946".to_string(),
+            incarnation: 0,
+            status: MemberStatus::Alive,
+            metadata: NodeMetadata::next(
+                0,
+                tags.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_next_version_is_monotonic() {
+        let first = NodeMetadata::next(0, BTreeMap::new());
+        let future = NodeMetadata::next(u64::MAX - 1, BTreeMap::new());
+        assert!(first.version > 0);
+        assert_eq!(future.version, u64::MAX);
+        assert_eq!(
+            NodeMetadata::next(u64::MAX, BTreeMap::new()).version,
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn test_query_matches() {
+        let node = member(&[
+            (METADATA_ACTOR_TYPES, "cart, order"),
+            (METADATA_ZONE, "zone-a"),
+            (METADATA_VERSION, "1.0.0"),
+        ]);
+
+        assert!(NodeQuery::new().matches(&node));
+        assert!(NodeQuery::new().hosting("order").matches(&node));
+        assert!(
+            NodeQuery::new()
+                .hosting("cart")
+                .in_zone("zone-a")
+                .matches(&node)
+        );
+        assert!(
+            NodeQuery::new()
+                .with_tag(METADATA_VERSION, "1.0.0")
+                .matches(&node)
+        );
+        assert!(!NodeQuery::new().hosting("payment").matches(&node));
+        assert!(
+            !NodeQuery::new()
+                .hosting("cart")
+                .in_zone("zone-b")
+                .matches(&node)
+        );
+        assert!(!NodeQuery::new().hosting("car").matches(&node));
+        assert!(!NodeQuery::new().hosting("cart").matches(&member(&[])));
+    }
+}
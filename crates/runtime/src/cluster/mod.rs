@@ -0,0 +1,36 @@
+//! Clustering building block.
+//!
+//! Runtime instances form a cluster with a SWIM-style gossip protocol: members are probed
+//! directly and, when they do not answer, indirectly through other members; unresponsive
+//! members are suspected before being declared failed, and every membership change is
+//! piggybacked on protocol messages so that it eventually reaches the whole cluster.
+//! Members also disseminate metadata about themselves, such as the actor types they host,
+//! which can be queried with a [`NodeQuery`].
+
+use snafu::Snafu;
+
+mod membership;
+mod message;
+mod metadata;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+mod transport;
+
+pub use membership::*;
+pub use message::*;
+pub use metadata::*;
+pub use transport::*;
+
+pub type Result<T> = std::result::Result<T, ClusterError>;
+
+#[derive(Debug, Snafu)]
+pub enum ClusterError {
+    #[snafu(display("Failed to bind cluster transport on {}: {}", addr, source))]
+    Bind {
+        addr: String,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Failed to join cluster: none of the seeds {:?} answered", seeds))]
+    JoinFailed { seeds: Vec<String> },
+}
@@ -0,0 +1,190 @@
+//! In-memory network used to exercise the membership protocol in tests.
+//!
+//! Packets can be dropped at random, delayed, or blocked between specific nodes to simulate
+//! partitions. Randomness comes from a seeded generator so that runs are reproducible.
+
+use super::Transport;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, mpsc};
+
+type Packet = (Vec<u8>, String);
+
+struct SimState {
+    nodes: HashMap<String, mpsc::UnboundedSender<Packet>>,
+    drop_rate: f64,
+    delay: Duration,
+    blocked: HashSet<(String, String)>,
+    isolated: HashSet<String>,
+    rng: StdRng,
+}
+
+/// SimNetwork connects [`SimTransport`]s bound on it.
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl Default for SimNetwork {
+    fn default() -> Self {
+        Self::with_seed(0)
+    }
+}
+
+impl SimNetwork {
+    /// Creates a network that delivers every packet immediately.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a network whose random packet drops are driven by the given seed.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SimState {
+                nodes: HashMap::new(),
+                drop_rate: 0.0,
+                delay: Duration::ZERO,
+                blocked: HashSet::new(),
+                isolated: HashSet::new(),
+                rng: StdRng::seed_from_u64(seed),
+            })),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, SimState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Attaches a new node to the network on the given address.
+    pub fn bind(&self, addr: &str) -> SimTransport {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.state().nodes.insert(addr.to_string(), tx);
+        SimTransport {
+            network: self.clone(),
+            addr: addr.to_string(),
+            rx: AsyncMutex::new(rx),
+        }
+    }
+
+    /// Sets the probability, between 0 and 1, that a packet is lost.
+    pub fn set_drop_rate(&self, rate: f64) {
+        self.state().drop_rate = rate.clamp(0.0, 1.0);
+    }
+
+    /// Sets how long packets take to be delivered.
+    pub fn set_delay(&self, delay: Duration) {
+        self.state().delay = delay;
+    }
+
+    /// Drops every packet exchanged between the two addresses.
+    pub fn partition(&self, a: &str, b: &str) {
+        let mut state = self.state();
+        state.blocked.insert((a.to_string(), b.to_string()));
+        state.blocked.insert((b.to_string(), a.to_string()));
+    }
+
+    /// Drops every packet sent to or from the address.
+    pub fn isolate(&self, addr: &str) {
+        self.state().isolated.insert(addr.to_string());
+    }
+
+    /// Removes every partition and isolation.
+    pub fn heal(&self) {
+        let mut state = self.state();
+        state.blocked.clear();
+        state.isolated.clear();
+    }
+
+    fn deliver(&self, from: &str, to: &str, payload: Vec<u8>) {
+        let (sender, delay) = {
+            let mut state = self.state();
+            let blocked = state.isolated.contains(from)
+                || state.isolated.contains(to)
+                || state.blocked.contains(&(from.to_string(), to.to_string()));
+            let drop_rate = state.drop_rate;
+            if blocked || (drop_rate > 0.0 && state.rng.random_bool(drop_rate)) {
+                return;
+            }
+            match state.nodes.get(to) {
+                Some(sender) => (sender.clone(), state.delay),
+                None => return,
+            }
+        };
+
+        let packet = (payload, from.to_string());
+        if delay.is_zero() {
+            let _ = sender.send(packet);
+        } else {
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = sender.send(packet);
+            });
+        }
+    }
+}
+
+/// SimTransport is the endpoint of a node attached to a [`SimNetwork`].
+pub struct SimTransport {
+    network: SimNetwork,
+    addr: String,
+    rx: AsyncMutex<mpsc::UnboundedReceiver<Packet>>,
+}
+
+#[async_trait::async_trait]
+impl Transport for SimTransport {
+    fn local_addr(&self) -> String {
+        self.addr.clone()
+    }
+
+    async fn send_to(&self, addr: &str, payload: Vec<u8>) -> io::Result<()> {
+        self.network.deliver(&self.addr, addr, payload);
+        Ok(())
+    }
+
+    async fn recv_from(&self) -> io::Result<Packet> {
+        self.rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_partition_and_heal() {
+        let network = SimNetwork::new();
+        let a = network.bind("a");
+        let b = network.bind("b");
+
+        network.partition("a", "b");
+        a.send_to("b", b"lost".to_vec()).await.unwrap();
+        network.heal();
+        a.send_to("b", b"delivered".to_vec()).await.unwrap();
+
+        let (payload, from) = b.recv_from().await.unwrap();
+        assert_eq!(payload, b"delivered");
+        assert_eq!(from, "a");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay() {
+        let network = SimNetwork::new();
+        network.set_delay(Duration::from_millis(50));
+        let a = network.bind("a");
+        let b = network.bind("b");
+
+        let sent = tokio::time::Instant::now();
+        a.send_to("b", b"late".to_vec()).await.unwrap();
+        b.recv_from().await.unwrap();
+        assert!(sent.elapsed() >= Duration::from_millis(50));
+    }
+}
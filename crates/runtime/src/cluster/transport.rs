@@ -0,0 +1,68 @@
+use std::io;
+use tokio::net::UdpSocket;
+
+/// Largest datagram the membership protocol sends or receives.
+pub const MAX_PACKET_SIZE: usize = 64 * 1024;
+
+/// Transport delivers unreliable, unordered packets between cluster members.
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    /// Returns the address other members use to reach this transport.
+    fn local_addr(&self) -> String;
+
+    /// Sends a packet. Delivery is not guaranteed.
+    async fn send_to(&self, addr: &str, payload: Vec<u8>) -> io::Result<()>;
+
+    /// Waits for the next packet and returns it along with the address of its sender.
+    async fn recv_from(&self) -> io::Result<(Vec<u8>, String)>;
+}
+
+/// UdpTransport exchanges packets over a UDP socket.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    local_addr: String,
+}
+
+impl UdpTransport {
+    /// Binds a UDP socket on the given address.
+    pub async fn bind(addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        let local_addr = socket.local_addr()?.to_string();
+        Ok(Self { socket, local_addr })
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for UdpTransport {
+    fn local_addr(&self) -> String {
+        self.local_addr.clone()
+    }
+
+    async fn send_to(&self, addr: &str, payload: Vec<u8>) -> io::Result<()> {
+        self.socket.send_to(&payload, addr).await.map(|_| ())
+    }
+
+    async fn recv_from(&self) -> io::Result<(Vec<u8>, String)> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let (len, from) = self.socket.recv_from(&mut buf).await?;
+        buf.truncate(len);
+        Ok((buf, from.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_udp_transport_round_trip() {
+        let a = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0").await.unwrap();
+
+        a.send_to(&b.local_addr(), b"ping".to_vec()).await.unwrap();
+        let (payload, from) = b.recv_from().await.unwrap();
+        assert_eq!(payload, b"ping");
+        assert_eq!(from, a.local_addr());
+    }
+}
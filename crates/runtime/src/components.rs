@@ -0,0 +1,459 @@
+//! Loading of the component resources of the runtime.
+//!
+//! Components and resiliency policies are read from the YAML or JSON files of the resources
+//! directories. A file may hold several resources separated by `---`: resources of other
+//! kinds are skipped, as are the components scoped to other apps. Every component is then
+//! created by the registry of its building block, from its type and version.
+//!
+//! A component which fails to initialize fails the runtime, unless it is marked
+//! `ignoreErrors`. Either way its status is reported by the [`Health`] of the sidecar.
+
+use crate::api::ComponentMetadata;
+use crate::crypto::Crypto;
+use crate::health::Health;
+use crate::meta::{Meta, MetaError};
+use crate::resiliency::{Resiliency, ResiliencyError};
+use rapr_apis::components::v1alpha1::{self as components, Component};
+use rapr_apis::resiliency::v1alpha1 as resiliency;
+use rapr_contributes::component::{Closer, ComponentError, DEFAULT_VERSION, Pinger};
+use rapr_contributes::crypto;
+use rapr_contributes::nameresolution::{self, NameResolver};
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Prefix of the types of the crypto components.
+const CRYPTO_PREFIX: &str = "crypto.";
+/// Prefix of the types of the name resolution components.
+const NAME_RESOLUTION_PREFIX: &str = "nameresolution.";
+
+#[derive(Debug, Snafu)]
+pub enum ComponentsError {
+    #[snafu(display("Failed to read resources {}: {}", path.display(), source))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid resources {}: {}", path.display(), source))]
+    Parse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+
+    #[snafu(display("Component {} is defined more than once", name))]
+    DuplicateName { name: String },
+
+    #[snafu(display("Invalid metadata of component {}: {}", name, source))]
+    Metadata { name: String, source: MetaError },
+
+    #[snafu(display("Component {} has unsupported type {:?}", name, component_type))]
+    UnsupportedType {
+        name: String,
+        component_type: String,
+    },
+
+    #[snafu(display("Component {} is a second name resolver, {} is loaded", name, loaded))]
+    DuplicateNameResolver { name: String, loaded: String },
+
+    #[snafu(display("Failed to initialize component {}: {}", name, source))]
+    Init {
+        name: String,
+        source: ComponentError,
+    },
+
+    #[snafu(display("Invalid resiliency: {}", source))]
+    InvalidResiliency { source: ResiliencyError },
+}
+
+pub type Result<T> = std::result::Result<T, ComponentsError>;
+
+/// Resources holds the resources read from the resources directories.
+#[derive(Debug, Default)]
+pub struct Resources {
+    /// Components of the app.
+    pub components: Vec<Component>,
+    /// Resiliency resources, including those scoped to other apps.
+    pub resiliencies: Vec<resiliency::Resiliency>,
+}
+
+/// Reads the resources of the app `app_id` from the files of the resources directories, in
+/// the order of the directories and of the file names.
+pub fn load_dirs(paths: &[PathBuf], app_id: &str) -> Result<Resources> {
+    let mut resources = Resources::default();
+    let mut names = HashSet::new();
+    for dir in paths {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir).context(ReadSnafu { path: dir })? {
+            let path = entry.context(ReadSnafu { path: dir })?.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if path.is_file() && matches!(extension, Some("yaml" | "yml" | "json")) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        for path in files {
+            let contents = std::fs::read_to_string(&path).context(ReadSnafu { path: &path })?;
+            let (components, resiliencies) = parse(&contents, &path)?;
+            resources.resiliencies.extend(resiliencies);
+            for component in components {
+                let scopes = component.get_scopes();
+                if !scopes.is_empty() && !scopes.iter().any(|scope| scope == app_id) {
+                    tracing::debug!(
+                        component = component.get_name(),
+                        "Component is out of scope"
+                    );
+                    continue;
+                }
+                if !names.insert(component.get_name().to_string()) {
+                    return DuplicateNameSnafu {
+                        name: component.get_name(),
+                    }
+                    .fail();
+                }
+                resources.components.push(component);
+            }
+        }
+    }
+    Ok(resources)
+}
+
+/// Parses the components and the resiliency resources among the resources of a file.
+fn parse(contents: &str, path: &Path) -> Result<(Vec<Component>, Vec<resiliency::Resiliency>)> {
+    let mut components = Vec::new();
+    let mut resiliencies = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(contents) {
+        let resource = serde_yaml::Value::deserialize(document).context(ParseSnafu { path })?;
+        match resource.get("kind").and_then(serde_yaml::Value::as_str) {
+            Some(components::KIND) => {
+                components.push(serde_yaml::from_value(resource).context(ParseSnafu { path })?);
+            }
+            Some(resiliency::KIND) => {
+                resiliencies.push(serde_yaml::from_value(resource).context(ParseSnafu { path })?);
+            }
+            _ => {}
+        }
+    }
+    Ok((components, resiliencies))
+}
+
+/// Components holds the components initialized from their resources, by building block.
+#[derive(Default)]
+pub struct Components {
+    pub crypto: Crypto,
+    /// Policies of the app, applied to the crypto components and to service invocation.
+    pub resiliency: Arc<Resiliency>,
+    /// Resolver of the apps invoked by id, if a name resolution component is loaded.
+    pub name_resolver: Option<Arc<dyn NameResolver>>,
+    /// Descriptions of the initialized components, for the metadata API.
+    pub metadata: Vec<ComponentMetadata>,
+    /// Initialized components which can check their own health, by name.
+    pub pingers: Vec<(String, Arc<dyn Pinger>)>,
+    /// Initialized components to close when the runtime shuts down, by name, in the order
+    /// they were initialized.
+    pub closers: Vec<(String, Arc<dyn Closer>)>,
+}
+
+impl Components {
+    /// Initializes components in order, reporting their status to `health`. Components
+    /// marked `ignoreErrors` which fail to initialize are left out.
+    pub fn init(meta: &Meta, resources: &Resources, health: &Health) -> Result<Self> {
+        let resiliency = Resiliency::from_resources(&resources.resiliencies, &meta.id)
+            .context(InvalidResiliencySnafu)?;
+        let resiliency = Arc::new(resiliency);
+        let mut components = Self {
+            crypto: Crypto::default().with_resiliency(resiliency.clone()),
+            resiliency,
+            ..Default::default()
+        };
+        let crypto = crypto::registry();
+        let name_resolution = nameresolution::registry();
+        let mut resolver_name = None;
+        for component in &resources.components {
+            let name = component.get_name();
+            let (component_type, version, ignore_errors) = match &component.spec {
+                Some(spec) => (
+                    spec.cmpt_type.as_str(),
+                    spec.version.as_str(),
+                    spec.ignore_errors,
+                ),
+                None => ("", "", false),
+            };
+            health.register_component(component);
+            let base = meta
+                .to_base_metadata(component.clone())
+                .context(MetadataSnafu { name });
+            let mut capabilities = Vec::new();
+            let result = base.and_then(|base| {
+                if component_type.starts_with(CRYPTO_PREFIX) {
+                    let store = crypto
+                        .create(component_type, version, &base.properties)
+                        .context(InitSnafu { name })?;
+                    capabilities = store.algorithms();
+                    components.pingers.push((name.to_string(), store.clone()));
+                    components.closers.push((name.to_string(), store.clone()));
+                    components.crypto.add_component_resource(component, store);
+                } else if component_type.starts_with(NAME_RESOLUTION_PREFIX) {
+                    if let Some(loaded) = &resolver_name {
+                        return DuplicateNameResolverSnafu { name, loaded }.fail();
+                    }
+                    let resolver = name_resolution
+                        .create(component_type, version, &base.properties)
+                        .context(InitSnafu { name })?;
+                    components.name_resolver = Some(resolver);
+                    resolver_name = Some(name.to_string());
+                } else {
+                    return UnsupportedTypeSnafu {
+                        name,
+                        component_type,
+                    }
+                    .fail();
+                }
+                Ok(base)
+            });
+            match result {
+                Ok(base) => {
+                    let version = match version {
+                        "" => DEFAULT_VERSION,
+                        version => version,
+                    };
+                    tracing::info!(component = %component.log_name(), "Component loaded");
+                    health.component_ready(name);
+                    components.metadata.push(ComponentMetadata::new(
+                        &base,
+                        component_type,
+                        version,
+                        capabilities,
+                    ));
+                }
+                Err(e) => {
+                    health.component_failed(name, &e);
+                    if !ignore_errors {
+                        return Err(e);
+                    }
+                    tracing::warn!(component = %component.log_name(), error = %e, "Ignoring component which failed to initialize");
+                }
+            }
+        }
+        Ok(components)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::ComponentStatus;
+    use crate::meta::Options;
+    use rapr_common::RaprMode;
+
+    fn meta() -> Meta {
+        Meta::new(Options {
+            id: "orders".to_string(),
+            pod_name: String::new(),
+            namespace: "default".to_string(),
+            strict_sandbox: false,
+            mode: RaprMode::Standalone,
+        })
+    }
+
+    fn component(name: &str, component_type: &str, extra: &str) -> String {
+        format!(
+            r#"
+apiVersion: takulatech.rapr.io/v1alpha1
+kind: Component
+metadata:
+  name: {name}
+spec:
+  type: {component_type}
+  version: v1
+{extra}"#
+        )
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let keys = dir.path().display().to_string();
+        let resources = [
+            component(
+                "keys",
+                "crypto.rapr.localstorage",
+                &format!("  metadata:\n    - name: path\n      value: {keys}"),
+            ),
+            component(
+                "peers",
+                "nameresolution.static",
+                "  metadata:\n    - name: billing\n      value: 10.0.0.2:50002",
+            ),
+            // Out of scope.
+            component(
+                "billing-keys",
+                "crypto.rapr.localstorage",
+                "scopes: [billing]",
+            ),
+            "\nkind: Configuration\nmetadata:\n  name: appconfig\n".to_string(),
+        ];
+        std::fs::write(dir.path().join("components.yaml"), resources.join("\n---")).unwrap();
+        std::fs::write(
+            dir.path().join("statestore.yml"),
+            component("store", "state.redis", "  ignoreErrors: true"),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "kind: Component").unwrap();
+
+        let resources = load_dirs(&[dir.path().to_path_buf()], "orders").unwrap();
+        let names: Vec<&str> = resources
+            .components
+            .iter()
+            .map(Component::get_name)
+            .collect();
+        assert_eq!(names, ["keys", "peers", "store"]);
+
+        let health = Health::new();
+        let components = Components::init(&meta(), &resources, &health).unwrap();
+        assert_eq!(components.crypto.component_names(), ["keys"]);
+        assert!(components.name_resolver.is_some());
+        assert_eq!(components.pingers.len(), 1);
+        assert_eq!(components.pingers[0].0, "keys");
+        assert_eq!(components.closers.len(), 1);
+        // The state store failed, but ignoring its errors keeps the sidecar healthy.
+        let report = health.report();
+        assert!(report.healthy);
+        let statuses: Vec<(&str, ComponentStatus)> = report
+            .components
+            .iter()
+            .map(|component| (component.name.as_str(), component.status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("keys", ComponentStatus::Ready),
+                ("peers", ComponentStatus::Ready),
+                ("store", ComponentStatus::Failed),
+            ]
+        );
+        let names: Vec<&str> = components
+            .metadata
+            .iter()
+            .map(|component| component.name.as_str())
+            .collect();
+        assert_eq!(names, ["keys", "peers"]);
+        assert!(
+            components.metadata[0]
+                .capabilities
+                .iter()
+                .any(|c| c == "A256KW")
+        );
+        assert!(components.metadata[1].capabilities.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_load_resiliency() {
+        use crate::invocation::{DirectMessaging, InvocationError, InvokeRequest};
+        use crate::resiliency::PolicyError;
+
+        // The billing app accepts connections but never answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let resources = [
+            component(
+                "peers",
+                "nameresolution.static",
+                &format!("  metadata:\n    - name: billing\n      value: {address}"),
+            ),
+            r#"
+apiVersion: takulatech.rapr.io/v1alpha1
+kind: Resiliency
+metadata:
+  name: billing
+spec:
+  policies:
+    timeouts:
+      fast: 100ms
+  targets:
+    apps:
+      billing:
+        timeout: fast"#
+                .to_string(),
+        ];
+        std::fs::write(dir.path().join("resources.yaml"), resources.join("\n---")).unwrap();
+
+        let resources = load_dirs(&[dir.path().to_path_buf()], "orders").unwrap();
+        assert_eq!(resources.resiliencies.len(), 1);
+        let components = Components::init(&meta(), &resources, &Health::new()).unwrap();
+        assert!(components.resiliency.app_policy("billing").is_some());
+        let messaging = DirectMessaging::new(&meta(), components.name_resolver.unwrap(), 0)
+            .with_resiliency(components.resiliency);
+        let result = messaging
+            .invoke("billing", InvokeRequest::new("charge"))
+            .await;
+        assert!(
+            matches!(
+                result,
+                Err(InvocationError::Resiliency {
+                    source: PolicyError::Timeout { .. },
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let init = |resources: &[String]| {
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("components.yaml"), resources.join("\n---")).unwrap();
+            load_dirs(&[dir.path().to_path_buf()], "orders")
+                .and_then(|resources| Components::init(&meta(), &resources, &Health::new()))
+                .map(|_| ())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            init(&[component("store", "state.redis", "")]),
+            "Component store has unsupported type \"state.redis\""
+        );
+        assert_eq!(
+            init(&[component("keys", "crypto.rapr.localstorage", "")]),
+            "Failed to initialize component keys: Failed to initialize component \
+             crypto.rapr.localstorage: Missing metadata path"
+        );
+        assert_eq!(
+            init(&[
+                component("keys", "crypto.rapr.localstorage", ""),
+                component("keys", "state.redis", ""),
+            ]),
+            "Component keys is defined more than once"
+        );
+        assert_eq!(
+            init(&[
+                component(
+                    "dns",
+                    "nameresolution.dns",
+                    "  metadata:\n    - name: server\n      value: 127.0.0.1:53"
+                ),
+                component("peers", "nameresolution.static", ""),
+            ]),
+            "Component peers is a second name resolver, dns is loaded"
+        );
+        assert_eq!(
+            init(&[r#"
+apiVersion: takulatech.rapr.io/v1alpha1
+kind: Resiliency
+metadata:
+  name: billing
+spec:
+  targets:
+    apps:
+      billing:
+        retry: missing"#
+                .to_string()]),
+            "Invalid resiliency: Target app billing of resiliency billing refers to unknown retry \
+             policy missing"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! Loading of the [`Configuration`] resource of the runtime.
+//!
+//! In standalone mode the configuration is read from a YAML or JSON file; in Kubernetes mode
+//! it is fetched by name from the API server, in the namespace of the app.
+
+use kube::api::{Api, ApiResource, DynamicObject, GroupVersionKind};
+use rapr_apis::configuration::GROUP_NAME;
+use rapr_apis::configuration::v1alpha1::{Configuration, KIND, PLURAL, VERSION};
+use snafu::{ResultExt, Snafu};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Snafu)]
+pub enum ConfigurationError {
+    #[snafu(display("Failed to read configuration {}: {}", path.display(), source))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid configuration {}: {}", path.display(), source))]
+    Parse {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+
+    #[snafu(display("Resource {} is a {}, not a {}", name, kind, KIND))]
+    WrongKind { name: String, kind: String },
+
+    #[snafu(display("Failed to connect to the Kubernetes API server: {}", source))]
+    Client { source: Box<kube::Error> },
+
+    #[snafu(display("Failed to get configuration {}/{}: {}", namespace, name, source))]
+    Get {
+        namespace: String,
+        name: String,
+        source: Box<kube::Error>,
+    },
+
+    #[snafu(display("Invalid configuration {}/{}: {}", namespace, name, source))]
+    Decode {
+        namespace: String,
+        name: String,
+        source: serde_json::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, ConfigurationError>;
+
+/// ConfigurationSource tells where the configuration of the runtime is loaded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigurationSource {
+    /// A YAML or JSON file.
+    File(PathBuf),
+    /// A resource of the Kubernetes API server, by name.
+    Kubernetes { namespace: String, name: String },
+}
+
+impl ConfigurationSource {
+    /// Loads the configuration.
+    pub async fn load(&self) -> Result<Configuration> {
+        match self {
+            ConfigurationSource::File(path) => load_file(path),
+            ConfigurationSource::Kubernetes { namespace, name } => {
+                let client = kube::Client::try_default()
+                    .await
+                    .map_err(Box::new)
+                    .context(ClientSnafu)?;
+                load_kubernetes(client, namespace, name).await
+            }
+        }
+    }
+}
+
+/// Reads a configuration from a YAML or JSON file.
+pub fn load_file(path: &Path) -> Result<Configuration> {
+    let contents = std::fs::read_to_string(path).context(ReadSnafu { path })?;
+    parse(&contents, path)
+}
+
+fn parse(contents: &str, path: &Path) -> Result<Configuration> {
+    let configuration: Configuration =
+        serde_yaml::from_str(contents).context(ParseSnafu { path })?;
+    check_kind(&configuration, &path.display().to_string())?;
+    Ok(configuration)
+}
+
+/// Fetches a configuration from the Kubernetes API server.
+pub async fn load_kubernetes(
+    client: kube::Client,
+    namespace: &str,
+    name: &str,
+) -> Result<Configuration> {
+    let resource = ApiResource::from_gvk_with_plural(
+        &GroupVersionKind::gvk(GROUP_NAME, VERSION, KIND),
+        PLURAL,
+    );
+    let api: Api<DynamicObject> = Api::namespaced_with(client, namespace, &resource);
+    let object = api
+        .get(name)
+        .await
+        .map_err(Box::new)
+        .context(GetSnafu { namespace, name })?;
+    let configuration = serde_json::to_value(object)
+        .and_then(serde_json::from_value)
+        .context(DecodeSnafu { namespace, name })?;
+    Ok(configuration)
+}
+
+fn check_kind(configuration: &Configuration, name: &str) -> Result<()> {
+    let kind = &configuration.type_meta.kind;
+    if kind != KIND {
+        return Err(ConfigurationError::WrongKind {
+            name: name.to_string(),
+            kind: kind.clone(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_load_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"
+apiVersion: takulatech.rapr.io/v1alpha1
+kind: Configuration
+metadata:
+  name: appconfig
+spec:
+  features:
+    - name: Actors
+      enabled: true
+  wasm:
+    strictSandbox: true
+"#
+        )
+        .unwrap();
+
+        let configuration = load_file(file.path()).unwrap();
+        assert_eq!(configuration.get_name(), "appconfig");
+        assert!(configuration.spec.is_feature_enabled("Actors"));
+        assert!(configuration.spec.wasm_strict_sandbox());
+    }
+
+    #[test]
+    fn test_load_file_errors() {
+        let path = Path::new("config.yaml");
+        assert!(matches!(
+            parse("spec: [", path),
+            Err(ConfigurationError::Parse { .. })
+        ));
+        assert_eq!(
+            parse("apiVersion: v1\nkind: Component\n", path)
+                .unwrap_err()
+                .to_string(),
+            "Resource config.yaml is a Component, not a Configuration"
+        );
+        assert!(matches!(
+            load_file(Path::new("/does/not/exist.yaml")),
+            Err(ConfigurationError::Read { .. })
+        ));
+    }
+}
@@ -0,0 +1,664 @@
+//! Cryptography building block.
+//!
+//! Payloads are encrypted with a random file key, which is itself wrapped by a key held in a
+//! [`KeyStore`] component: the key store never sees the payload, and the payload can be
+//! decrypted as long as the wrapping key exists.
+//!
+//! # Format (version 1)
+//!
+//! ```text
+//! rapr.io/enc/v1\n
+//! {"keyName":...,"keyWrapAlgorithm":...,"wrappedKey":...,"cipher":"AES-GCM","noncePrefix":...}\n
+//! segment 0 | segment 1 | ... | last segment
+//! ```
+//!
+//! Every segment but the last holds [`SEGMENT_SIZE`] bytes of plaintext, sealed with
+//! AES-256-GCM along with its 16 bytes tag. The nonce of a segment is the 7 bytes nonce
+//! prefix, the segment index as a big endian `u32` and a byte set to 1 for the last
+//! segment only, so that reordered, dropped and truncated segments fail to decrypt. The
+//! header line is the additional authenticated data of every segment.
+
+use crate::diagnostics::Metrics;
+use crate::logging::component_span;
+use crate::resiliency::{PolicyError, Resiliency};
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload, rand_core::RngCore};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rapr_apis::components::v1alpha1::Component;
+use rapr_contributes::crypto::{self as components, Jwk, KeyStore};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tracing::Instrument;
+
+/// First line of payloads encrypted with the version 1 format.
+pub const FORMAT_V1: &str = "rapr.io/enc/v1";
+
+/// Size of the plaintext of a segment.
+pub const SEGMENT_SIZE: usize = 64 * 1024;
+
+const TAG_SIZE: usize = 16;
+const NONCE_PREFIX_SIZE: usize = 7;
+const CIPHER: &str = "AES-GCM";
+/// Upper bound of the header line, which is read before anything can be authenticated.
+const MAX_HEADER_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Snafu)]
+pub enum CryptoError {
+    #[snafu(display("Crypto component {} not found", name))]
+    ComponentNotFound { name: String },
+
+    #[snafu(display("Key store operation failed: {}", source))]
+    KeyStore { source: components::CryptoError },
+
+    #[snafu(display("Key store operation failed: {}", source))]
+    Resiliency { source: PolicyError },
+
+    #[snafu(display("Unsupported encryption format {:?}", version))]
+    UnsupportedFormat { version: String },
+
+    #[snafu(display("Invalid encryption header: {}", reason))]
+    InvalidHeader { reason: String },
+
+    #[snafu(display(
+        "Failed to decrypt segment {}: wrong key, or tampered or truncated payload",
+        segment
+    ))]
+    Decryption { segment: u32 },
+
+    #[snafu(display("Payload is too large to be encrypted"))]
+    TooLarge,
+
+    #[snafu(display("I/O error: {}", source))]
+    Io { source: io::Error },
+}
+
+pub type Result<T> = std::result::Result<T, CryptoError>;
+
+/// EncryptOptions selects the key wrapping the file key of an encrypted payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptOptions {
+    /// Name of the crypto component.
+    pub component: String,
+    /// Name of the key wrapping the file key.
+    pub key_name: String,
+    /// Algorithm wrapping the file key, such as `A256KW` or `RSA-OAEP-256`.
+    pub key_wrap_algorithm: String,
+    /// Name of the key to unwrap the file key with, when it differs from `key_name`,
+    /// e.g. when encrypting with a public key. Recorded in the header.
+    pub decryption_key_name: Option<String>,
+}
+
+/// DecryptOptions selects the key unwrapping the file key of an encrypted payload.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecryptOptions {
+    /// Name of the crypto component.
+    pub component: String,
+    /// Overrides the name of the key recorded in the header.
+    pub key_name: Option<String>,
+}
+
+/// Header of the version 1 format.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Header {
+    key_name: String,
+    key_wrap_algorithm: String,
+    /// Base64 encoded.
+    wrapped_key: String,
+    cipher: String,
+    /// Base64 encoded.
+    nonce_prefix: String,
+}
+
+/// Crypto runs the operations of the cryptography building block on named key stores.
+#[derive(Default, Clone)]
+pub struct Crypto {
+    components: HashMap<String, Arc<dyn KeyStore>>,
+    /// Log names of the components added from their resource, by name.
+    log_names: HashMap<String, String>,
+    /// Policies applied to the calls to the key stores.
+    resiliency: Option<Arc<Resiliency>>,
+    metrics: Option<Metrics>,
+}
+
+impl Crypto {
+    /// Creates a building block without components.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key store under the name of its component.
+    pub fn add_component(&mut self, name: impl Into<String>, store: Arc<dyn KeyStore>) {
+        self.components.insert(name.into(), store);
+    }
+
+    /// Adds the key store of a component resource, whose log lines and metrics carry its log
+    /// name.
+    pub fn add_component_resource(&mut self, component: &Component, store: Arc<dyn KeyStore>) {
+        let name = component.get_name().to_string();
+        self.log_names.insert(name.clone(), component.log_name());
+        self.add_component(name, store);
+    }
+
+    /// Applies the outbound resiliency policies of the components to the calls to their key
+    /// stores.
+    pub fn with_resiliency(mut self, resiliency: Arc<Resiliency>) -> Self {
+        self.resiliency = Some(resiliency);
+        self
+    }
+
+    /// Records the metrics of the calls to the key stores.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Returns the key store of a component.
+    pub fn component(&self, name: &str) -> Result<&Arc<dyn KeyStore>> {
+        self.components
+            .get(name)
+            .ok_or_else(|| CryptoError::ComponentNotFound {
+                name: name.to_string(),
+            })
+    }
+
+    /// Returns the names of the components.
+    pub fn component_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.components.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Returns the public part of a key.
+    pub async fn get_key(&self, component: &str, key_name: &str) -> Result<Jwk> {
+        self.call(component, "get_key", |store| async move {
+            store.get_key(key_name).await
+        })
+        .await
+    }
+
+    /// Wraps a key with a key of the key store.
+    pub async fn wrap_key(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        plaintext_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.call(component, "wrap_key", |store| async move {
+            store.wrap_key(key_name, algorithm, plaintext_key).await
+        })
+        .await
+    }
+
+    /// Unwraps a key with a key of the key store.
+    pub async fn unwrap_key(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        wrapped_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.call(component, "unwrap_key", |store| async move {
+            store.unwrap_key(key_name, algorithm, wrapped_key).await
+        })
+        .await
+    }
+
+    /// Signs a digest with a key of the key store.
+    pub async fn sign(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        digest: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.call(component, "sign", |store| async move {
+            store.sign(key_name, algorithm, digest).await
+        })
+        .await
+    }
+
+    /// Verifies the signature of a digest with a key of the key store.
+    pub async fn verify(
+        &self,
+        component: &str,
+        key_name: &str,
+        algorithm: &str,
+        digest: &[u8],
+        signature: &[u8],
+    ) -> Result<bool> {
+        self.call(component, "verify", |store| async move {
+            store.verify(key_name, algorithm, digest, signature).await
+        })
+        .await
+    }
+
+    /// Calls the key store of a component within its span, recording the metrics of the
+    /// operation.
+    async fn call<T, F, Fut>(&self, component: &str, name: &str, operation: F) -> Result<T>
+    where
+        F: FnMut(Arc<dyn KeyStore>) -> Fut,
+        Fut: Future<Output = components::Result<T>>,
+    {
+        let store = self.component(component)?;
+        let log_name = self
+            .log_names
+            .get(component)
+            .map_or(component, String::as_str);
+        let call = self
+            .call_store(component, store, operation)
+            .instrument(component_span(log_name));
+        let Some(metrics) = &self.metrics else {
+            return call.await;
+        };
+        let start = Instant::now();
+        let result = call.await;
+        let elapsed = start.elapsed();
+        metrics.record_component_operation("crypto", log_name, name, result.is_ok(), elapsed);
+        result
+    }
+
+    /// Calls a key store, under the outbound policy of its component.
+    async fn call_store<T, F, Fut>(
+        &self,
+        component: &str,
+        store: &Arc<dyn KeyStore>,
+        mut operation: F,
+    ) -> Result<T>
+    where
+        F: FnMut(Arc<dyn KeyStore>) -> Fut,
+        Fut: Future<Output = components::Result<T>>,
+    {
+        let policy = self
+            .resiliency
+            .as_ref()
+            .and_then(|resiliency| resiliency.component_outbound_policy(component));
+        let Some(policy) = policy else {
+            return operation(store.clone()).await.context(KeyStoreSnafu);
+        };
+        policy
+            .execute(|| operation(store.clone()))
+            .await
+            .map_err(|error| match error.downcast::<components::CryptoError>() {
+                Ok(error) => CryptoError::KeyStore { source: *error },
+                Err(error) => CryptoError::Resiliency {
+                    source: *error
+                        .downcast::<PolicyError>()
+                        .expect("policies only add policy errors"),
+                },
+            })
+    }
+
+    /// Encrypts everything read from `reader` into `writer`, one segment at a time.
+    pub async fn encrypt<R, W>(
+        &self,
+        reader: R,
+        mut writer: W,
+        options: &EncryptOptions,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let file_key = Aes256Gcm::generate_key(OsRng);
+        let wrapped_key = self
+            .wrap_key(
+                &options.component,
+                &options.key_name,
+                &options.key_wrap_algorithm,
+                &file_key,
+            )
+            .await?;
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        let header = Header {
+            key_name: options
+                .decryption_key_name
+                .clone()
+                .unwrap_or_else(|| options.key_name.clone()),
+            key_wrap_algorithm: options.key_wrap_algorithm.clone(),
+            wrapped_key: STANDARD.encode(wrapped_key),
+            cipher: CIPHER.to_string(),
+            nonce_prefix: STANDARD.encode(nonce_prefix),
+        };
+        let header = serde_json::to_string(&header).expect("headers serialize to JSON");
+        let prelude = format!("{FORMAT_V1}\n{header}\n");
+        writer
+            .write_all(prelude.as_bytes())
+            .await
+            .context(IoSnafu)?;
+
+        let cipher = Aes256Gcm::new(&file_key);
+        let mut segments = Segments::new(reader, SEGMENT_SIZE);
+        let mut index = 0u32;
+        loop {
+            let (plaintext, last) = segments.next().await?;
+            let nonce = nonce(&nonce_prefix, index, last);
+            let sealed = cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: plaintext,
+                        aad: header.as_bytes(),
+                    },
+                )
+                .map_err(|_| CryptoError::TooLarge)?;
+            writer.write_all(&sealed).await.context(IoSnafu)?;
+            if last {
+                break;
+            }
+            index = index.checked_add(1).ok_or(CryptoError::TooLarge)?;
+        }
+        writer.flush().await.context(IoSnafu)
+    }
+
+    /// Decrypts a payload produced by [`Crypto::encrypt`] into `writer`. Segments are only
+    /// written once authenticated, but a payload failing to decrypt midway leaves the
+    /// segments before the failure in `writer`.
+    pub async fn decrypt<R, W>(
+        &self,
+        reader: R,
+        mut writer: W,
+        options: &DecryptOptions,
+    ) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        self.component(&options.component)?;
+        let mut reader = BufReader::new(reader);
+        let version = read_line(&mut reader).await?;
+        if version != FORMAT_V1 {
+            return Err(CryptoError::UnsupportedFormat { version });
+        }
+        let header_line = read_line(&mut reader).await?;
+        let header: Header = serde_json::from_str(&header_line).map_err(invalid_header)?;
+        if header.cipher != CIPHER {
+            return Err(invalid_header(format!(
+                "unsupported cipher {}",
+                header.cipher
+            )));
+        }
+        let nonce_prefix: [u8; NONCE_PREFIX_SIZE] = STANDARD
+            .decode(&header.nonce_prefix)
+            .ok()
+            .and_then(|prefix| prefix.try_into().ok())
+            .ok_or_else(|| invalid_header("invalid nonce prefix"))?;
+        let wrapped_key = STANDARD
+            .decode(&header.wrapped_key)
+            .map_err(|_| invalid_header("invalid wrapped key"))?;
+
+        let key_name = options.key_name.as_deref().unwrap_or(&header.key_name);
+        let file_key = self
+            .unwrap_key(
+                &options.component,
+                key_name,
+                &header.key_wrap_algorithm,
+                &wrapped_key,
+            )
+            .await?;
+        let cipher = Aes256Gcm::new_from_slice(&file_key)
+            .map_err(|_| invalid_header("the wrapped key is not an AES-256 key"))?;
+
+        let mut segments = Segments::new(reader, SEGMENT_SIZE + TAG_SIZE);
+        let mut index = 0u32;
+        loop {
+            let (sealed, last) = segments.next().await?;
+            let plaintext = cipher
+                .decrypt(
+                    &nonce(&nonce_prefix, index, last),
+                    Payload {
+                        msg: sealed,
+                        aad: header_line.as_bytes(),
+                    },
+                )
+                .map_err(|_| CryptoError::Decryption { segment: index })?;
+            writer.write_all(&plaintext).await.context(IoSnafu)?;
+            if last {
+                break;
+            }
+            index = index
+                .checked_add(1)
+                .ok_or(CryptoError::Decryption { segment: index })?;
+        }
+        writer.flush().await.context(IoSnafu)
+    }
+}
+
+fn invalid_header(reason: impl ToString) -> CryptoError {
+    CryptoError::InvalidHeader {
+        reason: reason.to_string(),
+    }
+}
+
+fn nonce(
+    prefix: &[u8; NONCE_PREFIX_SIZE],
+    index: u32,
+    last: bool,
+) -> Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce.into()
+}
+
+/// Reads a line of the prelude, without its line feed.
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<String> {
+    let mut line = Vec::new();
+    (&mut *reader)
+        .take(MAX_HEADER_SIZE)
+        .read_until(b'\n', &mut line)
+        .await
+        .context(IoSnafu)?;
+    if line.pop() != Some(b'\n') {
+        return Err(invalid_header("missing or too long header line"));
+    }
+    String::from_utf8(line).map_err(|_| invalid_header("header is not UTF-8"))
+}
+
+/// Segments splits a stream into segments of a fixed size, reading one segment ahead to
+/// tell the last segment apart. The last segment may be short or even empty.
+struct Segments<R> {
+    reader: R,
+    current: Vec<u8>,
+    next: Vec<u8>,
+    next_len: Option<usize>,
+}
+
+impl<R: AsyncRead + Unpin> Segments<R> {
+    fn new(reader: R, size: usize) -> Self {
+        Self {
+            reader,
+            current: vec![0; size],
+            next: vec![0; size],
+            next_len: None,
+        }
+    }
+
+    async fn next(&mut self) -> Result<(&[u8], bool)> {
+        let len = match self.next_len.take() {
+            Some(len) => {
+                std::mem::swap(&mut self.current, &mut self.next);
+                len
+            }
+            None => read_full(&mut self.reader, &mut self.current).await?,
+        };
+        let last = if len < self.current.len() {
+            true
+        } else {
+            let next_len = read_full(&mut self.reader, &mut self.next).await?;
+            self.next_len = Some(next_len).filter(|len| *len > 0);
+            next_len == 0
+        };
+        Ok((&self.current[..len], last))
+    }
+}
+
+/// Reads until the buffer is full or the end of the stream, returning the bytes read.
+async fn read_full<R: AsyncRead + Unpin>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]).await.context(IoSnafu)? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapr_contributes::crypto::{A256KW, LocalStorageKeyStore};
+
+    fn crypto() -> (tempfile::TempDir, Crypto) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("aes"), [5u8; 32]).unwrap();
+        std::fs::write(dir.path().join("other"), [6u8; 32]).unwrap();
+        let mut crypto = Crypto::new();
+        crypto.add_component("keys", Arc::new(LocalStorageKeyStore::new(dir.path())));
+        (dir, crypto)
+    }
+
+    fn encrypt_options() -> EncryptOptions {
+        EncryptOptions {
+            component: "keys".to_string(),
+            key_name: "aes".to_string(),
+            key_wrap_algorithm: A256KW.to_string(),
+            decryption_key_name: None,
+        }
+    }
+
+    fn decrypt_options() -> DecryptOptions {
+        DecryptOptions {
+            component: "keys".to_string(),
+            key_name: None,
+        }
+    }
+
+    async fn encrypt(crypto: &Crypto, plaintext: &[u8]) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        crypto
+            .encrypt(plaintext, &mut encrypted, &encrypt_options())
+            .await
+            .unwrap();
+        encrypted
+    }
+
+    async fn decrypt(
+        crypto: &Crypto,
+        encrypted: &[u8],
+        options: &DecryptOptions,
+    ) -> Result<Vec<u8>> {
+        let mut decrypted = Vec::new();
+        crypto.decrypt(encrypted, &mut decrypted, options).await?;
+        Ok(decrypted)
+    }
+
+    /// Returns the length of the version and header lines.
+    fn prelude_len(encrypted: &[u8]) -> usize {
+        let header = FORMAT_V1.len() + 1;
+        header
+            + encrypted[header..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .unwrap()
+            + 1
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let (_dir, crypto) = crypto();
+        for len in [0, 1, SEGMENT_SIZE - 1, SEGMENT_SIZE, 2 * SEGMENT_SIZE + 7] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = encrypt(&crypto, &plaintext).await;
+            assert!(encrypted.starts_with(b"rapr.io/enc/v1\n{\"keyName\":\"aes\""));
+            // A full last segment is not followed by an empty one.
+            let segments = len.div_ceil(SEGMENT_SIZE).max(1);
+            assert_eq!(
+                encrypted.len(),
+                prelude_len(&encrypted) + len + segments * TAG_SIZE
+            );
+
+            let decrypted = decrypt(&crypto, &encrypted, &decrypt_options())
+                .await
+                .unwrap();
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tampering() {
+        let (_dir, crypto) = crypto();
+        let plaintext = vec![1u8; 2 * SEGMENT_SIZE];
+        let encrypted = encrypt(&crypto, &plaintext).await;
+        let options = decrypt_options();
+
+        let truncated = &encrypted[..encrypted.len() - 1];
+        assert!(matches!(
+            decrypt(&crypto, truncated, &options).await,
+            Err(CryptoError::Decryption { segment: 1 })
+        ));
+        // Dropping the last segment makes the one before look like the last one.
+        let truncated = &encrypted[..encrypted.len() - SEGMENT_SIZE - TAG_SIZE];
+        assert!(matches!(
+            decrypt(&crypto, truncated, &options).await,
+            Err(CryptoError::Decryption { segment: 0 })
+        ));
+
+        let mut flipped = encrypted.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert!(matches!(
+            decrypt(&crypto, &flipped, &options).await,
+            Err(CryptoError::Decryption { segment: 1 })
+        ));
+
+        // The header is authenticated.
+        let mut tampered = encrypted.clone();
+        tampered.insert(FORMAT_V1.len() + 2, b' ');
+        assert!(matches!(
+            decrypt(&crypto, &tampered, &options).await,
+            Err(CryptoError::Decryption { segment: 0 })
+        ));
+
+        let wrong_key = DecryptOptions {
+            key_name: Some("other".to_string()),
+            ..options.clone()
+        };
+        assert!(matches!(
+            decrypt(&crypto, &encrypted, &wrong_key).await,
+            Err(CryptoError::KeyStore { .. })
+        ));
+        assert!(matches!(
+            decrypt(&crypto, b"rapr.io/enc/v2\n{}\n", &options).await,
+            Err(CryptoError::UnsupportedFormat { .. })
+        ));
+        assert!(matches!(
+            decrypt(&crypto, b"rapr.io/enc/v1\n", &options).await,
+            Err(CryptoError::InvalidHeader { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_component() {
+        let (_dir, crypto) = crypto();
+        let options = EncryptOptions {
+            component: "missing".to_string(),
+            ..encrypt_options()
+        };
+        let result = crypto.encrypt(&b"data"[..], Vec::new(), &options).await;
+        assert!(matches!(result, Err(CryptoError::ComponentNotFound { .. })));
+        assert_eq!(crypto.component_names(), ["keys"]);
+    }
+}
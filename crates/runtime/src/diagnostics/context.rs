@@ -0,0 +1,189 @@
+use axum::http::{HeaderMap, HeaderValue};
+use serde_json::{Map, Value};
+use std::fmt::Write;
+
+/// Header carrying the trace and parent span of a call, as `00-<trace-id>-<span-id>-<flags>`.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+/// Header carrying the vendor-specific state of a trace.
+pub const TRACESTATE_HEADER: &str = "tracestate";
+
+/// Flag of the traces which are recorded.
+const FLAG_SAMPLED: u8 = 0x01;
+
+/// TraceContext identifies a span within its trace, as propagated by the
+/// [W3C trace context](https://www.w3.org/TR/trace-context/) headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    /// Whether the trace is recorded.
+    pub sampled: bool,
+    /// Vendor-specific state, propagated unchanged.
+    pub trace_state: String,
+}
+
+impl TraceContext {
+    /// Parses the values of the `traceparent` and `tracestate` headers. Invalid contexts,
+    /// such as those with all-zero IDs, are ignored, as the specification requires.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let traceparent = traceparent.trim();
+        let mut fields = traceparent.splitn(5, '-');
+        let version = u8::from_str_radix(fields.next().filter(|v| v.len() == 2)?, 16).ok()?;
+        let trace_id = decode_hex::<16>(fields.next()?)?;
+        let span_id = decode_hex::<8>(fields.next()?)?;
+        let [flags] = decode_hex::<1>(fields.next()?)?;
+        // Future versions may append fields, version 0 may not.
+        if version == 0xff || (version == 0 && fields.next().is_some()) {
+            return None;
+        }
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(Self {
+            trace_id,
+            span_id,
+            sampled: flags & FLAG_SAMPLED != 0,
+            trace_state: tracestate.unwrap_or_default().trim().to_string(),
+        })
+    }
+
+    /// Reads the context of HTTP headers.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let traceparent = headers.get(TRACEPARENT_HEADER)?.to_str().ok()?;
+        let tracestate = headers
+            .get(TRACESTATE_HEADER)
+            .and_then(|value| value.to_str().ok());
+        Self::parse(traceparent, tracestate)
+    }
+
+    /// Writes the context to HTTP headers, replacing the one they carry.
+    pub fn to_headers(&self, headers: &mut HeaderMap) {
+        let traceparent =
+            HeaderValue::try_from(self.traceparent()).expect("traceparent values are valid");
+        headers.insert(TRACEPARENT_HEADER, traceparent);
+        match HeaderValue::try_from(self.trace_state.as_str()) {
+            Ok(tracestate) if !self.trace_state.is_empty() => {
+                headers.insert(TRACESTATE_HEADER, tracestate);
+            }
+            _ => {
+                headers.remove(TRACESTATE_HEADER);
+            }
+        }
+    }
+
+    /// Reads the context of the `traceparent` and `tracestate` extension attributes of a
+    /// CloudEvent.
+    pub fn from_cloud_event(event: &Map<String, Value>) -> Option<Self> {
+        let traceparent = event.get(TRACEPARENT_HEADER)?.as_str()?;
+        let tracestate = event.get(TRACESTATE_HEADER).and_then(Value::as_str);
+        Self::parse(traceparent, tracestate)
+    }
+
+    /// Writes the context to the extension attributes of a CloudEvent.
+    pub fn to_cloud_event(&self, event: &mut Map<String, Value>) {
+        event.insert(TRACEPARENT_HEADER.to_string(), self.traceparent().into());
+        match self.trace_state.is_empty() {
+            true => event.remove(TRACESTATE_HEADER),
+            false => event.insert(
+                TRACESTATE_HEADER.to_string(),
+                self.trace_state.clone().into(),
+            ),
+        };
+    }
+
+    /// Returns the value of the `traceparent` header.
+    pub fn traceparent(&self) -> String {
+        let flags = if self.sampled { FLAG_SAMPLED } else { 0 };
+        format!(
+            "00-{}-{}-{}",
+            encode_hex(&self.trace_id),
+            encode_hex(&self.span_id),
+            encode_hex(&[flags])
+        )
+    }
+
+    /// Returns the trace ID as lowercase hex.
+    pub fn trace_id_hex(&self) -> String {
+        encode_hex(&self.trace_id)
+    }
+
+    /// Returns the span ID as lowercase hex.
+    pub fn span_id_hex(&self) -> String {
+        encode_hex(&self.span_id)
+    }
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+/// Decodes exactly `N` bytes of lowercase hex.
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || hex.bytes().any(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse() {
+        let context = TraceContext::parse(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+        assert_eq!(context.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id_hex(), "00f067aa0ba902b7");
+        assert!(context.sampled);
+        assert_eq!(context.trace_state, "congo=t61rcWkgMzE");
+        assert_eq!(context.traceparent(), TRACEPARENT);
+
+        let unsampled = TraceContext::parse(&TRACEPARENT.replace("-01", "-00"), None).unwrap();
+        assert!(!unsampled.sampled);
+        // Later versions may carry more fields.
+        assert!(TraceContext::parse(&format!("cc{}-extra", &TRACEPARENT[2..]), None).is_some());
+
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceContext::parse(invalid, None), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_propagation() {
+        let context = TraceContext::parse(TRACEPARENT, Some("congo=t61rcWkgMzE")).unwrap();
+        let mut headers = HeaderMap::new();
+        context.to_headers(&mut headers);
+        assert_eq!(headers[TRACEPARENT_HEADER], TRACEPARENT);
+        assert_eq!(TraceContext::from_headers(&headers), Some(context.clone()));
+
+        let mut event = serde_json::json!({"specversion": "1.0", "tracestate": "old=1"});
+        let event = event.as_object_mut().unwrap();
+        let unstated = TraceContext {
+            trace_state: String::new(),
+            ..context.clone()
+        };
+        unstated.to_cloud_event(event);
+        assert_eq!(event["traceparent"], TRACEPARENT);
+        assert!(!event.contains_key("tracestate"));
+        context.to_cloud_event(event);
+        assert_eq!(TraceContext::from_cloud_event(event), Some(context));
+    }
+}
@@ -0,0 +1,560 @@
+use crate::api::ActorCounter;
+use axum::Router;
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use rapr_apis::configuration::v1alpha1::MetricsSpec;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Default port of the metrics endpoint.
+pub const DEFAULT_METRICS_PORT: u16 = 9090;
+/// Path of the metrics endpoint.
+pub const METRICS_PATH: &str = "/metrics";
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Value reported in place of the label values which are not allowed.
+pub const OTHER_VALUE: &str = "_";
+
+/// Upper bounds of the buckets of the latency histograms, in seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Counter,
+    Gauge,
+    Histogram,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        })
+    }
+}
+
+/// Descriptor names a metric and its labels.
+struct Descriptor {
+    name: &'static str,
+    help: &'static str,
+    kind: Kind,
+    labels: &'static [&'static str],
+}
+
+const API_REQUESTS: Descriptor = Descriptor {
+    name: "rapr_api_requests_total",
+    help: "Calls to the building-block API.",
+    kind: Kind::Counter,
+    labels: &["protocol", "building_block", "operation", "status"],
+};
+const API_LATENCY: Descriptor = Descriptor {
+    name: "rapr_api_latency_seconds",
+    help: "Latency of the calls to the building-block API.",
+    kind: Kind::Histogram,
+    labels: &["protocol", "building_block", "operation", "status"],
+};
+const INVOCATION_REQUESTS: Descriptor = Descriptor {
+    name: "rapr_service_invocation_requests_total",
+    help: "Service invocations made to other apps (client) or received from them (server).",
+    kind: Kind::Counter,
+    labels: &["side", "app_id", "method", "status"],
+};
+const INVOCATION_LATENCY: Descriptor = Descriptor {
+    name: "rapr_service_invocation_latency_seconds",
+    help: "Latency of the service invocations.",
+    kind: Kind::Histogram,
+    labels: &["side", "app_id", "method", "status"],
+};
+const COMPONENT_OPERATIONS: Descriptor = Descriptor {
+    name: "rapr_component_operations_total",
+    help: "Operations of the components.",
+    kind: Kind::Counter,
+    labels: &["building_block", "component", "operation", "status"],
+};
+const COMPONENT_LATENCY: Descriptor = Descriptor {
+    name: "rapr_component_latency_seconds",
+    help: "Latency of the operations of the components.",
+    kind: Kind::Histogram,
+    labels: &["building_block", "component", "operation", "status"],
+};
+const ACTORS_ACTIVE: Descriptor = Descriptor {
+    name: "rapr_actors_active",
+    help: "Actors currently active.",
+    kind: Kind::Gauge,
+    labels: &["actor_type"],
+};
+const CLUSTER_MEMBERS: Descriptor = Descriptor {
+    name: "rapr_cluster_members",
+    help: "Active members of the cluster, including the local node.",
+    kind: Kind::Gauge,
+    labels: &[],
+};
+
+/// Metrics exposed, in order.
+const DESCRIPTORS: &[&Descriptor] = &[
+    &API_REQUESTS,
+    &API_LATENCY,
+    &INVOCATION_REQUESTS,
+    &INVOCATION_LATENCY,
+    &COMPONENT_OPERATIONS,
+    &COMPONENT_LATENCY,
+    &ACTORS_ACTIVE,
+    &CLUSTER_MEMBERS,
+];
+
+/// Side of a service invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvocationSide {
+    /// Invocation made to another app.
+    Client,
+    /// Invocation received from another app.
+    Server,
+}
+
+impl InvocationSide {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Client => "client",
+            Self::Server => "server",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Series {
+    Value(f64),
+    Histogram {
+        /// Observations of each bucket, not cumulative.
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+/// LabelFilter bounds the values labels are reported with.
+#[derive(Debug, Clone, Default)]
+struct LabelFilter {
+    /// Patterns of the invoked methods, whose segments between braces match any segment.
+    paths: Vec<Vec<String>>,
+    /// Values allowed by label name.
+    allowed: HashMap<String, Vec<String>>,
+}
+
+impl LabelFilter {
+    fn from_spec(spec: &MetricsSpec) -> Self {
+        let paths = spec
+            .http
+            .iter()
+            .flat_map(|http| &http.path_matching)
+            .map(|pattern| segments(pattern).map(str::to_string).collect())
+            .collect();
+        let allowed = spec
+            .labels
+            .iter()
+            .map(|label| (label.name.clone(), label.allowed_values.clone()))
+            .collect();
+        Self { paths, allowed }
+    }
+
+    /// Returns the pattern matching an invoked method, `_` if none does. Methods are
+    /// reported as is without patterns.
+    fn method(&self, method: &str) -> String {
+        if self.paths.is_empty() {
+            return method.to_string();
+        }
+        let method: Vec<&str> = segments(method).collect();
+        self.paths
+            .iter()
+            .find(|pattern| {
+                pattern.len() == method.len()
+                    && pattern.iter().zip(&method).all(|(pattern, segment)| {
+                        (pattern.starts_with('{') && pattern.ends_with('}')) || pattern == segment
+                    })
+            })
+            .map(|pattern| format!("/{}", pattern.join("/")))
+            .unwrap_or_else(|| OTHER_VALUE.to_string())
+    }
+
+    fn value(&self, label: &str, value: &str) -> String {
+        match self.allowed.get(label) {
+            Some(allowed) if !allowed.iter().any(|allowed| allowed == value) => {
+                OTHER_VALUE.to_string()
+            }
+            _ => value.to_string(),
+        }
+    }
+}
+
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Metrics records the metrics of the runtime and renders them in the Prometheus text
+/// exposition format. Labels listed in the configuration are reported with their allowed
+/// values only, and invoked methods with the path pattern they match, so that the number of
+/// series stays bounded.
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    filter: LabelFilter,
+    /// Series by metric name, then by label values.
+    series: Mutex<HashMap<&'static str, BTreeMap<Vec<String>, Series>>>,
+    actors: Option<ActorCounter>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    /// Creates metrics reporting every label value.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                filter: LabelFilter::default(),
+                series: Mutex::new(HashMap::new()),
+                actors: None,
+            }),
+        }
+    }
+
+    /// Creates the metrics of a configuration, or none if it disables them.
+    pub fn from_spec(spec: &MetricsSpec) -> Option<Self> {
+        if spec.enabled == Some(false) {
+            return None;
+        }
+        let mut metrics = Self::new();
+        metrics.inner_mut().filter = LabelFilter::from_spec(spec);
+        Some(metrics)
+    }
+
+    /// Reports the active actors counted by `counter`.
+    pub fn with_actor_counter(mut self, counter: ActorCounter) -> Self {
+        self.inner_mut().actors = Some(counter);
+        self
+    }
+
+    /// Records a call to the building-block API, such as a `crypto` call to
+    /// `POST /v1.0/crypto/{component}/sign` which ended with status `200`.
+    pub fn record_api_call(
+        &self,
+        protocol: &str,
+        building_block: &str,
+        operation: &str,
+        status: &str,
+        elapsed: Duration,
+    ) {
+        let labels = [protocol, building_block, operation, status];
+        self.increment(&API_REQUESTS, &labels);
+        self.observe(&API_LATENCY, &labels, elapsed);
+    }
+
+    /// Records a service invocation of a method of the app `app_id`, or from it for the
+    /// server side. Methods are reported by the path pattern they match.
+    pub fn record_invocation(
+        &self,
+        side: InvocationSide,
+        app_id: &str,
+        method: &str,
+        status: &str,
+        elapsed: Duration,
+    ) {
+        let method = self.inner.filter.method(method);
+        let labels = [side.as_str(), app_id, &method, status];
+        self.increment(&INVOCATION_REQUESTS, &labels);
+        self.observe(&INVOCATION_LATENCY, &labels, elapsed);
+    }
+
+    /// Records an operation of a component, named by its log name such as
+    /// `vault (crypto.rapr.localstorage/v1)`.
+    pub fn record_component_operation(
+        &self,
+        building_block: &str,
+        component: &str,
+        operation: &str,
+        success: bool,
+        elapsed: Duration,
+    ) {
+        let status = if success { "success" } else { "failure" };
+        let labels = [building_block, component, operation, status];
+        self.increment(&COMPONENT_OPERATIONS, &labels);
+        self.observe(&COMPONENT_LATENCY, &labels, elapsed);
+    }
+
+    /// Sets the number of active members of the cluster, as seen by the local node.
+    pub(crate) fn set_cluster_members(&self, count: usize) {
+        self.set(&CLUSTER_MEMBERS, &[], count as f64);
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        if let Some(count) = &self.inner.actors {
+            for actors in count() {
+                self.set(&ACTORS_ACTIVE, &[&actors.actor_type], actors.count as f64);
+            }
+        }
+        let series = self.series();
+        let mut out = String::new();
+        for descriptor in DESCRIPTORS {
+            let Some(series) = series.get(descriptor.name) else {
+                continue;
+            };
+            let _ = writeln!(out, "# HELP {} {}", descriptor.name, descriptor.help);
+            let _ = writeln!(out, "# TYPE {} {}", descriptor.name, descriptor.kind);
+            for (values, series) in series {
+                let labels: Vec<(&str, &str)> = descriptor
+                    .labels
+                    .iter()
+                    .copied()
+                    .zip(values.iter().map(String::as_str))
+                    .collect();
+                write_series(&mut out, descriptor.name, &labels, series);
+            }
+        }
+        out
+    }
+
+    /// Returns the routes serving the metrics at [`METRICS_PATH`].
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(METRICS_PATH, get(serve))
+            .with_state(self.clone())
+    }
+
+    fn increment(&self, descriptor: &'static Descriptor, values: &[&str]) {
+        self.update(descriptor, values, |series| match series {
+            Series::Value(value) => *value += 1.0,
+            Series::Histogram { .. } => {}
+        });
+    }
+
+    fn set(&self, descriptor: &'static Descriptor, values: &[&str], to: f64) {
+        self.update(descriptor, values, |series| *series = Series::Value(to));
+    }
+
+    fn observe(&self, descriptor: &'static Descriptor, values: &[&str], elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        self.update(descriptor, values, |series| {
+            if let Series::Histogram {
+                buckets,
+                sum,
+                count,
+            } = series
+            {
+                let bucket = LATENCY_BUCKETS
+                    .iter()
+                    .position(|bound| seconds <= *bound)
+                    .unwrap_or(LATENCY_BUCKETS.len());
+                buckets[bucket] += 1;
+                *sum += seconds;
+                *count += 1;
+            }
+        });
+    }
+
+    fn update(
+        &self,
+        descriptor: &'static Descriptor,
+        values: &[&str],
+        update: impl FnOnce(&mut Series),
+    ) {
+        let values: Vec<String> = descriptor
+            .labels
+            .iter()
+            .zip(values)
+            .map(|(label, value)| self.inner.filter.value(label, value))
+            .collect();
+        let mut series = self.series();
+        let series = series
+            .entry(descriptor.name)
+            .or_default()
+            .entry(values)
+            .or_insert_with(|| match descriptor.kind {
+                Kind::Counter | Kind::Gauge => Series::Value(0.0),
+                Kind::Histogram => Series::Histogram {
+                    // One more bucket for +Inf.
+                    buckets: vec![0; LATENCY_BUCKETS.len() + 1],
+                    sum: 0.0,
+                    count: 0,
+                },
+            });
+        update(series);
+    }
+
+    fn series(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<&'static str, BTreeMap<Vec<String>, Series>>> {
+        self.inner.series.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.inner).expect("metrics are configured before they are shared")
+    }
+}
+
+async fn serve(State(metrics): State<Metrics>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, CONTENT_TYPE)], metrics.render())
+}
+
+fn write_series(out: &mut String, name: &str, labels: &[(&str, &str)], series: &Series) {
+    match series {
+        Series::Value(value) => {
+            let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+        }
+        Series::Histogram {
+            buckets,
+            sum,
+            count,
+        } => {
+            let mut cumulative = 0;
+            let bounds = LATENCY_BUCKETS.iter().map(f64::to_string);
+            for (bound, observations) in bounds.chain(["+Inf".to_string()]).zip(buckets) {
+                cumulative += observations;
+                let labels = format_labels(labels, Some(&bound));
+                let _ = writeln!(out, "{name}_bucket{labels} {cumulative}");
+            }
+            let labels = format_labels(labels, None);
+            let _ = writeln!(out, "{name}_sum{labels} {sum}");
+            let _ = writeln!(out, "{name}_count{labels} {count}");
+        }
+    }
+}
+
+/// Formats labels as `{name="value",...}`, with the `le` label of a histogram bucket.
+fn format_labels(labels: &[(&str, &str)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    match pairs.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", pairs.join(",")),
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ActiveActors;
+    use rapr_apis::configuration::v1alpha1::{MetricsHttpSpec, MetricsLabelSpec};
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new().with_actor_counter(Arc::new(|| {
+            vec![ActiveActors {
+                actor_type: "cart".to_string(),
+                count: 3,
+            }]
+        }));
+        let elapsed = Duration::from_millis(20);
+        metrics.record_api_call("http", "metadata", "GET /v1.0/metadata", "200", elapsed);
+        metrics.record_api_call("http", "metadata", "GET /v1.0/metadata", "200", elapsed);
+        metrics.record_component_operation(
+            "crypto",
+            "vault (crypto.rapr.localstorage/v1)",
+            "sign",
+            false,
+            elapsed,
+        );
+        metrics.set_cluster_members(4);
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE rapr_api_requests_total counter\n"));
+        assert!(text.contains(
+            "rapr_api_requests_total{protocol=\"http\",building_block=\"metadata\",operation=\"GET /v1.0/metadata\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains("# TYPE rapr_api_latency_seconds histogram\n"));
+        assert!(text.contains("status=\"200\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("status=\"200\",le=\"0.025\"} 2\n"));
+        assert!(text.contains("status=\"200\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains(
+            "rapr_component_operations_total{building_block=\"crypto\",component=\"vault (crypto.rapr.localstorage/v1)\",operation=\"sign\",status=\"failure\"} 1\n"
+        ));
+        assert!(text.contains("rapr_actors_active{actor_type=\"cart\"} 3\n"));
+        assert!(text.contains("rapr_cluster_members 4\n"));
+        assert!(!text.contains("rapr_service_invocation_requests_total"));
+    }
+
+    #[test]
+    fn test_cardinality_guards() {
+        let metrics = Metrics::from_spec(&MetricsSpec {
+            http: Some(MetricsHttpSpec {
+                path_matching: vec!["/orders/{id}".to_string()],
+            }),
+            labels: vec![MetricsLabelSpec {
+                name: "app_id".to_string(),
+                allowed_values: vec!["shop".to_string()],
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let elapsed = Duration::from_millis(1);
+        for method in ["orders/1", "/orders/2", "/users/1"] {
+            metrics.record_invocation(InvocationSide::Client, "shop", method, "200", elapsed);
+        }
+        metrics.record_invocation(
+            InvocationSide::Client,
+            "billing",
+            "/users/2",
+            "200",
+            elapsed,
+        );
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "rapr_service_invocation_requests_total{side=\"client\",app_id=\"shop\",method=\"/orders/{id}\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "rapr_service_invocation_requests_total{side=\"client\",app_id=\"shop\",method=\"_\",status=\"200\"} 1\n"
+        ));
+        assert!(text.contains(
+            "rapr_service_invocation_requests_total{side=\"client\",app_id=\"_\",method=\"_\",status=\"200\"} 1\n"
+        ));
+
+        assert!(
+            Metrics::from_spec(&MetricsSpec {
+                enabled: Some(false),
+                ..Default::default()
+            })
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            format_labels(&[("method", "a\"b\\c\nd")], None),
+            "{method=\"a\\\"b\\\\c\\nd\"}"
+        );
+        assert_eq!(format_labels(&[], None), "");
+    }
+}
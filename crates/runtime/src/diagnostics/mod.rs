@@ -0,0 +1,60 @@
+//! Distributed tracing and metrics of the calls going through the sidecar.
+//!
+//! Calls carry their [`TraceContext`] in the W3C `traceparent` and `tracestate` headers.
+//! The sidecar starts a span for each call it handles or makes with its [`Tracer`], as a
+//! child of the context of the caller, and propagates the context of that span to the
+//! calls it makes on behalf of the caller. Sampled spans are written to the standard output
+//! and exported to an OpenTelemetry collector over OTLP/gRPC by the [`OtlpExporter`], in
+//! batches.
+//!
+//! [`Metrics`] count the calls to the API, service invocations and component operations
+//! and measure their latency, along with the active actors and cluster members. They are
+//! served in the Prometheus text format at [`METRICS_PATH`].
+
+mod context;
+mod metrics;
+mod otlp;
+mod tracer;
+
+pub use context::*;
+pub use metrics::*;
+pub use otlp::*;
+pub use tracer::*;
+
+#[cfg(test)]
+pub(crate) use otlp::tests::{Collector, attribute};
+
+use axum::http::Request;
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum DiagnosticsError {
+    #[snafu(display("Invalid sampling rate {:?}: expected a number between 0 and 1", rate))]
+    InvalidSamplingRate { rate: String },
+
+    #[snafu(display("Unsupported trace export protocol {:?}: expected grpc", protocol))]
+    UnsupportedProtocol { protocol: String },
+
+    #[snafu(display("Invalid trace collector address {}: {}", address, source))]
+    InvalidEndpoint {
+        address: String,
+        source: tonic::transport::Error,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, DiagnosticsError>;
+
+/// Starts the span of a request the sidecar handles, as a child of the context the request
+/// carries. The headers of the request then carry the context of the span, which is also
+/// added to its extensions for the handlers.
+pub fn start_server_span<B>(
+    tracer: &Tracer,
+    name: impl Into<String>,
+    request: &mut Request<B>,
+) -> Span {
+    let parent = TraceContext::from_headers(request.headers());
+    let span = tracer.start(name, SpanKind::Server, parent.as_ref());
+    span.context().to_headers(request.headers_mut());
+    request.extensions_mut().insert(span.context().clone());
+    span
+}
@@ -0,0 +1,351 @@
+use super::{AttributeValue, InvalidEndpointSnafu, Result, SpanData, SpanKind};
+use proto::collector::trace::v1::ExportTraceServiceRequest;
+use proto::collector::trace::v1::trace_service_client::TraceServiceClient;
+use proto::common::v1::{AnyValue, InstrumentationScope, KeyValue, any_value};
+use proto::resource::v1::Resource;
+use proto::trace::v1::{ResourceSpans, ScopeSpans, Status, span, status};
+use snafu::ResultExt;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, oneshot};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+/// Spans exported in one request at most.
+const MAX_BATCH_SIZE: usize = 512;
+/// Spans waiting for export at most, past which spans are dropped.
+const MAX_QUEUE_SIZE: usize = 2048;
+/// Delay between exports of the spans which ended.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Name of the instrumentation the spans are recorded by.
+const SCOPE_NAME: &str = "rapr-runtime";
+
+#[allow(missing_docs, unused_qualifications, clippy::all)]
+pub mod proto {
+    pub mod common {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.common.v1");
+        }
+    }
+    pub mod resource {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.resource.v1");
+        }
+    }
+    pub mod trace {
+        pub mod v1 {
+            tonic::include_proto!("opentelemetry.proto.trace.v1");
+        }
+    }
+    pub mod collector {
+        pub mod trace {
+            pub mod v1 {
+                tonic::include_proto!("opentelemetry.proto.collector.trace.v1");
+            }
+        }
+    }
+}
+
+enum Message {
+    Span(SpanData),
+    Flush(oneshot::Sender<()>),
+}
+
+/// OtlpExporter exports spans to an OpenTelemetry collector over OTLP/gRPC, in batches sent
+/// by a background task. Export failures are logged and the spans dropped.
+#[derive(Clone)]
+pub struct OtlpExporter {
+    sender: mpsc::Sender<Message>,
+}
+
+impl OtlpExporter {
+    /// Starts exporting the spans of the app `service_name` to the collector at `address`,
+    /// a `host:port` pair or a URL. Connects on first export.
+    pub fn start(address: &str, secure: bool, service_name: &str) -> Result<Self> {
+        let url = match address.contains("://") {
+            true => address.to_string(),
+            false if secure => format!("https://{address}"),
+            false => format!("http://{address}"),
+        };
+        let mut endpoint = Endpoint::from_shared(url).context(InvalidEndpointSnafu { address })?;
+        if secure {
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().with_native_roots())
+                .context(InvalidEndpointSnafu { address })?;
+        }
+        let client = TraceServiceClient::new(endpoint.connect_lazy());
+        let (sender, receiver) = mpsc::channel(MAX_QUEUE_SIZE);
+        tokio::spawn(export(client, resource(service_name), receiver));
+        Ok(Self { sender })
+    }
+
+    /// Queues a span for export.
+    pub(super) fn send(&self, span: SpanData) {
+        if self.sender.try_send(Message::Span(span)).is_err() {
+            tracing::debug!("Trace export queue full: dropping span");
+        }
+    }
+
+    /// Waits until the spans queued were exported.
+    pub(super) async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(Message::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+/// Exports the queued spans every [`EXPORT_INTERVAL`] or as soon as a batch is full, until
+/// every exporter is dropped.
+async fn export(
+    mut client: TraceServiceClient<Channel>,
+    resource: Resource,
+    mut receiver: mpsc::Receiver<Message>,
+) {
+    let mut batch = Vec::new();
+    let mut interval = tokio::time::interval(EXPORT_INTERVAL);
+    loop {
+        let flushed = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() < MAX_BATCH_SIZE {
+                        continue;
+                    }
+                    None
+                }
+                Some(Message::Flush(done)) => Some(done),
+                None => break,
+            },
+            _ = interval.tick() => None,
+        };
+        if !batch.is_empty() {
+            send(&mut client, &resource, std::mem::take(&mut batch)).await;
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+    if !batch.is_empty() {
+        send(&mut client, &resource, batch).await;
+    }
+}
+
+async fn send(client: &mut TraceServiceClient<Channel>, resource: &Resource, batch: Vec<SpanData>) {
+    let count = batch.len();
+    let request = ExportTraceServiceRequest {
+        resource_spans: vec![ResourceSpans {
+            resource: Some(resource.clone()),
+            scope_spans: vec![ScopeSpans {
+                scope: Some(InstrumentationScope {
+                    name: SCOPE_NAME.to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                }),
+                spans: batch.into_iter().map(encode).collect(),
+                schema_url: String::new(),
+            }],
+            schema_url: String::new(),
+        }],
+    };
+    match client.export(request).await {
+        Ok(response) => {
+            if let Some(partial) = response.into_inner().partial_success
+                && partial.rejected_spans > 0
+            {
+                tracing::warn!(
+                    rejected = partial.rejected_spans,
+                    error = partial.error_message,
+                    "Trace collector rejected spans"
+                );
+            }
+        }
+        Err(e) => tracing::warn!(error = %e, spans = count, "Failed to export spans"),
+    }
+}
+
+fn resource(service_name: &str) -> Resource {
+    Resource {
+        attributes: vec![attribute("service.name", service_name.into())],
+        dropped_attributes_count: 0,
+    }
+}
+
+fn encode(span: SpanData) -> proto::trace::v1::Span {
+    let kind = match span.kind {
+        SpanKind::Internal => span::SpanKind::Internal,
+        SpanKind::Server => span::SpanKind::Server,
+        SpanKind::Client => span::SpanKind::Client,
+        SpanKind::Producer => span::SpanKind::Producer,
+        SpanKind::Consumer => span::SpanKind::Consumer,
+    };
+    let status = match span.error {
+        Some(message) => Status {
+            message,
+            code: status::StatusCode::Error.into(),
+        },
+        None => Status::default(),
+    };
+    proto::trace::v1::Span {
+        trace_id: span.context.trace_id.to_vec(),
+        span_id: span.context.span_id.to_vec(),
+        trace_state: span.context.trace_state,
+        parent_span_id: span
+            .parent_span_id
+            .map(|id| id.to_vec())
+            .unwrap_or_default(),
+        flags: u32::from(span.context.sampled),
+        name: span.name,
+        kind: kind.into(),
+        start_time_unix_nano: unix_nanos(span.start_time),
+        end_time_unix_nano: unix_nanos(span.end_time),
+        attributes: span
+            .attributes
+            .into_iter()
+            .map(|(key, value)| attribute(&key, value))
+            .collect(),
+        dropped_attributes_count: 0,
+        status: Some(status),
+    }
+}
+
+fn attribute(key: &str, value: AttributeValue) -> KeyValue {
+    let value = match value {
+        AttributeValue::String(value) => any_value::Value::StringValue(value),
+        AttributeValue::Int(value) => any_value::Value::IntValue(value),
+        AttributeValue::Bool(value) => any_value::Value::BoolValue(value),
+    };
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue { value: Some(value) }),
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::diagnostics::{TraceContext, Tracer};
+    use proto::collector::trace::v1::ExportTraceServiceResponse;
+    use proto::collector::trace::v1::trace_service_server::{TraceService, TraceServiceServer};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{Request, Response};
+
+    /// A span exported along with the attributes of its resource.
+    type Exported = (Vec<KeyValue>, proto::trace::v1::Span);
+
+    /// Collector keeps the spans exported to it.
+    #[derive(Clone, Default)]
+    pub(crate) struct Collector {
+        spans: Arc<Mutex<Vec<Exported>>>,
+    }
+
+    impl Collector {
+        /// Serves the collector, returning its address.
+        pub(crate) async fn serve(&self) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let server = tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(self.clone()))
+                .serve_with_incoming(TcpListenerStream::new(listener));
+            tokio::spawn(server);
+            address
+        }
+
+        /// Returns a tracer recording every trace and exporting to the collector.
+        pub(crate) async fn tracer(&self) -> Tracer {
+            let exporter = OtlpExporter::start(&self.serve().await, false, "orders").unwrap();
+            Tracer::new(1.0).with_exporter(exporter)
+        }
+
+        pub(crate) fn spans(&self) -> Vec<proto::trace::v1::Span> {
+            let spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+            spans.iter().map(|(_, span)| span.clone()).collect()
+        }
+
+        /// Returns the span of a name, or panics.
+        pub(crate) fn span(&self, name: &str) -> proto::trace::v1::Span {
+            let spans = self.spans();
+            spans
+                .into_iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no span {name}"))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: Request<ExportTraceServiceRequest>,
+        ) -> std::result::Result<Response<ExportTraceServiceResponse>, tonic::Status> {
+            let mut spans = self.spans.lock().unwrap_or_else(|e| e.into_inner());
+            for resource_spans in request.into_inner().resource_spans {
+                let resource = resource_spans.resource.unwrap_or_default().attributes;
+                for scope_spans in resource_spans.scope_spans {
+                    for span in scope_spans.spans {
+                        spans.push((resource.clone(), span));
+                    }
+                }
+            }
+            Ok(Response::new(ExportTraceServiceResponse::default()))
+        }
+    }
+
+    /// Returns the value of an attribute of a span.
+    pub(crate) fn attribute(span: &proto::trace::v1::Span, key: &str) -> Option<any_value::Value> {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key == key)
+            .and_then(|attribute| attribute.value.clone()?.value)
+    }
+
+    #[tokio::test]
+    async fn test_export() {
+        let collector = Collector::default();
+        let tracer = collector.tracer().await;
+        let parent = TraceContext::parse(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            None,
+        )
+        .unwrap();
+        {
+            let mut span = tracer.start("/v1.0/invoke", SpanKind::Server, Some(&parent));
+            span.set_attribute("http.status_code", 500);
+            span.set_error("app failed");
+            let _child = tracer.start("CallLocal", SpanKind::Client, Some(span.context()));
+        }
+        tracer.flush().await;
+
+        let spans = collector.spans.lock().unwrap().clone();
+        assert_eq!(spans.len(), 2);
+        let (resource, _) = &spans[0];
+        assert_eq!(resource[0].key, "service.name");
+        assert_eq!(
+            resource[0].value.clone().unwrap().value,
+            Some(any_value::Value::StringValue("orders".to_string()))
+        );
+
+        let server = collector.span("/v1.0/invoke");
+        assert_eq!(server.trace_id, parent.trace_id);
+        assert_eq!(server.parent_span_id, parent.span_id);
+        assert_eq!(server.kind, span::SpanKind::Server as i32);
+        assert_eq!(
+            attribute(&server, "http.status_code"),
+            Some(any_value::Value::IntValue(500))
+        );
+        let status = server.status.unwrap();
+        assert_eq!(status.code, status::StatusCode::Error as i32);
+        assert_eq!(status.message, "app failed");
+        assert!(server.end_time_unix_nano >= server.start_time_unix_nano);
+
+        let client = collector.span("CallLocal");
+        assert_eq!(client.trace_id, parent.trace_id);
+        assert_eq!(client.parent_span_id, server.span_id);
+        assert_eq!(client.kind, span::SpanKind::Client as i32);
+    }
+}
@@ -0,0 +1,351 @@
+use super::{
+    InvalidSamplingRateSnafu, OtlpExporter, Result, TraceContext, UnsupportedProtocolSnafu,
+};
+use rapr_apis::configuration::v1alpha1::TracingSpec;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Fraction of the traces sampled when the configuration does not set one.
+pub const DEFAULT_SAMPLING_RATE: f64 = 0.0001;
+/// Target of the log lines of spans written to the standard output.
+const STDOUT_TARGET: &str = "rapr::trace";
+
+/// Role of a span in a call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpanKind {
+    Internal,
+    /// Handling of a call received.
+    Server,
+    /// Call made to another process.
+    Client,
+    /// Message published.
+    Producer,
+    /// Message delivered.
+    Consumer,
+}
+
+/// Value of an attribute of a span.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+/// SpanData is a recorded span.
+#[derive(Debug, Clone)]
+pub struct SpanData {
+    pub context: TraceContext,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start_time: SystemTime,
+    pub end_time: SystemTime,
+    pub attributes: Vec<(String, AttributeValue)>,
+    /// Description of the failure of the operation, if it failed.
+    pub error: Option<String>,
+}
+
+/// Tracer starts spans and records those of sampled traces. Traces started by the sidecar
+/// are sampled at the sampling rate; the others follow the decision of their caller.
+#[derive(Clone)]
+pub struct Tracer {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    sampling_rate: f64,
+    stdout: bool,
+    exporter: Option<OtlpExporter>,
+}
+
+impl Tracer {
+    /// Creates a tracer sampling a fraction of the traces, between 0 and 1, which records
+    /// nothing until it gets an exporter.
+    pub fn new(sampling_rate: f64) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                sampling_rate,
+                stdout: false,
+                exporter: None,
+            }),
+        }
+    }
+
+    /// Also writes spans to the standard output, as log lines.
+    pub fn with_stdout(mut self) -> Self {
+        self.inner_mut().stdout = true;
+        self
+    }
+
+    /// Exports spans to an OpenTelemetry collector.
+    pub fn with_exporter(mut self, exporter: OtlpExporter) -> Self {
+        self.inner_mut().exporter = Some(exporter);
+        self
+    }
+
+    /// Creates the tracer of a configuration, exporting the spans of the app
+    /// `service_name`.
+    pub fn from_spec(spec: &TracingSpec, service_name: &str) -> Result<Self> {
+        let sampling_rate = match spec.sampling_rate.trim() {
+            "" => DEFAULT_SAMPLING_RATE,
+            rate => rate
+                .parse::<f64>()
+                .ok()
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .ok_or_else(|| InvalidSamplingRateSnafu { rate }.build())?,
+        };
+        let mut tracer = Self::new(sampling_rate);
+        if spec.stdout {
+            tracer = tracer.with_stdout();
+        }
+        if let Some(otel) = &spec.otel {
+            if !matches!(otel.protocol.to_ascii_lowercase().as_str(), "" | "grpc") {
+                return UnsupportedProtocolSnafu {
+                    protocol: &otel.protocol,
+                }
+                .fail();
+            }
+            let exporter =
+                OtlpExporter::start(&otel.endpoint_address, otel.is_secure, service_name)?;
+            tracer = tracer.with_exporter(exporter);
+        }
+        Ok(tracer)
+    }
+
+    /// Starts a span, child of `parent` or else root of a new trace. Spans end when they
+    /// are dropped.
+    pub fn start(
+        &self,
+        name: impl Into<String>,
+        kind: SpanKind,
+        parent: Option<&TraceContext>,
+    ) -> Span {
+        let trace_id = match parent {
+            Some(parent) => parent.trace_id,
+            None => random_id(),
+        };
+        let sampled = match parent {
+            Some(parent) => parent.sampled,
+            None => self.sample(&trace_id),
+        };
+        let context = TraceContext {
+            trace_id,
+            span_id: random_id(),
+            sampled,
+            trace_state: parent
+                .map(|parent| parent.trace_state.clone())
+                .unwrap_or_default(),
+        };
+        let recording = sampled && (self.inner.stdout || self.inner.exporter.is_some());
+        let data = recording.then(|| SpanData {
+            context: context.clone(),
+            parent_span_id: parent.map(|parent| parent.span_id),
+            name: name.into(),
+            kind,
+            start_time: SystemTime::now(),
+            end_time: SystemTime::now(),
+            attributes: Vec::new(),
+            error: None,
+        });
+        Span {
+            context,
+            data,
+            tracer: self.clone(),
+        }
+    }
+
+    /// Waits until the spans which ended were exported.
+    pub async fn flush(&self) {
+        if let Some(exporter) = &self.inner.exporter {
+            exporter.flush().await;
+        }
+    }
+
+    /// Samples the traces whose random lower half of ID falls below the sampling rate, so
+    /// that all sidecars make the same decision for a trace.
+    fn sample(&self, trace_id: &[u8; 16]) -> bool {
+        let rate = self.inner.sampling_rate;
+        if rate >= 1.0 {
+            return true;
+        }
+        let value = u64::from_be_bytes(trace_id[8..].try_into().expect("8 bytes"));
+        (value as f64) < rate * u64::MAX as f64
+    }
+
+    fn record(&self, span: SpanData) {
+        if self.inner.stdout {
+            tracing::info!(
+                target: STDOUT_TARGET,
+                trace_id = %span.context.trace_id_hex(),
+                span_id = %span.context.span_id_hex(),
+                parent_span_id = %span.parent_span_id.map(|id| super::encode_hex(&id)).unwrap_or_default(),
+                kind = ?span.kind,
+                duration = ?span.end_time.duration_since(span.start_time).unwrap_or_default(),
+                error = span.error.as_deref().unwrap_or_default(),
+                "{}",
+                span.name
+            );
+        }
+        if let Some(exporter) = &self.inner.exporter {
+            exporter.send(span);
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.inner).expect("tracers are configured before they are shared")
+    }
+}
+
+/// Span is an operation in a trace, recorded when it is dropped if its trace is sampled.
+pub struct Span {
+    context: TraceContext,
+    data: Option<SpanData>,
+    tracer: Tracer,
+}
+
+impl Span {
+    /// Returns the context to propagate to the calls made within the span.
+    pub fn context(&self) -> &TraceContext {
+        &self.context
+    }
+
+    /// Returns true if the span is recorded: setting its attributes is wasted otherwise.
+    pub fn is_recording(&self) -> bool {
+        self.data.is_some()
+    }
+
+    pub fn set_attribute(&mut self, key: impl Into<String>, value: impl Into<AttributeValue>) {
+        if let Some(data) = &mut self.data {
+            data.attributes.push((key.into(), value.into()));
+        }
+    }
+
+    /// Marks the operation of the span as failed.
+    pub fn set_error(&mut self, message: impl Into<String>) {
+        if let Some(data) = &mut self.data {
+            data.error = Some(message.into());
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut data) = self.data.take() {
+            data.end_time = SystemTime::now();
+            self.tracer.record(data);
+        }
+    }
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    loop {
+        let id: [u8; N] = rand::random();
+        if id != [0; N] {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling() {
+        let parent = |sampled| TraceContext {
+            trace_id: [1; 16],
+            span_id: [2; 8],
+            sampled,
+            trace_state: "vendor=1".to_string(),
+        };
+        let never = Tracer::new(0.0).with_stdout();
+        let always = Tracer::new(1.0).with_stdout();
+        assert!(
+            !never
+                .start("root", SpanKind::Server, None)
+                .context()
+                .sampled
+        );
+        assert!(always.start("root", SpanKind::Server, None).is_recording());
+
+        // Children follow their parent, whatever the rate.
+        let span = never.start("child", SpanKind::Server, Some(&parent(true)));
+        assert!(span.is_recording());
+        assert_eq!(span.context().trace_id, [1; 16]);
+        assert_ne!(span.context().span_id, [2; 8]);
+        assert_eq!(span.context().trace_state, "vendor=1");
+        assert!(
+            !always
+                .start("child", SpanKind::Server, Some(&parent(false)))
+                .is_recording()
+        );
+
+        // Without exporter, nothing is recorded.
+        assert!(
+            !Tracer::new(1.0)
+                .start("root", SpanKind::Server, None)
+                .is_recording()
+        );
+
+        let half = Tracer::new(0.5);
+        let sampled = (0..1000)
+            .filter(|_| half.start("root", SpanKind::Server, None).context().sampled)
+            .count();
+        assert!((400..600).contains(&sampled), "{sampled}");
+    }
+
+    #[test]
+    fn test_from_spec() {
+        let spec = |rate: &str| TracingSpec {
+            sampling_rate: rate.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Tracer::from_spec(&spec(""), "app")
+                .unwrap()
+                .inner
+                .sampling_rate,
+            DEFAULT_SAMPLING_RATE
+        );
+        assert_eq!(
+            Tracer::from_spec(&spec("0.5"), "app")
+                .unwrap()
+                .inner
+                .sampling_rate,
+            0.5
+        );
+        assert_eq!(
+            Tracer::from_spec(&spec("2"), "app")
+                .err()
+                .unwrap()
+                .to_string(),
+            "Invalid sampling rate \"2\": expected a number between 0 and 1"
+        );
+        assert!(Tracer::from_spec(&spec("all"), "app").is_err());
+    }
+}
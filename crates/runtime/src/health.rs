@@ -0,0 +1,337 @@
+//! Health of the sidecar, as probed by orchestrators.
+//!
+//! The sidecar is healthy once every component it depends on is initialized, the app
+//! channel is ready and, when it hosts actors, placement is connected. Components marked
+//! `ignoreErrors` never make the sidecar unhealthy. The outbound health leaves the app
+//! out: it tells whether the app may already call the building blocks, which it needs to
+//! do before it reports itself ready.
+//!
+//! With the [`COMPONENT_PING_FEATURE`] feature gate, initialized components which can check
+//! their own health are also pinged periodically.
+
+use rapr_apis::components::v1alpha1::Component;
+use rapr_contributes::component::{DEFAULT_VERSION, Pinger};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Feature gate enabling the periodic ping of the components.
+pub const COMPONENT_PING_FEATURE: &str = "ComponentPing";
+
+/// Default interval between two pings of a component.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Status of a component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ComponentStatus {
+    /// The component is being initialized.
+    Initializing,
+    /// The component is initialized and answered its last ping, if any.
+    Ready,
+    /// The component failed to initialize.
+    Failed,
+    /// The component is initialized but failed its last ping.
+    Unhealthy,
+}
+
+/// ComponentHealth details the health of a component.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ComponentHealth {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub component_type: String,
+    pub version: String,
+    pub ignore_errors: bool,
+    pub status: ComponentStatus,
+    /// Why the component failed to initialize or its last ping.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ComponentHealth {
+    /// Returns true if the component does not make the sidecar unhealthy.
+    pub fn is_healthy(&self) -> bool {
+        self.ignore_errors || self.status == ComponentStatus::Ready
+    }
+}
+
+/// HealthReport is the answer to a health probe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub healthy: bool,
+    /// Whether the app channel is ready, if the sidecar has one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_channel_ready: Option<bool>,
+    /// Whether placement is connected, if the sidecar hosts actors.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub placement_connected: Option<bool>,
+    pub components: Vec<ComponentHealth>,
+}
+
+#[derive(Default)]
+struct State {
+    components: BTreeMap<String, ComponentHealth>,
+    app_channel_ready: Option<bool>,
+    placement_connected: Option<bool>,
+}
+
+/// Health tracks what the health of the sidecar depends on. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct Health {
+    state: Arc<Mutex<State>>,
+    /// Interval between two pings of a component, if they are pinged.
+    ping_interval: Option<Duration>,
+}
+
+impl Health {
+    /// Creates a health which depends on nothing yet, and so is healthy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pings the components watched with [`Health::watch_component`] every `interval`.
+    pub fn with_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = Some(interval);
+        self
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Makes the health depend on a component, which is initializing. Registering a
+    /// component again resets its status.
+    pub fn register_component(&self, component: &Component) {
+        let spec = component.spec.as_ref();
+        let version = match spec.map_or("", |spec| spec.version.as_str()) {
+            "" => DEFAULT_VERSION,
+            version => version,
+        };
+        let health = ComponentHealth {
+            name: component.get_name().to_string(),
+            component_type: spec.map(|spec| spec.cmpt_type.clone()).unwrap_or_default(),
+            version: version.to_string(),
+            ignore_errors: spec.is_some_and(|spec| spec.ignore_errors),
+            status: ComponentStatus::Initializing,
+            error: None,
+        };
+        self.state().components.insert(health.name.clone(), health);
+    }
+
+    /// Reports that a component is initialized.
+    pub fn component_ready(&self, name: &str) {
+        self.set_component_status(name, ComponentStatus::Ready, None);
+    }
+
+    /// Reports that a component failed to initialize.
+    pub fn component_failed(&self, name: &str, error: impl Display) {
+        self.set_component_status(name, ComponentStatus::Failed, Some(error.to_string()));
+    }
+
+    fn set_component_status(&self, name: &str, status: ComponentStatus, error: Option<String>) {
+        if let Some(component) = self.state().components.get_mut(name) {
+            component.status = status;
+            component.error = error;
+        }
+    }
+
+    /// Makes the health depend on the app channel, which is not ready yet.
+    pub fn require_app_channel(&self) {
+        self.state().app_channel_ready.get_or_insert(false);
+    }
+
+    /// Reports whether the app channel is ready.
+    pub fn set_app_channel_ready(&self, ready: bool) {
+        self.state().app_channel_ready = Some(ready);
+    }
+
+    /// Makes the health depend on placement, which is not connected yet.
+    pub fn require_placement(&self) {
+        self.state().placement_connected.get_or_insert(false);
+    }
+
+    /// Reports whether placement is connected.
+    pub fn set_placement_connected(&self, connected: bool) {
+        self.state().placement_connected = Some(connected);
+    }
+
+    /// Returns the health of the sidecar.
+    pub fn report(&self) -> HealthReport {
+        let mut report = self.report_outbound();
+        let app_channel_ready = self.state().app_channel_ready;
+        report.healthy &= app_channel_ready != Some(false);
+        report.app_channel_ready = app_channel_ready;
+        report
+    }
+
+    /// Returns the health of the sidecar, regardless of the app channel.
+    pub fn report_outbound(&self) -> HealthReport {
+        let state = self.state();
+        let components: Vec<ComponentHealth> = state.components.values().cloned().collect();
+        HealthReport {
+            healthy: state.placement_connected != Some(false)
+                && components.iter().all(ComponentHealth::is_healthy),
+            app_channel_ready: None,
+            placement_connected: state.placement_connected,
+            components,
+        }
+    }
+
+    /// Pings an initialized component periodically, if pings are enabled, and reports it
+    /// unhealthy while its pings fail. Returns the task pinging it.
+    pub fn watch_component(&self, name: &str, pinger: Arc<dyn Pinger>) -> Option<JoinHandle<()>> {
+        let interval = self.ping_interval?;
+        let health = self.clone();
+        let name = name.to_string();
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let result = pinger.ping().await;
+                health.record_ping(&name, result.map_err(|e| e.to_string()));
+            }
+        }))
+    }
+
+    /// Updates the status of an initialized component after a ping.
+    fn record_ping(&self, name: &str, result: Result<(), String>) {
+        let mut state = self.state();
+        let Some(component) = state.components.get_mut(name) else {
+            return;
+        };
+        match (component.status, result) {
+            (ComponentStatus::Ready, Err(error)) => {
+                tracing::warn!(component = name, %error, "Component is unhealthy");
+                component.status = ComponentStatus::Unhealthy;
+                component.error = Some(error);
+            }
+            (ComponentStatus::Unhealthy, Ok(())) => {
+                tracing::info!(component = name, "Component is healthy again");
+                component.status = ComponentStatus::Ready;
+                component.error = None;
+            }
+            (ComponentStatus::Unhealthy, Err(error)) => component.error = Some(error),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapr_contributes::component::PingError;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn component(name: &str, ignore_errors: bool) -> Component {
+        serde_json::from_value(json!({
+            "apiVersion": "rapr.io/v1alpha1",
+            "kind": "Component",
+            "metadata": {"name": name},
+            "spec": {"type": "state.redis", "version": "", "ignoreErrors": ignore_errors},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_report() {
+        let health = Health::new();
+        assert!(health.report().healthy);
+
+        health.register_component(&component("store", false));
+        health.register_component(&component("cache", true));
+        health.require_app_channel();
+        health.require_placement();
+        assert!(!health.report().healthy);
+
+        health.component_failed("cache", "connection refused");
+        health.component_ready("store");
+        health.set_placement_connected(true);
+        assert!(health.report_outbound().healthy);
+        let report = health.report();
+        assert!(!report.healthy);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "healthy": false,
+                "appChannelReady": false,
+                "placementConnected": true,
+                "components": [
+                    {
+                        "name": "cache",
+                        "type": "state.redis",
+                        "version": "v1",
+                        "ignoreErrors": true,
+                        "status": "failed",
+                        "error": "connection refused"
+                    },
+                    {
+                        "name": "store",
+                        "type": "state.redis",
+                        "version": "v1",
+                        "ignoreErrors": false,
+                        "status": "ready"
+                    }
+                ]
+            })
+        );
+
+        health.set_app_channel_ready(true);
+        assert!(health.report().healthy);
+        health.set_placement_connected(false);
+        assert!(!health.report().healthy);
+        assert!(!health.report_outbound().healthy);
+    }
+
+    struct Flaky(AtomicBool);
+
+    #[async_trait::async_trait]
+    impl Pinger for Flaky {
+        async fn ping(&self) -> Result<(), PingError> {
+            match self.0.load(Ordering::SeqCst) {
+                true => Ok(()),
+                false => Err("timed out".into()),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch_component() {
+        let pinger = Arc::new(Flaky(AtomicBool::new(false)));
+        assert!(
+            Health::new()
+                .watch_component("store", pinger.clone())
+                .is_none()
+        );
+
+        let health = Health::new().with_ping_interval(Duration::from_secs(10));
+        health.register_component(&component("store", false));
+        let task = health.watch_component("store", pinger.clone()).unwrap();
+        // Components are only pinged once initialized.
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert_eq!(
+            health.report().components[0].status,
+            ComponentStatus::Initializing
+        );
+
+        health.component_ready("store");
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let report = health.report();
+        assert!(!report.healthy);
+        assert_eq!(report.components[0].status, ComponentStatus::Unhealthy);
+        assert_eq!(report.components[0].error.as_deref(), Some("timed out"));
+
+        pinger.0.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(health.report().healthy);
+        task.abort();
+    }
+}
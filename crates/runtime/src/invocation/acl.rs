@@ -0,0 +1,332 @@
+//! Access control of the invocations an app receives.
+//!
+//! Callers are matched against the policies of the [`AccessControlSpec`] of the
+//! configuration by app id, trust domain and namespace. The operation rules of the matching
+//! policy decide on the method and HTTP verb; callers without policy get the default action.
+//! Methods are checked once normalized by [`normalize_method`](super::normalize_method), as
+//! the app receives them.
+
+use rapr_apis::configuration::v1alpha1::{
+    ACTION_ALLOW, ACTION_DENY, AccessControlSpec, AppOperation, AppPolicySpec,
+};
+use snafu::Snafu;
+
+/// Trust domain of the callers without one.
+pub const DEFAULT_TRUST_DOMAIN: &str = "public";
+
+#[derive(Debug, Snafu)]
+pub enum AccessControlError {
+    #[snafu(display(
+        "Invalid action {:?} in {}: expected {} or {}",
+        action,
+        location,
+        ACTION_ALLOW,
+        ACTION_DENY
+    ))]
+    InvalidAction { location: String, action: String },
+
+    #[snafu(display("Access control policy {} has no app id", index))]
+    MissingAppId { index: usize },
+
+    #[snafu(display("Duplicate access control policy for app {}", app_id))]
+    DuplicatePolicy { app_id: String },
+}
+
+pub type Result<T> = std::result::Result<T, AccessControlError>;
+
+/// Action is the decision of access control on an invocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+impl Action {
+    /// Parses an action, `default` when empty. Empty actions are invalid without default.
+    fn parse(
+        action: &str,
+        default: Option<Action>,
+        location: impl FnOnce() -> String,
+    ) -> Result<Self> {
+        match (action.to_ascii_lowercase().as_str(), default) {
+            ("", Some(default)) => Ok(default),
+            (ACTION_ALLOW, _) => Ok(Action::Allow),
+            (ACTION_DENY, _) => Ok(Action::Deny),
+            _ => Err(AccessControlError::InvalidAction {
+                location: location(),
+                action: action.to_string(),
+            }),
+        }
+    }
+}
+
+/// Caller identifies the app making an invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub app_id: String,
+    pub namespace: String,
+    pub trust_domain: String,
+}
+
+/// AccessControl decides which invocations an app accepts.
+#[derive(Debug, Clone)]
+pub struct AccessControl {
+    default_action: Action,
+    policies: Vec<AppPolicy>,
+}
+
+#[derive(Debug, Clone)]
+struct AppPolicy {
+    app_id: String,
+    trust_domain: String,
+    /// Matches every namespace when empty.
+    namespace: String,
+    default_action: Action,
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone)]
+struct Operation {
+    segments: Vec<String>,
+    /// Upper-cased. Matches every verb when empty.
+    verbs: Vec<String>,
+    action: Action,
+}
+
+impl AccessControl {
+    /// Validates the access control section of a configuration. Callers are allowed when
+    /// the spec has no default action.
+    pub fn from_spec(spec: &AccessControlSpec) -> Result<Self> {
+        let default_action = Action::parse(&spec.default_action, Some(Action::Allow), || {
+            "the default action".to_string()
+        })?;
+        let default_trust_domain = match spec.trust_domain.as_str() {
+            "" => DEFAULT_TRUST_DOMAIN,
+            trust_domain => trust_domain,
+        };
+
+        let mut policies: Vec<AppPolicy> = Vec::with_capacity(spec.policies.len());
+        for (index, policy) in spec.policies.iter().enumerate() {
+            let policy = AppPolicy::from_spec(index, policy, default_action, default_trust_domain)?;
+            if policies.iter().any(|other| {
+                other.app_id == policy.app_id
+                    && other.trust_domain == policy.trust_domain
+                    && other.namespace == policy.namespace
+            }) {
+                return Err(AccessControlError::DuplicatePolicy {
+                    app_id: policy.app_id,
+                });
+            }
+            policies.push(policy);
+        }
+        Ok(Self {
+            default_action,
+            policies,
+        })
+    }
+
+    /// Decides on the invocation of a method by a caller.
+    pub fn check(&self, caller: &Caller, verb: &str, method: &str) -> Action {
+        let trust_domain = match caller.trust_domain.as_str() {
+            "" => DEFAULT_TRUST_DOMAIN,
+            trust_domain => trust_domain,
+        };
+        // Policies of a namespace win over those of every namespace.
+        let policy = self
+            .policies
+            .iter()
+            .filter(|policy| policy.app_id == caller.app_id && policy.trust_domain == trust_domain)
+            .filter(|policy| policy.namespace.is_empty() || policy.namespace == caller.namespace)
+            .max_by_key(|policy| !policy.namespace.is_empty());
+        match policy {
+            Some(policy) => policy.check(verb, method),
+            None => self.default_action,
+        }
+    }
+}
+
+impl AppPolicy {
+    fn from_spec(
+        index: usize,
+        spec: &AppPolicySpec,
+        default_action: Action,
+        default_trust_domain: &str,
+    ) -> Result<Self> {
+        if spec.app_id.is_empty() {
+            return Err(AccessControlError::MissingAppId { index });
+        }
+        let location = || format!("the policy of app {}", spec.app_id);
+        let operations = spec
+            .operations
+            .iter()
+            .map(|operation| Operation::from_spec(operation, &location))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            app_id: spec.app_id.clone(),
+            trust_domain: match spec.trust_domain.as_str() {
+                "" => default_trust_domain.to_string(),
+                trust_domain => trust_domain.to_string(),
+            },
+            namespace: spec.namespace.clone(),
+            default_action: Action::parse(&spec.default_action, Some(default_action), location)?,
+            operations,
+        })
+    }
+
+    /// Applies the first operation rule matching the invocation.
+    fn check(&self, verb: &str, method: &str) -> Action {
+        let segments: Vec<&str> = method.trim_matches('/').split('/').collect();
+        self.operations
+            .iter()
+            .find(|operation| operation.matches(verb, &segments))
+            .map_or(self.default_action, |operation| operation.action)
+    }
+}
+
+impl Operation {
+    fn from_spec(spec: &AppOperation, location: &impl Fn() -> String) -> Result<Self> {
+        let action = Action::parse(&spec.action, None, || {
+            format!("operation {} of {}", spec.name, location())
+        })?;
+        Ok(Self {
+            segments: spec
+                .name
+                .trim_matches('/')
+                .split('/')
+                .map(str::to_string)
+                .collect(),
+            verbs: spec
+                .http_verb
+                .iter()
+                .filter(|verb| verb.as_str() != "*")
+                .map(|verb| verb.to_ascii_uppercase())
+                .collect(),
+            action,
+        })
+    }
+
+    fn matches(&self, verb: &str, method: &[&str]) -> bool {
+        let verb_matches =
+            self.verbs.is_empty() || self.verbs.iter().any(|v| v.eq_ignore_ascii_case(verb));
+        verb_matches && matches_path(&self.segments, method)
+    }
+}
+
+/// Matches the segments of a method against those of a pattern, where `*` matches a
+/// segment and a trailing `**` any number of them.
+fn matches_path(pattern: &[String], method: &[&str]) -> bool {
+    match (pattern.split_first(), method.split_first()) {
+        (Some((first, [])), _) if first == "**" => true,
+        (Some((first, pattern)), Some((segment, method))) => {
+            (first == "*" || first == segment) && matches_path(pattern, method)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(app_id: &str, namespace: &str) -> Caller {
+        Caller {
+            app_id: app_id.to_string(),
+            namespace: namespace.to_string(),
+            trust_domain: String::new(),
+        }
+    }
+
+    fn access_control(spec: serde_json::Value) -> Result<AccessControl> {
+        AccessControl::from_spec(&serde_json::from_value(spec).unwrap())
+    }
+
+    #[test]
+    fn test_check() {
+        let acl = access_control(serde_json::json!({
+            "defaultAction": "deny",
+            "policies": [
+                {
+                    "appId": "checkout",
+                    "defaultAction": "allow",
+                    "operations": [
+                        {"name": "/admin/**", "action": "deny"},
+                        {"name": "/orders/*", "httpVerb": ["POST"], "action": "deny"}
+                    ]
+                },
+                {"appId": "checkout", "namespace": "prod", "defaultAction": "deny",
+                 "operations": [{"name": "/orders/*", "httpVerb": ["*"], "action": "allow"}]},
+                {"appId": "billing", "trustDomain": "finance", "defaultAction": "allow"}
+            ]
+        }))
+        .unwrap();
+
+        let checkout = caller("checkout", "default");
+        assert_eq!(acl.check(&checkout, "GET", "orders/1"), Action::Allow);
+        assert_eq!(acl.check(&checkout, "post", "orders/1"), Action::Deny);
+        assert_eq!(
+            acl.check(&checkout, "POST", "orders/1/items"),
+            Action::Allow
+        );
+        assert_eq!(acl.check(&checkout, "GET", "admin"), Action::Deny);
+        assert_eq!(acl.check(&checkout, "GET", "admin/users/1"), Action::Deny);
+
+        // The policy of the namespace wins.
+        let prod = caller("checkout", "prod");
+        assert_eq!(acl.check(&prod, "POST", "orders/1"), Action::Allow);
+        assert_eq!(acl.check(&prod, "GET", "admin"), Action::Deny);
+
+        // Callers are matched by trust domain.
+        let mut billing = caller("billing", "default");
+        assert_eq!(acl.check(&billing, "GET", "orders"), Action::Deny);
+        billing.trust_domain = "finance".to_string();
+        assert_eq!(acl.check(&billing, "GET", "orders"), Action::Allow);
+
+        assert_eq!(
+            acl.check(&caller("unknown", "default"), "GET", "orders"),
+            Action::Deny
+        );
+    }
+
+    #[test]
+    fn test_allows_by_default() {
+        let acl = AccessControl::from_spec(&AccessControlSpec::default()).unwrap();
+        assert_eq!(
+            acl.check(&caller("any", "default"), "GET", ""),
+            Action::Allow
+        );
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        let cases = [
+            (
+                serde_json::json!({"defaultAction": "maybe"}),
+                "Invalid action \"maybe\" in the default action: expected allow or deny",
+            ),
+            (
+                serde_json::json!({"policies": [{"appId": ""}]}),
+                "Access control policy 0 has no app id",
+            ),
+            (
+                serde_json::json!({"policies": [{"appId": "a"}, {"appId": "a"}]}),
+                "Duplicate access control policy for app a",
+            ),
+            (
+                serde_json::json!({"policies": [
+                    {"appId": "a", "operations": [{"name": "/x", "action": "skip"}]}
+                ]}),
+                "Invalid action \"skip\" in operation /x of the policy of app a: expected allow or deny",
+            ),
+            (
+                serde_json::json!({"policies": [
+                    {"appId": "a", "operations": [{"name": "/x", "action": ""}]}
+                ]}),
+                "Invalid action \"\" in operation /x of the policy of app a: expected allow or deny",
+            ),
+        ];
+        for (spec, message) in cases {
+            assert_eq!(access_control(spec).unwrap_err().to_string(), message);
+        }
+    }
+}
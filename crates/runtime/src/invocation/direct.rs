@@ -0,0 +1,779 @@
+use super::grpc::ServiceInvocationClient;
+use super::{
+    CALLEE_APP_ID_HEADER, CALLER_APP_ID_HEADER, CALLER_NAMESPACE_HEADER, InvalidAddressSnafu,
+    InvocationError, InvocationHandler, InvokeRequest, InvokeResponse, RemoteSnafu, ResolveSnafu,
+    Result,
+};
+use crate::diagnostics::{InvocationSide, Metrics, SpanKind, Tracer};
+use crate::meta::Meta;
+use crate::resiliency::{PolicyError, Resiliency};
+use crate::security::{Credentials, SpiffeId, TlsConnector};
+use rapr_contributes::nameresolution::{NameResolver, ResolveRequest};
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::transport::{Channel, Endpoint};
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+
+/// DirectMessaging invokes apps by id, forwarding invocations to their sidecars.
+pub struct DirectMessaging {
+    app_id: String,
+    namespace: String,
+    sidecars: Arc<Sidecars>,
+    /// Delivers invocations addressed to this app without a network hop.
+    local: Option<Arc<dyn InvocationHandler>>,
+    /// Policies applied to invocations of other apps.
+    resiliency: Option<Arc<Resiliency>>,
+    tracer: Option<Tracer>,
+    metrics: Option<Metrics>,
+}
+
+impl DirectMessaging {
+    /// Creates a client invoking apps as the app of `meta`.
+    pub fn new(meta: &Meta, resolver: Arc<dyn NameResolver>, port: u16) -> Self {
+        Self {
+            app_id: meta.id.clone(),
+            namespace: meta.namespace.clone(),
+            sidecars: Arc::new(Sidecars {
+                resolver,
+                port,
+                credentials: None,
+                channels: Mutex::new(HashMap::new()),
+            }),
+            local: None,
+            resiliency: None,
+            tracer: None,
+            metrics: None,
+        }
+    }
+
+    /// Delivers invocations addressed to this app to the handler directly.
+    pub fn with_local_handler(mut self, handler: Arc<dyn InvocationHandler>) -> Self {
+        self.local = Some(handler);
+        self
+    }
+
+    /// Calls other sidecars over mutual TLS, checking that they hold the identity of the
+    /// invoked app in the trust domain of the credentials.
+    pub fn with_credentials(mut self, credentials: Arc<Credentials>) -> Self {
+        let sidecars = Arc::get_mut(&mut self.sidecars)
+            .expect("credentials are set before the client is shared");
+        sidecars.credentials = Some(credentials);
+        self
+    }
+
+    /// Applies the resiliency policies of the target apps to invocations. Policies wrap the
+    /// resolution of the app and the call to its sidecar, so retries may reach other
+    /// instances of the app.
+    pub fn with_resiliency(mut self, resiliency: Arc<Resiliency>) -> Self {
+        self.resiliency = Some(resiliency);
+        self
+    }
+
+    /// Traces invocations, as children of the trace context they carry.
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Records the metrics of invocations, by target app.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Invokes a method of the target app, given as `<app-id>` or `<app-id>.<namespace>`.
+    /// Apps without namespace are looked up in the namespace of the caller.
+    pub async fn invoke(&self, target: &str, request: InvokeRequest) -> Result<InvokeResponse> {
+        let Some(metrics) = &self.metrics else {
+            return self.invoke_traced(target, request).await;
+        };
+        let method = request.method.clone();
+        let start = Instant::now();
+        let result = self.invoke_traced(target, request).await;
+        let status = super::metrics_status(&result);
+        metrics.record_invocation(
+            InvocationSide::Client,
+            target,
+            &method,
+            &status,
+            start.elapsed(),
+        );
+        result
+    }
+
+    async fn invoke_traced(
+        &self,
+        target: &str,
+        mut request: InvokeRequest,
+    ) -> Result<InvokeResponse> {
+        let Some(tracer) = &self.tracer else {
+            return self.invoke_untraced(target, request).await;
+        };
+        let parent = request.trace_context();
+        let mut span = tracer.start(
+            format!("CallLocal/{target}"),
+            SpanKind::Client,
+            parent.as_ref(),
+        );
+        span.set_attribute("rpc.system", "grpc");
+        span.set_attribute("rpc.service", "rapr.invocation.v1.ServiceInvocation");
+        span.set_attribute("rpc.method", "CallLocal");
+        span.set_attribute("rapr.app_id", target);
+        span.set_attribute("rapr.method", request.method.as_str());
+        request.set_trace_context(span.context());
+        let result = self.invoke_untraced(target, request).await;
+        super::record_result(&mut span, &result);
+        result
+    }
+
+    async fn invoke_untraced(
+        &self,
+        target: &str,
+        mut request: InvokeRequest,
+    ) -> Result<InvokeResponse> {
+        let (app_id, namespace) = self.parse_target(target)?;
+
+        // Identity headers set by the app are overwritten: they are only trusted because
+        // sidecars set them.
+        request.set_header(CALLER_APP_ID_HEADER, self.app_id.clone());
+        request.set_header(CALLER_NAMESPACE_HEADER, self.namespace.clone());
+        request.set_header(CALLEE_APP_ID_HEADER, app_id);
+
+        if app_id == self.app_id
+            && namespace == self.namespace
+            && let Some(local) = &self.local
+        {
+            return local.on_invoke(request).await;
+        }
+
+        let policy = self
+            .resiliency
+            .as_ref()
+            .and_then(|resiliency| resiliency.app_policy(app_id));
+        let Some(policy) = policy else {
+            return self.sidecars.invoke(app_id, namespace, request).await;
+        };
+        let sidecars = self.sidecars.clone();
+        let (callee, namespace) = (app_id.to_string(), namespace.to_string());
+        let service = tower::service_fn(move |request| {
+            let (sidecars, callee, namespace) =
+                (sidecars.clone(), callee.clone(), namespace.clone());
+            async move { sidecars.invoke(&callee, &namespace, request).await }
+        });
+        ServiceBuilder::new()
+            .layer(policy.layer())
+            .service(service)
+            .oneshot(request)
+            .await
+            .map_err(|e| from_box_error(app_id, e))
+    }
+
+    fn parse_target<'a>(&'a self, target: &'a str) -> Result<(&'a str, &'a str)> {
+        let (app_id, namespace) = match target.split_once('.') {
+            Some((app_id, namespace)) => (app_id, namespace),
+            None => (target, self.namespace.as_str()),
+        };
+        if app_id.is_empty() || namespace.contains('.') || target.ends_with('.') {
+            return Err(InvocationError::InvalidTarget {
+                target: target.to_string(),
+            });
+        }
+        Ok((app_id, namespace))
+    }
+}
+
+/// Channels to sidecars which were not resolved for this long are closed.
+const CHANNEL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Key of the channel to a sidecar: its address, and the namespace and id of the app it
+/// must prove to serve over mutual TLS.
+type ChannelKey = (String, String, String);
+
+/// Channel to a sidecar, with the last time the resolver returned its address.
+struct CachedChannel {
+    channel: Channel,
+    resolved: Instant,
+}
+
+/// Sidecars forwards invocations to the sidecars of other apps.
+struct Sidecars {
+    resolver: Arc<dyn NameResolver>,
+    /// Port other sidecars accept invocations on.
+    port: u16,
+    /// Credentials of the sidecar, when sidecars talk over mutual TLS.
+    credentials: Option<Arc<Credentials>>,
+    /// Channels to other sidecars. Channels reconnect by themselves, but are dropped when
+    /// the sidecar cannot be reached or is no longer resolved.
+    channels: Mutex<HashMap<ChannelKey, CachedChannel>>,
+}
+
+impl Sidecars {
+    async fn invoke(
+        &self,
+        app_id: &str,
+        namespace: &str,
+        request: InvokeRequest,
+    ) -> Result<InvokeResponse> {
+        let resolve = ResolveRequest {
+            id: app_id.to_string(),
+            namespace: namespace.to_string(),
+            port: self.port,
+            data: HashMap::new(),
+        };
+        let address = self
+            .resolver
+            .resolve(&resolve)
+            .await
+            .context(ResolveSnafu { app_id })?;
+        let key = (address, namespace.to_string(), app_id.to_string());
+        let channel = self.channel(&key)?;
+
+        let result = ServiceInvocationClient::new(channel)
+            .call_local(tonic::Request::new(request.into()))
+            .await;
+        if let Err(status) = &result
+            && status.code() == tonic::Code::Unavailable
+        {
+            self.channels
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&key);
+        }
+        Ok(result.context(RemoteSnafu { app_id })?.into_inner().into())
+    }
+
+    fn channel(&self, key: &ChannelKey) -> Result<Channel> {
+        let (address, namespace, app_id) = key;
+        let now = Instant::now();
+        self.drop_idle_channels(now);
+        let mut channels = self.channels.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = channels.get_mut(key) {
+            cached.resolved = now;
+            return Ok(cached.channel.clone());
+        }
+        // TLS is handled by the connector: the channel itself speaks plain HTTP/2.
+        let endpoint = Endpoint::from_shared(format!("http://{address}"))
+            .context(InvalidAddressSnafu { app_id, address })?;
+        let channel = match &self.credentials {
+            Some(credentials) => {
+                let trust_domain = credentials.identity().trust_domain;
+                let expected = SpiffeId::new(trust_domain, namespace, app_id);
+                endpoint.connect_with_connector_lazy(TlsConnector::new(credentials, Some(expected)))
+            }
+            None => endpoint.connect_lazy(),
+        };
+        let cached = CachedChannel {
+            channel: channel.clone(),
+            resolved: now,
+        };
+        channels.insert(key.clone(), cached);
+        Ok(channel)
+    }
+
+    /// Drops the channels to sidecars which were not resolved for [`CHANNEL_IDLE_TIMEOUT`]
+    /// at `now`.
+    fn drop_idle_channels(&self, now: Instant) {
+        self.channels
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|_, cached| now.duration_since(cached.resolved) < CHANNEL_IDLE_TIMEOUT);
+    }
+}
+
+/// Recovers the error of an invocation made through a policy.
+fn from_box_error(app_id: &str, error: BoxError) -> InvocationError {
+    let error = match error.downcast::<InvocationError>() {
+        Ok(error) => return *error,
+        Err(error) => error,
+    };
+    match error.downcast::<PolicyError>() {
+        Ok(error) => InvocationError::Resiliency {
+            app_id: app_id.to_string(),
+            source: *error,
+        },
+        Err(error) => InvocationError::App {
+            reason: error.to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::CertificateAuthority;
+    use crate::invocation::AccessControl;
+    use crate::invocation::grpc::InvocationService;
+    use crate::meta::Options;
+    use crate::security::incoming;
+    use rapr_common::RaprMode;
+    use rapr_contributes::nameresolution::NameResolutionError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::net::TcpListener;
+    use tonic::transport::Server;
+    use tonic::transport::server::TcpIncoming;
+
+    /// Resolves every app of the `default` namespace to the same address.
+    struct FixedResolver(String);
+
+    #[async_trait::async_trait]
+    impl NameResolver for FixedResolver {
+        async fn resolve(
+            &self,
+            request: &ResolveRequest,
+        ) -> rapr_contributes::nameresolution::Result<String> {
+            if request.namespace != "default" {
+                return Err(NameResolutionError::NotFound {
+                    id: request.id.clone(),
+                });
+            }
+            Ok(self.0.clone())
+        }
+    }
+
+    /// Fails to resolve the first `failures` lookups.
+    struct FlakyResolver {
+        address: String,
+        failures: u32,
+        lookups: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl NameResolver for FlakyResolver {
+        async fn resolve(
+            &self,
+            request: &ResolveRequest,
+        ) -> rapr_contributes::nameresolution::Result<String> {
+            if self.lookups.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(NameResolutionError::NotFound {
+                    id: request.id.clone(),
+                });
+            }
+            Ok(self.address.clone())
+        }
+    }
+
+    /// Echoes the invocation in the response headers and body.
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl InvocationHandler for Echo {
+        async fn on_invoke(&self, request: InvokeRequest) -> Result<InvokeResponse> {
+            if request.method == "fail" {
+                return Err(InvocationError::App {
+                    reason: "failed".to_string(),
+                });
+            }
+            let mut response = InvokeResponse::ok(request.content_type, request.data);
+            response.headers = request.headers;
+            response.headers.push((
+                "method".to_string(),
+                format!("{} /{}", request.verb, request.method),
+            ));
+            Ok(response)
+        }
+    }
+
+    fn meta(id: &str) -> Meta {
+        Meta::new(Options {
+            id: id.to_string(),
+            pod_name: String::new(),
+            namespace: "default".to_string(),
+            strict_sandbox: false,
+            mode: RaprMode::Standalone,
+        })
+    }
+
+    async fn serve() -> String {
+        serve_with(InvocationService::new(Arc::new(Echo))).await
+    }
+
+    async fn serve_with(service: InvocationService) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(
+            Server::builder()
+                .add_service(service.into_server())
+                .serve_with_incoming(TcpIncoming::from(listener)),
+        );
+        address
+    }
+
+    #[tokio::test]
+    async fn test_invoke_remote_app() {
+        let address = serve().await;
+        let messaging = DirectMessaging::new(&meta("caller"), Arc::new(FixedResolver(address)), 0);
+
+        let mut request = InvokeRequest::new("/orders/1")
+            .with_verb("get")
+            .with_data("text/plain", "hello");
+        request
+            .headers
+            .push(("x-tenant".to_string(), "a".to_string()));
+        request.set_header("Rapr-Caller-App-Id", "spoofed");
+
+        let response = messaging.invoke("orders", request).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.data, b"hello");
+        assert_eq!(response.content_type, "text/plain");
+        assert_eq!(response.header("method"), Some("GET /orders/1"));
+        assert_eq!(response.header("x-tenant"), Some("a"));
+        assert_eq!(response.header(CALLER_APP_ID_HEADER), Some("caller"));
+        assert_eq!(response.header(CALLER_NAMESPACE_HEADER), Some("default"));
+        assert_eq!(response.header(CALLEE_APP_ID_HEADER), Some("orders"));
+        assert_eq!(
+            response
+                .headers
+                .iter()
+                .filter(|(n, _)| n.eq_ignore_ascii_case(CALLER_APP_ID_HEADER))
+                .count(),
+            1
+        );
+
+        let response = messaging
+            .invoke("orders.default", InvokeRequest::new("ping"))
+            .await
+            .unwrap();
+        assert_eq!(response.header("method"), Some("POST /ping"));
+    }
+
+    #[tokio::test]
+    async fn test_invoke_errors() {
+        let address = serve().await;
+        let messaging = DirectMessaging::new(&meta("caller"), Arc::new(FixedResolver(address)), 0);
+
+        for target in ["", ".default", "orders.", "orders.a.b"] {
+            assert!(matches!(
+                messaging.invoke(target, InvokeRequest::new("m")).await,
+                Err(InvocationError::InvalidTarget { .. })
+            ));
+        }
+        assert!(matches!(
+            messaging
+                .invoke("orders.prod", InvokeRequest::new("m"))
+                .await,
+            Err(InvocationError::Resolve { .. })
+        ));
+        match messaging.invoke("orders", InvokeRequest::new("fail")).await {
+            Err(InvocationError::Remote { source, .. }) => {
+                assert_eq!(source.code(), tonic::Code::Internal)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channels() {
+        let address = serve().await;
+        let messaging =
+            DirectMessaging::new(&meta("caller"), Arc::new(FixedResolver(address.clone())), 0);
+        let keys = || {
+            let channels = messaging.sidecars.channels.lock().unwrap();
+            let mut keys: Vec<ChannelKey> = channels.keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let key = |app_id: &str| (address.clone(), "default".to_string(), app_id.to_string());
+
+        // Sidecars at the same address serving other apps get their own channel.
+        for target in ["orders", "billing", "orders"] {
+            messaging
+                .invoke(target, InvokeRequest::new("ping"))
+                .await
+                .unwrap();
+        }
+        assert_eq!(keys(), [key("billing"), key("orders")]);
+
+        // Channels to sidecars which are no longer resolved are dropped.
+        let later = Instant::now() + CHANNEL_IDLE_TIMEOUT;
+        messaging
+            .sidecars
+            .channels
+            .lock()
+            .unwrap()
+            .get_mut(&key("orders"))
+            .unwrap()
+            .resolved = later;
+        messaging.sidecars.drop_idle_channels(later);
+        assert_eq!(keys(), [key("orders")]);
+
+        // Channels to sidecars which cannot be reached are dropped.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let messaging =
+            DirectMessaging::new(&meta("caller"), Arc::new(FixedResolver(unreachable)), 0);
+        assert!(
+            messaging
+                .invoke("orders", InvokeRequest::new("ping"))
+                .await
+                .is_err()
+        );
+        assert!(messaging.sidecars.channels.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invoke_with_access_control() {
+        let spec = serde_json::from_value(serde_json::json!({
+            "defaultAction": "deny",
+            "policies": [{
+                "appId": "checkout",
+                "defaultAction": "allow",
+                "operations": [{"name": "/admin/**", "action": "deny"}]
+            }]
+        }))
+        .unwrap();
+        let access_control = Arc::new(AccessControl::from_spec(&spec).unwrap());
+        let address =
+            serve_with(InvocationService::new(Arc::new(Echo)).with_access_control(access_control))
+                .await;
+        let resolver = Arc::new(FixedResolver(address));
+
+        let checkout = DirectMessaging::new(&meta("checkout"), resolver.clone(), 0);
+        let response = checkout
+            .invoke("orders", InvokeRequest::new("orders/1"))
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+        match checkout
+            .invoke("orders", InvokeRequest::new("admin/users"))
+            .await
+        {
+            Err(InvocationError::Remote { source, .. }) => {
+                assert_eq!(source.code(), tonic::Code::PermissionDenied);
+                assert_eq!(
+                    source.message(),
+                    "Access denied: app checkout of namespace default may not invoke POST /admin/users"
+                );
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        // Methods the app would resolve to a denied path are rejected or normalized first,
+        // such as `admin%3Fx` once decoded by the HTTP API.
+        for method in [
+            "admin?x",
+            "admin#x",
+            "x/../admin/users",
+            "./admin",
+            "%2E%2e/admin",
+            "admin%2Fusers",
+        ] {
+            match checkout.invoke("orders", InvokeRequest::new(method)).await {
+                Err(InvocationError::Remote { source, .. }) => {
+                    assert_eq!(source.code(), tonic::Code::InvalidArgument, "{method}")
+                }
+                other => panic!("unexpected {other:?} for {method}"),
+            }
+        }
+        for method in ["/admin//users", "%61dmin/users", "%61%64%6D%69%6E/users"] {
+            match checkout.invoke("orders", InvokeRequest::new(method)).await {
+                Err(InvocationError::Remote { source, .. }) => {
+                    assert_eq!(source.code(), tonic::Code::PermissionDenied, "{method}")
+                }
+                other => panic!("unexpected {other:?} for {method}"),
+            }
+        }
+        let response = checkout
+            .invoke("orders", InvokeRequest::new("orders//1"))
+            .await
+            .unwrap();
+        assert_eq!(response.header("method"), Some("POST /orders/1"));
+
+        let billing = DirectMessaging::new(&meta("billing"), resolver, 0);
+        match billing
+            .invoke("orders", InvokeRequest::new("orders/1"))
+            .await
+        {
+            Err(InvocationError::Remote { source, .. }) => {
+                assert_eq!(source.code(), tonic::Code::PermissionDenied)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invoke_over_mtls() {
+        let ca = CertificateAuthority::generate("cluster.local").unwrap();
+        let credentials = |ca: &CertificateAuthority, app_id: &str| {
+            let issued = ca
+                .issue("default", app_id, Duration::from_secs(3600))
+                .unwrap();
+            Arc::new(
+                Credentials::from_pem(
+                    &issued.cert_chain_pem,
+                    &issued.private_key_pem,
+                    ca.trust_bundle_pem(),
+                )
+                .unwrap(),
+            )
+        };
+        let spec = serde_json::from_value(serde_json::json!({
+            "defaultAction": "deny",
+            "policies": [{"appId": "checkout", "trustDomain": "cluster.local", "defaultAction": "allow"}]
+        }))
+        .unwrap();
+        let access_control = Arc::new(AccessControl::from_spec(&spec).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(
+            Server::builder()
+                .add_service(
+                    InvocationService::new(Arc::new(Echo))
+                        .with_access_control(access_control)
+                        .into_server(),
+                )
+                .serve_with_incoming(incoming(listener, credentials(&ca, "orders"))),
+        );
+        let resolver = Arc::new(FixedResolver(address));
+
+        // Callers are identified by their certificate, not by the headers.
+        let checkout = DirectMessaging::new(&meta("billing"), resolver.clone(), 0)
+            .with_credentials(credentials(&ca, "checkout"));
+        let response = checkout
+            .invoke("orders", InvokeRequest::new("ping"))
+            .await
+            .unwrap();
+        assert_eq!(response.header(CALLER_APP_ID_HEADER), Some("checkout"));
+
+        let billing = DirectMessaging::new(&meta("checkout"), resolver.clone(), 0)
+            .with_credentials(credentials(&ca, "billing"));
+        match billing.invoke("orders", InvokeRequest::new("ping")).await {
+            Err(InvocationError::Remote { source, .. }) => {
+                assert_eq!(source.code(), tonic::Code::PermissionDenied)
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+
+        // The server does not hold the identity of the invoked app.
+        let checkout = DirectMessaging::new(&meta("checkout"), resolver.clone(), 0)
+            .with_credentials(credentials(&ca, "checkout"));
+        assert!(
+            checkout
+                .invoke("payments", InvokeRequest::new("ping"))
+                .await
+                .is_err()
+        );
+
+        // Neither side trusts a foreign CA.
+        let other = CertificateAuthority::generate("cluster.local").unwrap();
+        let stranger = DirectMessaging::new(&meta("checkout"), resolver.clone(), 0)
+            .with_credentials(credentials(&other, "checkout"));
+        assert!(
+            stranger
+                .invoke("orders", InvokeRequest::new("ping"))
+                .await
+                .is_err()
+        );
+        // Plain text clients are refused.
+        let plain = DirectMessaging::new(&meta("checkout"), resolver, 0);
+        assert!(
+            plain
+                .invoke("orders", InvokeRequest::new("ping"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invoke_self_locally() {
+        // Nothing listens at the resolved address: self invocations must not use it.
+        let messaging = DirectMessaging::new(
+            &meta("orders"),
+            Arc::new(FixedResolver("127.0.0.1:1".to_string())),
+            0,
+        )
+        .with_local_handler(Arc::new(Echo));
+        let response = messaging
+            .invoke("orders", InvokeRequest::new("ping"))
+            .await
+            .unwrap();
+        assert_eq!(response.header(CALLER_APP_ID_HEADER), Some("orders"));
+    }
+
+    #[tokio::test]
+    async fn test_invoke_with_resiliency() {
+        let resources: Vec<rapr_apis::resiliency::v1alpha1::Resiliency> =
+            serde_json::from_value(serde_json::json!([{
+                "apiVersion": "takulatech.rapr.io/v1alpha1",
+                "kind": "Resiliency",
+                "metadata": {"name": "default"},
+                "spec": {
+                    "policies": {"retries": {"twice": {"duration": "10ms", "maxRetries": 2}}},
+                    "targets": {"apps": {"orders": {"retry": "twice"}}}
+                }
+            }]))
+            .unwrap();
+        let resiliency = Arc::new(Resiliency::from_resources(&resources, "caller").unwrap());
+        let address = serve().await;
+        let messaging = |failures| {
+            let resolver = FlakyResolver {
+                address: address.clone(),
+                failures,
+                lookups: AtomicU32::new(0),
+            };
+            DirectMessaging::new(&meta("caller"), Arc::new(resolver), 0)
+                .with_resiliency(resiliency.clone())
+        };
+
+        let response = messaging(2)
+            .invoke("orders", InvokeRequest::new("ping"))
+            .await
+            .unwrap();
+        assert_eq!(response.header("method"), Some("POST /ping"));
+        assert!(matches!(
+            messaging(3)
+                .invoke("orders", InvokeRequest::new("ping"))
+                .await,
+            Err(InvocationError::Resolve { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invoke_traced() {
+        use crate::diagnostics::{Collector, TraceContext};
+
+        let collector = Collector::default();
+        let tracer = collector.tracer().await;
+        let address =
+            serve_with(InvocationService::new(Arc::new(Echo)).with_tracer(tracer.clone())).await;
+        let messaging = DirectMessaging::new(&meta("caller"), Arc::new(FixedResolver(address)), 0)
+            .with_tracer(tracer.clone());
+
+        let response = messaging
+            .invoke("orders", InvokeRequest::new("ping"))
+            .await
+            .unwrap();
+        assert!(
+            messaging
+                .invoke("orders", InvokeRequest::new("fail"))
+                .await
+                .is_err()
+        );
+        tracer.flush().await;
+
+        // Invocations without context start a trace, continued by the callee.
+        let spans = collector.spans();
+        let client = collector.span("CallLocal/orders");
+        assert!(client.parent_span_id.is_empty());
+        let server = spans
+            .iter()
+            .find(|span| span.name == "CallLocal" && span.trace_id == client.trace_id)
+            .unwrap();
+        assert_eq!(server.parent_span_id, client.span_id);
+        let received = TraceContext::parse(response.header("traceparent").unwrap(), None).unwrap();
+        assert_eq!(received.span_id.as_slice(), server.span_id);
+
+        let failed: Vec<_> = spans
+            .iter()
+            .filter(|span| span.trace_id != client.trace_id)
+            .collect();
+        assert_eq!(failed.len(), 2);
+        assert!(
+            failed
+                .iter()
+                .all(|span| span.status.as_ref().unwrap().message.contains("failed"))
+        );
+    }
+}
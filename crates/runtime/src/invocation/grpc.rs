@@ -0,0 +1,275 @@
+//! gRPC channel carrying service invocation between sidecars.
+
+use super::{
+    AccessControl, Action, CALLER_APP_ID_HEADER, CALLER_NAMESPACE_HEADER, Caller, InvocationError,
+    InvocationHandler, InvokeRequest, InvokeResponse, normalize_method,
+};
+use crate::diagnostics::{InvocationSide, Metrics, SpanKind, Tracer};
+use crate::resiliency::PolicyError;
+use crate::security::{PeerInfo, SpiffeId};
+use proto::service_invocation_server::ServiceInvocation;
+use std::sync::Arc;
+use std::time::Instant;
+use tonic::{Code, Request, Response, Status};
+
+pub use proto::service_invocation_client::ServiceInvocationClient;
+pub use proto::service_invocation_server::ServiceInvocationServer;
+
+#[allow(missing_docs, unused_qualifications, clippy::all)]
+pub mod proto {
+    tonic::include_proto!("rapr.invocation.v1");
+}
+
+/// Port sidecars accept the invocations of other sidecars on by default.
+pub const DEFAULT_INTERNAL_GRPC_PORT: u16 = 50002;
+
+/// Target of the audit log lines of access control decisions.
+const AUDIT_TARGET: &str = "rapr::audit";
+
+/// InvocationService receives the invocations forwarded by other sidecars and hands them
+/// to the app through an [`InvocationHandler`].
+#[derive(Clone)]
+pub struct InvocationService {
+    handler: Arc<dyn InvocationHandler>,
+    access_control: Option<Arc<AccessControl>>,
+    tracer: Option<Tracer>,
+    metrics: Option<Metrics>,
+}
+
+impl InvocationService {
+    /// Creates a service delivering invocations to the handler.
+    pub fn new(handler: Arc<dyn InvocationHandler>) -> Self {
+        Self {
+            handler,
+            access_control: None,
+            tracer: None,
+            metrics: None,
+        }
+    }
+
+    /// Rejects the invocations the access control policies deny.
+    pub fn with_access_control(mut self, access_control: Arc<AccessControl>) -> Self {
+        self.access_control = Some(access_control);
+        self
+    }
+
+    /// Traces the invocations received, as children of the trace context they carry. The
+    /// app receives the context of the span of the sidecar.
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Records the metrics of the invocations received, by caller app.
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Wraps the service into a server which can be added to a tonic router.
+    pub fn into_server(self) -> ServiceInvocationServer<Self> {
+        ServiceInvocationServer::new(self)
+    }
+
+    /// Checks an invocation against the access control policies and delivers it to the app.
+    /// Both see the normalized method.
+    async fn handle(
+        &self,
+        peer: Option<&SpiffeId>,
+        mut request: InvokeRequest,
+    ) -> super::Result<InvokeResponse> {
+        request.method = normalize_method(&request.method)?;
+        if let Some(access_control) = &self.access_control {
+            check_access(access_control, peer, &request)?;
+        }
+        self.handler.on_invoke(request).await
+    }
+}
+
+pub(crate) fn to_status(e: InvocationError) -> Status {
+    match e {
+        InvocationError::Remote { source, .. } => source,
+        e => Status::new(error_code(&e), e.to_string()),
+    }
+}
+
+/// Returns the gRPC code of an invocation failure.
+pub(crate) fn error_code(e: &InvocationError) -> Code {
+    match e {
+        InvocationError::InvalidTarget { .. } | InvocationError::InvalidMethod { .. } => {
+            Code::InvalidArgument
+        }
+        InvocationError::Resolve { .. } | InvocationError::InvalidAddress { .. } => {
+            Code::Unavailable
+        }
+        InvocationError::Remote { source, .. } => source.code(),
+        InvocationError::Resiliency { source, .. } => match source {
+            PolicyError::Timeout { .. } => Code::DeadlineExceeded,
+            PolicyError::CircuitOpen { .. } | PolicyError::TooManyRequests { .. } => {
+                Code::Unavailable
+            }
+        },
+        InvocationError::AccessDenied { .. } => Code::PermissionDenied,
+        InvocationError::NoHandler => Code::Unimplemented,
+        InvocationError::App { .. } => Code::Internal,
+    }
+}
+
+/// Checks an invocation against the access control policies, logging the decision. Callers
+/// without verified identity are identified by the headers of the invocation.
+fn check_access(
+    access_control: &AccessControl,
+    peer: Option<&SpiffeId>,
+    request: &InvokeRequest,
+) -> super::Result<()> {
+    let caller = Caller {
+        app_id: request
+            .header(CALLER_APP_ID_HEADER)
+            .unwrap_or_default()
+            .to_string(),
+        namespace: request
+            .header(CALLER_NAMESPACE_HEADER)
+            .unwrap_or_default()
+            .to_string(),
+        trust_domain: peer
+            .map(|identity| identity.trust_domain.clone())
+            .unwrap_or_default(),
+    };
+    match access_control.check(&caller, &request.verb, &request.method) {
+        Action::Allow => {
+            tracing::debug!(
+                target: AUDIT_TARGET,
+                caller_app_id = %caller.app_id,
+                caller_namespace = %caller.namespace,
+                caller_trust_domain = %caller.trust_domain,
+                verb = %request.verb,
+                method = %request.method,
+                "Invocation allowed"
+            );
+            Ok(())
+        }
+        Action::Deny => {
+            tracing::warn!(
+                target: AUDIT_TARGET,
+                caller_app_id = %caller.app_id,
+                caller_namespace = %caller.namespace,
+                caller_trust_domain = %caller.trust_domain,
+                verb = %request.verb,
+                method = %request.method,
+                "Invocation denied by access control"
+            );
+            Err(InvocationError::AccessDenied {
+                app_id: caller.app_id,
+                namespace: caller.namespace,
+                verb: request.verb.clone(),
+                method: request.method.clone(),
+            })
+        }
+    }
+}
+
+fn to_headers(headers: Vec<(String, String)>) -> Vec<proto::Header> {
+    headers
+        .into_iter()
+        .map(|(name, value)| proto::Header { name, value })
+        .collect()
+}
+
+fn from_headers(headers: Vec<proto::Header>) -> Vec<(String, String)> {
+    headers.into_iter().map(|h| (h.name, h.value)).collect()
+}
+
+impl From<InvokeRequest> for proto::InvokeRequest {
+    fn from(request: InvokeRequest) -> Self {
+        Self {
+            method: request.method,
+            http_verb: request.verb,
+            query_string: request.query,
+            headers: to_headers(request.headers),
+            content_type: request.content_type,
+            data: request.data,
+        }
+    }
+}
+
+impl From<proto::InvokeRequest> for InvokeRequest {
+    fn from(request: proto::InvokeRequest) -> Self {
+        Self {
+            method: request.method,
+            verb: request.http_verb,
+            query: request.query_string,
+            headers: from_headers(request.headers),
+            content_type: request.content_type,
+            data: request.data,
+        }
+    }
+}
+
+impl From<InvokeResponse> for proto::InvokeResponse {
+    fn from(response: InvokeResponse) -> Self {
+        Self {
+            status_code: response.status.into(),
+            headers: to_headers(response.headers),
+            content_type: response.content_type,
+            data: response.data,
+        }
+    }
+}
+
+impl From<proto::InvokeResponse> for InvokeResponse {
+    fn from(response: proto::InvokeResponse) -> Self {
+        Self {
+            status: u16::try_from(response.status_code).unwrap_or(500),
+            headers: from_headers(response.headers),
+            content_type: response.content_type,
+            data: response.data,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ServiceInvocation for InvocationService {
+    async fn call_local(
+        &self,
+        request: Request<proto::InvokeRequest>,
+    ) -> Result<Response<proto::InvokeResponse>, Status> {
+        let peer = request
+            .extensions()
+            .get::<PeerInfo>()
+            .map(|peer| peer.identity.clone());
+        let mut request = InvokeRequest::from(request.into_inner());
+        // Over mutual TLS, the caller is who its certificate says, whatever the headers.
+        if let Some(identity) = &peer {
+            request.set_header(CALLER_APP_ID_HEADER, identity.app_id.clone());
+            request.set_header(CALLER_NAMESPACE_HEADER, identity.namespace.clone());
+        }
+        let mut span = self.tracer.as_ref().map(|tracer| {
+            let parent = request.trace_context();
+            let span = tracer.start("CallLocal", SpanKind::Server, parent.as_ref());
+            request.set_trace_context(span.context());
+            span
+        });
+        if let Some(span) = &mut span {
+            span.set_attribute("rpc.system", "grpc");
+            span.set_attribute("rpc.service", "rapr.invocation.v1.ServiceInvocation");
+            span.set_attribute("rpc.method", "CallLocal");
+            span.set_attribute("rapr.method", request.method.as_str());
+        }
+        let caller = request
+            .header(CALLER_APP_ID_HEADER)
+            .unwrap_or_default()
+            .to_string();
+        let method = request.method.clone();
+        let start = Instant::now();
+        let result = self.handle(peer.as_ref(), request).await;
+        if let Some(span) = &mut span {
+            super::record_result(span, &result);
+        }
+        if let Some(metrics) = &self.metrics {
+            let status = super::metrics_status(&result);
+            let elapsed = start.elapsed();
+            metrics.record_invocation(InvocationSide::Server, &caller, &method, &status, elapsed);
+        }
+        Ok(Response::new(result.map_err(to_status)?.into()))
+    }
+}
@@ -0,0 +1,337 @@
+//! Service invocation building block.
+//!
+//! An app invokes a method of another app by its id: the sidecar of the caller resolves the
+//! id to the address of a sidecar of the callee and forwards the invocation to it over
+//! gRPC, and that sidecar delivers it to its app. The identity of the caller travels with
+//! the invocation in the [`CALLER_APP_ID_HEADER`] and [`CALLER_NAMESPACE_HEADER`] headers,
+//! which the sidecar of the callee checks against its [`AccessControl`] policies. The
+//! [`TraceContext`] of the invocation travels in its `traceparent` and `tracestate`
+//! headers. Invocations are counted and timed on both sides by [`Metrics`].
+
+mod acl;
+mod direct;
+pub mod grpc;
+
+pub use acl::*;
+pub use direct::*;
+
+#[cfg(doc)]
+use crate::diagnostics::Metrics;
+use crate::diagnostics::{Span, TRACEPARENT_HEADER, TRACESTATE_HEADER, TraceContext};
+use crate::resiliency::PolicyError;
+use rapr_contributes::nameresolution::NameResolutionError;
+use snafu::Snafu;
+
+/// Header holding the id of the app making an invocation.
+pub const CALLER_APP_ID_HEADER: &str = "rapr-caller-app-id";
+/// Header holding the namespace of the app making an invocation.
+pub const CALLER_NAMESPACE_HEADER: &str = "rapr-caller-namespace";
+/// Header holding the id of the app an invocation is addressed to.
+pub const CALLEE_APP_ID_HEADER: &str = "rapr-callee-app-id";
+
+#[derive(Debug, Snafu)]
+pub enum InvocationError {
+    #[snafu(display(
+        "Invalid target app {:?}: expected <app-id> or <app-id>.<namespace>",
+        target
+    ))]
+    InvalidTarget { target: String },
+
+    #[snafu(display("Failed to resolve app {}: {}", app_id, source))]
+    Resolve {
+        app_id: String,
+        source: NameResolutionError,
+    },
+
+    #[snafu(display("Invalid address {} for app {}: {}", address, app_id, source))]
+    InvalidAddress {
+        app_id: String,
+        address: String,
+        source: tonic::transport::Error,
+    },
+
+    #[snafu(display("Failed to invoke app {}: {}", app_id, source))]
+    Remote {
+        app_id: String,
+        source: tonic::Status,
+    },
+
+    #[snafu(display("Failed to invoke app {}: {}", app_id, source))]
+    Resiliency { app_id: String, source: PolicyError },
+
+    #[snafu(display(
+        "Access denied: app {} of namespace {} may not invoke {} /{}",
+        app_id,
+        namespace,
+        verb,
+        method
+    ))]
+    AccessDenied {
+        app_id: String,
+        namespace: String,
+        verb: String,
+        method: String,
+    },
+
+    #[snafu(display("Invalid method {:?}: {}", method, reason))]
+    InvalidMethod { method: String, reason: String },
+
+    #[snafu(display("No app is listening for invocations"))]
+    NoHandler,
+
+    #[snafu(display("App failed to handle the invocation: {}", reason))]
+    App { reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, InvocationError>;
+
+/// InvokeRequest is the invocation of a method of an app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvokeRequest {
+    /// Path of the method, without leading slash.
+    pub method: String,
+    /// HTTP verb the method is invoked with.
+    pub verb: String,
+    /// Query string, without leading question mark.
+    pub query: String,
+    /// Headers, in order. Names may be repeated.
+    pub headers: Vec<(String, String)>,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl InvokeRequest {
+    /// Creates a `POST` invocation of a method.
+    pub fn new(method: impl Into<String>) -> Self {
+        Self {
+            method: method.into().trim_start_matches('/').to_string(),
+            verb: "POST".to_string(),
+            query: String::new(),
+            headers: Vec::new(),
+            content_type: String::new(),
+            data: Vec::new(),
+        }
+    }
+
+    /// Sets the HTTP verb of the invocation.
+    pub fn with_verb(mut self, verb: &str) -> Self {
+        self.verb = verb.to_ascii_uppercase();
+        self
+    }
+
+    /// Sets the payload of the invocation.
+    pub fn with_data(mut self, content_type: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        self.content_type = content_type.into();
+        self.data = data.into();
+        self
+    }
+
+    /// Returns the first value of a header, whose name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Replaces every value of a header by a single one.
+    pub fn set_header(&mut self, name: &str, value: impl Into<String>) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.into()));
+    }
+
+    /// Returns the trace context the invocation carries in its headers, if valid.
+    pub fn trace_context(&self) -> Option<TraceContext> {
+        TraceContext::parse(
+            self.header(TRACEPARENT_HEADER)?,
+            self.header(TRACESTATE_HEADER),
+        )
+    }
+
+    /// Replaces the trace context the invocation carries in its headers.
+    pub fn set_trace_context(&mut self, context: &TraceContext) {
+        self.set_header(TRACEPARENT_HEADER, context.traceparent());
+        match context.trace_state.is_empty() {
+            true => self
+                .headers
+                .retain(|(n, _)| !n.eq_ignore_ascii_case(TRACESTATE_HEADER)),
+            false => self.set_header(TRACESTATE_HEADER, context.trace_state.clone()),
+        }
+    }
+}
+
+/// Normalizes the path of an invoked method, so that access control decides on the path the
+/// app receives: empty segments are collapsed, percent-encoded segments are decoded and
+/// encoded again only where needed, and methods holding a query, a fragment, dot segments
+/// or encoded slashes, which the app would resolve to another path, are rejected.
+pub fn normalize_method(method: &str) -> Result<String> {
+    let invalid = |reason: &str| InvocationError::InvalidMethod {
+        method: method.to_string(),
+        reason: reason.to_string(),
+    };
+    if method.contains(['?', '#']) {
+        return Err(invalid("methods may not hold a query or a fragment"));
+    }
+    let mut segments = Vec::new();
+    for segment in method.split('/').filter(|segment| !segment.is_empty()) {
+        let decoded = percent_decode(segment)
+            .ok_or_else(|| invalid("methods may not hold invalid percent-encodings"))?;
+        if decoded.contains(&b'/') || decoded.contains(&b'\\') {
+            return Err(invalid("methods may not hold encoded slashes"));
+        }
+        if decoded == b"." || decoded == b".." {
+            return Err(invalid("methods may not hold dot segments"));
+        }
+        segments.push(percent_encode(&decoded));
+    }
+    let mut normalized = segments.join("/");
+    if method.ends_with('/') && !normalized.is_empty() {
+        normalized.push('/');
+    }
+    Ok(normalized)
+}
+
+/// Decodes the percent-encoded bytes of a path segment, or returns `None` if an encoding is
+/// invalid.
+fn percent_decode(segment: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(segment.len());
+    let mut bytes = segment.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+        let high = char::from(bytes.next()?).to_digit(16)?;
+        let low = char::from(bytes.next()?).to_digit(16)?;
+        decoded.push((high * 16 + low) as u8);
+    }
+    Some(decoded)
+}
+
+/// Percent-encodes the bytes of a path segment, but for the unreserved characters and the
+/// delimiters allowed in segments.
+fn percent_encode(segment: &[u8]) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for &byte in segment {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'!'
+            | b'$'
+            | b'&'
+            | b'\''
+            | b'('
+            | b')'
+            | b'*'
+            | b'+'
+            | b','
+            | b';'
+            | b'='
+            | b':'
+            | b'@' => encoded.push(char::from(byte)),
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// InvokeResponse is the response of an app to an invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvokeResponse {
+    /// HTTP status code.
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl InvokeResponse {
+    /// Creates a successful response.
+    pub fn ok(content_type: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            headers: Vec::new(),
+            content_type: content_type.into(),
+            data: data.into(),
+        }
+    }
+
+    /// Returns the first value of a header, whose name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.headers, name)
+    }
+}
+
+/// Marks the span of an invocation as failed if the invocation or the app failed.
+fn record_result(span: &mut Span, result: &Result<InvokeResponse>) {
+    match result {
+        Ok(response) if response.status >= 500 => {
+            span.set_error(format!("App responded with status {}", response.status))
+        }
+        Ok(_) => {}
+        Err(e) => span.set_error(e.to_string()),
+    }
+}
+
+/// Returns the status an invocation is reported with in the metrics: the HTTP status the
+/// app responded with, or the gRPC code of the failure.
+fn metrics_status(result: &Result<InvokeResponse>) -> String {
+    match result {
+        Ok(response) => response.status.to_string(),
+        Err(e) => format!("{:?}", grpc::error_code(e)),
+    }
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// InvocationHandler delivers the invocations a sidecar receives to its app.
+#[async_trait::async_trait]
+pub trait InvocationHandler: Send + Sync {
+    /// Invokes a method of the app.
+    async fn on_invoke(&self, request: InvokeRequest) -> Result<InvokeResponse>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_method() {
+        for (method, normalized) in [
+            ("orders/1", "orders/1"),
+            ("/orders//1/", "orders/1/"),
+            ("%61dmin/users", "admin/users"),
+            ("orders/a%20b%25", "orders/a%20b%25"),
+            ("orders/caf%C3%A9", "orders/caf%C3%A9"),
+            ("orders/a b", "orders/a%20b"),
+        ] {
+            assert_eq!(normalize_method(method).unwrap(), normalized, "{method}");
+        }
+        for method in [
+            "admin?x",
+            "admin#x",
+            "x/../admin",
+            "%2E%2e/admin",
+            "admin%2Fusers",
+            "admin%2fusers",
+            "admin%5Cusers",
+            "admin%2",
+            "admin%zz",
+        ] {
+            assert!(
+                matches!(
+                    normalize_method(method),
+                    Err(InvocationError::InvalidMethod { .. })
+                ),
+                "{method}"
+            );
+        }
+    }
+}
@@ -7,4 +7,21 @@
 #![allow(missing_docs)]
 #![allow(dead_code)]
 
+pub mod actors;
+pub mod api;
+pub mod ca;
+pub mod channel;
+pub mod cli;
+pub mod cluster;
+pub mod components;
+pub mod configuration;
+pub mod crypto;
+pub mod diagnostics;
+pub mod health;
+pub mod invocation;
+pub mod logging;
 pub mod meta;
+pub mod resiliency;
+pub mod security;
+pub mod shutdown;
+pub mod workflow;
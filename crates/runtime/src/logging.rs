@@ -0,0 +1,470 @@
+//! Logging of the runtime.
+//!
+//! Log lines are written as text, in `key=value` pairs, or as JSON objects, one per line.
+//! Every line carries the id of the app, its namespace and the instance of the sidecar, and
+//! the log name of the component it is about, when logged within a [`component_span`] or
+//! with a `component` field.
+//!
+//! The scope of a line is the target it is logged with, without the `rapr` prefix and with
+//! dots as separators: lines of `rapr_runtime::actors::turn` are in the
+//! `runtime.actors.turn` scope. [`LogLevels`] set a level per scope, such as
+//! `info,runtime.actors=debug`, the most specific scope applying.
+
+#[cfg(doc)]
+use rapr_common::utils::component_log_name;
+use serde_json::{Map, Value};
+use snafu::Snafu;
+use std::fmt::{self, Write as _};
+use std::io::Write as _;
+use std::str::FromStr;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Filter, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Layer, filter::LevelFilter};
+
+/// Name of the field holding the log name of a component.
+pub const COMPONENT_FIELD: &str = "component";
+
+#[derive(Debug, Snafu)]
+pub enum LoggingError {
+    #[snafu(display(
+        "Invalid log level {:?}: expected trace, debug, info, warn or error",
+        level
+    ))]
+    InvalidLevel { level: String },
+
+    #[snafu(display("Invalid log format {:?}: expected text or json", format))]
+    InvalidFormat { format: String },
+}
+
+/// Format of the log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `key=value` pairs.
+    #[default]
+    Text,
+    /// JSON objects.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = LoggingError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => InvalidFormatSnafu { format }.fail(),
+        }
+    }
+}
+
+/// LogLevels sets the level of the lines logged, per scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLevels {
+    /// Level of the scopes without a level of their own.
+    default: LevelFilter,
+    /// Levels by scope, most specific first.
+    scopes: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        Self::new(Level::INFO)
+    }
+}
+
+impl LogLevels {
+    /// Logs every scope at the same level.
+    pub fn new(level: Level) -> Self {
+        Self {
+            default: LevelFilter::from_level(level),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Returns the level of a scope: that of its most specific parent scope with a level,
+    /// itself included, or else the default level.
+    pub fn level(&self, scope: &str) -> LevelFilter {
+        self.scopes
+            .iter()
+            .find(|(prefix, _)| {
+                scope
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        // Spans are always recorded, so that lines logged within them get their fields.
+        metadata.is_span() || *metadata.level() <= self.level(&scope(metadata.target()))
+    }
+}
+
+impl FromStr for LogLevels {
+    type Err = LoggingError;
+
+    /// Parses a comma separated list of a default level and of `scope=level` directives.
+    fn from_str(directives: &str) -> Result<Self, Self::Err> {
+        let level = |level: &str| {
+            level
+                .trim()
+                .parse::<Level>()
+                .map(LevelFilter::from_level)
+                .map_err(|_| LoggingError::InvalidLevel {
+                    level: level.trim().to_string(),
+                })
+        };
+        let mut levels = Self::default();
+        for directive in directives.split(',').filter(|d| !d.trim().is_empty()) {
+            match directive.split_once('=') {
+                Some((scope, value)) => levels
+                    .scopes
+                    .push((scope.trim().to_string(), level(value)?)),
+                None => levels.default = level(directive)?,
+            }
+        }
+        levels
+            .scopes
+            .sort_by_key(|(scope, _)| std::cmp::Reverse(scope.len()));
+        Ok(levels)
+    }
+}
+
+impl<S> Filter<S> for LogLevels {
+    fn enabled(&self, metadata: &Metadata<'_>, _: &Context<'_, S>) -> bool {
+        LogLevels::enabled(self, metadata)
+    }
+
+    fn callsite_enabled(&self, metadata: &'static Metadata<'static>) -> Interest {
+        match LogLevels::enabled(self, metadata) {
+            true => Interest::always(),
+            false => Interest::never(),
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        let scopes = self.scopes.iter().map(|(_, level)| *level);
+        scopes.chain([self.default]).max()
+    }
+}
+
+/// Returns the scope of a target, such as `runtime.api.http` for `rapr_runtime::api::http`
+/// and `audit` for `rapr::audit`.
+pub fn scope(target: &str) -> String {
+    let target = target
+        .strip_prefix("rapr::")
+        .or_else(|| target.strip_prefix("rapr_"))
+        .unwrap_or(target);
+    target.replace("::", ".")
+}
+
+/// Returns a span whose lines carry the log name of a component, as returned by
+/// [`component_log_name`], such as `vault (crypto.rapr.localstorage/v1)`.
+pub fn component_span(log_name: &str) -> tracing::Span {
+    tracing::info_span!("component", component = log_name)
+}
+
+/// LogFields are the fields of every line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFields {
+    pub app_id: String,
+    pub namespace: String,
+    /// Name of the pod of the sidecar, or of its host.
+    pub instance: String,
+}
+
+/// Installs the logging of the runtime as the global subscriber.
+pub fn init(format: LogFormat, levels: LogLevels, fields: LogFields) {
+    let layer = LogLayer::new(format, fields, std::io::stdout).with_filter(levels);
+    tracing_subscriber::registry().with(layer).init();
+}
+
+/// LogLayer writes the lines logged to a writer, in a format.
+pub struct LogLayer<W> {
+    format: LogFormat,
+    fields: LogFields,
+    writer: W,
+}
+
+/// Log name of the component of a span, held in its extensions.
+struct ComponentName(String);
+
+impl<W> LogLayer<W> {
+    /// Creates a layer writing lines with the fields to the writers made by `writer`.
+    pub fn new(format: LogFormat, fields: LogFields, writer: W) -> Self {
+        Self {
+            format,
+            fields,
+            writer,
+        }
+    }
+
+    fn format(&self, event: &Event<'_>, component: Option<String>) -> String {
+        let metadata = event.metadata();
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let mut line: Vec<(&str, Value)> = vec![
+            ("time", Value::String(timestamp(SystemTime::now()))),
+            (
+                "level",
+                Value::String(metadata.level().as_str().to_ascii_lowercase()),
+            ),
+            ("msg", Value::String(visitor.message.unwrap_or_default())),
+            ("scope", Value::String(scope(metadata.target()))),
+            ("app_id", Value::String(self.fields.app_id.clone())),
+            ("namespace", Value::String(self.fields.namespace.clone())),
+            ("instance", Value::String(self.fields.instance.clone())),
+        ];
+        let component = visitor.component.or(component);
+        if let Some(component) = component {
+            line.push((COMPONENT_FIELD, Value::String(component)));
+        }
+        line.extend(visitor.fields);
+        match self.format {
+            LogFormat::Json => {
+                let object: Map<String, Value> = line
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect();
+                Value::Object(object).to_string()
+            }
+            LogFormat::Text => {
+                let mut text = String::new();
+                for (key, value) in line {
+                    if !text.is_empty() {
+                        text.push(' ');
+                    }
+                    let value = match value {
+                        Value::String(value) => value,
+                        value => value.to_string(),
+                    };
+                    let _ = write!(text, "{key}={}", quote(&value));
+                }
+                text
+            }
+        }
+    }
+}
+
+impl<S, W> Layer<S> for LogLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        if let Some(component) = visitor.component
+            && let Some(span) = ctx.span(id)
+        {
+            span.extensions_mut().insert(ComponentName(component));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // The innermost component span names the component.
+        let component = ctx.event_scope(event).and_then(|scope| {
+            scope
+                .into_iter()
+                .find_map(|span| Some(span.extensions().get::<ComponentName>()?.0.clone()))
+        });
+        let mut line = self.format(event, component);
+        line.push('\n');
+        let _ = self.writer.make_writer().write_all(line.as_bytes());
+    }
+}
+
+/// FieldVisitor collects the fields of a line, setting apart its message and component.
+#[derive(Default)]
+struct FieldVisitor {
+    message: Option<String>,
+    component: Option<String>,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: Value) {
+        let string = || match &value {
+            Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        match field.name() {
+            "message" => self.message = Some(string()),
+            COMPONENT_FIELD => self.component = Some(string()),
+            name => self.fields.push((name, value)),
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, Value::String(value.to_string()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, Value::Bool(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, Value::String(format!("{value:?}")));
+    }
+}
+
+/// Quotes text values which would not read back as a single value.
+fn quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=' || c.is_control());
+    match plain {
+        true => value.to_string(),
+        false => format!("{value:?}"),
+    }
+}
+
+/// Formats a time as RFC 3339 in UTC, with milliseconds.
+fn timestamp(time: SystemTime) -> String {
+    let time = time::OffsetDateTime::from(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.millisecond()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rapr_common::utils::component_log_name;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<String> {
+            let bytes = self.0.lock().unwrap();
+            String::from_utf8_lossy(&bytes)
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    fn log(format: LogFormat, levels: &str, log: impl FnOnce()) -> Vec<String> {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let fields = LogFields {
+            app_id: "orders".to_string(),
+            namespace: "prod".to_string(),
+            instance: "orders-0".to_string(),
+        };
+        let layer = LogLayer::new(format, fields, move || writer.clone())
+            .with_filter(levels.parse::<LogLevels>().unwrap());
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), log);
+        buffer.lines()
+    }
+
+    #[test]
+    fn test_levels() {
+        let levels: LogLevels = "warn, runtime.actors=debug,runtime.actors.turn=trace"
+            .parse()
+            .unwrap();
+        assert_eq!(levels.level("runtime"), LevelFilter::WARN);
+        assert_eq!(levels.level("runtime.actors"), LevelFilter::DEBUG);
+        assert_eq!(levels.level("runtime.actors.turn"), LevelFilter::TRACE);
+        assert_eq!(levels.level("runtime.actorsx"), LevelFilter::WARN);
+        assert_eq!(
+            "runtime.api=debug".parse::<LogLevels>().unwrap().default,
+            LevelFilter::INFO
+        );
+        assert_eq!(
+            "runtime=loud".parse::<LogLevels>().unwrap_err().to_string(),
+            "Invalid log level \"loud\": expected trace, debug, info, warn or error"
+        );
+        assert_eq!(scope("rapr_runtime::api::http"), "runtime.api.http");
+        assert_eq!(scope("rapr::audit"), "audit");
+        assert_eq!(scope("hyper::proto"), "hyper.proto");
+    }
+
+    #[test]
+    fn test_json() {
+        let lines = log(LogFormat::Json, "info,runtime.actors=debug", || {
+            tracing::debug!(target: "rapr_runtime::api", "hidden");
+            tracing::debug!(target: "rapr_runtime::actors::turn", actor = "cart||1", "Locked");
+            let log_name = component_log_name("vault", "crypto.rapr.localstorage", "v1");
+            let span = component_span(&log_name);
+            let _entered = span.enter();
+            tracing::warn!(target: "rapr_runtime::crypto", attempts = 3, "Key store failed");
+        });
+        assert_eq!(lines.len(), 2);
+
+        let line: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["level"], "debug");
+        assert_eq!(line["msg"], "Locked");
+        assert_eq!(line["scope"], "runtime.actors.turn");
+        assert_eq!(line["app_id"], "orders");
+        assert_eq!(line["namespace"], "prod");
+        assert_eq!(line["instance"], "orders-0");
+        assert_eq!(line["actor"], "cart||1");
+        assert!(line.get(COMPONENT_FIELD).is_none());
+
+        let line: Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(line[COMPONENT_FIELD], "vault (crypto.rapr.localstorage/v1)");
+        assert_eq!(line["attempts"], 3);
+        assert!(line["time"].as_str().unwrap().ends_with('Z'));
+    }
+
+    #[test]
+    fn test_text() {
+        let lines = log(LogFormat::Text, "info", || {
+            tracing::info!(target: "rapr::audit", component = "store (state.redis/v1)", allowed = true, "Access granted");
+        });
+        let line = lines[0].split_once(' ').unwrap().1;
+        assert_eq!(
+            line,
+            "level=info msg=\"Access granted\" scope=audit app_id=orders namespace=prod instance=orders-0 component=\"store (state.redis/v1)\" allowed=true"
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_123);
+        assert_eq!(timestamp(time), "2023-11-14T22:13:20.123Z");
+    }
+}
@@ -1,4 +1,383 @@
 #![allow(missing_docs)]
 #![allow(dead_code)]
 
-fn main() {}
+use clap::Parser;
+use futures::{FutureExt, TryFutureExt};
+use rapr_apis::configuration::v1alpha1::ConfigurationSpec;
+use rapr_contributes::component::Metadata;
+use rapr_contributes::nameresolution::{DnsResolver, NameResolver};
+use rapr_runtime::api::grpc::GrpcServer;
+use rapr_runtime::api::http::HttpServer;
+use rapr_runtime::api::{Api, ApiRules, ServerError, ServerOptions, socket_path};
+use rapr_runtime::ca::{CaClient, CertificateAuthority};
+use rapr_runtime::channel::{poll_until_ready, wait_until_ready};
+use rapr_runtime::cli::{Cli, RuntimeConfig};
+use rapr_runtime::components::{self, Components};
+use rapr_runtime::diagnostics::{Metrics, Tracer};
+use rapr_runtime::health::{COMPONENT_PING_FEATURE, DEFAULT_PING_INTERVAL, Health};
+use rapr_runtime::invocation::grpc::InvocationService;
+use rapr_runtime::invocation::{AccessControl, DEFAULT_TRUST_DOMAIN, DirectMessaging};
+use rapr_runtime::logging::{self, LogFields};
+use rapr_runtime::meta::Meta;
+use rapr_runtime::security::{CredentialFiles, Credentials, SpiffeId, incoming};
+use rapr_runtime::shutdown::{Shutdown, Stage, StepError};
+use rapr_runtime::workflow::{Backend, FileBackend, InMemoryBackend, WorkflowEngine};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tonic::transport::Server;
+use tonic::transport::server::TcpIncoming;
+
+/// Interval between two probes of the health of the app while waiting for it.
+const APP_READY_INTERVAL: Duration = Duration::from_millis(500);
+/// Time the app is given to become ready.
+const APP_READY_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval between two checks of the credentials files for changes.
+const CREDENTIALS_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// Validity of the certificates issued by the self-signed CA.
+const SELF_SIGNED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let config = match Cli::parse().resolve() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let instance = match config.pod_name.as_str() {
+        "" => std::env::var("HOSTNAME").unwrap_or_default(),
+        pod_name => pod_name.to_string(),
+    };
+    let fields = LogFields {
+        app_id: config.app_id.clone(),
+        namespace: config.namespace.clone(),
+        instance,
+    };
+    logging::init(config.log_format, config.log_levels.clone(), fields);
+
+    match run(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!(error = %e, "Runtime failed");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(config: RuntimeConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let configuration = match &config.config {
+        Some(source) => source.load().await?.spec,
+        None => ConfigurationSpec::default(),
+    };
+    let meta = Meta::new(config.meta_options(&configuration));
+    let tracer = configuration
+        .tracing
+        .as_ref()
+        .map(|spec| Tracer::from_spec(spec, &meta.id))
+        .transpose()?;
+    let metrics = Metrics::from_spec(&configuration.metrics.clone().unwrap_or_default());
+    let mut health = Health::new();
+    if configuration.is_feature_enabled(COMPONENT_PING_FEATURE) {
+        health = health.with_ping_interval(DEFAULT_PING_INTERVAL);
+    }
+    let access_control = configuration
+        .access_control
+        .as_ref()
+        .map(AccessControl::from_spec)
+        .transpose()?
+        .map(Arc::new);
+    let credentials = match config.enable_mtls {
+        true => Some(credentials(&config, &configuration, &meta).await?),
+        false => None,
+    };
+    let resources = components::load_dirs(&config.resources_paths, &meta.id)?;
+    let components = Components::init(&meta, &resources, &health)?;
+    for (name, pinger) in components.pingers {
+        health.watch_component(&name, pinger);
+    }
+    let mut graceful = Shutdown::new(config.shutdown_grace_period);
+    for (name, closer) in components.closers {
+        graceful.add_step(Stage::Components, name, async move { closer.close().await });
+    }
+
+    let options = |port: u16, protocol| ServerOptions {
+        unix_socket: config
+            .uds_dir
+            .as_ref()
+            .map(|dir| socket_path(dir, &meta.id, protocol)),
+        ..ServerOptions::localhost(port)
+    };
+    let grpc = GrpcServer::bind(&options(config.grpc_port, "grpc")).await?;
+    let http = HttpServer::bind(&options(config.http_port, "http")).await?;
+    tracing::info!(
+        app_id = %meta.id,
+        grpc = %grpc.local_addr()?,
+        http = %http.local_addr()?,
+        "Runtime listening"
+    );
+
+    // Prometheus scrapes the sidecar from outside the pod.
+    let mut metrics_listener = None;
+    if let Some(metrics) = metrics.clone() {
+        let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.metrics_port));
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|source| ServerError::Bind { address, source })?;
+        tracing::info!(address = %listener.local_addr()?, "Metrics endpoint listening");
+        metrics_listener = Some((metrics, listener));
+    }
+
+    let app_channel = config
+        .app_channel
+        .as_ref()
+        .map(|channel| channel.build())
+        .transpose()?;
+
+    // Other sidecars can only invoke apps which listen.
+    let mut internal = None;
+    if let Some(channel) = app_channel.clone() {
+        let mut service = InvocationService::new(channel.clone());
+        if let Some(access_control) = access_control {
+            service = service.with_access_control(access_control);
+        }
+        if let Some(tracer) = tracer.clone() {
+            service = service.with_tracer(tracer);
+        }
+        if let Some(metrics) = metrics.clone() {
+            service = service.with_metrics(metrics);
+        }
+        let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.internal_grpc_port));
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|source| ServerError::Bind { address, source })?;
+        tracing::info!(address = %listener.local_addr()?, mtls = config.enable_mtls, "Internal gRPC server listening");
+        internal = Some((service, listener));
+
+        health.require_app_channel();
+        let health = health.clone();
+        tokio::spawn(async move {
+            let channel = channel.as_ref();
+            if let Err(e) = wait_until_ready(channel, APP_READY_INTERVAL, APP_READY_TIMEOUT).await {
+                // Slow apps are still served once they start.
+                tracing::warn!(error = %e, "App is not ready, still waiting for it");
+                poll_until_ready(channel, APP_READY_INTERVAL).await;
+            }
+            tracing::info!("App is ready");
+            health.set_app_channel_ready(true);
+        });
+    }
+
+    // Apps are resolved by the name resolution component or else, like in Kubernetes, by
+    // the DNS server of the host.
+    let name_resolver = match components.name_resolver {
+        Some(resolver) => Some(resolver),
+        None => match DnsResolver::from_metadata(&Metadata::new()) {
+            Ok(resolver) => Some(Arc::new(resolver) as Arc<dyn NameResolver>),
+            Err(e) => {
+                tracing::warn!(error = %e, "No name resolver: service invocation is disabled");
+                None
+            }
+        },
+    };
+    let messaging = name_resolver.map(|resolver| {
+        let mut messaging = DirectMessaging::new(&meta, resolver, config.internal_grpc_port)
+            .with_resiliency(components.resiliency.clone());
+        if let Some(channel) = app_channel {
+            messaging = messaging.with_local_handler(channel);
+        }
+        if let Some(credentials) = credentials.clone() {
+            messaging = messaging.with_credentials(credentials);
+        }
+        if let Some(tracer) = tracer.clone() {
+            messaging = messaging.with_tracer(tracer);
+        }
+        if let Some(metrics) = metrics.clone() {
+            messaging = messaging.with_metrics(metrics);
+        }
+        Arc::new(messaging)
+    });
+    let mut crypto = components.crypto;
+    if let Some(metrics) = metrics.clone() {
+        crypto = crypto.with_metrics(metrics);
+    }
+
+    // The binary registers no workflow: it serves the management of the stored instances.
+    let backend: Arc<dyn Backend> = match &config.workflow_dir {
+        Some(dir) => Arc::new(FileBackend::new(dir)?),
+        None => Arc::new(InMemoryBackend::new()),
+    };
+    let workflows = WorkflowEngine::new(backend);
+
+    let mut api = Api::new(meta)
+        .with_health(health)
+        .with_crypto(Arc::new(crypto))
+        .with_workflow_engine(workflows);
+    if let Some(messaging) = messaging {
+        api = api.with_direct_messaging(messaging);
+    }
+    for component in components.metadata {
+        api.add_component(component);
+    }
+    if let Some(token) = config.api_token.clone() {
+        api = api.with_api_token(token);
+    }
+    if let Some(spec) = &configuration.api {
+        api = api.with_api_rules(ApiRules::from_spec(spec)?);
+    }
+    if let Some(tracer) = tracer.clone() {
+        api = api.with_tracer(tracer);
+    }
+    if let Some(metrics) = metrics {
+        api = api.with_metrics(metrics);
+    }
+    let shutdown = shutdown_signal().shared();
+    let serve_metrics = {
+        let shutdown = shutdown.clone();
+        async move {
+            let Some((metrics, listener)) = metrics_listener else {
+                return Ok(());
+            };
+            axum::serve(listener, metrics.router())
+                .with_graceful_shutdown(shutdown)
+                .await
+                .map_err(|source| ServerError::Http { source })
+        }
+    };
+    let serve_internal = {
+        let shutdown = shutdown.clone();
+        async move {
+            let Some((service, listener)) = internal else {
+                return Ok(());
+            };
+            let router = Server::builder().add_service(service.into_server());
+            match credentials {
+                Some(credentials) => {
+                    router
+                        .serve_with_incoming_shutdown(incoming(listener, credentials), shutdown)
+                        .await
+                }
+                None => {
+                    router
+                        .serve_with_incoming_shutdown(TcpIncoming::from(listener), shutdown)
+                        .await
+                }
+            }
+            .map_err(|source| ServerError::Grpc { source })
+        }
+    };
+    // Servers stop accepting calls once the runtime is asked to stop, then drain the calls
+    // in flight.
+    let mut serving = Box::pin(futures::future::try_join4(
+        grpc.serve(api.clone(), shutdown.clone()),
+        http.serve(api, shutdown.clone()),
+        serve_internal,
+        serve_metrics,
+    ));
+    // A failing server stops the runtime too, but only once the other components are shut
+    // down.
+    let mut failure = None;
+    tokio::select! {
+        result = &mut serving => {
+            failure = result.err();
+        }
+        () = shutdown => {
+            let drain = serving.map_ok(|_| ()).map_err(StepError::from);
+            graceful.add_step(Stage::Drain, "API servers", drain);
+        }
+    }
+    let report = graceful.run().await;
+    if let Some(tracer) = tracer {
+        tracer.flush().await;
+    }
+    if let Some(error) = failure {
+        return Err(error.into());
+    }
+    if !report.is_complete() {
+        return Err("shutdown did not complete".into());
+    }
+    Ok(())
+}
+
+/// Returns the credentials of the sidecar: those of the credentials directory, reloaded when
+/// they change, or else ones issued by the certificate authority, renewed before they expire,
+/// or else ones issued by a self-signed CA, which no other sidecar trusts.
+async fn credentials(
+    config: &RuntimeConfig,
+    configuration: &ConfigurationSpec,
+    meta: &Meta,
+) -> Result<Arc<Credentials>, Box<dyn std::error::Error>> {
+    // Sidecars are only trusted with the identity of their app.
+    let trust_domain = configuration
+        .access_control
+        .as_ref()
+        .map(|access_control| access_control.trust_domain.as_str())
+        .filter(|trust_domain| !trust_domain.is_empty())
+        .unwrap_or(DEFAULT_TRUST_DOMAIN);
+    let identity = SpiffeId::from_meta(trust_domain, meta);
+
+    if let Some(dir) = &config.credentials_dir {
+        let files = CredentialFiles::in_dir(dir);
+        let credentials = Arc::new(Credentials::load(&files)?.with_identity(identity)?);
+        credentials.watch(files, CREDENTIALS_RELOAD_INTERVAL);
+        tracing::info!(identity = %credentials.identity(), "Loaded credentials");
+        return Ok(credentials);
+    }
+
+    if let Some(ca) = &config.ca {
+        let trust_bundle = std::fs::read_to_string(&ca.trust_bundle)?;
+        let client = CaClient::new(
+            &ca.address,
+            &trust_bundle,
+            &meta.namespace,
+            &meta.id,
+            &ca.token,
+        )?;
+        let (credentials, expires_at) = client.credentials().await?;
+        let credentials = Arc::new(credentials.with_identity(identity)?);
+        client.keep_renewed(credentials.clone(), expires_at);
+        tracing::info!(identity = %credentials.identity(), "Requested credentials");
+        return Ok(credentials);
+    }
+
+    tracing::warn!("No credentials directory: issuing credentials from a self-signed CA");
+    let ca = CertificateAuthority::generate(trust_domain)?;
+    let issued = ca.issue(&meta.namespace, &meta.id, SELF_SIGNED_TTL)?;
+    Ok(Arc::new(Credentials::from_pem(
+        &issued.cert_chain_pem,
+        &issued.private_key_pem,
+        ca.trust_bundle_pem(),
+    )?))
+}
+
+/// Completes on Ctrl+C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "Failed to listen for Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received Ctrl+C, shutting down"),
+        _ = terminate => tracing::info!("Received SIGTERM, shutting down"),
+    }
+}
@@ -2,8 +2,7 @@ use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta as Metav1Object;
 use kube::Resource as ClientObject;
 use rapr_apis::{common::NameValuePair, components::v1alpha1::Component};
 use rapr_common::RaprMode;
-use serde::{Deserialize, Serialize};
-use snafu::{ResultExt, Snafu};
+use snafu::Snafu;
 use std::collections::HashMap;
 use uuid::Uuid;
 
@@ -42,10 +41,10 @@ impl MetaBase {
 
         for name in names {
             let lowercase_name = name.to_lowercase();
-            if let Some(map_key) = mdkeys.get(&lowercase_name) {
-                if let Some(value) = self.properties.get(map_key) {
-                    return Some(value.clone());
-                }
+            if let Some(map_key) = mdkeys.get(&lowercase_name)
+                && let Some(value) = self.properties.get(map_key)
+            {
+                return Some(value.clone());
             }
         }
 
@@ -151,7 +150,7 @@ impl Meta {
         let cmpt_type = comp
             .spec
             .as_ref()
-            .and_then(|s| Some(s.cmpt_type.as_str()))
+            .map(|s| s.cmpt_type.as_str())
             .unwrap_or_default();
 
         if is_wasm_component_type(cmpt_type) {
@@ -161,7 +160,7 @@ impl Meta {
         let items = comp
             .spec
             .as_ref()
-            .and_then(|s| Some(s.metadata.as_slice()))
+            .map(|s| s.metadata.as_slice())
             .unwrap_or_default();
 
         let props = self.convert_items_to_props(items)?;
@@ -176,11 +175,7 @@ impl Meta {
         let mut properties = HashMap::new();
 
         for item in items {
-            let mut val = item
-                .value
-                .as_ref()
-                .and_then(|v| Some(v.to_str()))
-                .unwrap_or_default();
+            let mut val = item.value.as_ref().map(|v| v.to_str()).unwrap_or_default();
 
             // Replace {uuid} placeholders
             while val.contains("{uuid}") {
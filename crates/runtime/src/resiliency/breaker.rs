@@ -0,0 +1,275 @@
+use super::{PolicyError, TripExpression};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Counts are the outcomes of the requests a circuit breaker let through, since it last
+/// changed state or cleared its counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub requests: u32,
+    pub total_successes: u32,
+    pub total_failures: u32,
+    pub consecutive_successes: u32,
+    pub consecutive_failures: u32,
+}
+
+impl Counts {
+    fn record(&mut self, success: bool) {
+        if success {
+            self.total_successes += 1;
+            self.consecutive_successes += 1;
+            self.consecutive_failures = 0;
+        } else {
+            self.total_failures += 1;
+            self.consecutive_failures += 1;
+            self.consecutive_successes = 0;
+        }
+    }
+}
+
+/// State of a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Requests go through.
+    Closed,
+    /// Requests are rejected until the breaker timeout elapses.
+    Open,
+    /// A limited number of requests go through to probe the target.
+    HalfOpen,
+}
+
+/// CircuitBreaker stops calling a failing target for a while.
+///
+/// A closed breaker opens when its trip expression holds. An open breaker goes half-open
+/// after its timeout. A half-open breaker closes once `max_requests` requests succeeded,
+/// and opens again on the first failure.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    max_requests: u32,
+    /// Period after which the counts of a closed breaker are cleared, if any.
+    interval: Option<Duration>,
+    timeout: Duration,
+    trip: TripExpression,
+    state: Mutex<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    /// Incremented on every state change or clear, so that the outcomes of requests let
+    /// through before are ignored.
+    generation: u64,
+    counts: Counts,
+    /// When the counts of a closed breaker are cleared, or when an open breaker goes
+    /// half-open.
+    expiry: Option<Instant>,
+}
+
+/// Ticket of a request let through by a circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ticket(u64);
+
+impl CircuitBreaker {
+    /// Creates a closed breaker.
+    pub fn new(
+        name: impl Into<String>,
+        max_requests: u32,
+        interval: Option<Duration>,
+        timeout: Duration,
+        trip: TripExpression,
+    ) -> Self {
+        let breaker = Self {
+            name: name.into(),
+            max_requests: max_requests.max(1),
+            interval: interval.filter(|interval| !interval.is_zero()),
+            timeout,
+            trip,
+            state: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                generation: 0,
+                counts: Counts::default(),
+                expiry: None,
+            }),
+        };
+        breaker.lock().expiry = breaker.interval.map(|interval| Instant::now() + interval);
+        breaker
+    }
+
+    /// Returns the name of the policy the breaker was created from.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the current state of the breaker.
+    pub fn state(&self) -> BreakerState {
+        let mut inner = self.lock();
+        self.refresh(&mut inner, Instant::now());
+        inner.state
+    }
+
+    /// Returns the counts of the current state.
+    pub fn counts(&self) -> Counts {
+        let mut inner = self.lock();
+        self.refresh(&mut inner, Instant::now());
+        inner.counts
+    }
+
+    /// Lets a request through, unless the breaker is open or half-open with too many
+    /// requests already. The outcome of the request must be reported with [`Self::record`].
+    pub fn acquire(&self) -> Result<Ticket, PolicyError> {
+        let mut inner = self.lock();
+        self.refresh(&mut inner, Instant::now());
+        match inner.state {
+            BreakerState::Open => {
+                return Err(PolicyError::CircuitOpen {
+                    name: self.name.clone(),
+                });
+            }
+            BreakerState::HalfOpen if inner.counts.requests >= self.max_requests => {
+                return Err(PolicyError::TooManyRequests {
+                    name: self.name.clone(),
+                });
+            }
+            _ => {}
+        }
+        inner.counts.requests += 1;
+        Ok(Ticket(inner.generation))
+    }
+
+    /// Records the outcome of a request let through.
+    pub fn record(&self, ticket: Ticket, success: bool) {
+        let now = Instant::now();
+        let mut inner = self.lock();
+        self.refresh(&mut inner, now);
+        if ticket.0 != inner.generation {
+            return;
+        }
+        inner.counts.record(success);
+        match (inner.state, success) {
+            (BreakerState::Closed, false) if self.trip.evaluate(&inner.counts) => {
+                self.set_state(&mut inner, BreakerState::Open, now)
+            }
+            (BreakerState::HalfOpen, false) => self.set_state(&mut inner, BreakerState::Open, now),
+            (BreakerState::HalfOpen, true)
+                if inner.counts.consecutive_successes >= self.max_requests =>
+            {
+                self.set_state(&mut inner, BreakerState::Closed, now)
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies the transitions due to time.
+    fn refresh(&self, inner: &mut Inner, now: Instant) {
+        let Some(expiry) = inner.expiry.filter(|expiry| *expiry <= now) else {
+            return;
+        };
+        match inner.state {
+            BreakerState::Closed => {
+                inner.generation += 1;
+                inner.counts = Counts::default();
+                inner.expiry = self.interval.map(|interval| expiry.max(now) + interval);
+            }
+            BreakerState::Open => self.set_state(inner, BreakerState::HalfOpen, now),
+            BreakerState::HalfOpen => {}
+        }
+    }
+
+    fn set_state(&self, inner: &mut Inner, state: BreakerState, now: Instant) {
+        if state == BreakerState::Open {
+            tracing::warn!(breaker = %self.name, "Circuit breaker opened");
+        }
+        inner.state = state;
+        inner.generation += 1;
+        inner.counts = Counts::default();
+        inner.expiry = match state {
+            BreakerState::Closed => self.interval.map(|interval| now + interval),
+            BreakerState::Open => Some(now + self.timeout),
+            BreakerState::HalfOpen => None,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(max_requests: u32) -> CircuitBreaker {
+        CircuitBreaker::new(
+            "cb",
+            max_requests,
+            None,
+            Duration::from_secs(30),
+            TripExpression::parse("consecutiveFailures >= 2").unwrap(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_open_half_open_close() {
+        let breaker = breaker(2);
+        for success in [false, true, false, false] {
+            let ticket = breaker.acquire().unwrap();
+            breaker.record(ticket, success);
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(matches!(
+            breaker.acquire(),
+            Err(PolicyError::CircuitOpen { .. })
+        ));
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        let first = breaker.acquire().unwrap();
+        let second = breaker.acquire().unwrap();
+        assert!(matches!(
+            breaker.acquire(),
+            Err(PolicyError::TooManyRequests { .. })
+        ));
+        breaker.record(first, true);
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        breaker.record(second, true);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_half_open_failure_reopens() {
+        let breaker = breaker(1);
+        let stale = breaker.acquire().unwrap();
+        for _ in 0..2 {
+            let ticket = breaker.acquire().unwrap();
+            breaker.record(ticket, false);
+        }
+        tokio::time::advance(Duration::from_secs(30)).await;
+        // Outcomes of requests from a previous state are ignored.
+        breaker.record(stale, true);
+        assert_eq!(breaker.counts(), Counts::default());
+
+        let ticket = breaker.acquire().unwrap();
+        breaker.record(ticket, false);
+        assert_eq!(breaker.state(), BreakerState::Open);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interval_clears_counts() {
+        let breaker = CircuitBreaker::new(
+            "cb",
+            1,
+            Some(Duration::from_secs(10)),
+            Duration::from_secs(30),
+            TripExpression::parse("totalFailures >= 2").unwrap(),
+        );
+        let ticket = breaker.acquire().unwrap();
+        breaker.record(ticket, false);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let ticket = breaker.acquire().unwrap();
+        breaker.record(ticket, false);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.counts().total_failures, 1);
+    }
+}
@@ -0,0 +1,287 @@
+//! Trip expressions of circuit breakers, such as `consecutiveFailures > 5` or
+//! `requests >= 10 && totalFailures / requests > 0.5`.
+//!
+//! Expressions combine the [`Counts`] of a breaker with numbers, arithmetic (`+ - * /`),
+//! comparisons (`== != < <= > >=`), `!`, `&&`, `||` and parentheses. Booleans are
+//! numbers, false being zero, and dividing by zero yields zero.
+
+use super::Counts;
+
+/// TripExpression is a parsed trip expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripExpression(Expr);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Variable(Variable),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    Requests,
+    TotalSuccesses,
+    TotalFailures,
+    ConsecutiveSuccesses,
+    ConsecutiveFailures,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl TripExpression {
+    /// Parses an expression.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(Self(expr)),
+            Some(token) => Err(format!("unexpected {token:?}")),
+        }
+    }
+
+    /// Returns true if the counts trip the breaker.
+    pub fn evaluate(&self, counts: &Counts) -> bool {
+        eval(&self.0, counts) != 0.0
+    }
+}
+
+fn eval(expr: &Expr, counts: &Counts) -> f64 {
+    let bool = |b: bool| if b { 1.0 } else { 0.0 };
+    match expr {
+        Expr::Number(n) => *n,
+        Expr::Variable(variable) => f64::from(match variable {
+            Variable::Requests => counts.requests,
+            Variable::TotalSuccesses => counts.total_successes,
+            Variable::TotalFailures => counts.total_failures,
+            Variable::ConsecutiveSuccesses => counts.consecutive_successes,
+            Variable::ConsecutiveFailures => counts.consecutive_failures,
+        }),
+        Expr::Not(expr) => bool(eval(expr, counts) == 0.0),
+        Expr::Binary(left, Op::Or, right) => {
+            bool(eval(left, counts) != 0.0 || eval(right, counts) != 0.0)
+        }
+        Expr::Binary(left, Op::And, right) => {
+            bool(eval(left, counts) != 0.0 && eval(right, counts) != 0.0)
+        }
+        Expr::Binary(left, op, right) => {
+            let (l, r) = (eval(left, counts), eval(right, counts));
+            match op {
+                Op::Eq => bool(l == r),
+                Op::Ne => bool(l != r),
+                Op::Lt => bool(l < r),
+                Op::Le => bool(l <= r),
+                Op::Gt => bool(l > r),
+                Op::Ge => bool(l >= r),
+                Op::Add => l + r,
+                Op::Sub => l - r,
+                Op::Mul => l * r,
+                Op::Div if r == 0.0 => 0.0,
+                Op::Div => l / r,
+                Op::Or | Op::And => unreachable!("handled above"),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 16] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "!", "(", ")", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap_or_default();
+        let len = if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..len]
+                .parse()
+                .map_err(|_| format!("invalid number {:?}", &rest[..len]))?;
+            tokens.push(Token::Number(number));
+            len
+        } else if c.is_ascii_alphabetic() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            len
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .filter(|symbol| **symbol != "=")
+                .ok_or_else(|| format!("unexpected character {c:?}"))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn eat(&mut self, symbols: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) if symbols.contains(symbol) => {
+                self.position += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        symbols: &[&'static str],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut expr = operand(self)?;
+        while let Some(symbol) = self.eat(symbols) {
+            let op = match symbol {
+                "||" => Op::Or,
+                "&&" => Op::And,
+                "==" => Op::Eq,
+                "!=" => Op::Ne,
+                "<" => Op::Lt,
+                "<=" => Op::Le,
+                ">" => Op::Gt,
+                ">=" => Op::Ge,
+                "+" => Op::Add,
+                "-" => Op::Sub,
+                "*" => Op::Mul,
+                _ => Op::Div,
+            };
+            expr = Expr::Binary(Box::new(expr), op, Box::new(operand(self)?));
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&["&&"], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(&["==", "!=", "<", "<=", ">", ">="], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(&["*", "/"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat(&["!"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat(&["("]).is_some() {
+            let expr = self.or()?;
+            return match self.eat(&[")"]) {
+                Some(_) => Ok(expr),
+                None => Err("missing closing parenthesis".to_string()),
+            };
+        }
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(ident)) => Ok(Expr::Variable(match ident.as_str() {
+                "requests" => Variable::Requests,
+                "totalSuccesses" => Variable::TotalSuccesses,
+                "totalFailures" => Variable::TotalFailures,
+                "consecutiveSuccesses" => Variable::ConsecutiveSuccesses,
+                "consecutiveFailures" => Variable::ConsecutiveFailures,
+                _ => return Err(format!("unknown variable {ident:?}")),
+            })),
+            Some(token) => Err(format!("unexpected {token:?}")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(requests: u32, failures: u32, consecutive_failures: u32) -> Counts {
+        Counts {
+            requests,
+            total_successes: requests - failures,
+            total_failures: failures,
+            consecutive_successes: 0,
+            consecutive_failures,
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let trip = TripExpression::parse("consecutiveFailures > 5").unwrap();
+        assert!(!trip.evaluate(&counts(10, 5, 5)));
+        assert!(trip.evaluate(&counts(10, 6, 6)));
+
+        let trip =
+            TripExpression::parse("requests >= 10 && totalFailures / requests > 0.5").unwrap();
+        assert!(!trip.evaluate(&counts(4, 4, 4)));
+        assert!(!trip.evaluate(&counts(10, 5, 1)));
+        assert!(trip.evaluate(&counts(10, 6, 1)));
+
+        let trip = TripExpression::parse("!(requests == 0) || 2 * (1 + 1) != 4").unwrap();
+        assert!(!trip.evaluate(&counts(0, 0, 0)));
+        assert!(trip.evaluate(&counts(1, 0, 0)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for source in [
+            "",
+            "failures > 5",
+            "consecutiveFailures = 5",
+            "(requests > 1",
+            "requests > 1)",
+            "requests >",
+            "1..2",
+            "requests # 2",
+        ] {
+            assert!(TripExpression::parse(source).is_err(), "{source}");
+        }
+    }
+}
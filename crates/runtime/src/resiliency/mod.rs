@@ -0,0 +1,359 @@
+//! Resiliency policies.
+//!
+//! [`Resiliency`] resources define named timeout, retry and circuit breaker policies and
+//! the targets they apply to: apps called with service invocation, actor types and
+//! components. The runtime looks up the [`Policy`] of a target and applies it around the
+//! calls to the target, with [`Policy::execute`] or as a tower layer.
+
+mod breaker;
+mod expr;
+mod policy;
+
+pub use breaker::*;
+pub use expr::*;
+pub use policy::*;
+
+use rapr_apis::resiliency::v1alpha1::{self as api, PolicyNames};
+use rapr_common::utils::parse_duration;
+use snafu::Snafu;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_BREAKER_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_TRIP: &str = "consecutiveFailures > 5";
+
+/// Errors in resiliency resources.
+#[derive(Debug, Snafu)]
+pub enum ResiliencyError {
+    #[snafu(display(
+        "Invalid {} policy {} in resiliency {}: {}",
+        kind,
+        policy,
+        resource,
+        reason
+    ))]
+    InvalidPolicy {
+        resource: String,
+        kind: &'static str,
+        policy: String,
+        reason: String,
+    },
+
+    #[snafu(display(
+        "Target {} of resiliency {} refers to unknown {} policy {}",
+        target,
+        resource,
+        kind,
+        policy
+    ))]
+    UnknownPolicy {
+        resource: String,
+        target: String,
+        kind: &'static str,
+        policy: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, ResiliencyError>;
+
+/// Errors produced by policies around the calls to a target.
+#[derive(Debug, Clone, PartialEq, Eq, Snafu)]
+pub enum PolicyError {
+    #[snafu(display("Operation timed out after {:?}", timeout))]
+    Timeout { timeout: Duration },
+
+    #[snafu(display("Circuit breaker {} is open", name))]
+    CircuitOpen { name: String },
+
+    #[snafu(display("Circuit breaker {} is half-open and probing the target", name))]
+    TooManyRequests { name: String },
+}
+
+/// Resiliency holds the policies of the targets of the resiliency resources of an app.
+#[derive(Debug, Default)]
+pub struct Resiliency {
+    apps: HashMap<String, Arc<Policy>>,
+    actors: HashMap<String, Arc<Policy>>,
+    components_outbound: HashMap<String, Arc<Policy>>,
+    components_inbound: HashMap<String, Arc<Policy>>,
+}
+
+impl Resiliency {
+    /// Builds the policies of the resources scoped to the app. Targets defined by several
+    /// resources get the policy of the last one.
+    pub fn from_resources(resources: &[api::Resiliency], app_id: &str) -> Result<Self> {
+        let mut resiliency = Self::default();
+        for resource in resources.iter().filter(|r| r.scoped.is_app_scoped(app_id)) {
+            let policies = Policies::parse(resource)?;
+            let targets = &resource.spec.targets;
+            for (app, names) in &targets.apps {
+                let policy = policies.build(&format!("app {app}"), names)?;
+                resiliency.apps.insert(app.clone(), policy);
+            }
+            for (actor_type, names) in &targets.actors {
+                let policy = policies.build(&format!("actor {actor_type}"), names)?;
+                resiliency.actors.insert(actor_type.clone(), policy);
+            }
+            for (component, directions) in &targets.components {
+                if let Some(names) = &directions.outbound {
+                    let policy = policies.build(&format!("component {component}"), names)?;
+                    resiliency
+                        .components_outbound
+                        .insert(component.clone(), policy);
+                }
+                if let Some(names) = &directions.inbound {
+                    let policy = policies.build(&format!("component {component}"), names)?;
+                    resiliency
+                        .components_inbound
+                        .insert(component.clone(), policy);
+                }
+            }
+        }
+        Ok(resiliency)
+    }
+
+    /// Returns the policy of service invocation to an app.
+    pub fn app_policy(&self, app_id: &str) -> Option<Arc<Policy>> {
+        self.apps.get(app_id).cloned()
+    }
+
+    /// Returns the policy of invocations of an actor type.
+    pub fn actor_policy(&self, actor_type: &str) -> Option<Arc<Policy>> {
+        self.actors.get(actor_type).cloned()
+    }
+
+    /// Returns the policy of the calls the runtime makes to a component.
+    pub fn component_outbound_policy(&self, component: &str) -> Option<Arc<Policy>> {
+        self.components_outbound.get(component).cloned()
+    }
+
+    /// Returns the policy of the calls a component makes to the app.
+    pub fn component_inbound_policy(&self, component: &str) -> Option<Arc<Policy>> {
+        self.components_inbound.get(component).cloned()
+    }
+}
+
+/// Parsed policies of a resource.
+struct Policies<'a> {
+    resource: &'a str,
+    timeouts: HashMap<&'a str, Duration>,
+    retries: HashMap<&'a str, RetryPolicy>,
+    circuit_breakers: HashMap<&'a str, &'a api::CircuitBreaker>,
+    /// Trip expressions, parsed once.
+    trips: HashMap<&'a str, TripExpression>,
+}
+
+impl<'a> Policies<'a> {
+    fn parse(resource: &'a api::Resiliency) -> Result<Self> {
+        let name = resource.get_name();
+        let invalid = |kind, policy: &str, reason: String| ResiliencyError::InvalidPolicy {
+            resource: name.to_string(),
+            kind,
+            policy: policy.to_string(),
+            reason,
+        };
+        let duration = |value: &str, default| match value {
+            "" => Ok(default),
+            value => parse_duration(value),
+        };
+        let spec = &resource.spec.policies;
+
+        let mut timeouts = HashMap::new();
+        for (policy, timeout) in &spec.timeouts {
+            let timeout = parse_duration(timeout).map_err(|e| invalid("timeout", policy, e))?;
+            timeouts.insert(policy.as_str(), timeout);
+        }
+
+        let mut retries = HashMap::new();
+        for (policy, retry) in &spec.retries {
+            let backoff = match retry.policy.as_str() {
+                "" | "constant" => Backoff::Constant(
+                    duration(&retry.duration, DEFAULT_RETRY_INTERVAL)
+                        .map_err(|e| invalid("retry", policy, e))?,
+                ),
+                "exponential" => Backoff::Exponential {
+                    initial: RetryPolicy::INITIAL_INTERVAL,
+                    max: duration(&retry.max_interval, DEFAULT_MAX_RETRY_INTERVAL)
+                        .map_err(|e| invalid("retry", policy, e))?,
+                },
+                other => {
+                    return Err(invalid(
+                        "retry",
+                        policy,
+                        format!("unknown policy {other:?}, expected constant or exponential"),
+                    ));
+                }
+            };
+            let max_retries = match retry.max_retries {
+                None => RetryPolicy::DEFAULT_MAX_RETRIES,
+                Some(max) => u32::try_from(max).map_err(|_| {
+                    invalid(
+                        "retry",
+                        policy,
+                        format!("invalid maxRetries {max}, expected 0 to {}", u32::MAX),
+                    )
+                })?,
+            };
+            retries.insert(
+                policy.as_str(),
+                RetryPolicy {
+                    backoff,
+                    max_retries,
+                },
+            );
+        }
+
+        let mut circuit_breakers = HashMap::new();
+        let mut trips = HashMap::new();
+        for (policy, breaker) in &spec.circuit_breakers {
+            duration(&breaker.interval, Duration::ZERO)
+                .and_then(|_| duration(&breaker.timeout, DEFAULT_BREAKER_TIMEOUT))
+                .map_err(|e| invalid("circuit breaker", policy, e))?;
+            let trip = match breaker.trip.as_str() {
+                "" => DEFAULT_TRIP,
+                trip => trip,
+            };
+            let trip = TripExpression::parse(trip)
+                .map_err(|e| invalid("circuit breaker", policy, format!("invalid trip: {e}")))?;
+            circuit_breakers.insert(policy.as_str(), breaker);
+            trips.insert(policy.as_str(), trip);
+        }
+
+        Ok(Self {
+            resource: name,
+            timeouts,
+            retries,
+            circuit_breakers,
+            trips,
+        })
+    }
+
+    /// Builds the policy of a target. Every target gets its own circuit breaker.
+    fn build(&self, target: &str, names: &PolicyNames) -> Result<Arc<Policy>> {
+        let unknown = |kind, policy: &str| ResiliencyError::UnknownPolicy {
+            resource: self.resource.to_string(),
+            target: target.to_string(),
+            kind,
+            policy: policy.to_string(),
+        };
+        let mut policy = Policy::default();
+        if let Some(name) = &names.timeout {
+            let timeout = self.timeouts.get(name.as_str());
+            policy.timeout = Some(*timeout.ok_or_else(|| unknown("timeout", name))?);
+        }
+        if let Some(name) = &names.retry {
+            let retry = self.retries.get(name.as_str());
+            policy.retry = Some(*retry.ok_or_else(|| unknown("retry", name))?);
+        }
+        if let Some(name) = &names.circuit_breaker {
+            let breaker = self
+                .circuit_breakers
+                .get(name.as_str())
+                .ok_or_else(|| unknown("circuit breaker", name))?;
+            // Durations were validated while parsing.
+            let interval = parse_duration(&breaker.interval).ok();
+            let timeout = parse_duration(&breaker.timeout).unwrap_or(DEFAULT_BREAKER_TIMEOUT);
+            policy.circuit_breaker = Some(Arc::new(CircuitBreaker::new(
+                format!("{name} ({target})"),
+                breaker.max_requests.unwrap_or(1),
+                interval,
+                timeout,
+                self.trips[name.as_str()].clone(),
+            )));
+        }
+        Ok(Arc::new(policy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(spec: serde_json::Value) -> api::Resiliency {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "takulatech.rapr.io/v1alpha1",
+            "kind": "Resiliency",
+            "metadata": {"name": "default"},
+            "spec": spec,
+            "scopes": ["checkout"],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_resources() {
+        let resources = [resource(serde_json::json!({
+            "policies": {
+                "timeouts": {"fast": "500ms"},
+                "retries": {
+                    "three": {"duration": "1s", "maxRetries": 3},
+                    "backoff": {"policy": "exponential", "maxInterval": "10s"}
+                },
+                "circuitBreakers": {"cb": {"timeout": "30s", "trip": "consecutiveFailures > 2"}}
+            },
+            "targets": {
+                "apps": {"orders": {"timeout": "fast", "retry": "three", "circuitBreaker": "cb"}},
+                "actors": {"cart": {"retry": "backoff"}},
+                "components": {"keys": {"outbound": {"timeout": "fast"}}}
+            }
+        }))];
+        let resiliency = Resiliency::from_resources(&resources, "checkout").unwrap();
+
+        let orders = resiliency.app_policy("orders").unwrap();
+        assert_eq!(orders.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(
+            orders.retry,
+            Some(RetryPolicy {
+                backoff: Backoff::Constant(Duration::from_secs(1)),
+                max_retries: 3
+            })
+        );
+        assert_eq!(
+            orders.circuit_breaker.as_ref().unwrap().name(),
+            "cb (app orders)"
+        );
+        assert_eq!(
+            resiliency.actor_policy("cart").unwrap().retry,
+            Some(RetryPolicy {
+                backoff: Backoff::Exponential {
+                    initial: RetryPolicy::INITIAL_INTERVAL,
+                    max: Duration::from_secs(10)
+                },
+                max_retries: RetryPolicy::DEFAULT_MAX_RETRIES
+            })
+        );
+        assert!(resiliency.component_outbound_policy("keys").is_some());
+        assert!(resiliency.component_inbound_policy("keys").is_none());
+        assert!(resiliency.app_policy("billing").is_none());
+
+        // Resources not scoped to the app are ignored.
+        let other = Resiliency::from_resources(&resources, "billing").unwrap();
+        assert!(other.app_policy("orders").is_none());
+    }
+
+    #[test]
+    fn test_invalid_resources() {
+        let invalid = [
+            serde_json::json!({"policies": {"timeouts": {"t": "soon"}}}),
+            serde_json::json!({"policies": {"retries": {"r": {"policy": "linear"}}}}),
+            serde_json::json!({"policies": {"retries": {"r": {"maxRetries": -1}}}}),
+            serde_json::json!({"policies": {"circuitBreakers": {"cb": {"trip": "failures > 1"}}}}),
+        ];
+        for spec in invalid {
+            assert!(matches!(
+                Resiliency::from_resources(&[resource(spec)], "checkout"),
+                Err(ResiliencyError::InvalidPolicy { .. })
+            ));
+        }
+        let unknown = resource(serde_json::json!({
+            "targets": {"apps": {"orders": {"retry": "missing"}}}
+        }));
+        assert!(matches!(
+            Resiliency::from_resources(&[unknown], "checkout"),
+            Err(ResiliencyError::UnknownPolicy { .. })
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+use super::{CircuitBreaker, PolicyError};
+use futures::future::BoxFuture;
+use rapr_contributes::nameresolution::NameResolutionError;
+use std::error::Error;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+/// Backoff of a retry policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Waits the same interval before every retry.
+    Constant(Duration),
+    /// Waits `initial * 1.5^n` before the n-th retry, up to `max`.
+    Exponential { initial: Duration, max: Duration },
+}
+
+/// RetryPolicy retries operations which failed with a transient error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub backoff: Backoff,
+    /// Number of retries after the first attempt.
+    pub max_retries: u32,
+}
+
+impl RetryPolicy {
+    /// First interval of exponential backoffs.
+    pub const INITIAL_INTERVAL: Duration = Duration::from_millis(500);
+    /// Number of retries of policies which do not set one.
+    pub const DEFAULT_MAX_RETRIES: u32 = 3;
+    const MULTIPLIER: f64 = 1.5;
+
+    /// Returns the interval to wait before the given retry, counted from zero, or `None`
+    /// when the policy gives up.
+    pub fn interval(&self, retry: u32) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        Some(match self.backoff {
+            Backoff::Constant(interval) => interval,
+            Backoff::Exponential { initial, max } => {
+                let factor = Self::MULTIPLIER.powi(retry.min(i32::MAX as u32) as i32);
+                initial.mul_f64(factor.min(u32::MAX as f64)).min(max)
+            }
+        })
+    }
+}
+
+/// Returns true if an operation which failed with `error` may succeed when retried: it
+/// timed out, its target is unavailable or could not be reached. The first error of the
+/// chain of sources with a known type decides; errors of unknown types are not transient.
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        next = error.source();
+        if error.is::<PolicyError>() || error.is::<tonic::transport::Error>() {
+            return true;
+        }
+        if let Some(status) = error.downcast_ref::<tonic::Status>() {
+            return matches!(
+                status.code(),
+                tonic::Code::Unavailable | tonic::Code::DeadlineExceeded
+            );
+        }
+        if let Some(error) = error.downcast_ref::<NameResolutionError>() {
+            // No instance of the app may be registered yet.
+            return matches!(
+                error,
+                NameResolutionError::NotFound { .. } | NameResolutionError::Lookup { .. }
+            );
+        }
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return matches!(
+                error.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+            );
+        }
+    }
+    false
+}
+
+/// Policy is the combination of policies applied to a target.
+///
+/// Every attempt is subject to the timeout and to the circuit breaker. Attempts which
+/// failed with a [transient](is_transient) error, including those rejected by an open
+/// breaker, are retried with the retry policy.
+#[derive(Debug, Default, Clone)]
+pub struct Policy {
+    pub timeout: Option<Duration>,
+    pub retry: Option<RetryPolicy>,
+    /// Shared by every call to the target.
+    pub circuit_breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl Policy {
+    /// Runs an operation under the policy. `operation` is called once per attempt.
+    pub async fn execute<T, E, F, Fut>(&self, mut operation: F) -> Result<T, BoxError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<BoxError>,
+    {
+        let mut retry = 0;
+        loop {
+            let error = match self.attempt(&mut operation).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let interval = match &self.retry {
+                Some(policy) if is_transient(&*error) => policy.interval(retry),
+                _ => None,
+            };
+            let Some(interval) = interval else {
+                return Err(error);
+            };
+            tracing::debug!(retry, %error, "Retrying failed operation");
+            tokio::time::sleep(interval).await;
+            retry += 1;
+        }
+    }
+
+    async fn attempt<T, E, F, Fut>(&self, operation: F) -> Result<T, BoxError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Into<BoxError>,
+    {
+        let ticket = match &self.circuit_breaker {
+            Some(breaker) => Some(breaker.acquire()?),
+            None => None,
+        };
+        let operation = operation();
+        let result = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, operation).await {
+                Ok(result) => result.map_err(Into::into),
+                Err(_) => Err(PolicyError::Timeout { timeout }.into()),
+            },
+            None => operation.await.map_err(Into::into),
+        };
+        if let (Some(breaker), Some(ticket)) = (&self.circuit_breaker, ticket) {
+            breaker.record(ticket, result.is_ok());
+        }
+        result
+    }
+
+    /// Returns a tower layer applying the policy to a service.
+    pub fn layer(self: &Arc<Self>) -> PolicyLayer {
+        PolicyLayer {
+            policy: self.clone(),
+        }
+    }
+}
+
+/// PolicyLayer applies a [`Policy`] to the requests of a service. Requests are cloned for
+/// every attempt.
+#[derive(Debug, Clone)]
+pub struct PolicyLayer {
+    policy: Arc<Policy>,
+}
+
+impl<S> Layer<S> for PolicyLayer {
+    type Service = PolicyService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        PolicyService {
+            policy: self.policy.clone(),
+            inner,
+        }
+    }
+}
+
+/// PolicyService is the service wrapped by a [`PolicyLayer`].
+#[derive(Debug, Clone)]
+pub struct PolicyService<S> {
+    policy: Arc<Policy>,
+    inner: S,
+}
+
+impl<S, Req> Service<Req> for PolicyService<S>
+where
+    S: Service<Req> + Clone + Send + 'static,
+    S::Response: Send,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    Req: Clone + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<S::Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // Every attempt waits for a clone of the inner service to be ready.
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Req) -> Self::Future {
+        let policy = self.policy.clone();
+        let inner = self.inner.clone();
+        Box::pin(async move {
+            policy
+                .execute(move || inner.clone().oneshot(request.clone()))
+                .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resiliency::TripExpression;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tower::ServiceBuilder;
+
+    #[test]
+    fn test_backoff() {
+        let constant = RetryPolicy {
+            backoff: Backoff::Constant(Duration::from_secs(5)),
+            max_retries: 2,
+        };
+        assert_eq!(constant.interval(1), Some(Duration::from_secs(5)));
+        assert_eq!(constant.interval(2), None);
+
+        let exponential = RetryPolicy {
+            backoff: Backoff::Exponential {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(3),
+            },
+            max_retries: u32::MAX,
+        };
+        let intervals: Vec<_> = (0..4).map(|r| exponential.interval(r).unwrap()).collect();
+        assert_eq!(
+            intervals,
+            [
+                Duration::from_secs(1),
+                Duration::from_millis(1500),
+                Duration::from_millis(2250),
+                Duration::from_secs(3)
+            ]
+        );
+        assert_eq!(
+            exponential.interval(u32::MAX - 1),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(exponential.interval(u32::MAX), None);
+    }
+
+    /// Fails the first `failures` calls, then echoes.
+    fn flaky(
+        failures: u32,
+    ) -> (
+        Arc<AtomicU32>,
+        impl Fn(u32) -> BoxFuture<'static, Result<u32, BoxError>> + Clone,
+    ) {
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = calls.clone();
+        let service = move |request: u32| -> BoxFuture<'static, Result<u32, BoxError>> {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async move {
+                if call < failures {
+                    Err(tonic::Status::unavailable("restarting").into())
+                } else {
+                    Ok(request)
+                }
+            })
+        };
+        (calls, service)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_layer_retries() {
+        let policy = Arc::new(Policy {
+            retry: Some(RetryPolicy {
+                backoff: Backoff::Constant(Duration::from_secs(1)),
+                max_retries: 3,
+            }),
+            ..Default::default()
+        });
+        let (calls, service) = flaky(2);
+        let service = ServiceBuilder::new()
+            .layer(policy.layer())
+            .service_fn(service);
+        assert_eq!(service.oneshot(7).await.unwrap(), 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let (calls, service) = flaky(10);
+        let service = ServiceBuilder::new()
+            .layer(policy.layer())
+            .service_fn(service);
+        assert!(service.oneshot(7).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // Errors which retrying would not fix are returned right away.
+        let calls = Arc::new(AtomicU32::new(0));
+        let error = policy
+            .execute(|| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err::<(), _>(tonic::Status::permission_denied("denied")) }
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<tonic::Status>().unwrap().code(),
+            tonic::Code::PermissionDenied
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let transient: [BoxError; 5] = [
+            tonic::Status::unavailable("restarting").into(),
+            tonic::Status::deadline_exceeded("slow").into(),
+            PolicyError::Timeout {
+                timeout: Duration::from_secs(1),
+            }
+            .into(),
+            io::Error::from(io::ErrorKind::ConnectionRefused).into(),
+            NameResolutionError::NotFound {
+                id: "orders".to_string(),
+            }
+            .into(),
+        ];
+        for error in transient {
+            assert!(is_transient(&*error), "{error}");
+        }
+        let permanent: [BoxError; 4] = [
+            tonic::Status::invalid_argument("bad").into(),
+            tonic::Status::permission_denied("denied").into(),
+            io::Error::from(io::ErrorKind::NotFound).into(),
+            "unknown".into(),
+        ];
+        for error in permanent {
+            assert!(!is_transient(&*error), "{error}");
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let policy = Policy {
+            timeout: Some(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let error = policy
+            .execute(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                Ok::<_, BoxError>(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PolicyError>(),
+            Some(PolicyError::Timeout { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker() {
+        let policy = Policy {
+            circuit_breaker: Some(Arc::new(CircuitBreaker::new(
+                "cb",
+                1,
+                None,
+                Duration::from_secs(60),
+                TripExpression::parse("consecutiveFailures >= 2").unwrap(),
+            ))),
+            ..Default::default()
+        };
+        let (calls, service) = flaky(u32::MAX);
+        for _ in 0..4 {
+            assert!(policy.execute(|| service(1)).await.is_err());
+        }
+        // The breaker opened after two failures.
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let error = policy.execute(|| service(1)).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PolicyError>(),
+            Some(PolicyError::CircuitOpen { .. })
+        ));
+    }
+}
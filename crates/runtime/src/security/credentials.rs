@@ -0,0 +1,544 @@
+use super::{
+    InvalidCredentialsSnafu, InvalidPemSnafu, ReadSnafu, Result, SecurityError, SpiffeId,
+    UnexpectedIdentitySnafu,
+};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{ResolvesClientCert, verify_server_cert_signed_by_trust_anchor};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ParsedCertificate, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, DistinguishedName, RootCertStore,
+    ServerConfig, SignatureScheme,
+};
+use snafu::ResultExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+
+/// ALPN protocol of gRPC.
+const ALPN_H2: &[u8] = b"h2";
+
+/// CredentialFiles are the PEM files holding the credentials of a sidecar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialFiles {
+    /// Certificate of the sidecar, followed by the intermediate certificates.
+    pub cert_chain: PathBuf,
+    pub private_key: PathBuf,
+    /// Certificates of the CAs trusted to issue the certificates of peers.
+    pub trust_bundle: PathBuf,
+}
+
+impl CredentialFiles {
+    /// Returns the `tls.crt`, `tls.key` and `ca.crt` files of a directory.
+    pub fn in_dir(dir: &Path) -> Self {
+        Self {
+            cert_chain: dir.join("tls.crt"),
+            private_key: dir.join("tls.key"),
+            trust_bundle: dir.join("ca.crt"),
+        }
+    }
+
+    fn paths(&self) -> [&Path; 3] {
+        [&self.cert_chain, &self.private_key, &self.trust_bundle]
+    }
+
+    fn read(&self) -> Result<[String; 3]> {
+        let read = |path: &Path| std::fs::read_to_string(path).context(ReadSnafu { path });
+        Ok([
+            read(&self.cert_chain)?,
+            read(&self.private_key)?,
+            read(&self.trust_bundle)?,
+        ])
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths()
+            .iter()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// Credentials are the certificate and trust bundle a sidecar authenticates itself and its
+/// peers with. They can be replaced while in use: new connections pick up the new ones.
+#[derive(Debug)]
+pub struct Credentials {
+    provider: Arc<CryptoProvider>,
+    state: RwLock<Arc<State>>,
+    /// Identity the credentials must hold, when set.
+    expected: Option<SpiffeId>,
+}
+
+#[derive(Debug)]
+struct State {
+    key: Arc<CertifiedKey>,
+    identity: SpiffeId,
+    roots: Arc<RootCertStore>,
+    client_verifier: Arc<dyn ClientCertVerifier>,
+}
+
+impl Credentials {
+    /// Creates credentials from a PEM certificate chain, private key and trust bundle.
+    pub fn from_pem(cert_chain: &str, private_key: &str, trust_bundle: &str) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let state = State::from_pem(&provider, cert_chain, private_key, trust_bundle)?;
+        Ok(Self {
+            provider,
+            state: RwLock::new(Arc::new(state)),
+            expected: None,
+        })
+    }
+
+    /// Requires the credentials, and those they are rotated to, to hold the identity of the
+    /// sidecar.
+    pub fn with_identity(mut self, expected: SpiffeId) -> Result<Self> {
+        check_identity(&self.state().identity, &expected)?;
+        self.expected = Some(expected);
+        Ok(self)
+    }
+
+    /// Reads credentials from files.
+    pub fn load(files: &CredentialFiles) -> Result<Self> {
+        let [cert_chain, private_key, trust_bundle] = files.read()?;
+        Self::from_pem(&cert_chain, &private_key, &trust_bundle)
+    }
+
+    /// Replaces the credentials. The current ones are kept if the new ones are invalid or
+    /// hold another identity.
+    pub fn rotate(&self, cert_chain: &str, private_key: &str, trust_bundle: &str) -> Result<()> {
+        let state = State::from_pem(&self.provider, cert_chain, private_key, trust_bundle)?;
+        if let Some(expected) = &self.expected {
+            check_identity(&state.identity, expected)?;
+        }
+        *self.state.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(state);
+        Ok(())
+    }
+
+    /// Replaces the credentials by those of files.
+    pub fn reload(&self, files: &CredentialFiles) -> Result<()> {
+        let [cert_chain, private_key, trust_bundle] = files.read()?;
+        self.rotate(&cert_chain, &private_key, &trust_bundle)
+    }
+
+    /// Reloads the credentials whenever their files change, checking every `interval`,
+    /// until the returned task is aborted.
+    pub fn watch(self: &Arc<Self>, files: CredentialFiles, interval: Duration) -> JoinHandle<()> {
+        let credentials = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut modified = files.modified();
+            let mut ticks = tokio::time::interval(interval);
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                let current = files.modified();
+                if current == modified {
+                    continue;
+                }
+                // Files written one after the other may not match yet: retry on the next
+                // tick until they do.
+                match credentials.reload(&files) {
+                    Ok(()) => {
+                        modified = current;
+                        tracing::info!(identity = %credentials.identity(), "Rotated credentials");
+                    }
+                    Err(e) => tracing::warn!(error = %e, "Failed to reload credentials"),
+                }
+            }
+        })
+    }
+
+    /// Returns the identity of the sidecar.
+    pub fn identity(&self) -> SpiffeId {
+        self.state().identity.clone()
+    }
+
+    /// Returns the configuration of servers requiring clients to present a certificate
+    /// issued by the trust bundle.
+    pub fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("the default provider supports the default protocol versions")
+            .with_client_cert_verifier(Arc::new(ClientVerifier(Arc::clone(self))))
+            .with_cert_resolver(Arc::new(Resolver(Arc::clone(self))));
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Arc::new(config)
+    }
+
+    /// Returns the configuration of clients accepting servers with a certificate issued by
+    /// the trust bundle, for the `expected` identity or, when `None`, any identity of the
+    /// trust domain of the sidecar.
+    pub fn client_config(self: &Arc<Self>, expected: Option<SpiffeId>) -> Arc<ClientConfig> {
+        let mut config = ClientConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()
+            .expect("the default provider supports the default protocol versions")
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(ServerVerifier {
+                credentials: Arc::clone(self),
+                expected,
+            }))
+            .with_client_cert_resolver(Arc::new(Resolver(Arc::clone(self))));
+        config.alpn_protocols = vec![ALPN_H2.to_vec()];
+        Arc::new(config)
+    }
+
+    fn state(&self) -> Arc<State> {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+impl State {
+    fn from_pem(
+        provider: &Arc<CryptoProvider>,
+        cert_chain: &str,
+        private_key: &str,
+        trust_bundle: &str,
+    ) -> Result<Self> {
+        let cert_chain = parse_certificates("certificate chain", cert_chain)?;
+        let private_key = PrivateKeyDer::from_pem_slice(private_key.as_bytes())
+            .map_err(|e| invalid_pem("private key", e))?;
+        let identity = SpiffeId::from_certificate(&cert_chain[0])?;
+        let key = CertifiedKey::from_der(cert_chain, private_key, provider)
+            .context(InvalidCredentialsSnafu)?;
+
+        let mut roots = RootCertStore::empty();
+        for certificate in parse_certificates("trust bundle", trust_bundle)? {
+            roots.add(certificate).context(InvalidCredentialsSnafu)?;
+        }
+        let roots = Arc::new(roots);
+        let client_verifier =
+            WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+                .build()
+                .map_err(|e| SecurityError::InvalidPem {
+                    name: "trust bundle".to_string(),
+                    reason: e.to_string(),
+                })?;
+        Ok(Self {
+            key: Arc::new(key),
+            identity,
+            roots,
+            client_verifier,
+        })
+    }
+}
+
+fn check_identity(identity: &SpiffeId, expected: &SpiffeId) -> Result<()> {
+    if identity != expected {
+        return UnexpectedIdentitySnafu {
+            identity: identity.to_string(),
+            expected: expected.to_string(),
+        }
+        .fail();
+    }
+    Ok(())
+}
+
+fn parse_certificates(name: &str, pem: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_slice_iter(pem.as_bytes())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| invalid_pem(name, e))?;
+    if certificates.is_empty() {
+        return InvalidPemSnafu {
+            name,
+            reason: "no certificate",
+        }
+        .fail();
+    }
+    Ok(certificates)
+}
+
+fn invalid_pem(name: &str, e: rustls::pki_types::pem::Error) -> SecurityError {
+    SecurityError::InvalidPem {
+        name: name.to_string(),
+        reason: e.to_string(),
+    }
+}
+
+/// Returns the identity of a peer certificate, as a TLS error.
+fn peer_identity(end_entity: &CertificateDer<'_>) -> std::result::Result<SpiffeId, rustls::Error> {
+    SpiffeId::from_certificate(end_entity).map_err(|e| {
+        rustls::Error::InvalidCertificate(CertificateError::Other(rustls::OtherError(Arc::new(e))))
+    })
+}
+
+/// Resolver presents the current certificate of the sidecar.
+#[derive(Debug)]
+struct Resolver(Arc<Credentials>);
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.state().key.clone())
+    }
+}
+
+impl ResolvesClientCert for Resolver {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.state().key.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// ClientVerifier accepts the clients with a SPIFFE ID issued by the current trust bundle.
+#[derive(Debug)]
+struct ClientVerifier(Arc<Credentials>);
+
+impl ClientCertVerifier for ClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        // The hints would borrow from credentials which may be rotated: send none.
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> std::result::Result<ClientCertVerified, rustls::Error> {
+        let verified =
+            self.0
+                .state()
+                .client_verifier
+                .verify_client_cert(end_entity, intermediates, now)?;
+        peer_identity(end_entity)?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0
+            .provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// ServerVerifier accepts the servers with the expected SPIFFE ID issued by the current
+/// trust bundle. Server names are not checked: sidecars are reached by address.
+#[derive(Debug)]
+struct ServerVerifier {
+    credentials: Arc<Credentials>,
+    expected: Option<SpiffeId>,
+}
+
+impl ServerCertVerifier for ServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let state = self.credentials.state();
+        let certificate = ParsedCertificate::try_from(end_entity)?;
+        verify_server_cert_signed_by_trust_anchor(
+            &certificate,
+            &state.roots,
+            intermediates,
+            now,
+            self.credentials
+                .provider
+                .signature_verification_algorithms
+                .all,
+        )?;
+        let identity = peer_identity(end_entity)?;
+        let accepted = match &self.expected {
+            Some(expected) => identity == *expected,
+            None => identity.trust_domain == state.identity.trust_domain,
+        };
+        if !accepted {
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.credentials.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.credentials.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.credentials
+            .provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::CertificateAuthority;
+
+    const TTL: Duration = Duration::from_secs(3600);
+
+    fn credentials(ca: &CertificateAuthority, app_id: &str) -> Credentials {
+        let issued = ca.issue("default", app_id, TTL).unwrap();
+        Credentials::from_pem(
+            &issued.cert_chain_pem,
+            &issued.private_key_pem,
+            ca.trust_bundle_pem(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_pem() {
+        let ca = CertificateAuthority::generate("cluster.local").unwrap();
+        assert_eq!(
+            credentials(&ca, "orders").identity(),
+            SpiffeId::new("cluster.local", "default", "orders")
+        );
+    }
+
+    #[test]
+    fn test_invalid_credentials() {
+        let ca = CertificateAuthority::generate("cluster.local").unwrap();
+        let orders = ca.issue("default", "orders", TTL).unwrap();
+        let billing = ca.issue("default", "billing", TTL).unwrap();
+        let bundle = ca.trust_bundle_pem();
+
+        assert_eq!(
+            Credentials::from_pem("", &orders.private_key_pem, bundle)
+                .unwrap_err()
+                .to_string(),
+            "Invalid certificate chain: no certificate"
+        );
+        assert!(matches!(
+            Credentials::from_pem(&orders.cert_chain_pem, "", bundle),
+            Err(SecurityError::InvalidPem { .. })
+        ));
+        assert!(matches!(
+            Credentials::from_pem(&orders.cert_chain_pem, &billing.private_key_pem, bundle),
+            Err(SecurityError::InvalidCredentials { .. })
+        ));
+        // The root of the CA names no app.
+        assert!(matches!(
+            Credentials::from_pem(bundle, &orders.private_key_pem, bundle),
+            Err(SecurityError::InvalidCertificate { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watch() {
+        let ca = CertificateAuthority::generate("cluster.local").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let files = CredentialFiles::in_dir(dir.path());
+        let write = |app_id: &str| {
+            let issued = ca.issue("default", app_id, TTL).unwrap();
+            std::fs::write(&files.cert_chain, issued.cert_chain_pem).unwrap();
+            std::fs::write(&files.private_key, issued.private_key_pem).unwrap();
+            std::fs::write(&files.trust_bundle, ca.trust_bundle_pem()).unwrap();
+        };
+        write("orders");
+        let credentials = Arc::new(Credentials::load(&files).unwrap());
+        let watch = credentials.watch(files.clone(), Duration::from_secs(1));
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+
+        // Invalid files are ignored until they are fixed.
+        std::fs::write(&files.private_key, "").unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(credentials.identity().app_id, "orders");
+
+        write("orders-v2");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(credentials.identity().app_id, "orders-v2");
+        watch.abort();
+    }
+
+    #[test]
+    fn test_with_identity() {
+        let ca = CertificateAuthority::generate("cluster.local").unwrap();
+        let orders = SpiffeId::new("cluster.local", "default", "orders");
+        assert_eq!(
+            credentials(&ca, "billing")
+                .with_identity(orders.clone())
+                .unwrap_err()
+                .to_string(),
+            "Credentials hold identity spiffe://cluster.local/ns/default/billing, expected \
+             spiffe://cluster.local/ns/default/orders"
+        );
+
+        // Rotations to another identity are rejected, keeping the current credentials.
+        let credentials = credentials(&ca, "orders")
+            .with_identity(orders.clone())
+            .unwrap();
+        let billing = ca.issue("default", "billing", TTL).unwrap();
+        assert!(matches!(
+            credentials.rotate(
+                &billing.cert_chain_pem,
+                &billing.private_key_pem,
+                ca.trust_bundle_pem()
+            ),
+            Err(SecurityError::UnexpectedIdentity { .. })
+        ));
+        assert_eq!(credentials.identity(), orders);
+        let renewed = ca.issue("default", "orders", TTL).unwrap();
+        credentials
+            .rotate(
+                &renewed.cert_chain_pem,
+                &renewed.private_key_pem,
+                ca.trust_bundle_pem(),
+            )
+            .unwrap();
+    }
+}
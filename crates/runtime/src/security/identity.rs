@@ -0,0 +1,117 @@
+use super::{InvalidSpiffeIdSnafu, Result, SecurityError};
+use crate::meta::Meta;
+use std::fmt;
+use std::str::FromStr;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const SCHEME: &str = "spiffe://";
+
+/// SpiffeId names the app of a sidecar: `spiffe://<trust-domain>/ns/<namespace>/<app-id>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpiffeId {
+    pub trust_domain: String,
+    pub namespace: String,
+    pub app_id: String,
+}
+
+impl SpiffeId {
+    pub fn new(
+        trust_domain: impl Into<String>,
+        namespace: impl Into<String>,
+        app_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            trust_domain: trust_domain.into(),
+            namespace: namespace.into(),
+            app_id: app_id.into(),
+        }
+    }
+
+    /// Returns the identity of the app of `meta` in a trust domain.
+    pub fn from_meta(trust_domain: impl Into<String>, meta: &Meta) -> Self {
+        Self::new(trust_domain, meta.namespace.clone(), meta.id.clone())
+    }
+
+    /// Reads the identity from the URI subject alternative names of a DER certificate.
+    pub fn from_certificate(der: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| SecurityError::InvalidCertificate {
+            reason: reason.to_string(),
+        };
+        let (_, certificate) =
+            X509Certificate::from_der(der).map_err(|e| invalid(&e.to_string()))?;
+        let names = certificate
+            .subject_alternative_name()
+            .map_err(|e| invalid(&e.to_string()))?
+            .ok_or_else(|| invalid("no subject alternative name"))?;
+        names
+            .value
+            .general_names
+            .iter()
+            .find_map(|name| match name {
+                GeneralName::URI(uri) if uri.starts_with(SCHEME) => Some(uri.parse()),
+                _ => None,
+            })
+            .unwrap_or_else(|| Err(invalid("no SPIFFE ID")))
+    }
+}
+
+impl fmt::Display for SpiffeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{SCHEME}{}/ns/{}/{}",
+            self.trust_domain, self.namespace, self.app_id
+        )
+    }
+}
+
+impl FromStr for SpiffeId {
+    type Err = SecurityError;
+
+    fn from_str(id: &str) -> Result<Self> {
+        let path = id.strip_prefix(SCHEME).ok_or_else(|| {
+            InvalidSpiffeIdSnafu {
+                id,
+                reason: format!("expected the {SCHEME} scheme"),
+            }
+            .build()
+        })?;
+        match path.split('/').collect::<Vec<_>>()[..] {
+            [trust_domain, "ns", namespace, app_id]
+                if !trust_domain.is_empty() && !namespace.is_empty() && !app_id.is_empty() =>
+            {
+                Ok(Self::new(trust_domain, namespace, app_id))
+            }
+            _ => InvalidSpiffeIdSnafu {
+                id,
+                reason: "expected spiffe://<trust-domain>/ns/<namespace>/<app-id>",
+            }
+            .fail(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let id: SpiffeId = "spiffe://cluster.local/ns/default/orders".parse().unwrap();
+        assert_eq!(id, SpiffeId::new("cluster.local", "default", "orders"));
+        assert_eq!(id.to_string(), "spiffe://cluster.local/ns/default/orders");
+
+        for id in [
+            "https://cluster.local/ns/default/orders",
+            "spiffe://cluster.local/default/orders",
+            "spiffe://cluster.local/ns//orders",
+            "spiffe://cluster.local/ns/default/orders/v1",
+        ] {
+            assert!(matches!(
+                id.parse::<SpiffeId>(),
+                Err(SecurityError::InvalidSpiffeId { .. })
+            ));
+        }
+    }
+}
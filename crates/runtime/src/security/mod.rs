@@ -0,0 +1,51 @@
+//! Authentication and encryption of the traffic between sidecars.
+//!
+//! Every sidecar holds a certificate naming its app with a [`SpiffeId`],
+//! `spiffe://<trust-domain>/ns/<namespace>/<app-id>`, issued by a CA of the trust bundle.
+//! Sidecars serve and call each other over mutual TLS, each verifying the certificate of the
+//! other against the bundle: servers learn the identity of their callers from
+//! [`PeerInfo`], and clients check that they reached the app they meant to.
+//!
+//! [`Credentials`] are read from PEM files, which [`Credentials::watch`] reloads when they
+//! change so certificates rotate without restart. They are issued by the
+//! [`CertificateAuthority`](crate::ca::CertificateAuthority).
+
+mod credentials;
+mod identity;
+mod transport;
+
+pub use credentials::*;
+pub use identity::*;
+pub use transport::*;
+
+use snafu::Snafu;
+use std::path::PathBuf;
+
+#[derive(Debug, Snafu)]
+pub enum SecurityError {
+    #[snafu(display("Invalid SPIFFE ID {:?}: {}", id, reason))]
+    InvalidSpiffeId { id: String, reason: String },
+
+    #[snafu(display("Invalid certificate: {}", reason))]
+    InvalidCertificate { reason: String },
+
+    #[snafu(display("Failed to read {}: {}", path.display(), source))]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid {}: {}", name, reason))]
+    InvalidPem { name: String, reason: String },
+
+    #[snafu(display("Credentials hold identity {}, expected {}", identity, expected))]
+    UnexpectedIdentity { identity: String, expected: String },
+
+    #[snafu(display("Invalid credentials: {}", source))]
+    InvalidCredentials { source: rustls::Error },
+
+    #[snafu(display("Failed to issue certificate: {}", source))]
+    Certificate { source: rcgen::Error },
+}
+
+pub type Result<T> = std::result::Result<T, SecurityError>;
@@ -0,0 +1,249 @@
+use super::{Credentials, SpiffeId};
+use futures::future::BoxFuture;
+use hyper::Uri;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Semaphore, mpsc};
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::server::Connected;
+
+/// Number of handshaken connections waiting for the server to take them.
+const BACKLOG: usize = 64;
+/// Time a peer is given to complete the handshake of a connection it opened.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of handshakes run at once. Further connections wait in the backlog of the
+/// listener.
+const MAX_HANDSHAKES: usize = 128;
+/// Bounds of the delay before accepting again after an error, such as running out of file
+/// descriptors.
+const MIN_ACCEPT_DELAY: Duration = Duration::from_millis(5);
+const MAX_ACCEPT_DELAY: Duration = Duration::from_secs(1);
+
+/// PeerInfo describes the peer of a connection accepted over mutual TLS. Servers find it
+/// in the extensions of requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub remote_addr: Option<SocketAddr>,
+    /// Identity of the certificate of the peer, verified during the handshake.
+    pub identity: SpiffeId,
+}
+
+/// TlsStream is a connection accepted over mutual TLS.
+pub struct TlsStream {
+    inner: tokio_rustls::server::TlsStream<TcpStream>,
+    peer: PeerInfo,
+}
+
+impl Connected for TlsStream {
+    type ConnectInfo = PeerInfo;
+
+    fn connect_info(&self) -> PeerInfo {
+        self.peer.clone()
+    }
+}
+
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Accepts the connections of a listener over mutual TLS, for
+/// [`tonic::transport::Server::serve_with_incoming`]. Up to [`MAX_HANDSHAKES`] handshakes
+/// run concurrently; the connections failing them, or not completing them within
+/// [`HANDSHAKE_TIMEOUT`], are dropped. Accepting stops when the stream is dropped.
+pub fn incoming(
+    listener: TcpListener,
+    credentials: Arc<Credentials>,
+) -> impl Stream<Item = io::Result<TlsStream>> {
+    let (tx, rx) = mpsc::channel(BACKLOG);
+    tokio::spawn(async move {
+        let acceptor = tokio_rustls::TlsAcceptor::from(credentials.server_config());
+        let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+        let mut delay = MIN_ACCEPT_DELAY;
+        loop {
+            let accept = async {
+                let permit = handshakes.clone().acquire_owned().await;
+                let permit = permit.expect("the semaphore is never closed");
+                (permit, listener.accept().await)
+            };
+            let (permit, stream, remote_addr) = tokio::select! {
+                (permit, accepted) = accept => match accepted {
+                    Ok((stream, remote_addr)) => {
+                        delay = MIN_ACCEPT_DELAY;
+                        (permit, stream, remote_addr)
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Failed to accept connection");
+                        tokio::select! {
+                            _ = tokio::time::sleep(delay) => {}
+                            _ = tx.closed() => return,
+                        }
+                        delay = (delay * 2).min(MAX_ACCEPT_DELAY);
+                        continue;
+                    }
+                },
+                _ = tx.closed() => return,
+            };
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let handshake =
+                    tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await;
+                drop(permit);
+                let stream = match handshake {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => {
+                        tracing::debug!(%remote_addr, error = %e, "TLS handshake failed");
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!(%remote_addr, "TLS handshake timed out");
+                        return;
+                    }
+                };
+                // The verifier rejected the certificates without SPIFFE ID.
+                let Some(identity) = stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certificates| certificates.first())
+                    .and_then(|certificate| SpiffeId::from_certificate(certificate).ok())
+                else {
+                    return;
+                };
+                let peer = PeerInfo {
+                    remote_addr: Some(remote_addr),
+                    identity,
+                };
+                let _ = tx
+                    .send(Ok(TlsStream {
+                        inner: stream,
+                        peer,
+                    }))
+                    .await;
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+/// TlsConnector connects tonic channels over mutual TLS, with
+/// [`tonic::transport::Endpoint::connect_with_connector`].
+#[derive(Clone)]
+pub struct TlsConnector {
+    connector: tokio_rustls::TlsConnector,
+}
+
+impl TlsConnector {
+    /// Creates a connector accepting servers with the `expected` identity or, when `None`,
+    /// any identity of the trust domain of the credentials.
+    pub fn new(credentials: &Arc<Credentials>, expected: Option<SpiffeId>) -> Self {
+        Self {
+            connector: tokio_rustls::TlsConnector::from(credentials.client_config(expected)),
+        }
+    }
+}
+
+impl tower::Service<Uri> for TlsConnector {
+    type Response = TokioIo<tokio_rustls::client::TlsStream<TcpStream>>;
+    type Error = io::Error;
+    type Future = BoxFuture<'static, io::Result<Self::Response>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.connector.clone();
+        Box::pin(async move {
+            let invalid =
+                || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid uri {uri}"));
+            let host = uri.host().ok_or_else(invalid)?;
+            let port = uri.port_u16().ok_or_else(invalid)?;
+            // Brackets of IPv6 addresses are part of the host of URIs.
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let server_name = ServerName::try_from(host.to_string()).map_err(|_| invalid())?;
+            let stream = TcpStream::connect((host, port)).await?;
+            stream.set_nodelay(true)?;
+            Ok(TokioIo::new(connector.connect(server_name, stream).await?))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ca::CertificateAuthority;
+    use tokio::io::AsyncReadExt;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handshake_timeout() {
+        let ca = CertificateAuthority::generate("cluster.local").unwrap();
+        let issued = ca
+            .issue("default", "orders", Duration::from_secs(3600))
+            .unwrap();
+        let credentials = Credentials::from_pem(
+            &issued.cert_chain_pem,
+            &issued.private_key_pem,
+            ca.trust_bundle_pem(),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut incoming = Box::pin(incoming(listener, Arc::new(credentials)));
+
+        // A peer which never starts its handshake is disconnected once it timed out.
+        let start = tokio::time::Instant::now();
+        let mut silent = TcpStream::connect(address).await.unwrap();
+        assert_eq!(silent.read(&mut [0; 16]).await.unwrap(), 0);
+        assert!(start.elapsed() >= HANDSHAKE_TIMEOUT);
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), incoming.next())
+                .await
+                .is_err()
+        );
+
+        // Connections wait for running handshakes to end before their own starts.
+        let start = tokio::time::Instant::now();
+        let mut silent = Vec::new();
+        for _ in 0..MAX_HANDSHAKES {
+            silent.push(TcpStream::connect(address).await.unwrap());
+        }
+        let mut waiting = TcpStream::connect(address).await.unwrap();
+        assert_eq!(waiting.read(&mut [0; 16]).await.unwrap(), 0);
+        assert!(start.elapsed() >= HANDSHAKE_TIMEOUT * 2);
+    }
+}
@@ -0,0 +1,216 @@
+//! Graceful shutdown of the runtime.
+//!
+//! Once the runtime is asked to stop, its servers stop accepting calls and [`Shutdown`]
+//! runs its steps stage by stage, within a grace period shared by all of them:
+//!
+//! 1. [`Stage::Drain`]: in-flight API calls, invocations and pub/sub deliveries finish.
+//! 2. [`Stage::Actors`]: active actors are deactivated, flushing their state.
+//! 3. [`Stage::InputBindings`]: input bindings stop reading.
+//! 4. [`Stage::Components`]: components are closed one at a time, in the reverse order of
+//!    their steps, so that a component is closed after the components depending on it.
+//!
+//! The steps of the other stages run concurrently. Steps still running when the grace
+//! period elapses are abandoned and reported as timed out.
+
+use futures::future::{self, BoxFuture};
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Default time the runtime is given to shut down.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Error returned by a failed shutdown step.
+pub type StepError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Stage of the shutdown, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    Drain,
+    Actors,
+    InputBindings,
+    Components,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Stage::Drain => "drain",
+            Stage::Actors => "actors",
+            Stage::InputBindings => "input bindings",
+            Stage::Components => "components",
+        })
+    }
+}
+
+struct Step {
+    stage: Stage,
+    name: String,
+    task: BoxFuture<'static, Result<(), StepError>>,
+}
+
+/// ShutdownReport lists the steps which did not complete.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Names of the steps which failed, with their error.
+    pub failed: Vec<(String, String)>,
+    /// Names of the steps still running when the grace period elapsed.
+    pub timed_out: Vec<String>,
+}
+
+impl ShutdownReport {
+    /// Returns true if every step completed.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.timed_out.is_empty()
+    }
+}
+
+/// Shutdown runs the steps stopping the runtime within a grace period.
+pub struct Shutdown {
+    grace_period: Duration,
+    steps: Vec<Step>,
+}
+
+impl Shutdown {
+    /// Creates a shutdown without steps, given `grace_period` to complete.
+    pub fn new(grace_period: Duration) -> Self {
+        Self {
+            grace_period,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Adds a step to a stage. The task only starts once the stage runs.
+    pub fn add_step(
+        &mut self,
+        stage: Stage,
+        name: impl Into<String>,
+        task: impl Future<Output = Result<(), StepError>> + Send + 'static,
+    ) {
+        self.steps.push(Step {
+            stage,
+            name: name.into(),
+            task: Box::pin(task),
+        });
+    }
+
+    /// Runs the stages in order and reports the steps which did not complete.
+    pub async fn run(mut self) -> ShutdownReport {
+        tracing::info!(grace_period = ?self.grace_period, "Shutting down");
+        let deadline = Instant::now() + self.grace_period;
+        let mut report = ShutdownReport::default();
+        // The sort is stable: steps keep their order within their stage.
+        self.steps.sort_by_key(|step| step.stage);
+        let mut steps = self.steps.into_iter().peekable();
+        while let Some(stage) = steps.peek().map(|step| step.stage) {
+            let mut batch = Vec::new();
+            while let Some(step) = steps.next_if(|step| step.stage == stage) {
+                batch.push(step);
+            }
+            if stage == Stage::Components {
+                for step in batch.into_iter().rev() {
+                    let outcome = run_step(step, deadline).await;
+                    record(&mut report, outcome);
+                }
+            } else {
+                for outcome in
+                    future::join_all(batch.into_iter().map(|s| run_step(s, deadline))).await
+                {
+                    record(&mut report, outcome);
+                }
+            }
+        }
+        match report.is_complete() {
+            true => tracing::info!("Shutdown complete"),
+            false => tracing::warn!(
+                failed = report.failed.len(),
+                timed_out = ?report.timed_out,
+                "Shutdown incomplete"
+            ),
+        }
+        report
+    }
+}
+
+/// Outcome of a step: its stage, its name and its result, none if it timed out.
+type Outcome = (Stage, String, Option<Result<(), StepError>>);
+
+async fn run_step(step: Step, deadline: Instant) -> Outcome {
+    let result = tokio::time::timeout_at(deadline, step.task).await.ok();
+    (step.stage, step.name, result)
+}
+
+fn record(report: &mut ShutdownReport, (stage, name, result): Outcome) {
+    match result {
+        Some(Ok(())) => tracing::debug!(%stage, step = %name, "Shutdown step completed"),
+        Some(Err(error)) => {
+            tracing::warn!(%stage, step = %name, %error, "Shutdown step failed");
+            report.failed.push((name, error.to_string()));
+        }
+        None => {
+            tracing::warn!(%stage, step = %name, "Shutdown step timed out");
+            report.timed_out.push(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut shutdown = Shutdown::new(DEFAULT_GRACE_PERIOD);
+        let mut add = |stage, name: &'static str| {
+            let log = log.clone();
+            shutdown.add_step(stage, name, async move {
+                log.lock().unwrap().push(name);
+                Ok(())
+            });
+        };
+        add(Stage::Components, "secretstore");
+        add(Stage::Components, "statestore");
+        add(Stage::InputBindings, "queue");
+        add(Stage::Actors, "cart");
+        add(Stage::Drain, "http");
+        assert!(log.lock().unwrap().is_empty());
+
+        assert!(shutdown.run().await.is_complete());
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["http", "cart", "queue", "statestore", "secretstore"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_grace_period() {
+        let mut shutdown = Shutdown::new(Duration::from_secs(5));
+        shutdown.add_step(Stage::Drain, "http", async {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+            Ok(())
+        });
+        shutdown.add_step(Stage::Actors, "cart", async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(())
+        });
+        shutdown.add_step(Stage::Components, "broker", async {
+            Err("connection reset".into())
+        });
+        // Steps which complete right away still run once the grace period elapsed.
+        shutdown.add_step(Stage::Components, "store", async { Ok(()) });
+
+        let start = Instant::now();
+        let report = shutdown.run().await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        assert_eq!(
+            report,
+            ShutdownReport {
+                failed: vec![("broker".to_string(), "connection reset".to_string())],
+                timed_out: vec!["cart".to_string()],
+            }
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use super::{BackendSnafu, HistoryRecord, InstanceExistsSnafu, Result};
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::io::AsyncWriteExt;
+
+/// Backend persists the history of workflow instances.
+///
+/// The engine is the only writer of an instance's history and appends to it from a single
+/// task at a time, so backends only need to make each call atomic.
+#[async_trait::async_trait]
+pub trait Backend: Send + Sync {
+    /// Creates an instance with its first history record.
+    /// Fails with [`super::WorkflowError::InstanceExists`] if the instance already exists.
+    async fn create(&self, instance_id: &str, started: HistoryRecord) -> Result<()>;
+
+    /// Appends records to the history of an existing instance.
+    async fn append(&self, instance_id: &str, records: &[HistoryRecord]) -> Result<()>;
+
+    /// Loads the history of an instance, or `None` if it does not exist.
+    async fn load(&self, instance_id: &str) -> Result<Option<Vec<HistoryRecord>>>;
+
+    /// Lists the ids of every instance, in ascending order.
+    async fn list(&self) -> Result<Vec<String>>;
+
+    /// Deletes an instance and its history. Returns false if it did not exist.
+    async fn purge(&self, instance_id: &str) -> Result<bool>;
+}
+
+/// InMemoryBackend keeps histories in memory. Useful for tests.
+#[derive(Debug, Default)]
+pub struct InMemoryBackend {
+    instances: Mutex<BTreeMap<String, Vec<HistoryRecord>>>,
+}
+
+impl InMemoryBackend {
+    /// Creates an empty backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn instances(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Vec<HistoryRecord>>> {
+        self.instances.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for InMemoryBackend {
+    async fn create(&self, instance_id: &str, started: HistoryRecord) -> Result<()> {
+        let mut instances = self.instances();
+        if instances.contains_key(instance_id) {
+            return InstanceExistsSnafu { instance_id }.fail();
+        }
+        instances.insert(instance_id.to_string(), vec![started]);
+        Ok(())
+    }
+
+    async fn append(&self, instance_id: &str, records: &[HistoryRecord]) -> Result<()> {
+        match self.instances().get_mut(instance_id) {
+            Some(history) => {
+                history.extend_from_slice(records);
+                Ok(())
+            }
+            None => Err(Error::from(ErrorKind::NotFound)).context(BackendSnafu),
+        }
+    }
+
+    async fn load(&self, instance_id: &str) -> Result<Option<Vec<HistoryRecord>>> {
+        Ok(self.instances().get(instance_id).cloned())
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        Ok(self.instances().keys().cloned().collect())
+    }
+
+    async fn purge(&self, instance_id: &str) -> Result<bool> {
+        Ok(self.instances().remove(instance_id).is_some())
+    }
+}
+
+const HISTORY_FILE_EXTENSION: &str = "history";
+
+/// FileBackend stores the history of each instance as a JSON-lines file in a directory.
+/// Records are synced to disk before an append returns.
+#[derive(Debug)]
+pub struct FileBackend {
+    dir: PathBuf,
+}
+
+impl FileBackend {
+    /// Creates a backend storing histories in the given directory, creating it if needed.
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(dir.as_ref()).context(BackendSnafu)?;
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+        })
+    }
+
+    fn path(&self, instance_id: &str) -> PathBuf {
+        self.dir.join(format!(
+            "{}.{HISTORY_FILE_EXTENSION}",
+            encode_id(instance_id)
+        ))
+    }
+
+    async fn write(&self, path: &Path, records: &[HistoryRecord], create: bool) -> Result<()> {
+        let mut content = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut content, record)
+                .map_err(Error::other)
+                .context(BackendSnafu)?;
+            content.push(b'\n');
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .create_new(create)
+            .open(path)
+            .await
+            .context(BackendSnafu)?;
+        file.write_all(&content).await.context(BackendSnafu)?;
+        file.sync_data().await.context(BackendSnafu)
+    }
+}
+
+#[async_trait::async_trait]
+impl Backend for FileBackend {
+    async fn create(&self, instance_id: &str, started: HistoryRecord) -> Result<()> {
+        match self.write(&self.path(instance_id), &[started], true).await {
+            Err(super::WorkflowError::Backend { source })
+                if source.kind() == ErrorKind::AlreadyExists =>
+            {
+                InstanceExistsSnafu { instance_id }.fail()
+            }
+            result => result,
+        }
+    }
+
+    async fn append(&self, instance_id: &str, records: &[HistoryRecord]) -> Result<()> {
+        self.write(&self.path(instance_id), records, false).await
+    }
+
+    async fn load(&self, instance_id: &str) -> Result<Option<Vec<HistoryRecord>>> {
+        let path = self.path(instance_id);
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context(BackendSnafu),
+        };
+
+        let mut records = Vec::new();
+        let mut valid_len = 0;
+        for line in content.split_inclusive('\n') {
+            if !line.ends_with('\n') {
+                // A crash in the middle of an append leaves a torn record behind: drop it so
+                // that the next append starts on a fresh line.
+                let file = tokio::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .await
+                    .context(BackendSnafu)?;
+                file.set_len(valid_len as u64).await.context(BackendSnafu)?;
+                break;
+            }
+            let record = serde_json::from_str(line)
+                .map_err(Error::other)
+                .context(BackendSnafu)?;
+            records.push(record);
+            valid_len += line.len();
+        }
+        Ok(Some(records))
+    }
+
+    async fn list(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await.context(BackendSnafu)?;
+        while let Some(entry) = entries.next_entry().await.context(BackendSnafu)? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(HISTORY_FILE_EXTENSION) {
+                continue;
+            }
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(decode_id)
+            {
+                ids.push(id);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    async fn purge(&self, instance_id: &str) -> Result<bool> {
+        match tokio::fs::remove_file(self.path(instance_id)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).context(BackendSnafu),
+        }
+    }
+}
+
+/// Escapes an instance id into a safe file name.
+fn encode_id(id: &str) -> String {
+    let mut encoded = String::with_capacity(id.len());
+    for b in id.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+fn decode_id(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = encoded.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::{HistoryEvent, WorkflowError};
+    use serde_json::json;
+
+    fn started() -> HistoryRecord {
+        HistoryRecord::now(HistoryEvent::WorkflowStarted {
+            name: "order".to_string(),
+            input: json!(null),
+            parent: None,
+        })
+    }
+
+    async fn exercise(backend: &dyn Backend) {
+        let id = "parent:1/child";
+        backend.create(id, started()).await.unwrap();
+        assert!(matches!(
+            backend.create(id, started()).await,
+            Err(WorkflowError::InstanceExists { .. })
+        ));
+
+        let fired = HistoryRecord::now(HistoryEvent::TimerFired { task_id: 0 });
+        backend
+            .append(id, std::slice::from_ref(&fired))
+            .await
+            .unwrap();
+        let history = backend.load(id).await.unwrap().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1], fired);
+
+        assert_eq!(backend.list().await.unwrap(), vec![id.to_string()]);
+        assert!(backend.purge(id).await.unwrap());
+        assert!(!backend.purge(id).await.unwrap());
+        assert!(backend.load(id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_backend() {
+        exercise(&InMemoryBackend::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&FileBackend::new(dir.path()).unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_file_backend_drops_torn_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = FileBackend::new(dir.path()).unwrap();
+        backend.create("id", started()).await.unwrap();
+
+        let path = backend.path("id");
+        let mut content = std::fs::read_to_string(&path).unwrap();
+        content.push_str("{\"timestamp\":1,\"ev");
+        std::fs::write(&path, content).unwrap();
+
+        assert_eq!(backend.load("id").await.unwrap().unwrap().len(), 1);
+        let fired = HistoryRecord::now(HistoryEvent::TimerFired { task_id: 0 });
+        backend.append("id", &[fired]).await.unwrap();
+        assert_eq!(backend.load("id").await.unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_encode_id() {
+        assert_eq!(encode_id("order-1_a"), "order-1_a");
+        assert_eq!(encode_id("a:b/c.d"), "a%3Ab%2Fc%2Ed");
+        assert_eq!(decode_id("a%3Ab%2Fc%2Ed").unwrap(), "a:b/c.d");
+        assert!(decode_id("a%3").is_none());
+    }
+}
@@ -0,0 +1,327 @@
+use super::HistoryEvent;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Outcome of a task scheduled by a workflow.
+pub(crate) type TaskOutcome = Result<Value, String>;
+
+/// ContextState is shared by a workflow instance and the runner driving it.
+///
+/// The runner makes history visible to the workflow one event at a time; the futures
+/// returned by the [`WorkflowContext`] only ever complete from what has been made visible.
+#[derive(Debug, Default)]
+pub(crate) struct ContextState {
+    /// Tasks scheduled in the history, by id.
+    pub(crate) scheduled: BTreeMap<u64, HistoryEvent>,
+    /// Scheduling events produced by the workflow and not yet in the history.
+    pub(crate) actions: Vec<HistoryEvent>,
+    /// Error found comparing the workflow with its history, if any.
+    pub(crate) nondeterminism: Option<String>,
+    pub(crate) replaying: bool,
+    /// Time of the history event applied last, in milliseconds since the unix epoch.
+    pub(crate) current_time: u64,
+    next_task_id: u64,
+    outcomes: HashMap<u64, TaskOutcome>,
+    task_wakers: HashMap<u64, Waker>,
+    events: HashMap<String, Vec<Value>>,
+    event_waits: HashMap<String, usize>,
+    event_wakers: Vec<Waker>,
+}
+
+impl ContextState {
+    /// Makes the outcome of a task visible to the workflow.
+    pub(crate) fn complete(&mut self, task_id: u64, outcome: TaskOutcome) {
+        self.outcomes.insert(task_id, outcome);
+        if let Some(waker) = self.task_wakers.remove(&task_id) {
+            waker.wake();
+        }
+    }
+
+    /// Makes an external event visible to the workflow.
+    pub(crate) fn raise(&mut self, name: String, payload: Value) {
+        self.events.entry(name).or_default().push(payload);
+        for waker in self.event_wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    /// Assigns the next task id and checks the call against the history.
+    /// `event` is the scheduling event the call would record.
+    fn schedule(&mut self, event: impl FnOnce(u64) -> HistoryEvent) -> u64 {
+        let task_id = self.next_task_id;
+        self.next_task_id += 1;
+
+        let event = event(task_id);
+        match self.scheduled.get(&task_id) {
+            Some(recorded) if same_task(recorded, &event) => {}
+            Some(recorded) => {
+                self.nondeterminism.get_or_insert_with(|| {
+                    format!("task {task_id} was recorded as {recorded:?} but is now {event:?}")
+                });
+            }
+            None => {
+                self.scheduled.insert(task_id, event.clone());
+                self.actions.push(event);
+            }
+        }
+        task_id
+    }
+}
+
+/// Returns true if two scheduling events describe the same call. Inputs are not compared
+/// so that a workflow can be fixed without invalidating the instances already running it.
+fn same_task(recorded: &HistoryEvent, event: &HistoryEvent) -> bool {
+    match (recorded, event) {
+        (
+            HistoryEvent::ActivityScheduled { name: a, .. },
+            HistoryEvent::ActivityScheduled { name: b, .. },
+        ) => a == b,
+        (HistoryEvent::TimerCreated { .. }, HistoryEvent::TimerCreated { .. }) => true,
+        (
+            HistoryEvent::SubWorkflowScheduled { name: a, .. },
+            HistoryEvent::SubWorkflowScheduled { name: b, .. },
+        ) => a == b,
+        _ => false,
+    }
+}
+
+/// WorkflowContext is handed to a workflow function to schedule durable work.
+///
+/// Tasks are identified by the order in which they are scheduled, which is what makes the
+/// outcomes recorded in the history line up with the calls of a replayed workflow.
+#[derive(Debug, Clone)]
+pub struct WorkflowContext {
+    instance_id: Arc<str>,
+    state: Arc<Mutex<ContextState>>,
+}
+
+impl WorkflowContext {
+    pub(crate) fn new(instance_id: &str, state: Arc<Mutex<ContextState>>) -> Self {
+        Self {
+            instance_id: instance_id.into(),
+            state,
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, ContextState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the id of the workflow instance.
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Returns true while the workflow is replaying recorded history.
+    /// Useful to avoid logging the same thing on every replay.
+    pub fn is_replaying(&self) -> bool {
+        self.state().replaying
+    }
+
+    /// Returns the time the current step of the workflow was recorded at.
+    /// Unlike the system clock, it is the same every time the workflow is replayed.
+    pub fn current_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.state().current_time)
+    }
+
+    /// Schedules an activity and returns its output.
+    pub fn call_activity<O: DeserializeOwned>(
+        &self,
+        name: &str,
+        input: impl Serialize,
+    ) -> impl Future<Output = Result<O, String>> + Send + 'static {
+        let task = serde_json::to_value(input).map(|input| {
+            self.task(|task_id| HistoryEvent::ActivityScheduled {
+                task_id,
+                name: name.to_string(),
+                input,
+            })
+        });
+        async move { decode(task.map_err(|e| e.to_string())?.await?) }
+    }
+
+    /// Returns a future completing once the duration has elapsed. The timer is durable: it
+    /// still fires at the same time if the engine restarts meanwhile.
+    pub fn create_timer(&self, duration: Duration) -> impl Future<Output = ()> + Send + 'static {
+        let fire_at = self.state().current_time + duration.as_millis() as u64;
+        let task = self.task(|task_id| HistoryEvent::TimerCreated { task_id, fire_at });
+        async move {
+            let _ = task.await;
+        }
+    }
+
+    /// Starts a sub-workflow and returns its output. The sub-workflow instance id is derived
+    /// from this instance id and the task id, so it is the same on every replay.
+    pub fn call_sub_workflow<O: DeserializeOwned>(
+        &self,
+        name: &str,
+        input: impl Serialize,
+    ) -> impl Future<Output = Result<O, String>> + Send + 'static {
+        let task = serde_json::to_value(input).map(|input| {
+            self.task(|task_id| HistoryEvent::SubWorkflowScheduled {
+                task_id,
+                name: name.to_string(),
+                instance_id: format!("{}:{task_id}", self.instance_id),
+                input,
+            })
+        });
+        async move { decode(task.map_err(|e| e.to_string())?.await?) }
+    }
+
+    /// Waits for an external event raised to the instance.
+    /// Events with the same name are delivered to the waits in the order they were raised.
+    pub fn wait_for_external_event<T: DeserializeOwned>(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<T, String>> + Send + 'static {
+        let index = {
+            let mut state = self.state();
+            let waits = state.event_waits.entry(name.to_string()).or_default();
+            *waits += 1;
+            *waits - 1
+        };
+        let wait = EventFuture {
+            name: name.to_string(),
+            index,
+            state: self.state.clone(),
+        };
+        async move { decode(wait.await) }
+    }
+
+    fn task(&self, event: impl FnOnce(u64) -> HistoryEvent) -> TaskFuture {
+        TaskFuture {
+            task_id: self.state().schedule(event),
+            state: self.state.clone(),
+        }
+    }
+}
+
+fn decode<T: DeserializeOwned>(value: Value) -> Result<T, String> {
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+/// Completes once the outcome of a task is visible.
+struct TaskFuture {
+    task_id: u64,
+    state: Arc<Mutex<ContextState>>,
+}
+
+impl Future for TaskFuture {
+    type Output = TaskOutcome;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.outcomes.get(&self.task_id) {
+            Some(outcome) => Poll::Ready(outcome.clone()),
+            None => {
+                state.task_wakers.insert(self.task_id, cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Completes once the `index`-th event with the given name is visible.
+struct EventFuture {
+    name: String,
+    index: usize,
+    state: Arc<Mutex<ContextState>>,
+}
+
+impl Future for EventFuture {
+    type Output = Value;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state
+            .events
+            .get(&self.name)
+            .and_then(|events| events.get(self.index))
+        {
+            Some(payload) => Poll::Ready(payload.clone()),
+            None => {
+                state.event_wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use serde_json::json;
+
+    #[test]
+    fn test_tasks_follow_history() {
+        let state = Arc::new(Mutex::new(ContextState::default()));
+        state.lock().unwrap().scheduled.insert(
+            0,
+            HistoryEvent::ActivityScheduled {
+                task_id: 0,
+                name: "charge".to_string(),
+                input: json!(1),
+            },
+        );
+        let ctx = WorkflowContext::new("id", state.clone());
+
+        let mut charge = Box::pin(ctx.call_activity::<u32>("charge", 1));
+        let mut timer = Box::pin(ctx.create_timer(Duration::from_secs(1)));
+        assert!(charge.as_mut().now_or_never().is_none());
+
+        {
+            let mut state = state.lock().unwrap();
+            assert_eq!(state.actions.len(), 1);
+            assert!(matches!(
+                state.actions[0],
+                HistoryEvent::TimerCreated { task_id: 1, .. }
+            ));
+            state.complete(0, Ok(json!(7)));
+            state.complete(1, Ok(Value::Null));
+            assert!(state.nondeterminism.is_none());
+        }
+        assert_eq!(charge.now_or_never(), Some(Ok(7)));
+        assert_eq!(timer.as_mut().now_or_never(), Some(()));
+    }
+
+    #[test]
+    fn test_detects_nondeterminism() {
+        let state = Arc::new(Mutex::new(ContextState::default()));
+        state.lock().unwrap().scheduled.insert(
+            0,
+            HistoryEvent::ActivityScheduled {
+                task_id: 0,
+                name: "charge".to_string(),
+                input: json!(1),
+            },
+        );
+        let ctx = WorkflowContext::new("id", state.clone());
+
+        drop(ctx.call_activity::<u32>("refund", 1));
+        assert!(state.lock().unwrap().nondeterminism.is_some());
+    }
+
+    #[test]
+    fn test_external_events_in_order() {
+        let state = Arc::new(Mutex::new(ContextState::default()));
+        let ctx = WorkflowContext::new("id", state.clone());
+
+        let first = ctx.wait_for_external_event::<u32>("approval");
+        let second = ctx.wait_for_external_event::<u32>("approval");
+        {
+            let mut state = state.lock().unwrap();
+            state.raise("approval".to_string(), json!(1));
+            state.raise("approval".to_string(), json!(2));
+        }
+        assert_eq!(second.now_or_never(), Some(Ok(2)));
+        assert_eq!(first.now_or_never(), Some(Ok(1)));
+    }
+}
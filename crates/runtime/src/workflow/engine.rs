@@ -0,0 +1,1239 @@
+use super::context::{ContextState, TaskOutcome};
+use super::{
+    Backend, EngineStoppedSnafu, HistoryEvent, HistoryRecord, InstanceNotFoundSnafu,
+    InstanceRunningSnafu, ParentInstance, Result, SerializationSnafu, WorkflowContext,
+    WorkflowError, WorkflowNotRegisteredSnafu, WorkflowPage, WorkflowQuery, WorkflowState,
+    WorkflowStatus, history::now_millis,
+};
+use futures::task::{ArcWake, waker};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use snafu::{OptionExt, ResultExt};
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+type WorkflowFn = Arc<dyn Fn(WorkflowContext, Value) -> BoxFuture<TaskOutcome> + Send + Sync>;
+type ActivityFn = Arc<dyn Fn(Value) -> BoxFuture<TaskOutcome> + Send + Sync>;
+/// Events sent to a runner, along with a channel signalled once the event was handled.
+type Inbox = mpsc::UnboundedSender<(HistoryEvent, oneshot::Sender<()>)>;
+
+/// Error reported to the parent of a terminated sub-workflow.
+const TERMINATED_ERROR: &str = "Workflow instance was terminated";
+
+/// WorkflowEngine runs workflow instances and persists their history through a [`Backend`].
+///
+/// Each running instance is driven by a single task, which is the only writer of its
+/// history. Activities, timers and sub-workflows run in tasks of their own and report back
+/// to it. Cloning the engine is cheap and clones share the same instances.
+#[derive(Clone)]
+pub struct WorkflowEngine {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    backend: Arc<dyn Backend>,
+    workflows: RwLock<HashMap<String, WorkflowFn>>,
+    activities: RwLock<HashMap<String, ActivityFn>>,
+    /// Inbox of every running instance.
+    runners: Mutex<HashMap<String, Inbox>>,
+    tasks: Mutex<JoinSet<()>>,
+    /// Bumped every time an instance completes.
+    completions: watch::Sender<u64>,
+    stopped: AtomicBool,
+}
+
+impl WorkflowEngine {
+    /// Creates an engine storing histories in the backend. Call [`WorkflowEngine::start`]
+    /// once workflows and activities are registered to resume the instances it holds.
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                backend,
+                workflows: RwLock::default(),
+                activities: RwLock::default(),
+                runners: Mutex::default(),
+                tasks: Mutex::default(),
+                completions: watch::Sender::new(0),
+                stopped: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Registers a workflow under a name.
+    pub fn register_workflow<I, O, F, Fut>(&self, name: impl Into<String>, workflow: F)
+    where
+        I: DeserializeOwned,
+        O: Serialize,
+        F: Fn(WorkflowContext, I) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<O, String>> + Send + 'static,
+    {
+        let workflow: WorkflowFn = Arc::new(move |ctx, input| {
+            let future = serde_json::from_value(input).map(|input| workflow(ctx, input));
+            Box::pin(async move {
+                let output = future.map_err(|e| e.to_string())?.await?;
+                serde_json::to_value(output).map_err(|e| e.to_string())
+            })
+        });
+        self.inner
+            .workflows
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.into(), workflow);
+    }
+
+    /// Registers an activity under a name.
+    /// Activities may run more than once if the engine restarts, so they should be idempotent.
+    pub fn register_activity<I, O, F, Fut>(&self, name: impl Into<String>, activity: F)
+    where
+        I: DeserializeOwned,
+        O: Serialize,
+        F: Fn(I) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<O, String>> + Send + 'static,
+    {
+        let activity: ActivityFn = Arc::new(move |input| {
+            let future = serde_json::from_value(input).map(&activity);
+            Box::pin(async move {
+                let output = future.map_err(|e| e.to_string())?.await?;
+                serde_json::to_value(output).map_err(|e| e.to_string())
+            })
+        });
+        self.inner
+            .activities
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.into(), activity);
+    }
+
+    /// Resumes every instance of the backend which is still running, replaying its history.
+    /// Returns the number of instances resumed.
+    pub async fn start(&self) -> Result<usize> {
+        let mut resumed = 0;
+        for instance_id in self.inner.backend.list().await? {
+            if let Some(history) = self.inner.backend.load(&instance_id).await?
+                && WorkflowState::from_history(&instance_id, &history)
+                    .is_some_and(|state| !state.status.is_terminal())
+            {
+                self.inner.spawn_runner(&instance_id, history)?;
+                resumed += 1;
+            }
+        }
+        Ok(resumed)
+    }
+
+    /// Starts a new instance of a workflow and returns its id.
+    /// A random id is generated unless one is given.
+    pub async fn schedule_workflow(
+        &self,
+        name: &str,
+        instance_id: Option<String>,
+        input: impl Serialize,
+    ) -> Result<String> {
+        let input = serde_json::to_value(input).context(SerializationSnafu)?;
+        let instance_id = instance_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        self.inner
+            .create_instance(&instance_id, name, input, None)
+            .await?;
+        Ok(instance_id)
+    }
+
+    /// Raises an external event to an instance.
+    /// Events raised to an instance which already completed are dropped.
+    pub async fn raise_event(
+        &self,
+        instance_id: &str,
+        name: &str,
+        payload: impl Serialize,
+    ) -> Result<()> {
+        let event = HistoryEvent::EventRaised {
+            name: name.to_string(),
+            payload: serde_json::to_value(payload).context(SerializationSnafu)?,
+        };
+        self.inner.send(instance_id, event).await
+    }
+
+    /// Pauses an instance: it keeps recording the outcome of its tasks and the events raised
+    /// to it, but the workflow does not see them until the instance is resumed.
+    /// Pausing an instance which already completed does nothing.
+    pub async fn suspend_instance(&self, instance_id: &str, reason: &str) -> Result<()> {
+        let event = HistoryEvent::ExecutionSuspended {
+            reason: reason.to_string(),
+        };
+        self.inner.send(instance_id, event).await
+    }
+
+    /// Resumes a paused instance.
+    pub async fn resume_instance(&self, instance_id: &str, reason: &str) -> Result<()> {
+        let event = HistoryEvent::ExecutionResumed {
+            reason: reason.to_string(),
+        };
+        self.inner.send(instance_id, event).await
+    }
+
+    /// Stops an instance, recording `output` as its output. With `recursive`, the
+    /// sub-workflows it is waiting for are terminated as well.
+    /// Terminating an instance which already completed does nothing.
+    pub async fn terminate_instance(
+        &self,
+        instance_id: &str,
+        output: impl Serialize,
+        recursive: bool,
+    ) -> Result<()> {
+        let event = HistoryEvent::ExecutionTerminated {
+            output: serde_json::to_value(output).context(SerializationSnafu)?,
+            recursive,
+        };
+        self.inner.send(instance_id, event).await
+    }
+
+    /// Deletes the history of a completed instance and returns the number of instances
+    /// deleted. With `recursive`, the history of its sub-workflows is deleted as well.
+    /// Fails without deleting anything if one of these instances is still running.
+    pub async fn purge_instance(&self, instance_id: &str, recursive: bool) -> Result<usize> {
+        let mut instances = vec![instance_id.to_string()];
+        let mut index = 0;
+        while let Some(id) = instances.get(index).cloned() {
+            index += 1;
+            let Some(history) = self.inner.backend.load(&id).await? else {
+                if id == instance_id {
+                    return InstanceNotFoundSnafu { instance_id }.fail();
+                }
+                continue;
+            };
+            if WorkflowState::from_history(&id, &history)
+                .is_none_or(|state| !state.status.is_terminal())
+            {
+                return InstanceRunningSnafu { instance_id: id }.fail();
+            }
+            if recursive {
+                instances.extend(sub_workflows(&history, false));
+            }
+        }
+
+        let mut purged = 0;
+        for id in instances {
+            if self.inner.backend.purge(&id).await? {
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Returns the instances matching a query, ordered by instance id.
+    ///
+    /// The backend has no index: every instance id is listed, and the history of every
+    /// instance after the continuation token is loaded until the page is full. A query
+    /// costs O(n) in the number of instances, and a query matching few of them may load
+    /// every history.
+    pub async fn query_instances(&self, query: &WorkflowQuery) -> Result<WorkflowPage> {
+        let mut page = WorkflowPage::default();
+        for instance_id in self.inner.backend.list().await? {
+            if query
+                .continuation_token
+                .as_ref()
+                .is_some_and(|token| instance_id.as_str() <= token.as_str())
+            {
+                continue;
+            }
+            let Some(state) = self.get_instance(&instance_id).await? else {
+                continue;
+            };
+            if !query.matches(&state) {
+                continue;
+            }
+            if query.page_size > 0 && page.instances.len() == query.page_size {
+                page.continuation_token = page.instances.last().map(|s| s.instance_id.clone());
+                break;
+            }
+            page.instances.push(state);
+        }
+        Ok(page)
+    }
+
+    /// Returns the state of an instance, or `None` if it does not exist.
+    pub async fn get_instance(&self, instance_id: &str) -> Result<Option<WorkflowState>> {
+        Ok(self
+            .inner
+            .backend
+            .load(instance_id)
+            .await?
+            .and_then(|history| WorkflowState::from_history(instance_id, &history)))
+    }
+
+    /// Returns the history of an instance, or `None` if it does not exist.
+    pub async fn get_history(&self, instance_id: &str) -> Result<Option<Vec<HistoryRecord>>> {
+        self.inner.backend.load(instance_id).await
+    }
+
+    /// Waits until an instance completes and returns its final state.
+    pub async fn wait_for_completion(&self, instance_id: &str) -> Result<WorkflowState> {
+        let mut completions = self.inner.completions.subscribe();
+        loop {
+            let state = self
+                .get_instance(instance_id)
+                .await?
+                .context(InstanceNotFoundSnafu { instance_id })?;
+            if state.status.is_terminal() {
+                return Ok(state);
+            }
+            if completions.changed().await.is_err() || self.inner.stopped.load(Ordering::SeqCst) {
+                return EngineStoppedSnafu.fail();
+            }
+        }
+    }
+
+    /// Stops every instance and task of the engine.
+    /// Their histories are left as they are, so another engine can resume them.
+    pub fn shutdown(&self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+        self.inner.runners().clear();
+        self.inner.tasks().abort_all();
+        self.inner
+            .completions
+            .send_modify(|n| *n = n.wrapping_add(1));
+    }
+}
+
+impl Inner {
+    fn runners(&self) -> MutexGuard<'_, HashMap<String, Inbox>> {
+        self.runners.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn tasks(&self) -> MutexGuard<'_, JoinSet<()>> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn workflow(&self, name: &str) -> Option<WorkflowFn> {
+        self.workflows
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+    }
+
+    fn activity(&self, name: &str) -> Option<ActivityFn> {
+        self.activities
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+    }
+
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks();
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Sends an event to the runner of an instance, if it is running. The returned channel
+    /// is signalled once the runner handled the event.
+    fn deliver(&self, instance_id: &str, event: HistoryEvent) -> Option<oneshot::Receiver<()>> {
+        let (handled, receiver) = oneshot::channel();
+        let runners = self.runners();
+        runners.get(instance_id)?.send((event, handled)).ok()?;
+        Some(receiver)
+    }
+
+    /// Sends an event to an instance. When the instance is not running in this engine, the
+    /// event is recorded in its history so that it is replayed once the instance resumes.
+    fn send(self: &Arc<Self>, instance_id: &str, event: HistoryEvent) -> BoxFuture<Result<()>> {
+        let engine = self.clone();
+        let instance_id = instance_id.to_string();
+        Box::pin(async move {
+            if let Some(handled) = engine.deliver(&instance_id, event.clone())
+                && handled.await.is_ok()
+            {
+                return Ok(());
+            }
+
+            let history =
+                engine
+                    .backend
+                    .load(&instance_id)
+                    .await?
+                    .context(InstanceNotFoundSnafu {
+                        instance_id: &instance_id,
+                    })?;
+            let Some(state) = WorkflowState::from_history(&instance_id, &history) else {
+                return InstanceNotFoundSnafu { instance_id }.fail();
+            };
+            let ignored = match &event {
+                _ if state.status.is_terminal() => true,
+                HistoryEvent::ExecutionSuspended { .. } => {
+                    state.status == WorkflowStatus::Suspended
+                }
+                HistoryEvent::ExecutionResumed { .. } => state.status == WorkflowStatus::Running,
+                _ => false,
+            };
+            if ignored {
+                tracing::debug!("ignoring {event:?} sent to workflow {instance_id}");
+                return Ok(());
+            }
+
+            engine
+                .backend
+                .append(&instance_id, &[HistoryRecord::now(event.clone())])
+                .await?;
+            if let HistoryEvent::ExecutionTerminated { output, recursive } = event {
+                let children = if recursive {
+                    sub_workflows(&history, true)
+                } else {
+                    Vec::new()
+                };
+                engine.terminated(state.parent.as_ref(), children, output);
+            }
+            Ok(())
+        })
+    }
+
+    /// Reports a terminated instance to its parent and terminates its children.
+    fn terminated(
+        self: &Arc<Self>,
+        parent: Option<&ParentInstance>,
+        children: Vec<String>,
+        output: Value,
+    ) {
+        if let Some(parent) = parent {
+            self.notify_parent(parent, Err(TERMINATED_ERROR.to_string()));
+        }
+        for child in children {
+            let event = HistoryEvent::ExecutionTerminated {
+                output: output.clone(),
+                recursive: true,
+            };
+            let terminate = self.send(&child, event);
+            self.spawn(async move {
+                if let Err(e) = terminate.await {
+                    tracing::warn!("failed to terminate workflow {child}: {e}");
+                }
+            });
+        }
+        self.completions.send_modify(|n| *n = n.wrapping_add(1));
+    }
+
+    async fn create_instance(
+        self: &Arc<Self>,
+        instance_id: &str,
+        name: &str,
+        input: Value,
+        parent: Option<ParentInstance>,
+    ) -> Result<()> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return EngineStoppedSnafu.fail();
+        }
+        if self.workflow(name).is_none() {
+            return WorkflowNotRegisteredSnafu { name }.fail();
+        }
+        let started = HistoryRecord::now(HistoryEvent::WorkflowStarted {
+            name: name.to_string(),
+            input,
+            parent,
+        });
+        self.backend.create(instance_id, started.clone()).await?;
+        self.spawn_runner(instance_id, vec![started])
+    }
+
+    /// Starts the sub-workflow scheduled by a parent. The sub-workflow may already exist if
+    /// the parent was resumed after a restart, in which case it is only resumed or reported.
+    async fn start_child(
+        self: &Arc<Self>,
+        instance_id: &str,
+        name: &str,
+        input: Value,
+        parent: ParentInstance,
+    ) -> Result<()> {
+        match self
+            .create_instance(instance_id, name, input, Some(parent.clone()))
+            .await
+        {
+            Err(WorkflowError::InstanceExists { .. }) => {
+                let history = self.backend.load(instance_id).await?.unwrap_or_default();
+                match WorkflowState::from_history(instance_id, &history) {
+                    Some(state) if state.status.is_terminal() => {
+                        let outcome = match state.status {
+                            WorkflowStatus::Completed => Ok(state.output.unwrap_or_default()),
+                            WorkflowStatus::Failed => Err(state.error.unwrap_or_default()),
+                            _ => Err(TERMINATED_ERROR.to_string()),
+                        };
+                        self.notify_parent(&parent, outcome);
+                        Ok(())
+                    }
+                    Some(_) => self.spawn_runner(instance_id, history),
+                    None => InstanceNotFoundSnafu { instance_id }.fail(),
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn notify_parent(&self, parent: &ParentInstance, outcome: TaskOutcome) {
+        let task_id = parent.task_id;
+        let event = match outcome {
+            Ok(result) => HistoryEvent::SubWorkflowCompleted { task_id, result },
+            Err(error) => HistoryEvent::SubWorkflowFailed { task_id, error },
+        };
+        self.deliver(&parent.instance_id, event);
+    }
+
+    fn spawn_runner(
+        self: &Arc<Self>,
+        instance_id: &str,
+        history: Vec<HistoryRecord>,
+    ) -> Result<()> {
+        if self.stopped.load(Ordering::SeqCst) {
+            return EngineStoppedSnafu.fail();
+        }
+        let inbox = {
+            let mut runners = self.runners();
+            if runners.contains_key(instance_id) {
+                return Ok(());
+            }
+            let (sender, inbox) = mpsc::unbounded_channel();
+            runners.insert(instance_id.to_string(), sender);
+            inbox
+        };
+
+        let engine = self.clone();
+        let instance_id = instance_id.to_string();
+        self.spawn(async move {
+            if let Err(e) = Runner::run(engine.clone(), &instance_id, history, inbox).await {
+                tracing::warn!("workflow instance {instance_id} stopped: {e}");
+            }
+            engine.runners().remove(&instance_id);
+        });
+        Ok(())
+    }
+}
+
+/// Returns the ids of the sub-workflows scheduled in a history.
+/// With `pending`, only the sub-workflows which did not report their outcome are returned.
+fn sub_workflows(history: &[HistoryRecord], pending: bool) -> Vec<String> {
+    let completed: BTreeSet<_> = history
+        .iter()
+        .filter_map(|record| record.event.completed_task())
+        .collect();
+    history
+        .iter()
+        .filter_map(|record| match &record.event {
+            HistoryEvent::SubWorkflowScheduled {
+                task_id,
+                instance_id,
+                ..
+            } if !pending || !completed.contains(task_id) => Some(instance_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Records that the workflow future asked to be polled again.
+#[derive(Default)]
+struct Woken(AtomicBool);
+
+impl ArcWake for Woken {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Runner drives one workflow instance: it replays the history through the workflow, then
+/// appends the events delivered to its inbox and feeds them to the workflow.
+struct Runner {
+    engine: Arc<Inner>,
+    instance_id: String,
+    parent: Option<ParentInstance>,
+    state: Arc<Mutex<ContextState>>,
+    workflow: BoxFuture<TaskOutcome>,
+    /// Tasks scheduled and not completed yet.
+    in_flight: BTreeSet<u64>,
+    suspended: bool,
+    /// Records held back from the workflow while it is suspended.
+    buffered: Vec<HistoryRecord>,
+}
+
+impl Runner {
+    async fn run(
+        engine: Arc<Inner>,
+        instance_id: &str,
+        history: Vec<HistoryRecord>,
+        mut inbox: mpsc::UnboundedReceiver<(HistoryEvent, oneshot::Sender<()>)>,
+    ) -> Result<()> {
+        let Some(HistoryEvent::WorkflowStarted {
+            name,
+            input,
+            parent,
+        }) = history.first().map(|record| record.event.clone())
+        else {
+            return InstanceNotFoundSnafu { instance_id }.fail();
+        };
+        let workflow = engine
+            .workflow(&name)
+            .context(WorkflowNotRegisteredSnafu { name })?;
+
+        let mut state = ContextState::default();
+        state.replaying = true;
+        state.current_time = history[0].timestamp;
+        for record in &history {
+            if let Some(task_id) = record.event.scheduled_task() {
+                state.scheduled.insert(task_id, record.event.clone());
+            }
+        }
+        let in_flight = state.scheduled.keys().copied().collect();
+        let state = Arc::new(Mutex::new(state));
+
+        let mut runner = Runner {
+            workflow: workflow(WorkflowContext::new(instance_id, state.clone()), input),
+            engine,
+            instance_id: instance_id.to_string(),
+            parent,
+            state,
+            in_flight,
+            suspended: false,
+            buffered: Vec::new(),
+        };
+
+        let mut outcome = runner.poll();
+        for record in &history[1..] {
+            if outcome.is_some() {
+                break;
+            }
+            if let Some(task_id) = record.event.completed_task() {
+                runner.in_flight.remove(&task_id);
+            }
+            outcome = runner.process(record.clone());
+        }
+        runner.state().replaying = false;
+        if let Some(outcome) = outcome {
+            return runner.finish(outcome).await;
+        }
+
+        // Tasks scheduled before a restart may never have run, so dispatch them again.
+        let pending: Vec<_> = {
+            let state = runner.state();
+            runner
+                .in_flight
+                .iter()
+                .filter_map(|task_id| state.scheduled.get(task_id).cloned())
+                .collect()
+        };
+        for event in pending {
+            runner.dispatch(event);
+        }
+        runner.flush().await?;
+
+        while let Some((event, handled)) = inbox.recv().await {
+            let done = runner.handle(event).await?;
+            let _ = handled.send(());
+            if done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records an event delivered to the runner and feeds it to the workflow.
+    /// Returns true once the instance completed.
+    async fn handle(&mut self, event: HistoryEvent) -> Result<bool> {
+        if let Some(task_id) = event.completed_task()
+            && !self.in_flight.remove(&task_id)
+        {
+            return Ok(false);
+        }
+        match event {
+            HistoryEvent::ExecutionSuspended { .. } if self.suspended => return Ok(false),
+            HistoryEvent::ExecutionResumed { .. } if !self.suspended => return Ok(false),
+            _ => {}
+        }
+
+        let record = HistoryRecord::now(event);
+        self.engine
+            .backend
+            .append(&self.instance_id, std::slice::from_ref(&record))
+            .await?;
+        if let HistoryEvent::ExecutionTerminated { output, recursive } = record.event {
+            let children = if recursive {
+                let state = self.state();
+                self.in_flight
+                    .iter()
+                    .filter_map(|task_id| match state.scheduled.get(task_id) {
+                        Some(HistoryEvent::SubWorkflowScheduled { instance_id, .. }) => {
+                            Some(instance_id.clone())
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            self.engine
+                .terminated(self.parent.as_ref(), children, output);
+            return Ok(true);
+        }
+        if let Some(outcome) = self.process(record) {
+            self.finish(outcome).await?;
+            return Ok(true);
+        }
+        self.flush().await?;
+        Ok(false)
+    }
+
+    /// Feeds a history record to the workflow, unless the instance is suspended.
+    /// Returns the outcome of the workflow once it completes.
+    fn process(&mut self, record: HistoryRecord) -> Option<TaskOutcome> {
+        match &record.event {
+            HistoryEvent::ExecutionSuspended { .. } => self.suspended = true,
+            HistoryEvent::ExecutionResumed { .. } => {
+                self.suspended = false;
+                for record in std::mem::take(&mut self.buffered) {
+                    if let Some(outcome) = self.process(record) {
+                        return Some(outcome);
+                    }
+                }
+            }
+            _ if self.suspended => self.buffered.push(record),
+            _ => {
+                if self.apply(&record) {
+                    return self.poll();
+                }
+            }
+        }
+        None
+    }
+
+    fn state(&self) -> MutexGuard<'_, ContextState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Makes a history record visible to the workflow.
+    /// Returns true if the workflow may make progress from it.
+    fn apply(&self, record: &HistoryRecord) -> bool {
+        let mut state = self.state();
+        state.current_time = record.timestamp;
+        match &record.event {
+            HistoryEvent::ActivityCompleted { task_id, result }
+            | HistoryEvent::SubWorkflowCompleted { task_id, result } => {
+                state.complete(*task_id, Ok(result.clone()))
+            }
+            HistoryEvent::ActivityFailed { task_id, error }
+            | HistoryEvent::SubWorkflowFailed { task_id, error } => {
+                state.complete(*task_id, Err(error.clone()))
+            }
+            HistoryEvent::TimerFired { task_id } => state.complete(*task_id, Ok(Value::Null)),
+            HistoryEvent::EventRaised { name, payload } => {
+                state.raise(name.clone(), payload.clone())
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Polls the workflow until it completes or waits for something not in the history yet.
+    fn poll(&mut self) -> Option<TaskOutcome> {
+        let woken = Arc::new(Woken::default());
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            let poll = self.workflow.as_mut().poll(&mut cx);
+            if let Some(error) = self.state().nondeterminism.take() {
+                return Some(Err(format!("Nondeterministic workflow: {error}")));
+            }
+            if let Poll::Ready(outcome) = poll {
+                return Some(outcome);
+            }
+            if !woken.0.swap(false, Ordering::SeqCst) {
+                return None;
+            }
+        }
+    }
+
+    /// Records the tasks newly scheduled by the workflow, then dispatches them.
+    async fn flush(&mut self) -> Result<()> {
+        let actions = std::mem::take(&mut self.state().actions);
+        if actions.is_empty() {
+            return Ok(());
+        }
+        let records: Vec<_> = actions.into_iter().map(HistoryRecord::now).collect();
+        self.engine
+            .backend
+            .append(&self.instance_id, &records)
+            .await?;
+        for record in records {
+            self.dispatch(record.event);
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, event: HistoryEvent) {
+        let engine = self.engine.clone();
+        let instance_id = self.instance_id.clone();
+        match event {
+            HistoryEvent::ActivityScheduled {
+                task_id,
+                name,
+                input,
+            } => {
+                self.in_flight.insert(task_id);
+                let activity = engine.activity(&name);
+                self.engine.spawn(async move {
+                    let outcome = match activity {
+                        Some(activity) => activity(input).await,
+                        None => Err(format!("Activity {name} is not registered")),
+                    };
+                    let event = match outcome {
+                        Ok(result) => HistoryEvent::ActivityCompleted { task_id, result },
+                        Err(error) => HistoryEvent::ActivityFailed { task_id, error },
+                    };
+                    engine.deliver(&instance_id, event);
+                });
+            }
+            HistoryEvent::TimerCreated { task_id, fire_at } => {
+                self.in_flight.insert(task_id);
+                self.engine.spawn(async move {
+                    let delay = fire_at.saturating_sub(now_millis());
+                    tokio::time::sleep(Duration::from_millis(delay)).await;
+                    engine.deliver(&instance_id, HistoryEvent::TimerFired { task_id });
+                });
+            }
+            HistoryEvent::SubWorkflowScheduled {
+                task_id,
+                name,
+                instance_id: child_id,
+                input,
+            } => {
+                self.in_flight.insert(task_id);
+                self.engine.spawn(async move {
+                    let parent = ParentInstance {
+                        instance_id: instance_id.clone(),
+                        task_id,
+                    };
+                    if let Err(e) = engine.start_child(&child_id, &name, input, parent).await {
+                        let error = e.to_string();
+                        engine.deliver(
+                            &instance_id,
+                            HistoryEvent::SubWorkflowFailed { task_id, error },
+                        );
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Records the outcome of the workflow and reports it to its parent, if any.
+    async fn finish(&mut self, outcome: TaskOutcome) -> Result<()> {
+        let event = match &outcome {
+            Ok(result) => HistoryEvent::WorkflowCompleted {
+                result: result.clone(),
+            },
+            Err(error) => HistoryEvent::WorkflowFailed {
+                error: error.clone(),
+            },
+        };
+        self.engine
+            .backend
+            .append(&self.instance_id, &[HistoryRecord::now(event)])
+            .await?;
+        if let Some(parent) = &self.parent {
+            self.engine.notify_parent(parent, outcome);
+        }
+        self.engine
+            .completions
+            .send_modify(|n| *n = n.wrapping_add(1));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::{FileBackend, InMemoryBackend};
+    use serde_json::json;
+    use std::sync::atomic::AtomicUsize;
+
+    fn engine() -> WorkflowEngine {
+        let engine = WorkflowEngine::new(Arc::new(InMemoryBackend::new()));
+        engine.register_activity("add_one", |n: u64| async move { Ok::<_, String>(n + 1) });
+        engine.register_activity("fail", |_: ()| async move {
+            Err::<(), _>("card declined".to_string())
+        });
+        engine
+    }
+
+    #[tokio::test]
+    async fn test_activity_chain() {
+        let engine = engine();
+        engine.register_workflow("chain", |ctx: WorkflowContext, n: u64| async move {
+            let n: u64 = ctx.call_activity("add_one", n).await?;
+            ctx.call_activity::<u64>("add_one", n).await
+        });
+
+        let id = engine
+            .schedule_workflow("chain", Some("chain-1".to_string()), 1)
+            .await
+            .unwrap();
+        assert_eq!(id, "chain-1");
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.status, WorkflowStatus::Completed);
+        assert_eq!(state.output, Some(json!(3)));
+
+        let history = engine.get_history(&id).await.unwrap().unwrap();
+        assert_eq!(history.len(), 6);
+        assert!(matches!(
+            engine
+                .schedule_workflow("chain", Some(id), 1)
+                .await
+                .unwrap_err(),
+            WorkflowError::InstanceExists { .. }
+        ));
+        assert!(matches!(
+            engine
+                .schedule_workflow("missing", None, 1)
+                .await
+                .unwrap_err(),
+            WorkflowError::WorkflowNotRegistered { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_activity_failure_fails_workflow() {
+        let engine = engine();
+        engine.register_workflow("pay", |ctx: WorkflowContext, _: ()| async move {
+            ctx.call_activity::<()>("fail", ()).await
+        });
+
+        let id = engine.schedule_workflow("pay", None, ()).await.unwrap();
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.status, WorkflowStatus::Failed);
+        assert_eq!(state.error.as_deref(), Some("card declined"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_durable_timer() {
+        let engine = engine();
+        engine.register_workflow("remind", |ctx: WorkflowContext, _: ()| async move {
+            ctx.create_timer(Duration::from_secs(3600)).await;
+            Ok(())
+        });
+
+        let id = engine.schedule_workflow("remind", None, ()).await.unwrap();
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.status, WorkflowStatus::Completed);
+
+        let history = engine.get_history(&id).await.unwrap().unwrap();
+        assert!(matches!(
+            history[1].event,
+            HistoryEvent::TimerCreated { task_id: 0, fire_at } if fire_at == history[0].timestamp + 3_600_000
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_external_events() {
+        let engine = engine();
+        engine.register_workflow("approve", |ctx: WorkflowContext, _: ()| async move {
+            let first: String = ctx.wait_for_external_event("approval").await?;
+            let second: String = ctx.wait_for_external_event("approval").await?;
+            Ok(format!("{first},{second}"))
+        });
+
+        let id = engine.schedule_workflow("approve", None, ()).await.unwrap();
+        engine.raise_event(&id, "approval", "alice").await.unwrap();
+        engine.raise_event(&id, "approval", "bob").await.unwrap();
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.output, Some(json!("alice,bob")));
+
+        // Events raised to completed instances are dropped.
+        engine.raise_event(&id, "approval", "carol").await.unwrap();
+        assert!(matches!(
+            engine.raise_event("missing", "approval", ()).await,
+            Err(WorkflowError::InstanceNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sub_workflow() {
+        let engine = engine();
+        engine.register_workflow("child", |ctx: WorkflowContext, n: u64| async move {
+            ctx.call_activity::<u64>("add_one", n).await
+        });
+        engine.register_workflow("parent", |ctx: WorkflowContext, n: u64| async move {
+            let (a, b) = futures::join!(
+                ctx.call_sub_workflow::<u64>("child", n),
+                ctx.call_sub_workflow::<u64>("child", n * 10),
+            );
+            let missing = ctx.call_sub_workflow::<u64>("missing", n).await;
+            Ok((a?, b?, missing.is_err()))
+        });
+
+        let id = engine
+            .schedule_workflow("parent", Some("order".to_string()), 1)
+            .await
+            .unwrap();
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.output, Some(json!([2, 11, true])));
+
+        let child = engine.get_instance("order:1").await.unwrap().unwrap();
+        assert_eq!(child.output, Some(json!(11)));
+        assert_eq!(
+            child.parent,
+            Some(ParentInstance {
+                instance_id: id,
+                task_id: 1,
+            })
+        );
+    }
+
+    async fn wait_for_status(engine: &WorkflowEngine, instance_id: &str, status: WorkflowStatus) {
+        loop {
+            if let Some(state) = engine.get_instance(instance_id).await.unwrap()
+                && state.status == status
+            {
+                return;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suspend_and_resume() {
+        let engine = engine();
+        engine.register_workflow("approve", |ctx: WorkflowContext, _: ()| async move {
+            ctx.wait_for_external_event::<String>("approval").await
+        });
+
+        let id = engine.schedule_workflow("approve", None, ()).await.unwrap();
+        engine.suspend_instance(&id, "audit").await.unwrap();
+        engine.suspend_instance(&id, "audit").await.unwrap();
+        engine.raise_event(&id, "approval", "alice").await.unwrap();
+        let state = engine.get_instance(&id).await.unwrap().unwrap();
+        assert_eq!(state.status, WorkflowStatus::Suspended);
+
+        engine.resume_instance(&id, "audited").await.unwrap();
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.output, Some(json!("alice")));
+
+        let history = engine.get_history(&id).await.unwrap().unwrap();
+        let suspensions = history
+            .iter()
+            .filter(|r| matches!(r.event, HistoryEvent::ExecutionSuspended { .. }))
+            .count();
+        assert_eq!(suspensions, 1);
+    }
+
+    #[tokio::test]
+    async fn test_terminate_recursively() {
+        let engine = engine();
+        engine.register_workflow("child", |ctx: WorkflowContext, _: ()| async move {
+            ctx.wait_for_external_event::<()>("never").await
+        });
+        engine.register_workflow("parent", |ctx: WorkflowContext, _: ()| async move {
+            ctx.call_sub_workflow::<()>("child", ()).await
+        });
+
+        let id = engine
+            .schedule_workflow("parent", Some("parent".to_string()), ())
+            .await
+            .unwrap();
+        wait_for_status(&engine, "parent:0", WorkflowStatus::Running).await;
+        engine
+            .terminate_instance(&id, "cancelled", true)
+            .await
+            .unwrap();
+
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.status, WorkflowStatus::Terminated);
+        assert_eq!(state.output, Some(json!("cancelled")));
+        let child = engine.wait_for_completion("parent:0").await.unwrap();
+        assert_eq!(child.status, WorkflowStatus::Terminated);
+
+        // Terminating a completed instance does nothing.
+        engine.terminate_instance(&id, "again", true).await.unwrap();
+        let state = engine.get_instance(&id).await.unwrap().unwrap();
+        assert_eq!(state.output, Some(json!("cancelled")));
+    }
+
+    #[tokio::test]
+    async fn test_purge() {
+        let engine = engine();
+        engine.register_workflow("child", |ctx: WorkflowContext, n: u64| async move {
+            ctx.call_activity::<u64>("add_one", n).await
+        });
+        engine.register_workflow("parent", |ctx: WorkflowContext, n: u64| async move {
+            ctx.call_sub_workflow::<u64>("child", n).await
+        });
+        engine.register_workflow("wait", |ctx: WorkflowContext, _: ()| async move {
+            ctx.wait_for_external_event::<()>("go").await
+        });
+
+        let parent = engine.schedule_workflow("parent", None, 1).await.unwrap();
+        engine.wait_for_completion(&parent).await.unwrap();
+        let running = engine.schedule_workflow("wait", None, ()).await.unwrap();
+
+        assert!(matches!(
+            engine.purge_instance(&running, false).await,
+            Err(WorkflowError::InstanceRunning { .. })
+        ));
+        assert_eq!(engine.purge_instance(&parent, true).await.unwrap(), 2);
+        assert!(
+            engine
+                .get_instance(&format!("{parent}:0"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            engine.purge_instance(&parent, true).await,
+            Err(WorkflowError::InstanceNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_query_instances() {
+        let engine = engine();
+        engine.register_workflow("echo", |_: WorkflowContext, n: u64| async move { Ok(n) });
+        engine.register_workflow("wait", |ctx: WorkflowContext, _: ()| async move {
+            ctx.wait_for_external_event::<()>("go").await
+        });
+        for n in 0..3 {
+            let id = engine
+                .schedule_workflow("echo", Some(format!("echo-{n}")), n)
+                .await
+                .unwrap();
+            engine.wait_for_completion(&id).await.unwrap();
+        }
+        engine
+            .schedule_workflow("wait", Some("wait".to_string()), ())
+            .await
+            .unwrap();
+
+        let mut query = WorkflowQuery {
+            statuses: vec![WorkflowStatus::Completed],
+            page_size: 2,
+            ..Default::default()
+        };
+        let page = engine.query_instances(&query).await.unwrap();
+        let ids: Vec<_> = page
+            .instances
+            .iter()
+            .map(|s| s.instance_id.as_str())
+            .collect();
+        assert_eq!(ids, ["echo-0", "echo-1"]);
+        assert_eq!(page.continuation_token.as_deref(), Some("echo-1"));
+
+        query.continuation_token = page.continuation_token;
+        let page = engine.query_instances(&query).await.unwrap();
+        assert_eq!(page.instances.len(), 1);
+        assert_eq!(page.instances[0].instance_id, "echo-2");
+        assert!(page.continuation_token.is_none());
+
+        let all = engine
+            .query_instances(&WorkflowQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(all.instances.len(), 4);
+        let none = engine
+            .query_instances(&WorkflowQuery {
+                created_to: Some(all.instances[0].created_at),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(none.instances.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let new_engine = || {
+            let engine = WorkflowEngine::new(Arc::new(FileBackend::new(dir.path()).unwrap()));
+            let counter = calls.clone();
+            engine.register_activity("reserve", move |item: String| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move { Ok::<_, String>(format!("reserved {item}")) }
+            });
+            engine.register_workflow("order", |ctx: WorkflowContext, item: String| async move {
+                let reservation: String = ctx.call_activity("reserve", item).await?;
+                let payment: String = ctx.wait_for_external_event("paid").await?;
+                Ok(format!("{reservation}, {payment}"))
+            });
+            engine
+        };
+
+        let engine = new_engine();
+        let id = engine
+            .schedule_workflow("order", None, "book")
+            .await
+            .unwrap();
+        loop {
+            let history = engine.get_history(&id).await.unwrap().unwrap();
+            if history
+                .iter()
+                .any(|r| matches!(r.event, HistoryEvent::ActivityCompleted { .. }))
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        engine.shutdown();
+        assert!(matches!(
+            engine.schedule_workflow("order", None, "pen").await,
+            Err(WorkflowError::EngineStopped)
+        ));
+
+        // The event is recorded while no engine runs the instance.
+        engine.raise_event(&id, "paid", "paid 10").await.unwrap();
+
+        let engine = new_engine();
+        assert_eq!(engine.start().await.unwrap(), 1);
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.output, Some(json!("reserved book, paid 10")));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(engine.start().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_nondeterministic_workflow_fails() {
+        let backend: Arc<dyn Backend> = Arc::new(InMemoryBackend::new());
+        let engine = WorkflowEngine::new(backend.clone());
+        engine.register_activity("first", |_: ()| {
+            futures::future::pending::<std::result::Result<(), String>>()
+        });
+        engine.register_workflow("flow", |ctx: WorkflowContext, _: ()| async move {
+            ctx.wait_for_external_event::<()>("go").await?;
+            ctx.call_activity::<()>("first", ()).await
+        });
+        let id = engine.schedule_workflow("flow", None, ()).await.unwrap();
+        engine.raise_event(&id, "go", ()).await.unwrap();
+        loop {
+            let history = engine.get_history(&id).await.unwrap().unwrap();
+            if history.iter().any(|r| r.event.scheduled_task().is_some()) {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        engine.shutdown();
+
+        let engine = WorkflowEngine::new(backend);
+        engine.register_workflow("flow", |ctx: WorkflowContext, _: ()| async move {
+            ctx.wait_for_external_event::<()>("go").await?;
+            ctx.call_activity::<()>("second", ()).await
+        });
+        engine.start().await.unwrap();
+        let state = engine.wait_for_completion(&id).await.unwrap();
+        assert_eq!(state.status, WorkflowStatus::Failed);
+        assert!(
+            state
+                .error
+                .unwrap()
+                .starts_with("Nondeterministic workflow")
+        );
+    }
+}
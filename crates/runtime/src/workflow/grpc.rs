@@ -0,0 +1,381 @@
+//! gRPC management service of the workflow engine.
+
+use super::{WorkflowEngine, WorkflowError, WorkflowQuery, WorkflowState, WorkflowStatus};
+use proto::workflows_server::Workflows;
+use serde_json::Value;
+use tonic::{Request, Response, Status};
+
+pub use proto::workflows_server::WorkflowsServer;
+
+#[allow(missing_docs, unused_qualifications, clippy::all)]
+pub mod proto {
+    tonic::include_proto!("rapr.workflow.v1");
+}
+
+/// WorkflowService exposes the management operations of a [`WorkflowEngine`] over gRPC.
+#[derive(Clone)]
+pub struct WorkflowService {
+    engine: WorkflowEngine,
+}
+
+impl WorkflowService {
+    /// Creates a service managing the instances of the engine.
+    pub fn new(engine: WorkflowEngine) -> Self {
+        Self { engine }
+    }
+
+    /// Wraps the service into a server which can be added to a tonic router.
+    pub fn into_server(self) -> WorkflowsServer<Self> {
+        WorkflowsServer::new(self)
+    }
+}
+
+fn to_status(e: WorkflowError) -> Status {
+    let message = e.to_string();
+    match e {
+        WorkflowError::WorkflowNotRegistered { .. } => Status::invalid_argument(message),
+        WorkflowError::InstanceExists { .. } => Status::already_exists(message),
+        WorkflowError::InstanceNotFound { .. } => Status::not_found(message),
+        WorkflowError::InstanceRunning { .. } => Status::failed_precondition(message),
+        WorkflowError::Serialization { .. } => Status::invalid_argument(message),
+        WorkflowError::Backend { .. } => Status::internal(message),
+        WorkflowError::EngineStopped => Status::unavailable(message),
+    }
+}
+
+/// Parses a JSON payload, an empty string standing for null.
+fn parse_json(field: &str, payload: &str) -> Result<Value, Status> {
+    if payload.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_str(payload)
+        .map_err(|e| Status::invalid_argument(format!("Invalid JSON in {field}: {e}")))
+}
+
+impl From<WorkflowStatus> for proto::WorkflowStatus {
+    fn from(status: WorkflowStatus) -> Self {
+        match status {
+            WorkflowStatus::Running => proto::WorkflowStatus::Running,
+            WorkflowStatus::Completed => proto::WorkflowStatus::Completed,
+            WorkflowStatus::Failed => proto::WorkflowStatus::Failed,
+            WorkflowStatus::Suspended => proto::WorkflowStatus::Suspended,
+            WorkflowStatus::Terminated => proto::WorkflowStatus::Terminated,
+        }
+    }
+}
+
+impl From<WorkflowState> for proto::WorkflowInstance {
+    fn from(state: WorkflowState) -> Self {
+        Self {
+            instance_id: state.instance_id,
+            workflow_name: state.name,
+            status: proto::WorkflowStatus::from(state.status) as i32,
+            input: state.input.to_string(),
+            output: state.output.map(|output| output.to_string()),
+            error: state.error,
+            parent_instance_id: state.parent.map(|parent| parent.instance_id),
+            created_at: state.created_at as i64,
+            last_updated_at: state.last_updated_at as i64,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Workflows for WorkflowService {
+    async fn start_workflow(
+        &self,
+        request: Request<proto::StartWorkflowRequest>,
+    ) -> Result<Response<proto::StartWorkflowResponse>, Status> {
+        let request = request.into_inner();
+        let input = parse_json("input", &request.input)?;
+        let instance_id = Some(request.instance_id).filter(|id| !id.is_empty());
+        let instance_id = self
+            .engine
+            .schedule_workflow(&request.workflow_name, instance_id, input)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::StartWorkflowResponse { instance_id }))
+    }
+
+    async fn get_workflow(
+        &self,
+        request: Request<proto::GetWorkflowRequest>,
+    ) -> Result<Response<proto::GetWorkflowResponse>, Status> {
+        let instance_id = request.into_inner().instance_id;
+        let state = self
+            .engine
+            .get_instance(&instance_id)
+            .await
+            .map_err(to_status)?
+            .ok_or_else(|| to_status(WorkflowError::InstanceNotFound { instance_id }))?;
+        Ok(Response::new(proto::GetWorkflowResponse {
+            instance: Some(state.into()),
+        }))
+    }
+
+    async fn raise_event(
+        &self,
+        request: Request<proto::RaiseEventRequest>,
+    ) -> Result<Response<proto::RaiseEventResponse>, Status> {
+        let request = request.into_inner();
+        let payload = parse_json("payload", &request.payload)?;
+        self.engine
+            .raise_event(&request.instance_id, &request.event_name, payload)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::RaiseEventResponse {}))
+    }
+
+    async fn pause_workflow(
+        &self,
+        request: Request<proto::PauseWorkflowRequest>,
+    ) -> Result<Response<proto::PauseWorkflowResponse>, Status> {
+        let request = request.into_inner();
+        self.engine
+            .suspend_instance(&request.instance_id, &request.reason)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::PauseWorkflowResponse {}))
+    }
+
+    async fn resume_workflow(
+        &self,
+        request: Request<proto::ResumeWorkflowRequest>,
+    ) -> Result<Response<proto::ResumeWorkflowResponse>, Status> {
+        let request = request.into_inner();
+        self.engine
+            .resume_instance(&request.instance_id, &request.reason)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::ResumeWorkflowResponse {}))
+    }
+
+    async fn terminate_workflow(
+        &self,
+        request: Request<proto::TerminateWorkflowRequest>,
+    ) -> Result<Response<proto::TerminateWorkflowResponse>, Status> {
+        let request = request.into_inner();
+        let output = parse_json("output", &request.output)?;
+        self.engine
+            .terminate_instance(&request.instance_id, output, request.recursive)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::TerminateWorkflowResponse {}))
+    }
+
+    async fn purge_workflow(
+        &self,
+        request: Request<proto::PurgeWorkflowRequest>,
+    ) -> Result<Response<proto::PurgeWorkflowResponse>, Status> {
+        let request = request.into_inner();
+        let deleted = self
+            .engine
+            .purge_instance(&request.instance_id, request.recursive)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::PurgeWorkflowResponse {
+            deleted_instance_count: deleted as u32,
+        }))
+    }
+
+    async fn query_workflows(
+        &self,
+        request: Request<proto::QueryWorkflowsRequest>,
+    ) -> Result<Response<proto::QueryWorkflowsResponse>, Status> {
+        let request = request.into_inner();
+        let mut statuses = Vec::new();
+        for &status in &request.statuses {
+            let status = proto::WorkflowStatus::try_from(status).map_err(|_| {
+                Status::invalid_argument(format!("Unknown workflow status {status}"))
+            })?;
+            statuses.push(match status {
+                proto::WorkflowStatus::Running => WorkflowStatus::Running,
+                proto::WorkflowStatus::Completed => WorkflowStatus::Completed,
+                proto::WorkflowStatus::Failed => WorkflowStatus::Failed,
+                proto::WorkflowStatus::Suspended => WorkflowStatus::Suspended,
+                proto::WorkflowStatus::Terminated => WorkflowStatus::Terminated,
+                proto::WorkflowStatus::Unspecified => {
+                    return Err(Status::invalid_argument("Unspecified workflow status"));
+                }
+            });
+        }
+        let query = WorkflowQuery {
+            statuses,
+            created_from: request.created_from.map(|t| t.max(0) as u64),
+            created_to: request.created_to.map(|t| t.max(0) as u64),
+            page_size: request.page_size as usize,
+            continuation_token: Some(request.continuation_token).filter(|t| !t.is_empty()),
+        };
+
+        let page = self
+            .engine
+            .query_instances(&query)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(proto::QueryWorkflowsResponse {
+            instances: page.instances.into_iter().map(Into::into).collect(),
+            continuation_token: page.continuation_token.unwrap_or_default(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workflow::{InMemoryBackend, WorkflowContext};
+    use std::sync::Arc;
+    use tonic::Code;
+
+    fn service() -> WorkflowService {
+        let engine = WorkflowEngine::new(Arc::new(InMemoryBackend::new()));
+        engine.register_workflow("approve", |ctx: WorkflowContext, amount: u64| async move {
+            let approver: String = ctx.wait_for_external_event("approval").await?;
+            Ok(format!("{amount} approved by {approver}"))
+        });
+        WorkflowService::new(engine)
+    }
+
+    async fn get(service: &WorkflowService, instance_id: &str) -> proto::WorkflowInstance {
+        service
+            .get_workflow(Request::new(proto::GetWorkflowRequest {
+                instance_id: instance_id.to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .instance
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_manage_workflow() {
+        let service = service();
+        let instance_id = service
+            .start_workflow(Request::new(proto::StartWorkflowRequest {
+                workflow_name: "approve".to_string(),
+                instance_id: "order-1".to_string(),
+                input: "10".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .instance_id;
+        assert_eq!(instance_id, "order-1");
+
+        service
+            .pause_workflow(Request::new(proto::PauseWorkflowRequest {
+                instance_id: instance_id.clone(),
+                reason: "audit".to_string(),
+            }))
+            .await
+            .unwrap();
+        service
+            .raise_event(Request::new(proto::RaiseEventRequest {
+                instance_id: instance_id.clone(),
+                event_name: "approval".to_string(),
+                payload: "\"alice\"".to_string(),
+            }))
+            .await
+            .unwrap();
+        assert_eq!(
+            get(&service, &instance_id).await.status(),
+            proto::WorkflowStatus::Suspended
+        );
+
+        service
+            .resume_workflow(Request::new(proto::ResumeWorkflowRequest {
+                instance_id: instance_id.clone(),
+                reason: String::new(),
+            }))
+            .await
+            .unwrap();
+        service
+            .engine
+            .wait_for_completion(&instance_id)
+            .await
+            .unwrap();
+        let instance = get(&service, &instance_id).await;
+        assert_eq!(instance.status(), proto::WorkflowStatus::Completed);
+        assert_eq!(instance.output.as_deref(), Some("\"10 approved by alice\""));
+
+        let page = service
+            .query_workflows(Request::new(proto::QueryWorkflowsRequest {
+                statuses: vec![proto::WorkflowStatus::Completed as i32],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(page.instances, vec![instance]);
+        assert!(page.continuation_token.is_empty());
+
+        let purged = service
+            .purge_workflow(Request::new(proto::PurgeWorkflowRequest {
+                instance_id: instance_id.clone(),
+                recursive: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(purged.deleted_instance_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let service = service();
+        let start = |name: &str, input: &str| proto::StartWorkflowRequest {
+            workflow_name: name.to_string(),
+            instance_id: "order".to_string(),
+            input: input.to_string(),
+        };
+
+        let err = service
+            .start_workflow(Request::new(start("approve", "{")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+        let err = service
+            .start_workflow(Request::new(start("missing", "")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        service
+            .start_workflow(Request::new(start("approve", "1")))
+            .await
+            .unwrap();
+        let err = service
+            .start_workflow(Request::new(start("approve", "1")))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::AlreadyExists);
+
+        let err = service
+            .purge_workflow(Request::new(proto::PurgeWorkflowRequest {
+                instance_id: "order".to_string(),
+                recursive: true,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::FailedPrecondition);
+
+        let err = service
+            .get_workflow(Request::new(proto::GetWorkflowRequest {
+                instance_id: "missing".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Code::NotFound);
+
+        for status in [proto::WorkflowStatus::Unspecified as i32, 42] {
+            let err = service
+                .query_workflows(Request::new(proto::QueryWorkflowsRequest {
+                    statuses: vec![status],
+                    ..Default::default()
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), Code::InvalidArgument);
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Returns the current time in milliseconds since the unix epoch.
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// ParentInstance identifies the workflow which started a sub-workflow.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParentInstance {
+    #[serde(rename = "instanceId")]
+    pub instance_id: String,
+    #[serde(rename = "taskId")]
+    pub task_id: u64,
+}
+
+/// HistoryEvent is something that happened to a workflow instance.
+///
+/// The history of an instance is append-only: replaying it through the workflow function
+/// deterministically rebuilds the state the instance was in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum HistoryEvent {
+    /// First event of every instance.
+    WorkflowStarted {
+        name: String,
+        input: Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parent: Option<ParentInstance>,
+    },
+    ActivityScheduled {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        name: String,
+        input: Value,
+    },
+    ActivityCompleted {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        result: Value,
+    },
+    ActivityFailed {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        error: String,
+    },
+    TimerCreated {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        /// Time the timer fires at, in milliseconds since the unix epoch.
+        #[serde(rename = "fireAt")]
+        fire_at: u64,
+    },
+    TimerFired {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+    },
+    SubWorkflowScheduled {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        name: String,
+        #[serde(rename = "instanceId")]
+        instance_id: String,
+        input: Value,
+    },
+    SubWorkflowCompleted {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        result: Value,
+    },
+    SubWorkflowFailed {
+        #[serde(rename = "taskId")]
+        task_id: u64,
+        error: String,
+    },
+    /// An external event was raised to the instance.
+    EventRaised {
+        name: String,
+        payload: Value,
+    },
+    /// The instance was paused: events recorded after this one are only fed to the workflow
+    /// once it is resumed.
+    ExecutionSuspended {
+        reason: String,
+    },
+    ExecutionResumed {
+        reason: String,
+    },
+    /// The instance was stopped before completing. `recursive` also terminates the
+    /// sub-workflows it was waiting for.
+    ExecutionTerminated {
+        output: Value,
+        recursive: bool,
+    },
+    WorkflowCompleted {
+        result: Value,
+    },
+    WorkflowFailed {
+        error: String,
+    },
+}
+
+impl HistoryEvent {
+    /// Returns the id of the task the event completes, if any.
+    pub fn completed_task(&self) -> Option<u64> {
+        match self {
+            HistoryEvent::ActivityCompleted { task_id, .. }
+            | HistoryEvent::ActivityFailed { task_id, .. }
+            | HistoryEvent::TimerFired { task_id }
+            | HistoryEvent::SubWorkflowCompleted { task_id, .. }
+            | HistoryEvent::SubWorkflowFailed { task_id, .. } => Some(*task_id),
+            _ => None,
+        }
+    }
+
+    /// Returns the id of the task the event schedules, if any.
+    pub fn scheduled_task(&self) -> Option<u64> {
+        match self {
+            HistoryEvent::ActivityScheduled { task_id, .. }
+            | HistoryEvent::TimerCreated { task_id, .. }
+            | HistoryEvent::SubWorkflowScheduled { task_id, .. } => Some(*task_id),
+            _ => None,
+        }
+    }
+}
+
+/// HistoryRecord is a history event along with the time it was recorded at.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: HistoryEvent,
+}
+
+impl HistoryRecord {
+    /// Records the event at the current time.
+    pub fn now(event: HistoryEvent) -> Self {
+        Self {
+            timestamp: now_millis(),
+            event,
+        }
+    }
+}
+
+/// WorkflowStatus is the runtime status of a workflow instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WorkflowStatus {
+    Running,
+    Completed,
+    Failed,
+    Suspended,
+    Terminated,
+}
+
+impl WorkflowStatus {
+    /// Returns true once the instance will not make progress anymore.
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, WorkflowStatus::Running | WorkflowStatus::Suspended)
+    }
+}
+
+/// WorkflowState summarizes a workflow instance from its history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkflowState {
+    #[serde(rename = "instanceId")]
+    pub instance_id: String,
+    pub name: String,
+    pub status: WorkflowStatus,
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<ParentInstance>,
+    /// Milliseconds since the unix epoch.
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+    /// Milliseconds since the unix epoch.
+    #[serde(rename = "lastUpdatedAt")]
+    pub last_updated_at: u64,
+}
+
+impl WorkflowState {
+    /// Builds the state of an instance from its history, which must start with
+    /// [`HistoryEvent::WorkflowStarted`].
+    pub fn from_history(instance_id: &str, history: &[HistoryRecord]) -> Option<Self> {
+        let first = history.first()?;
+        let HistoryEvent::WorkflowStarted {
+            name,
+            input,
+            parent,
+        } = &first.event
+        else {
+            return None;
+        };
+
+        let mut state = WorkflowState {
+            instance_id: instance_id.to_string(),
+            name: name.clone(),
+            status: WorkflowStatus::Running,
+            input: input.clone(),
+            output: None,
+            error: None,
+            parent: parent.clone(),
+            created_at: first.timestamp,
+            last_updated_at: first.timestamp,
+        };
+        for record in &history[1..] {
+            state.last_updated_at = record.timestamp;
+            match &record.event {
+                HistoryEvent::WorkflowCompleted { result } => {
+                    state.status = WorkflowStatus::Completed;
+                    state.output = Some(result.clone());
+                }
+                HistoryEvent::WorkflowFailed { error } => {
+                    state.status = WorkflowStatus::Failed;
+                    state.error = Some(error.clone());
+                }
+                HistoryEvent::ExecutionSuspended { .. } => {
+                    state.status = WorkflowStatus::Suspended;
+                }
+                HistoryEvent::ExecutionResumed { .. } => {
+                    state.status = WorkflowStatus::Running;
+                }
+                HistoryEvent::ExecutionTerminated { output, .. } => {
+                    state.status = WorkflowStatus::Terminated;
+                    state.output = Some(output.clone());
+                }
+                _ => {}
+            }
+        }
+        Some(state)
+    }
+}
+
+/// WorkflowQuery selects workflow instances by status and creation time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkflowQuery {
+    /// Statuses to match. Instances in any status match when empty.
+    pub statuses: Vec<WorkflowStatus>,
+    /// Only matches instances created at or after this time, in milliseconds since the unix
+    /// epoch.
+    pub created_from: Option<u64>,
+    /// Only matches instances created before this time, in milliseconds since the unix epoch.
+    pub created_to: Option<u64>,
+    /// Maximum number of instances to return. Every match is returned when zero.
+    pub page_size: usize,
+    /// Token returned with the previous page, to continue after it.
+    pub continuation_token: Option<String>,
+}
+
+impl WorkflowQuery {
+    /// Returns true if the instance satisfies the filters of the query.
+    pub fn matches(&self, state: &WorkflowState) -> bool {
+        (self.statuses.is_empty() || self.statuses.contains(&state.status))
+            && self
+                .created_from
+                .is_none_or(|from| state.created_at >= from)
+            && self.created_to.is_none_or(|to| state.created_at < to)
+    }
+}
+
+/// WorkflowPage is a page of the instances matching a [`WorkflowQuery`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkflowPage {
+    pub instances: Vec<WorkflowState>,
+    /// Set when more instances match the query: pass it in the next query to get them.
+    pub continuation_token: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_record_serialization() {
+        let record = HistoryRecord {
+            timestamp: 42,
+            event: HistoryEvent::ActivityScheduled {
+                task_id: 1,
+                name: "charge".to_string(),
+                input: json!({"amount": 10}),
+            },
+        };
+        let encoded = serde_json::to_string(&record).unwrap();
+        assert_eq!(
+            encoded,
+            r#"{"timestamp":42,"event":"activityScheduled","taskId":1,"name":"charge","input":{"amount":10}}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<HistoryRecord>(&encoded).unwrap(),
+            record
+        );
+    }
+
+    #[test]
+    fn test_state_from_history() {
+        let history = vec![
+            HistoryRecord {
+                timestamp: 1,
+                event: HistoryEvent::WorkflowStarted {
+                    name: "order".to_string(),
+                    input: json!(1),
+                    parent: None,
+                },
+            },
+            HistoryRecord {
+                timestamp: 5,
+                event: HistoryEvent::WorkflowCompleted { result: json!(2) },
+            },
+        ];
+
+        let state = WorkflowState::from_history("id", &history).unwrap();
+        assert_eq!(state.status, WorkflowStatus::Completed);
+        assert_eq!(state.output, Some(json!(2)));
+        assert_eq!(state.created_at, 1);
+        assert_eq!(state.last_updated_at, 5);
+        assert!(WorkflowState::from_history("id", &history[1..]).is_none());
+    }
+
+    #[test]
+    fn test_state_from_suspended_history() {
+        let history = [
+            HistoryRecord::now(HistoryEvent::WorkflowStarted {
+                name: "order".to_string(),
+                input: json!(null),
+                parent: None,
+            }),
+            HistoryRecord::now(HistoryEvent::ExecutionSuspended {
+                reason: "maintenance".to_string(),
+            }),
+            HistoryRecord::now(HistoryEvent::ExecutionResumed {
+                reason: String::new(),
+            }),
+            HistoryRecord::now(HistoryEvent::ExecutionTerminated {
+                output: json!("cancelled"),
+                recursive: true,
+            }),
+        ];
+        let status = |len| {
+            WorkflowState::from_history("id", &history[..len])
+                .unwrap()
+                .status
+        };
+        assert_eq!(status(2), WorkflowStatus::Suspended);
+        assert!(!status(2).is_terminal());
+        assert_eq!(status(3), WorkflowStatus::Running);
+        assert_eq!(status(4), WorkflowStatus::Terminated);
+        assert!(status(4).is_terminal());
+    }
+}
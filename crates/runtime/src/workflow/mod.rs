@@ -0,0 +1,54 @@
+//! Workflow building block.
+//!
+//! Workflows are async functions orchestrating activities, timers, external events and
+//! sub-workflows through a [`WorkflowContext`]. Everything that happens to a workflow
+//! instance is appended to its history, persisted by a [`Backend`]. When the engine
+//! restarts, the workflow function is replayed against that history: calls whose outcome is
+//! already recorded complete immediately instead of running again, so the instance resumes
+//! where it stopped.
+//!
+//! Because of replay, workflow functions must be deterministic: they must only await the
+//! futures returned by their context, and take decisions only from their input and the
+//! outcome of those futures. Side effects belong in activities.
+//!
+//! Running instances are operated through the [`WorkflowEngine`] or, remotely, through the
+//! gRPC service of the [`grpc`] module.
+
+use snafu::Snafu;
+
+mod backend;
+mod context;
+mod engine;
+pub mod grpc;
+mod history;
+
+pub use backend::*;
+pub use context::*;
+pub use engine::*;
+pub use history::*;
+
+pub type Result<T> = std::result::Result<T, WorkflowError>;
+
+#[derive(Debug, Snafu)]
+pub enum WorkflowError {
+    #[snafu(display("Workflow {} is not registered", name))]
+    WorkflowNotRegistered { name: String },
+
+    #[snafu(display("Workflow instance {} already exists", instance_id))]
+    InstanceExists { instance_id: String },
+
+    #[snafu(display("Workflow instance {} not found", instance_id))]
+    InstanceNotFound { instance_id: String },
+
+    #[snafu(display("Workflow instance {} is still running", instance_id))]
+    InstanceRunning { instance_id: String },
+
+    #[snafu(display("Failed to serialize workflow payload: {}", source))]
+    Serialization { source: serde_json::Error },
+
+    #[snafu(display("Failed to access workflow backend: {}", source))]
+    Backend { source: std::io::Error },
+
+    #[snafu(display("Workflow engine is shut down"))]
+    EngineStopped,
+}